@@ -27,7 +27,7 @@ async fn run() {
     println!("{:?}", reply);
 
     println!("Calling infinite loop");
-    let mut call: Call<()> = client.echo().infinite_loop(());
+    let mut call: Call<(), String> = client.echo().infinite_loop(());
     sleep(Duration::from_secs(3)).await;
     println!("Calling cancellation");
     call.cancel();
@@ -35,7 +35,7 @@ async fn run() {
     println!("{:?}", reply);
 
     println!("Calling infinite loop with timeout");
-    let call: Call<()> = client
+    let call: Call<(), String> = client
         .set_next_timeout(Duration::from_secs(3))
         .echo()
         .infinite_loop(());
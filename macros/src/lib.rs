@@ -21,6 +21,8 @@ pub(crate) const EXPORTED_TRAIT_SUFFIX: &str = "Handler";
 pub(crate) const CLIENT_SUFFIX: &str = "Client";
 #[cfg(all(feature = "client", feature = "runtime",))]
 pub(crate) const CLIENT_STUB_SUFFIX: &str = "ClientStub";
+#[cfg(all(feature = "client", feature = "runtime",))]
+pub(crate) const NOTIFY_STUB_SUFFIX: &str = "_notify";
 
 /// A macro that impls serde::Deserializer by simply calling the
 /// corresponding functions of the inner deserializer
@@ -257,6 +259,83 @@ pub fn impl_inner_deserializer(_: proc_macro::TokenStream) -> proc_macro::TokenS
 ///     }
 /// }
 /// ```
+///
+/// ### Example - Notification
+///
+/// A unary method that does not take a `RequestStream<T>` also gets a client stub with a
+/// `_notify` suffix, which sends a one-way request with `toy_rpc::client::Client::notify`.
+/// The stub is not generated if another exported method already has its name, ie. an
+/// exported `subtract_notify`.
+///
+/// ```rust
+/// // the server executes `subtract` without sending back the result
+/// client.abacus().subtract_notify((5, 3))?;
+/// ```
+///
+/// ### Example - Server-streaming method
+///
+/// A method that returns a stream of `Result<T, E>` will be exported as a server-streaming
+/// method, and the generated client stub will return a `toy_rpc::client::CallStream<T>`.
+/// The returned stream must not borrow `self`.
+///
+/// ```rust
+/// #[export_impl]
+/// impl Abacus {
+///     #[export_method]
+///     fn count_to(&self, args: u32) -> impl Stream<Item = Result<u32, String>> {
+///         futures::stream::iter((0..args).map(Ok))
+///     }
+/// }
+/// ```
+///
+/// ### Example - Client-streaming and bidirectional streaming methods
+///
+/// A method that takes a `toy_rpc::service::RequestStream<T>` receives the items sent by the
+/// client. The generated client stub takes no argument and returns a `toy_rpc::client::CallSink<T>`
+/// along with a `Call` or, if the method returns a stream, a `CallStream`.
+///
+/// ```rust
+/// #[export_impl]
+/// impl Abacus {
+///     #[export_method]
+///     async fn sum(&self, mut args: RequestStream<i32>) -> Result<i32, String> {
+///         let mut sum = 0;
+///         while let Some(item) = args.next().await {
+///             sum += item.map_err(|e| e.to_string())?;
+///         }
+///         Ok(sum)
+///     }
+///
+///     #[export_method]
+///     fn double(&self, args: RequestStream<i32>) -> impl Stream<Item = Result<i32, String>> {
+///         args.map(|item| item.map(|i| i * 2).map_err(|e| e.to_string()))
+///     }
+/// }
+///
+/// // on the client side
+/// let (mut sink, call) = client.abacus().sum();
+/// sink.send(1).await?;
+/// sink.close().await?;
+/// let sum = call.await?;
+/// ```
+///
+/// ### Example - Method with `RpcContext`
+///
+/// A method can take a `toy_rpc::service::RpcContext` right before the request argument.
+/// The context is injected by the server and is not part of the client stub.
+///
+/// ```rust
+/// #[export_impl]
+/// impl Abacus {
+///     #[export_method]
+///     async fn slow_add(&self, ctx: RpcContext, args: (i32, i32)) -> Result<i32, String> {
+///         futures::select! {
+///             _ = ctx.cancelled().fuse() => Err("Canceled".to_string()),
+///             _ = sleep(Duration::from_secs(1)).fuse() => Ok(args.0 + args.1),
+///         }
+///     }
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn export_impl(
     _attr: proc_macro::TokenStream,
@@ -28,11 +28,11 @@ use super::*;
 /// }
 /// pub fn increment_handler(
 ///     self: std::sync::Arc<Self>,
-///     mut deserializer: Box<dyn toy_rpc::erased_serde::Deserializer<'static> + Send>,
-/// ) -> toy_rpc::service::HandlerResultFut {
-///     Box::pin(async move {
-///     let req: i32 = toy_rpc::erased_serde::deserialize(&mut deserializer)
-///         .map_err(|e| toy_rpc::error::Error::ParseError(Box::new(e)))?;
+///     _ctx: toy_rpc::service::RpcContext,
+///     request: toy_rpc::service::RequestBody,
+/// ) -> toy_rpc::service::HandlerOutput {
+///     toy_rpc::service::HandlerOutput::Unary(Box::pin(async move {
+///     let req: i32 = request.deserialize()?;
 ///     let res = self
 ///         .increment(req) // executes the RPC method
 ///         .await
@@ -42,7 +42,7 @@ use super::*;
 ///         })
 ///         .map_err(|e| toy_rpc::error::Error::ExecutionError(e.to_string()));
 ///     res
-///     })
+///     }))
 /// }
 /// ```
 #[cfg(feature = "server")]
@@ -79,34 +79,24 @@ pub(crate) fn transform_impl_item(f: &mut syn::ImplItemMethod) {
     let concat_name = format!("{}_{}", &ident.to_string(), HANDLER_SUFFIX);
     let handler_ident = syn::Ident::new(&concat_name, ident.span());
 
-    // change asyncness
-    f.sig.asyncness = None;
-
     // transform function request type
     if let syn::FnArg::Typed(pt) = f.sig.inputs.last().unwrap() {
         let req_ty = &pt.ty;
+        let kind = parse_return_kind(&f.sig);
 
-        f.block = syn::parse_quote!({
-            Box::pin(
-                async move {
-                    let req: #req_ty = toy_rpc::erased_serde::deserialize(&mut deserializer)
-                        .map_err(|e| toy_rpc::error::Error::ParseError(Box::new(e)))?;
-                    self.#ident(req).await
-                        .map(|r| Box::new(r) as Box<dyn toy_rpc::erased_serde::Serialize + Send + Sync + 'static>)
-                        .map_err(|err| err.into())
-                }
-            )
-        });
+        let has_ctx = has_context_arg(&f.sig);
 
-        f.sig.inputs = syn::parse_quote!(
-            self: std::sync::Arc<Self>, mut deserializer: Box<dyn toy_rpc::erased_serde::Deserializer<'static> + Send>
-        );
+        f.block = generate_handler_block(&ident, req_ty, &kind, has_ctx);
+        f.sig.inputs = generate_handler_inputs(has_ctx);
 
         f.sig.output = syn::parse_quote!(
-            -> toy_rpc::service::HandlerResultFut
+            -> toy_rpc::service::HandlerOutput
         );
     };
 
+    // change asyncness
+    f.sig.asyncness = None;
+
     f.sig.ident = handler_ident;
 }
 
@@ -194,12 +184,25 @@ fn client_stub_impl_for_struct(
     input: &syn::ItemImpl,
 ) -> syn::ItemImpl {
     let input = filter_exported_impl_items(input.clone());
+    let method_names: Vec<String> = input
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::ImplItem::Method(f) => Some(f.sig.ident.to_string()),
+            _ => None,
+        })
+        .collect();
     let mut generated_items: Vec<syn::ImplItem> = Vec::new();
     input.items.iter().for_each(|item| {
         if let syn::ImplItem::Method(f) = item {
             if let Some(method) = generate_client_stub_for_struct_method(service_ident, f) {
                 generated_items.push(syn::ImplItem::Method(method));
             }
+            if let Some(method) =
+                generate_client_notify_stub_for_method(service_ident, &f.sig, &method_names)
+            {
+                generated_items.push(syn::ImplItem::Method(method));
+            }
         }
     });
 
@@ -218,22 +221,7 @@ pub(crate) fn generate_client_stub_for_struct_method(
     service_ident: &syn::Ident,
     f: &syn::ImplItemMethod,
 ) -> Option<syn::ImplItemMethod> {
-    if let syn::FnArg::Typed(pt) = f.sig.inputs.last().unwrap() {
-        let fn_ident = &f.sig.ident;
-        let req_ty = &pt.ty;
-
-        if let syn::ReturnType::Type(_, ret_ty) = f.sig.output.clone() {
-            let ok_ty = get_ok_ident_from_type(ret_ty)?;
-            return Some(generate_client_stub_for_struct_method_impl(
-                service_ident,
-                fn_ident,
-                &req_ty,
-                &ok_ty,
-            ));
-        }
-    }
-
-    None
+    generate_client_stub_for_method(service_ident, &f.sig)
 }
 
 /// Generate client stub for the service impl block
@@ -59,8 +59,9 @@ fn generate_transformed_trait_item(ident: &syn::Ident) -> syn::TraitItemMethod {
     syn::parse_quote!(
         fn #handler_ident(
             self: std::sync::Arc<Self>,
-            deserializer: Box<dyn toy_rpc::erased_serde::Deserializer<'static> + Send>
-        ) -> toy_rpc::service::HandlerResultFut;
+            ctx: toy_rpc::service::RpcContext,
+            request: toy_rpc::service::RequestBody
+        ) -> toy_rpc::service::HandlerOutput;
     )
 }
 
@@ -90,23 +91,14 @@ fn impl_transformed_trait(
             let req_ty = &pt.ty;
             let handler_ident = &handler_item.sig.ident;
             let orig_ident = &orig_item.sig.ident;
+            let kind = parse_return_kind(&orig_item.sig);
+            let has_ctx = has_context_arg(&orig_item.sig);
+            let block = generate_handler_block(orig_ident, req_ty, &kind, has_ctx);
+            let inputs = generate_handler_inputs(has_ctx);
 
             let f: syn::ImplItemMethod = syn::parse_quote!(
-                fn #handler_ident(
-                    self: std::sync::Arc<Self>,
-                    mut deserializer: Box<dyn toy_rpc::erased_serde::Deserializer<'static> + Send>
-                ) -> toy_rpc::service::HandlerResultFut
-                {
-                    Box::pin(
-                        async move {
-                            let req: #req_ty = toy_rpc::erased_serde::deserialize(&mut deserializer)
-                                .map_err(|e| toy_rpc::error::Error::ParseError(Box::new(e)))?;
-                            self.#orig_ident(req).await
-                                .map(|r| Box::new(r) as Box<dyn toy_rpc::erased_serde::Serialize + Send + Sync + 'static>)
-                                .map_err(|err| err.into())
-                        }
-                    )
-                }
+                fn #handler_ident(#inputs) -> toy_rpc::service::HandlerOutput
+                #block
             );
             trait_impl.items.push(syn::ImplItem::Method(f));
         }
@@ -239,12 +231,25 @@ fn client_stub_impl_for_trait(
     input: &syn::ItemTrait,
 ) -> syn::ItemImpl {
     let input = filter_exported_trait_items(input.clone());
+    let method_names: Vec<String> = input
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::TraitItem::Method(f) => Some(f.sig.ident.to_string()),
+            _ => None,
+        })
+        .collect();
     let mut generated_items: Vec<syn::ImplItem> = Vec::new();
     input.items.iter().for_each(|item| {
         if let syn::TraitItem::Method(f) = item {
             if let Some(method) = generate_client_stub_for_trait_method(service_ident, f) {
                 generated_items.push(syn::ImplItem::Method(method))
             }
+            if let Some(method) =
+                generate_client_notify_stub_for_method(service_ident, &f.sig, &method_names)
+            {
+                generated_items.push(syn::ImplItem::Method(method))
+            }
         }
     });
 
@@ -262,22 +267,7 @@ fn generate_client_stub_for_trait_method(
     service_ident: &syn::Ident,
     f: &syn::TraitItemMethod,
 ) -> Option<syn::ImplItemMethod> {
-    if let syn::FnArg::Typed(pt) = f.sig.inputs.last().unwrap() {
-        let fn_ident = &f.sig.ident;
-        let req_ty = &pt.ty;
-
-        if let syn::ReturnType::Type(_, ret_ty) = f.sig.output.clone() {
-            let ok_ty = get_ok_ident_from_type(ret_ty)?;
-            return Some(generate_client_stub_for_struct_method_impl(
-                service_ident,
-                fn_ident,
-                &req_ty,
-                &ok_ty,
-            ));
-        }
-    }
-
-    None
+    generate_client_stub_for_method(service_ident, &f.sig)
 }
 
 #[cfg(all(feature = "client", feature = "runtime"))]
@@ -356,22 +346,105 @@ fn generate_trait_method_impl_for_client(
         _ => panic!("Argument ident not found"),
     };
     let service_method = format!("{}.{}", service_ident, method_ident);
-    let block: syn::Block = syn::parse_quote!(
-        {
-            Box::pin(
-                async move {
-                    let success = self.call(#service_method, #arg_ident).await?;
-                    Ok(success)
-                }
-            )
+    // A `RequestStream<T>` argument is forwarded to the server through a `CallSink<T>`
+    let is_request_stream = match arg {
+        syn::FnArg::Typed(pt) => get_request_stream_item_type(&pt.ty).is_some(),
+        _ => false,
+    };
+    let block: syn::Block = match parse_return_kind(&method.sig) {
+        ReturnKind::Unary if is_request_stream => syn::parse_quote!(
+            {
+                Box::pin(
+                    async move {
+                        let (sink, call) = self.call_sink(#service_method);
+                        let (forwarded, res) = toy_rpc::futures::future::join(
+                            toy_rpc::futures::StreamExt::forward(#arg_ident, sink),
+                            call
+                        ).await;
+                        forwarded?;
+                        let success = res?;
+                        Ok(success)
+                    }
+                )
+            }
+        ),
+        ReturnKind::Unary => syn::parse_quote!(
+            {
+                Box::pin(
+                    async move {
+                        let success = self.call(#service_method, #arg_ident).await?;
+                        Ok(success)
+                    }
+                )
+            }
+        ),
+        ReturnKind::Stream { item, awaited } => {
+            let ok_ty = get_ok_ident_from_type(item.clone())
+                .expect("Expecting the stream item to be a Result");
+            let responses: syn::Expr = match is_request_stream {
+                // Requests are forwarded while the responses are polled. Errors
+                // while forwarding are yielded by the stream
+                true => syn::parse_quote!({
+                    let (sink, responses) = self.call_duplex(#service_method);
+                    let forward = toy_rpc::futures::StreamExt::filter_map(
+                        toy_rpc::futures::stream::once(
+                            toy_rpc::futures::StreamExt::forward(#arg_ident, sink)
+                        ),
+                        |res: Result<(), toy_rpc::Error>| toy_rpc::futures::future::ready(res.err().map(Err))
+                    );
+                    toy_rpc::futures::stream::select(forward, responses)
+                }),
+                false => syn::parse_quote!(self.call_stream(#service_method, #arg_ident)),
+            };
+            let stream: syn::Expr = syn::parse_quote!(
+                toy_rpc::futures::StreamExt::map(
+                    #responses,
+                    |item: Result<#ok_ty, toy_rpc::Error>| -> #item { item.map_err(|err| err.into()) }
+                )
+            );
+            let ret_ty = match &method.sig.output {
+                syn::ReturnType::Type(_, ty) => ty.as_ref(),
+                syn::ReturnType::Default => panic!("Expecting a return type"),
+            };
+            let stream_ty = match awaited {
+                true => get_future_output_type(ret_ty).unwrap_or(ret_ty),
+                false => ret_ty,
+            };
+            // `impl Stream` can be returned without boxing
+            let stream: syn::Expr = match stream_ty {
+                syn::Type::ImplTrait(_) => stream,
+                _ => syn::parse_quote!({
+                    let stream: #stream_ty = Box::pin(#stream);
+                    stream
+                }),
+            };
+            match awaited {
+                true => syn::parse_quote!(
+                    {
+                        Box::pin(
+                            async move { #stream }
+                        )
+                    }
+                ),
+                false => syn::parse_quote!({ #stream }),
+            }
         }
-    );
+    };
+
+    let mut sig = method.sig.clone();
+    // The `RpcContext` is only used on the server side
+    if has_context_arg(&sig) {
+        let len = sig.inputs.len();
+        if let Some(syn::FnArg::Typed(pt)) = sig.inputs.iter_mut().nth(len - 2) {
+            pt.pat = syn::parse_quote!(_);
+        }
+    }
 
     syn::ImplItemMethod {
         attrs: method.attrs.clone(),
         vis: syn::Visibility::Inherited,
         defaultness: None,
-        sig: method.sig.clone(),
+        sig,
         block,
     }
 }
@@ -1,5 +1,5 @@
 #[cfg(all(feature = "client", feature = "runtime",))]
-use super::{CLIENT_STUB_SUFFIX, CLIENT_SUFFIX};
+use super::{CLIENT_STUB_SUFFIX, CLIENT_SUFFIX, NOTIFY_STUB_SUFFIX};
 #[cfg(feature = "server")]
 use super::{EXPORTED_TRAIT_SUFFIX, HANDLER_SUFFIX};
 // #[cfg(any(feature = "server", feature = "client"))]
@@ -9,35 +9,317 @@ pub mod item_impl;
 
 pub mod item_trait;
 
+/// The kind of response(s) an exported method returns
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+pub(crate) enum ReturnKind {
+    /// The method returns a single `Result<T, E>`
+    Unary,
+    /// The method returns a stream of `Result<T, E>`. `awaited` indicates whether the
+    /// stream is returned from a future, ie. `async fn` or a method transformed
+    /// by `#[async_trait]`.
+    Stream { item: Box<syn::Type>, awaited: bool },
+}
+
+/// Determines whether the method is a server-streaming method. The following forms
+/// of return types are recognized as a stream, and they can be returned either
+/// directly or from an `async fn`
+///
+/// - `impl Stream<Item = Result<T, E>>`
+/// - `BoxStream<'static, Result<T, E>>`
+/// - `Pin<Box<dyn Stream<Item = Result<T, E>> + Send>>`
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+pub(crate) fn parse_return_kind(sig: &syn::Signature) -> ReturnKind {
+    let ret_ty = match &sig.output {
+        syn::ReturnType::Type(_, ty) => ty.as_ref(),
+        syn::ReturnType::Default => return ReturnKind::Unary,
+    };
+
+    if let Some(item) = get_stream_item_type(ret_ty) {
+        return ReturnKind::Stream {
+            item: Box::new(item.clone()),
+            awaited: sig.asyncness.is_some(),
+        };
+    }
+
+    // `#[async_trait]` turns the return type into `Pin<Box<dyn Future<Output = T>>>`
+    if sig.asyncness.is_none() {
+        if let Some(item) = get_future_output_type(ret_ty).and_then(get_stream_item_type) {
+            return ReturnKind::Stream {
+                item: Box::new(item.clone()),
+                awaited: true,
+            };
+        }
+    }
+
+    ReturnKind::Unary
+}
+
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+pub(crate) fn get_stream_item_type(ty: &syn::Type) -> Option<&syn::Type> {
+    get_assoc_type(ty, "Stream", "BoxStream", "Item")
+}
+
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+pub(crate) fn get_future_output_type(ty: &syn::Type) -> Option<&syn::Type> {
+    get_assoc_type(ty, "Future", "BoxFuture", "Output")
+}
+
+/// Finds the associated type of a trait in `impl Trait`, `dyn Trait`, `Pin<Box<dyn Trait>>`
+/// and the boxed alias of the trait (ie. `BoxStream<'_, T>` or `BoxFuture<'_, T>`)
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+fn get_assoc_type<'a>(
+    ty: &'a syn::Type,
+    trait_name: &str,
+    boxed_alias: &str,
+    assoc_name: &str,
+) -> Option<&'a syn::Type> {
+    match ty {
+        syn::Type::ImplTrait(impl_trait) => {
+            get_assoc_type_from_bounds(&impl_trait.bounds, trait_name, assoc_name)
+        }
+        syn::Type::TraitObject(tobj) => {
+            get_assoc_type_from_bounds(&tobj.bounds, trait_name, assoc_name)
+        }
+        syn::Type::Paren(paren) => get_assoc_type(&paren.elem, trait_name, boxed_alias, assoc_name),
+        syn::Type::Path(path) => {
+            let seg = path.path.segments.last()?;
+            let mut args = match &seg.arguments {
+                syn::PathArguments::AngleBracketed(angle_bracket) => {
+                    angle_bracket.args.iter().filter_map(|arg| match arg {
+                        syn::GenericArgument::Type(ty) => Some(ty),
+                        _ => None,
+                    })
+                }
+                _ => return None,
+            };
+            let ident = seg.ident.to_string();
+            if ident == boxed_alias {
+                args.next()
+            } else if ident == "Pin" || ident == "Box" {
+                get_assoc_type(args.next()?, trait_name, boxed_alias, assoc_name)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+fn get_assoc_type_from_bounds<'a>(
+    bounds: &'a syn::punctuated::Punctuated<syn::TypeParamBound, syn::Token![+]>,
+    trait_name: &str,
+    assoc_name: &str,
+) -> Option<&'a syn::Type> {
+    bounds.iter().find_map(|bound| match bound {
+        syn::TypeParamBound::Trait(bound) => {
+            let seg = bound.path.segments.last()?;
+            if seg.ident != trait_name {
+                return None;
+            }
+            match &seg.arguments {
+                syn::PathArguments::AngleBracketed(angle_bracket) => {
+                    angle_bracket.args.iter().find_map(|arg| match arg {
+                        syn::GenericArgument::Binding(binding) if binding.ident == assoc_name => {
+                            Some(&binding.ty)
+                        }
+                        _ => None,
+                    })
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Finds the item type `T` if the argument is a `RequestStream<T>`, ie. the method is
+/// a client-streaming or bidirectional streaming method
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+pub(crate) fn get_request_stream_item_type(ty: &syn::Type) -> Option<&syn::Type> {
+    match ty {
+        syn::Type::Path(path) => {
+            let seg = path.path.segments.last()?;
+            if seg.ident != "RequestStream" {
+                return None;
+            }
+            match &seg.arguments {
+                syn::PathArguments::AngleBracketed(angle_bracket) => {
+                    angle_bracket.args.iter().find_map(|arg| match arg {
+                        syn::GenericArgument::Type(ty) => Some(ty),
+                        _ => None,
+                    })
+                }
+                _ => None,
+            }
+        }
+        syn::Type::Paren(paren) => get_request_stream_item_type(&paren.elem),
+        _ => None,
+    }
+}
+
+/// Returns `true` if the method takes a `RpcContext` argument right before the request
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+pub(crate) fn has_context_arg(sig: &syn::Signature) -> bool {
+    let len = sig.inputs.len();
+    if len < 3 {
+        return false;
+    }
+    match sig.inputs.iter().nth(len - 2) {
+        Some(syn::FnArg::Typed(pt)) => is_context_type(&pt.ty),
+        _ => false,
+    }
+}
+
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+fn is_context_type(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|seg| seg.ident == "RpcContext")
+            .unwrap_or(false),
+        syn::Type::Paren(paren) => is_context_type(&paren.elem),
+        _ => false,
+    }
+}
+
+/// Generates the inputs of a method handler. The `RpcContext` is only named
+/// if the method takes it
+#[cfg(feature = "server")]
+pub(crate) fn generate_handler_inputs(
+    has_ctx: bool,
+) -> syn::punctuated::Punctuated<syn::FnArg, syn::Token![,]> {
+    match has_ctx {
+        true => syn::parse_quote!(
+            self: std::sync::Arc<Self>,
+            ctx: toy_rpc::service::RpcContext,
+            request: toy_rpc::service::RequestBody
+        ),
+        false => syn::parse_quote!(
+            self: std::sync::Arc<Self>,
+            _ctx: toy_rpc::service::RpcContext,
+            request: toy_rpc::service::RequestBody
+        ),
+    }
+}
+
+/// Generates the body of a method handler, which deserializes the request and
+/// calls the method `ident`
+#[cfg(feature = "server")]
+pub(crate) fn generate_handler_block(
+    ident: &syn::Ident,
+    req_ty: &syn::Type,
+    kind: &ReturnKind,
+    has_ctx: bool,
+) -> syn::Block {
+    // A `RequestStream<T>` argument takes the stream of requests sent by the client
+    let req: syn::Expr = match get_request_stream_item_type(req_ty) {
+        Some(_) => syn::parse_quote!(request.into_stream()),
+        None => syn::parse_quote!(request.deserialize()),
+    };
+    // The `RpcContext` is injected if the method takes it
+    let method_call: syn::Expr = match has_ctx {
+        true => syn::parse_quote!(self.#ident(ctx, req)),
+        false => syn::parse_quote!(self.#ident(req)),
+    };
+    // The error is converted with `Into<toy_rpc::Error>` if it implements it, and is
+    // otherwise sent as a typed error
+    let into_error: syn::Expr = syn::parse_quote!({
+        #[allow(unused_imports)]
+        use toy_rpc::error::conversion::{IntoKind, TypedDebugKind, TypedKind};
+        let kind = (&&toy_rpc::error::conversion::Wrap(&err)).error_kind();
+        kind.into_error(err)
+    });
+
+    match kind {
+        ReturnKind::Unary => syn::parse_quote!({
+            toy_rpc::service::HandlerOutput::Unary(Box::pin(
+                async move {
+                    let req: #req_ty = #req?;
+                    #method_call.await
+                        .map(|r| Box::new(r) as Box<dyn toy_rpc::erased_serde::Serialize + Send + Sync + 'static>)
+                        .map_err(|err| #into_error)
+                }
+            ))
+        }),
+        ReturnKind::Stream { awaited, .. } => {
+            let stream: syn::Expr = if *awaited {
+                syn::parse_quote!(
+                    toy_rpc::futures::StreamExt::flatten(
+                        toy_rpc::futures::stream::once(async move { #method_call.await })
+                    )
+                )
+            } else {
+                syn::parse_quote!(#method_call)
+            };
+
+            syn::parse_quote!({
+                let req: #req_ty = match #req {
+                    Ok(req) => req,
+                    Err(e) => {
+                        return toy_rpc::service::HandlerOutput::Unary(Box::pin(async move {
+                            Err(e)
+                        }))
+                    }
+                };
+                toy_rpc::service::HandlerOutput::Stream(Box::pin(
+                    toy_rpc::futures::StreamExt::map(#stream, |item| -> toy_rpc::service::HandlerResult {
+                        item.map(|r| Box::new(r) as Box<dyn toy_rpc::erased_serde::Serialize + Send + Sync + 'static>)
+                            .map_err(|err| #into_error)
+                    })
+                ))
+            })
+        }
+    }
+}
+
 #[cfg(all(feature = "client", feature = "runtime"))]
 pub(crate) fn get_ok_ident_from_type(ty: Box<syn::Type>) -> Option<syn::GenericArgument> {
     let ty = Box::leak(ty);
     let arg = syn::GenericArgument::Type(ty.to_owned());
-    recursively_get_result_from_generic_arg(&arg)
+    recursively_get_result_from_generic_arg(&arg, 0)
+}
+
+/// Gets the error type of the `Result` returned by a method, which is
+/// `toy_rpc::Error` if the `Result` is an alias that only takes the `Ok` type
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn get_err_ident_from_type(ty: Box<syn::Type>) -> syn::GenericArgument {
+    let arg = syn::GenericArgument::Type(*ty);
+    recursively_get_result_from_generic_arg(&arg, 1)
+        .unwrap_or_else(|| syn::parse_quote!(toy_rpc::Error))
 }
 
+/// Gets the generic argument at `index` of the `Result` in the type
 #[cfg(all(feature = "client", feature = "runtime"))]
 pub(crate) fn recursively_get_result_from_generic_arg(
     arg: &syn::GenericArgument,
+    index: usize,
 ) -> Option<syn::GenericArgument> {
     match &arg {
-        syn::GenericArgument::Type(ty) => recusively_get_result_from_type(&ty),
-        syn::GenericArgument::Binding(binding) => recusively_get_result_from_type(&binding.ty),
+        syn::GenericArgument::Type(ty) => recusively_get_result_from_type(&ty, index),
+        syn::GenericArgument::Binding(binding) => {
+            recusively_get_result_from_type(&binding.ty, index)
+        }
         _ => None,
     }
 }
 
 #[cfg(all(feature = "client", feature = "runtime"))]
-pub(crate) fn recusively_get_result_from_type(ty: &syn::Type) -> Option<syn::GenericArgument> {
+pub(crate) fn recusively_get_result_from_type(
+    ty: &syn::Type,
+    index: usize,
+) -> Option<syn::GenericArgument> {
     match ty {
         syn::Type::Path(ref path) => {
             let ident = &path.path.segments.last()?.ident.to_string()[..];
             match &path.path.segments.last()?.arguments {
                 syn::PathArguments::AngleBracketed(angle_bracket) => {
                     if ident == "Result" {
-                        return angle_bracket.args.first().map(|g| g.to_owned());
+                        return angle_bracket.args.iter().nth(index).map(|g| g.to_owned());
                     }
-                    recursively_get_result_from_generic_arg(angle_bracket.args.first()?)
+                    recursively_get_result_from_generic_arg(angle_bracket.args.first()?, index)
                 }
                 _ => None,
             }
@@ -46,7 +328,10 @@ pub(crate) fn recusively_get_result_from_type(ty: &syn::Type) -> Option<syn::Gen
             if let syn::TypeParamBound::Trait(bound) = tobj.bounds.first()? {
                 match &bound.path.segments.last()?.arguments {
                     syn::PathArguments::AngleBracketed(angle_bracket) => {
-                        return recursively_get_result_from_generic_arg(angle_bracket.args.first()?)
+                        return recursively_get_result_from_generic_arg(
+                            angle_bracket.args.first()?,
+                            index,
+                        )
                     }
                     _ => return None,
                 }
@@ -125,16 +410,170 @@ pub(crate) fn generate_client_stub_for_struct_method_impl(
     fn_ident: &syn::Ident,
     req_ty: &syn::Type,
     ok_ty: &syn::GenericArgument,
+    err_ty: &syn::GenericArgument,
 ) -> syn::ImplItemMethod {
     let service = service_ident.to_string();
     let method = fn_ident.to_string();
     let service_method = format!("{}.{}", service, method);
     syn::parse_quote!(
-        pub fn #fn_ident<A>(&'c self, args: A) -> toy_rpc::client::Call<#ok_ty>
+        pub fn #fn_ident<A>(&'c self, args: A) -> toy_rpc::client::Call<#ok_ty, #err_ty>
         where
             A: std::borrow::Borrow<#req_ty> + Send + Sync + toy_rpc::serde::Serialize + 'static,
         {
-            self.client.call(#service_method, args)
+            self.client.call(#service_method, args).with_error_type()
+        }
+    )
+}
+
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_client_stub_for_stream_method_impl(
+    service_ident: &syn::Ident,
+    fn_ident: &syn::Ident,
+    req_ty: &syn::Type,
+    ok_ty: &syn::GenericArgument,
+) -> syn::ImplItemMethod {
+    let service = service_ident.to_string();
+    let method = fn_ident.to_string();
+    let service_method = format!("{}.{}", service, method);
+    syn::parse_quote!(
+        pub fn #fn_ident<A>(&'c self, args: A) -> toy_rpc::client::CallStream<#ok_ty>
+        where
+            A: std::borrow::Borrow<#req_ty> + Send + Sync + toy_rpc::serde::Serialize + 'static,
+        {
+            self.client.call_stream(#service_method, args)
+        }
+    )
+}
+
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_client_stub_for_sink_method_impl(
+    service_ident: &syn::Ident,
+    fn_ident: &syn::Ident,
+    req_item_ty: &syn::Type,
+    ok_ty: &syn::GenericArgument,
+    err_ty: &syn::GenericArgument,
+) -> syn::ImplItemMethod {
+    let service = service_ident.to_string();
+    let method = fn_ident.to_string();
+    let service_method = format!("{}.{}", service, method);
+    syn::parse_quote!(
+        pub fn #fn_ident(&'c self) -> (toy_rpc::client::CallSink<#req_item_ty>, toy_rpc::client::Call<#ok_ty, #err_ty>) {
+            let (sink, call) = self.client.call_sink(#service_method);
+            (sink, call.with_error_type())
+        }
+    )
+}
+
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_client_stub_for_duplex_method_impl(
+    service_ident: &syn::Ident,
+    fn_ident: &syn::Ident,
+    req_item_ty: &syn::Type,
+    ok_ty: &syn::GenericArgument,
+) -> syn::ImplItemMethod {
+    let service = service_ident.to_string();
+    let method = fn_ident.to_string();
+    let service_method = format!("{}.{}", service, method);
+    syn::parse_quote!(
+        pub fn #fn_ident(&'c self) -> (toy_rpc::client::CallSink<#req_item_ty>, toy_rpc::client::CallStream<#ok_ty>) {
+            self.client.call_duplex(#service_method)
         }
     )
 }
+
+/// Generates the client stub that sends a notification to an exported unary
+/// method. The stub is named after the method with a `_notify` suffix, and is
+/// skipped if one of `method_names`, the exported methods, already has the name
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_client_notify_stub_for_method(
+    service_ident: &syn::Ident,
+    sig: &syn::Signature,
+    method_names: &[String],
+) -> Option<syn::ImplItemMethod> {
+    let req_ty = match sig.inputs.last()? {
+        syn::FnArg::Typed(pt) => &pt.ty,
+        _ => return None,
+    };
+    // Streaming methods cannot be notified
+    if get_request_stream_item_type(req_ty).is_some() {
+        return None;
+    }
+    if let ReturnKind::Stream { .. } = parse_return_kind(sig) {
+        return None;
+    }
+
+    let service_method = format!("{}.{}", service_ident, sig.ident);
+    let concat_name = format!("{}{}", sig.ident, NOTIFY_STUB_SUFFIX);
+    // The stub of the exported method with the same name is kept
+    if method_names.contains(&concat_name) {
+        return None;
+    }
+    let fn_ident = syn::Ident::new(&concat_name, sig.ident.span());
+    Some(syn::parse_quote!(
+        pub fn #fn_ident<A>(&'c self, args: A) -> Result<(), toy_rpc::Error>
+        where
+            A: std::borrow::Borrow<#req_ty> + Send + Sync + toy_rpc::serde::Serialize + 'static,
+        {
+            self.client.notify(#service_method, args)
+        }
+    ))
+}
+
+/// Generates the client stub of an exported method. Methods that take a
+/// `RequestStream<T>` get a stub that returns a `CallSink<T>` along with the
+/// `Call` or `CallStream`
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_client_stub_for_method(
+    service_ident: &syn::Ident,
+    sig: &syn::Signature,
+) -> Option<syn::ImplItemMethod> {
+    if let syn::FnArg::Typed(pt) = sig.inputs.last()? {
+        let fn_ident = &sig.ident;
+        let req_ty = &pt.ty;
+        let req_item_ty = get_request_stream_item_type(req_ty);
+
+        match parse_return_kind(sig) {
+            ReturnKind::Stream { item, .. } => {
+                let ok_ty = get_ok_ident_from_type(item)?;
+                if let Some(req_item_ty) = req_item_ty {
+                    return Some(generate_client_stub_for_duplex_method_impl(
+                        service_ident,
+                        fn_ident,
+                        req_item_ty,
+                        &ok_ty,
+                    ));
+                }
+                return Some(generate_client_stub_for_stream_method_impl(
+                    service_ident,
+                    fn_ident,
+                    req_ty,
+                    &ok_ty,
+                ));
+            }
+            ReturnKind::Unary => {
+                if let syn::ReturnType::Type(_, ret_ty) = sig.output.clone() {
+                    let ok_ty = get_ok_ident_from_type(ret_ty.clone())?;
+                    let err_ty = get_err_ident_from_type(ret_ty);
+                    if let Some(req_item_ty) = req_item_ty {
+                        return Some(generate_client_stub_for_sink_method_impl(
+                            service_ident,
+                            fn_ident,
+                            req_item_ty,
+                            &ok_ty,
+                            &err_ty,
+                        ));
+                    }
+                    return Some(generate_client_stub_for_struct_method_impl(
+                        service_ident,
+                        fn_ident,
+                        req_ty,
+                        &ok_ty,
+                        &err_ty,
+                    ));
+                }
+            }
+        }
+    }
+
+    None
+}
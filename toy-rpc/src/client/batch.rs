@@ -0,0 +1,146 @@
+//! Batch of RPC calls sent in a single message
+
+use futures::channel::oneshot;
+use serde::de::DeserializeOwned;
+use std::time::Instant;
+
+use crate::{
+    protocol::{MetadataMap, OutboundBody, FEATURE_BATCH},
+    Error,
+};
+
+use super::{
+    broker::{BatchCall, ClientBrokerItem, OutboundRequest},
+    Call, Client,
+};
+
+/// Batch of RPC calls that are sent to the server in a single message
+///
+/// A `Batch` is created with `Client::batch`. Each call added with `call()` returns a
+/// `Call<Res>`, which resolves once the batch is sent with `send()` and the response is
+/// received. The server executes the calls concurrently and sends back all results in
+/// a single response. The timeout and metadata of the batch are taken from the client
+/// when the batch is created and apply to every call in the batch.
+///
+/// The calls of a batch that is dropped without being sent resolve to
+/// `Err(Error::Canceled(id))`.
+///
+/// # Example
+///
+/// ```rust
+/// let mut batch = client.batch();
+/// let sum: Call<i32> = batch.call("Arith.add", (1i32, 6i32));
+/// let echo: Call<String> = batch.call("Echo.echo", "hello".to_string());
+/// batch.send()?;
+///
+/// let sum = sum.await?;
+/// let echo = echo.await?;
+/// ```
+pub struct Batch<'c, AckMode> {
+    client: &'c Client<AckMode>,
+    deadline: Option<Instant>,
+    metadata: MetadataMap,
+    calls: Vec<BatchCall>,
+}
+
+impl<'c, AckMode> Batch<'c, AckMode> {
+    pub(crate) fn new(
+        client: &'c Client<AckMode>,
+        deadline: Option<Instant>,
+        metadata: MetadataMap,
+    ) -> Self {
+        Self {
+            client,
+            deadline,
+            metadata,
+            calls: Vec::new(),
+        }
+    }
+
+    /// Adds a call to the named RPC function to the batch
+    ///
+    /// The returned `Call<Res>` should only be `.await`ed after the batch is sent.
+    pub fn call<Req, Res>(&mut self, service_method: impl ToString, args: Req) -> Call<Res>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: DeserializeOwned + Send + 'static,
+    {
+        let id = self.client.ids.acquire();
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.calls.push(BatchCall {
+            id,
+            service_method: service_method.to_string(),
+            body: Box::new(args) as Box<OutboundBody>,
+            resp_tx,
+        });
+        Call::new(id, self.client.broker.clone(), resp_rx)
+    }
+
+    /// Number of calls in the batch
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Returns `true` if there is no call in the batch
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Sends all calls in the batch in a single message
+    ///
+    /// If the server does not support batches, the calls are sent as individual requests
+    /// instead. An error is returned if the calls cannot be sent, ie. the connection is
+    /// closed. A `ResourceExhausted` status is returned while the broker queue of the client
+    /// is full, and the calls of the batch are canceled.
+    pub fn send(mut self) -> Result<(), Error> {
+        self.client.queues.check_broker_space()?;
+        let calls = std::mem::take(&mut self.calls);
+        if calls.is_empty() {
+            return Ok(());
+        }
+
+        let metadata = std::mem::take(&mut self.metadata);
+        let items = match self.client.capabilities.supports(FEATURE_BATCH) {
+            true => vec![ClientBrokerItem::Batch {
+                id: self.client.ids.acquire(),
+                deadline: self.deadline,
+                metadata,
+                calls,
+            }],
+            false => calls
+                .into_iter()
+                .map(|call| ClientBrokerItem::Request {
+                    request: OutboundRequest {
+                        id: call.id,
+                        service_method: call.service_method,
+                        deadline: self.deadline,
+                        body: Some(call.body),
+                        metadata: metadata.clone(),
+                    },
+                    resp_tx: call.resp_tx,
+                })
+                .collect(),
+        };
+        for item in items {
+            self.client.queues.broker.push();
+            if let Err(err) = self.client.broker.send(item) {
+                log::error!("{}", err);
+                // If Broker is dropped, then the connection is dropped as well
+                return Err(Error::IoError(std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "Cannot connect to client side broker",
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'c, AckMode> Drop for Batch<'c, AckMode> {
+    fn drop(&mut self) {
+        // The calls of a batch that is not sent are never pending
+        for call in self.calls.drain(..) {
+            self.client.ids.release(call.id);
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use cfg_if::cfg_if;
 use flume::Sender;
 use futures::channel::oneshot;
-use std::{marker::PhantomData, time::Duration};
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
 
 cfg_if! {
     if #[cfg(any(
@@ -9,11 +12,16 @@ cfg_if! {
         all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
         all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
     ))] {
-        use std::{sync::{Arc, atomic::Ordering}, collections::{HashMap, BTreeMap}};
+        use std::{sync::Arc, collections::{HashMap, BTreeMap, VecDeque}};
         use brw::{Context, Running};
         use futures::{Sink, SinkExt};
 
-        use crate::message::AtomicMessageId;
+        use crate::message::MessageIdAllocator;
+        use crate::execution::{execute_timed_call, timeout_at, Timing};
+        use crate::extension::{self, ExtensionMap};
+        use crate::keepalive::Keepalive;
+        use crate::service::{CancellationToken, HandlerOutput, MetadataScope, RpcContext};
+        use crate::queue::{GaugedSink, PushError};
 
         use super::{writer::ClientWriterItem};
     }
@@ -22,13 +30,64 @@ cfg_if! {
 use crate::{
     codec::Marshal,
     error::IoError,
+    execution::InboundCall,
+    extension::{ExtensionFrame, ExtensionMessage, Marker},
     message::MessageId,
-    protocol::{InboundBody, OutboundBody},
+    protocol::{InboundBody, MetadataMap, OutboundBody},
     pubsub::{AckModeAuto, AckModeManual, AckModeNone, SeqId},
+    queue::{FullQueuePolicy, QueueGauge, QueueSender},
+    service::{HandlerResult, RequestBody},
+    status::Status,
     Error,
 };
 
-use super::{pubsub::SubscriptionItem, ResponseResult};
+use super::{pubsub::SubscriptionItem, ResponseReply, ResponseResult};
+
+/// Queues of the connection to the server
+#[derive(Clone)]
+pub(crate) struct ClientQueues {
+    /// Messages waiting for the broker
+    pub broker: Arc<QueueGauge>,
+    /// Messages waiting to be written to the server
+    pub writer: Arc<QueueGauge>,
+    /// Policy of the queues of the local subscribers
+    pub policy: FullQueuePolicy,
+}
+
+impl ClientQueues {
+    pub fn new(broker_capacity: usize, writer_capacity: usize, policy: FullQueuePolicy) -> Self {
+        Self {
+            broker: Arc::new(QueueGauge::new(broker_capacity)),
+            writer: Arc::new(QueueGauge::new(writer_capacity)),
+            policy,
+        }
+    }
+
+    /// Fails while the broker queue is full, for the requests that cannot wait for it
+    pub fn check_broker_space(&self) -> Result<(), Error> {
+        match self.broker.is_full() {
+            true => Err(Error::Status(Status::resource_exhausted(
+                "The broker queue of the client is full",
+            ))),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Request of a call to a RPC method on the server
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct OutboundRequest {
+    pub id: MessageId,
+    pub service_method: String,
+    /// `None` if the call has no timeout
+    pub deadline: Option<Instant>,
+    /// `None` if the request items are sent through a `CallSink`
+    pub body: Option<Box<OutboundBody>>,
+    pub metadata: MetadataMap,
+}
 
 #[cfg_attr(
     all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
@@ -36,16 +95,50 @@ use super::{pubsub::SubscriptionItem, ResponseResult};
 )]
 pub(crate) enum ClientBrokerItem {
     Request {
+        request: OutboundRequest,
+        resp_tx: oneshot::Sender<Result<ResponseReply, Error>>,
+    },
+    Response {
+        id: MessageId,
+        result: ResponseResult,
+        metadata: MetadataMap,
+    },
+    /// Request to a streaming RPC method
+    StreamRequest {
+        request: OutboundRequest,
+        item_tx: QueueSender<Result<ResponseResult, Error>>,
+    },
+    /// One-way request that expects no response
+    Notify {
         id: MessageId,
         service_method: String,
-        duration: Duration,
         body: Box<OutboundBody>,
-        resp_tx: oneshot::Sender<Result<ResponseResult, Error>>,
+        metadata: MetadataMap,
     },
-    Response {
+    /// Batch of requests sent in a single message
+    Batch {
+        id: MessageId,
+        /// `None` if the calls have no timeout
+        deadline: Option<Instant>,
+        metadata: MetadataMap,
+        calls: Vec<BatchCall>,
+    },
+    /// All the results of a batch are received
+    BatchEnd(MessageId),
+    /// Item of a stream from the server
+    InboundStreamItem {
         id: MessageId,
         result: ResponseResult,
     },
+    /// End of a stream from the server
+    InboundStreamEnd(MessageId),
+    /// Request item of a client-streaming call
+    OutboundStreamItem {
+        id: MessageId,
+        body: Box<OutboundBody>,
+    },
+    /// End of the request items of a client-streaming call
+    OutboundStreamEnd(MessageId),
     Cancel(MessageId),
     /// New publication to the server
     Publish {
@@ -62,11 +155,11 @@ pub(crate) enum ClientBrokerItem {
         topic: String,
 
         // message is deserialized as it is read on the subscriber
-        item_sink: Sender<SubscriptionItem>,
+        item_sink: QueueSender<SubscriptionItem>,
     },
     NewLocalSubscriber {
         topic: String,
-        new_item_sink: Sender<SubscriptionItem>,
+        new_item_sink: QueueSender<SubscriptionItem>,
     },
     Unsubscribe {
         // id: MessageId,
@@ -78,10 +171,63 @@ pub(crate) enum ClientBrokerItem {
         topic: String,
         item: Box<InboundBody>,
     },
+    /// New message to a work queue on the server
+    Produce {
+        topic: String,
+        tickets: u32,
+        body: Box<OutboundBody>,
+    },
+    /// Pulls one message from a work queue on the server
+    Consume {
+        topic: String,
+        item_sink: oneshot::Sender<SubscriptionItem>,
+    },
+    /// Work queue message from the server
+    Delivery {
+        id: SeqId,
+        topic: String,
+        item: Box<InboundBody>,
+    },
+    /// Extension message to the server
+    Extension {
+        id: MessageId,
+        marker: Marker,
+        content: String,
+        body: Box<OutboundBody>,
+        resp_tx: oneshot::Sender<Result<ExtensionMessage, Error>>,
+    },
+    /// Extension message or reply from the server
+    InboundExtension {
+        id: MessageId,
+        marker: Marker,
+        content: String,
+        body: Box<InboundBody>,
+    },
+    /// Reply of a local extension handler
+    ExtensionReply(ExtensionFrame),
+    /// Reverse call from the server to a service registered on the client
+    InboundRequest {
+        call: InboundCall,
+        request: RequestBody,
+    },
+    /// Result of a reverse call
+    OutboundResponse {
+        id: MessageId,
+        result: HandlerResult,
+        metadata: MetadataMap,
+    },
+    /// The server cancels a reverse call
+    InboundCancel(MessageId),
     /// Ack reply from server
     InboundAck(SeqId),
     /// (Manual) Ack reply for incoming Publish message
     OutboundAck(SeqId),
+    /// Time to send a keepalive ping
+    KeepaliveTick,
+    /// Keepalive ping from the server
+    InboundPing(MessageId),
+    /// Answer to a keepalive ping from the server
+    InboundPong(MessageId),
 
     /// Begin the stop process
     // #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
@@ -93,12 +239,49 @@ pub(crate) enum ClientBrokerItem {
     Stop(Option<std::io::Error>),
 }
 
+impl ClientBrokerItem {
+    /// Whether the item writes a request, a response or a publication to the server. The
+    /// broker only waits for the writer queue before these items, and the control items
+    /// are handled right away
+    fn writes_data(&self) -> bool {
+        matches!(
+            self,
+            Self::Request { .. }
+                | Self::StreamRequest { .. }
+                | Self::Notify { .. }
+                | Self::Batch { .. }
+                | Self::OutboundStreamItem { .. }
+                | Self::Publish { .. }
+                | Self::PublishRetry { .. }
+                | Self::Produce { .. }
+                | Self::Extension { .. }
+                | Self::ExtensionReply(_)
+                | Self::OutboundResponse { .. }
+        )
+    }
+}
+
+/// A call in a batch
+pub(crate) struct BatchCall {
+    pub id: MessageId,
+    pub service_method: String,
+    pub body: Box<OutboundBody>,
+    pub resp_tx: oneshot::Sender<Result<ResponseReply, Error>>,
+}
+
 enum ClientBrokerState {
     Started,
     Stopping,
     Stopped,
 }
 
+/// Sender of the stream items and the sender that stops the timeout task
+/// once dropped
+pub(crate) type PendingStream = (
+    QueueSender<Result<ResponseResult, Error>>,
+    oneshot::Sender<()>,
+);
+
 #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
 use ::async_std::task::{self};
 #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
@@ -111,17 +294,51 @@ use ::tokio::task::{self};
 ))]
 pub(crate) struct ClientBroker<AckMode, C> {
     state: ClientBrokerState,
-    pub count: Arc<AtomicMessageId>,
-    pub pending: HashMap<MessageId, oneshot::Sender<Result<ResponseResult, Error>>>,
-    pub subscriptions: HashMap<String, Sender<SubscriptionItem>>,
+    pub ids: Arc<MessageIdAllocator>,
+    pub pending: HashMap<MessageId, oneshot::Sender<Result<ResponseReply, Error>>>,
+    pub pending_streams: HashMap<MessageId, PendingStream>,
+    // Calls of the batches that are sent, which fail if the server cannot read the batch
+    pub pending_batches: HashMap<MessageId, Vec<MessageId>>,
+    pub subscriptions: HashMap<String, QueueSender<SubscriptionItem>>,
+    // Local consumers waiting for a work queue message, in the order of their pulls
+    pub pending_consumes: HashMap<String, VecDeque<oneshot::Sender<SubscriptionItem>>>,
     pub pending_acks: BTreeMap<MessageId, oneshot::Sender<()>>,
+    pub pending_extensions: HashMap<MessageId, oneshot::Sender<Result<ExtensionMessage, Error>>>,
+    pub extensions: Arc<ExtensionMap>,
+    // Reverse calls that are being executed on the client
+    pub executions: HashMap<MessageId, CancellationToken>,
+    // Period the reverse calls are given to clean up after they are canceled
+    pub grace_period: Duration,
     pub pub_retry_timeout: Duration,
     pub max_num_retries: u32,
+    pub keepalive: Keepalive,
+    pub queues: ClientQueues,
 
     pub ack_mode: PhantomData<AckMode>,
     pub codec: PhantomData<C>,
 }
 
+/// Writer item that starts a request. A request without a body opens a
+/// client-streaming call whose items are sent afterwards
+#[cfg(any(
+    feature = "docs",
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+fn request_writer_item(request: OutboundRequest) -> ClientWriterItem {
+    let OutboundRequest {
+        id,
+        service_method,
+        deadline,
+        body,
+        metadata,
+    } = request;
+    match body {
+        Some(body) => ClientWriterItem::Request(id, service_method, deadline, metadata, body),
+        None => ClientWriterItem::OpenStream(id, service_method, deadline, metadata),
+    }
+}
+
 #[cfg(any(
     feature = "docs",
     all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
@@ -129,18 +346,31 @@ pub(crate) struct ClientBroker<AckMode, C> {
 ))]
 impl<AckMode, C> ClientBroker<AckMode, C> {
     pub fn new(
-        count: Arc<AtomicMessageId>,
+        ids: Arc<MessageIdAllocator>,
         pub_retry_timeout: Duration,
         max_num_retries: u32,
+        extensions: Arc<ExtensionMap>,
+        keepalive_miss_threshold: u32,
+        grace_period: Duration,
+        queues: ClientQueues,
     ) -> Self {
         Self {
             state: ClientBrokerState::Started,
-            count,
+            ids,
             pending: HashMap::new(),
+            pending_streams: HashMap::new(),
+            pending_batches: HashMap::new(),
             subscriptions: HashMap::new(),
+            pending_consumes: HashMap::new(),
             pending_acks: BTreeMap::new(),
+            pending_extensions: HashMap::new(),
+            extensions,
+            executions: HashMap::new(),
             pub_retry_timeout,
             max_num_retries,
+            keepalive: Keepalive::new(keepalive_miss_threshold),
+            grace_period,
+            queues,
 
             ack_mode: PhantomData,
             codec: PhantomData,
@@ -150,16 +380,33 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
     async fn handle_request<'w, W>(
         &'w mut self,
         writer: &'w mut W,
-        id: MessageId,
-        service_method: String,
-        duration: Duration,
-        body: Box<OutboundBody>,
-        resp_tx: oneshot::Sender<Result<ResponseResult, Error>>,
+        request: OutboundRequest,
+        resp_tx: oneshot::Sender<Result<ResponseReply, Error>>,
     ) -> Result<(), Error>
     where
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
-        // fetch_add returns the previous value
+        let (id, deadline) = (request.id, request.deadline);
+        if let Err(_) = writer.send(request_writer_item(request)).await {
+            self.ids.release(id);
+            return Err(Error::IoError(IoError::new(
+                std::io::ErrorKind::Other,
+                "Writer is disconnected",
+            )));
+        }
+
+        self.wait_for_response(id, deadline, resp_tx);
+        Ok(())
+    }
+
+    /// Waits for the response of a request that is written to the server until the
+    /// deadline
+    fn wait_for_response(
+        &mut self,
+        id: MessageId,
+        deadline: Option<Instant>,
+        resp_tx: oneshot::Sender<Result<ResponseReply, Error>>,
+    ) {
         let (tx, rx) = oneshot::channel();
         let fut = async move {
             // takes care of receiving/cancel  error
@@ -168,23 +415,10 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
                 Err(_) => Err(Error::Canceled(id)),
             }
         };
-        let item = ClientWriterItem::Request(id, service_method, duration, body);
-        if let Err(_) = writer.send(item).await {
-            return Err(Error::IoError(IoError::new(
-                std::io::ErrorKind::Other,
-                "Writer is disconnected",
-            )));
-        }
-
         task::spawn(async move {
-            #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
-            let timout_result = ::tokio::time::timeout(duration, fut).await;
-            #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-            let timout_result = ::async_std::future::timeout(duration, fut).await;
-
-            let cancellation_result = match timout_result {
-                Ok(res) => res,
-                Err(_) => {
+            let cancellation_result = match timeout_at(deadline, fut).await {
+                Some(res) => res,
+                None => {
                     if let Err(_) = resp_tx.send(Err(Error::Timeout(id))) {
                         log::trace!("InternalError: Unable to send Error::Timeout({}) over response channel, response receiver is dropped", id);
                     }
@@ -197,23 +431,174 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
                     resp_tx.send(response_result)
                         .unwrap_or_else(|_| log::trace!("InternalError: Unable to send RPC response over response channel, response receiver is dropped"));
                 }
-                Err(_) => {
+                Err(Error::Canceled(_)) => {
                     // RPC request is already canceled, simply return
                     return;
                 }
+                Err(err) => {
+                    // ie. the connection is lost
+                    resp_tx.send(Err(err))
+                        .unwrap_or_else(|_| log::trace!("InternalError: Unable to send error over response channel, response receiver is dropped"));
+                }
             };
         });
 
         self.pending.insert(id, tx);
-        // request_result.map_err(|err| err.into())
+    }
+
+    async fn handle_batch<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+        deadline: Option<Instant>,
+        metadata: MetadataMap,
+        calls: Vec<BatchCall>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        let mut requests = Vec::with_capacity(calls.len());
+        let mut resp_txs = Vec::with_capacity(calls.len());
+        for call in calls {
+            requests.push((call.id, call.service_method, call.body));
+            resp_txs.push((call.id, call.resp_tx));
+        }
+        let result = writer
+            .send(ClientWriterItem::Batch(id, deadline, metadata, requests))
+            .await;
+        if let Err(_) = result {
+            self.ids.release(id);
+            for (id, _) in resp_txs {
+                self.ids.release(id);
+            }
+            return Err(Error::IoError(IoError::new(
+                std::io::ErrorKind::Other,
+                "Writer is disconnected",
+            )));
+        }
+
+        // The id of the batch stays live until the results are received or the server
+        // fails the whole batch
+        let calls = resp_txs.iter().map(|(id, _)| *id).collect();
+        self.pending_batches.insert(id, calls);
+        for (id, resp_tx) in resp_txs {
+            self.wait_for_response(id, deadline, resp_tx);
+        }
+        Ok(())
+    }
+
+    fn handle_batch_end(&mut self, id: MessageId) -> Result<(), Error> {
+        if self.pending_batches.remove(&id).is_some() {
+            self.ids.release(id);
+        }
         Ok(())
     }
 
-    fn handle_response(&mut self, id: MessageId, result: ResponseResult) -> Result<(), Error> {
+    /// Fails the calls of a batch that the server could not read, ie. because the body
+    /// of the batch is malformed
+    fn fail_batch(&mut self, id: MessageId, calls: Vec<MessageId>, result: ResponseResult) {
+        self.ids.release(id);
+        let err = match result {
+            Ok(_) => Error::Internal("Unexpected response to a batch".into()),
+            Err(err) => err,
+        };
+        log::error!("Batch {} failed: {}", id, err);
+        for call_id in calls {
+            if let Some(tx) = self.pending.remove(&call_id) {
+                self.ids.release(call_id);
+                // the error has no data that is lost when it is copied through its status
+                let call_err = match err {
+                    Error::InvalidArgument => Error::InvalidArgument,
+                    ref err => Error::Status(err.status()),
+                };
+                let _ = tx.send(Ok((Err(call_err), MetadataMap::new())));
+            }
+        }
+    }
+
+    async fn handle_stream_request<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        ctx: &'w Arc<Context<ClientBrokerItem>>,
+        request: OutboundRequest,
+        item_tx: QueueSender<Result<ResponseResult, Error>>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        let (id, deadline) = (request.id, request.deadline);
+        if let Err(_) = writer.send(request_writer_item(request)).await {
+            self.ids.release(id);
+            return Err(Error::IoError(IoError::new(
+                std::io::ErrorKind::Other,
+                "Writer is disconnected",
+            )));
+        }
+
+        // The timeout applies to the whole stream. The task is stopped
+        // once the stream is removed from `pending_streams`
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        let timeout_tx = item_tx.clone();
+        let broker = ctx.broker.clone();
+        task::spawn(async move {
+            if timeout_at(deadline, done_rx).await.is_none() {
+                if timeout_tx.push(Err(Error::Timeout(id))).await.is_err() {
+                    log::trace!("InternalError: Unable to send Error::Timeout({}) over stream channel, stream receiver is dropped", id);
+                }
+                broker
+                    .send_async(ClientBrokerItem::Cancel(id))
+                    .await
+                    .unwrap_or_else(|_| log::error!("Error found sending Cancel"));
+            }
+        });
+
+        self.pending_streams.insert(id, (item_tx, done_tx));
+        Ok(())
+    }
+
+    async fn handle_notify<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+        service_method: String,
+        body: Box<OutboundBody>,
+        metadata: MetadataMap,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        let item = ClientWriterItem::Notify(id, service_method, metadata, body);
+        let result = writer.send(item).await;
+        // There is no response to wait for, so the id can be reused right away
+        self.ids.release(id);
+        result.map_err(|_| {
+            Error::IoError(IoError::new(
+                std::io::ErrorKind::Other,
+                "Writer is disconnected",
+            ))
+        })
+    }
+
+    async fn handle_response(
+        &mut self,
+        id: MessageId,
+        result: ResponseResult,
+        metadata: MetadataMap,
+    ) -> Result<(), Error> {
         if let Some(tx) = self.pending.remove(&id) {
-            tx.send(Ok(result)).map_err(|_| {
+            self.ids.release(id);
+            tx.send(Ok((result, metadata))).map_err(|_| {
                 Error::Internal("InternalError: client failed to send response over channel".into())
             })
+        } else if let Some((tx, _)) = self.pending_streams.remove(&id) {
+            self.ids.release(id);
+            // A response to a streaming request (ie. an error) terminates the stream
+            tx.push(Ok(result)).await.map_err(|_| {
+                Error::Internal("InternalError: client failed to send response over channel".into())
+            })
+        } else if let Some(calls) = self.pending_batches.remove(&id) {
+            self.fail_batch(id, calls, result);
+            Ok(())
         } else {
             Err(Error::Internal(
                 format!("InternalError: Response channel not found for id: {}", id).into(),
@@ -221,6 +606,93 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
         }
     }
 
+    /// Queues the item without waiting for the stream to be read, so that the other calls
+    /// of the connection are not held back by a stream that is read out of order
+    async fn handle_inbound_stream_item(
+        &mut self,
+        ctx: &Arc<Context<ClientBrokerItem>>,
+        id: MessageId,
+        result: ResponseResult,
+    ) -> Result<(), Error> {
+        if let Some((tx, _)) = self.pending_streams.get(&id) {
+            match tx.push(Ok(result)).await {
+                Ok(_) => Ok(()),
+                Err(PushError::Full) => {
+                    // the stream ends once the call is canceled
+                    ctx.broker.send(ClientBrokerItem::Cancel(id))?;
+                    Err(Error::Internal(
+                        "Stream is read too slowly, canceling the call".into(),
+                    ))
+                }
+                Err(PushError::Closed) => Err(Error::Internal(
+                    "InternalError: client failed to send stream item over channel".into(),
+                )),
+            }
+        } else {
+            Err(Error::Internal(
+                format!("InternalError: Stream channel not found for id: {}", id).into(),
+            ))
+        }
+    }
+
+    fn handle_inbound_stream_end(&mut self, id: MessageId) -> Result<(), Error> {
+        // Dropping the sender terminates the stream on the receiving end
+        if self.pending_streams.remove(&id).is_some() {
+            self.ids.release(id);
+        }
+        Ok(())
+    }
+
+    fn is_pending(&self, id: &MessageId) -> bool {
+        self.pending.contains_key(id) || self.pending_streams.contains_key(id)
+    }
+
+    async fn handle_outbound_stream_item<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+        body: Box<OutboundBody>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        // The call may have already finished or been canceled
+        if !self.is_pending(&id) {
+            return Ok(());
+        }
+        writer
+            .send(ClientWriterItem::StreamItem(id, body))
+            .await
+            .map_err(|_| {
+                Error::IoError(IoError::new(
+                    std::io::ErrorKind::Other,
+                    "Writer is disconnected",
+                ))
+            })
+    }
+
+    async fn handle_outbound_stream_end<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        if !self.is_pending(&id) {
+            return Ok(());
+        }
+        writer
+            .send(ClientWriterItem::StreamEnd(id))
+            .await
+            .map_err(|_| {
+                Error::IoError(IoError::new(
+                    std::io::ErrorKind::Other,
+                    "Writer is disconnected",
+                ))
+            })
+    }
+
     async fn handle_cancel<'w, W>(
         &'w mut self,
         writer: &'w mut W,
@@ -229,6 +701,9 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
     where
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
+        if self.is_pending(&id) {
+            self.ids.release(id);
+        }
         if let Some(tx) = self.pending.remove(&id) {
             tx.send(Err(Error::Canceled(id))).map_err(|_| {
                 Error::Internal(
@@ -240,6 +715,7 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
                 )
             })?;
         }
+        self.pending_streams.remove(&id);
         writer
             .send(ClientWriterItem::Cancel(id))
             .await
@@ -331,6 +807,8 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
             );
             result
         } else {
+            self.pending_acks.remove(&id);
+            self.ids.release(id);
             Err(Error::MaxRetriesReached(id))
         }
     }
@@ -339,12 +817,12 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
         &'w mut self,
         writer: &'w mut W,
         topic: String,
-        item_sink: Sender<SubscriptionItem>,
+        item_sink: QueueSender<SubscriptionItem>,
     ) -> Result<(), Error>
     where
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
-        let id = self.count.fetch_add(1, Ordering::Relaxed);
+        let id = self.ids.next();
         // NOTE: Only one local subscriber is allowed
         self.subscriptions.insert(topic.clone(), item_sink);
 
@@ -362,7 +840,7 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
     fn handle_new_local_subscriber(
         &mut self,
         topic: String,
-        new_item_sink: Sender<SubscriptionItem>,
+        new_item_sink: QueueSender<SubscriptionItem>,
     ) -> Result<(), Error> {
         self.subscriptions.insert(topic, new_item_sink);
         Ok(())
@@ -376,7 +854,7 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
     where
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
-        let id = self.count.fetch_add(1, Ordering::Relaxed);
+        let id = self.ids.next();
         // NOTE: the sender should be dropped on the Client side
         writer
             .send(ClientWriterItem::Unsubscribe(id, topic))
@@ -389,8 +867,266 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
             })
     }
 
+    async fn handle_produce<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        topic: String,
+        tickets: u32,
+        body: Box<OutboundBody>,
+    ) -> Result<(), Error>
+    where
+        C: Marshal,
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        // No Ack is expected for a produced message
+        let id = self.ids.next();
+        let body = Arc::new(C::marshal(&body)?);
+        writer
+            .send(ClientWriterItem::Produce(id, topic, tickets, body))
+            .await
+            .map_err(|_| {
+                Error::IoError(IoError::new(
+                    std::io::ErrorKind::Other,
+                    "Writer is disconnected",
+                ))
+            })
+    }
+
+    async fn handle_consume<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        topic: String,
+        item_sink: oneshot::Sender<SubscriptionItem>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        let id = self.ids.next();
+        self.pending_consumes
+            .entry(topic.clone())
+            .or_default()
+            .push_back(item_sink);
+
+        writer
+            .send(ClientWriterItem::Consume(id, topic))
+            .await
+            .map_err(|_| {
+                Error::IoError(IoError::new(
+                    std::io::ErrorKind::Other,
+                    "Writer is disconnected",
+                ))
+            })
+    }
+
+    fn handle_delivery(
+        &mut self,
+        id: SeqId,
+        topic: String,
+        item: Box<InboundBody>,
+    ) -> Result<(), Error> {
+        let mut item = SubscriptionItem::new(id, item);
+        if let Some(sinks) = self.pending_consumes.get_mut(&topic) {
+            // Skips the consumers that are dropped before the delivery
+            while let Some(sink) = sinks.pop_front() {
+                match sink.send(item) {
+                    Ok(_) => return Ok(()),
+                    Err(returned) => item = returned,
+                }
+            }
+        }
+        // The delivery is made available again after the visibility timeout
+        Err(Error::Internal(
+            format!("No local consumer is waiting on topic: {}", topic).into(),
+        ))
+    }
+
+    async fn handle_extension<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        frame: ExtensionFrame,
+        resp_tx: oneshot::Sender<Result<ExtensionMessage, Error>>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        let id = frame.id;
+        self.pending_extensions.insert(id, resp_tx);
+        writer
+            .send(ClientWriterItem::Extension(frame))
+            .await
+            .map_err(|_| {
+                self.pending_extensions.remove(&id);
+                self.ids.release(id);
+                Error::IoError(IoError::new(
+                    std::io::ErrorKind::Other,
+                    "Writer is disconnected",
+                ))
+            })
+    }
+
+    async fn handle_inbound_extension<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        ctx: &'w Arc<Context<ClientBrokerItem>>,
+        id: MessageId,
+        marker: Marker,
+        content: String,
+        body: Box<InboundBody>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        if extension::is_reply(marker) {
+            let resp_tx = self.pending_extensions.remove(&id).ok_or_else(|| {
+                Error::Internal(format!("Extension message ({}) is not found", id).into())
+            })?;
+            self.ids.release(id);
+            let result = extension::into_reply_result(marker, content, body);
+            return resp_tx
+                .send(result)
+                .map_err(|_| Error::Internal("InternalError: Extension caller is dropped".into()));
+        }
+
+        match self.extensions.get(&marker) {
+            Some(handler) => {
+                let fut = handler(ExtensionMessage::new(marker, content, body));
+                let broker = ctx.broker.clone();
+                task::spawn(async move {
+                    let frame = ExtensionFrame::reply(id, fut.await);
+                    if let Err(err) = broker
+                        .send_async(ClientBrokerItem::ExtensionReply(frame))
+                        .await
+                    {
+                        log::error!("{}", err);
+                    }
+                });
+                Ok(())
+            }
+            None => {
+                let frame = ExtensionFrame::unknown_marker(id, marker);
+                self.handle_extension_reply(writer, frame).await
+            }
+        }
+    }
+
+    async fn handle_extension_reply<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        frame: ExtensionFrame,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        writer
+            .send(ClientWriterItem::Extension(frame))
+            .await
+            .map_err(|_| {
+                Error::IoError(IoError::new(
+                    std::io::ErrorKind::Other,
+                    "Writer is disconnected",
+                ))
+            })
+    }
+
+    fn handle_inbound_request<'w>(
+        &'w mut self,
+        ctx: &'w Arc<Context<ClientBrokerItem>>,
+        call: InboundCall,
+        request: RequestBody,
+    ) -> Result<(), Error> {
+        let InboundCall {
+            call,
+            id,
+            method,
+            deadline,
+            metadata,
+        } = call;
+        let broker = ctx.broker.clone();
+        let scope = MetadataScope::new(metadata, deadline);
+        let token = CancellationToken::new();
+        // The server does not have a client id nor a known address
+        let rpc_ctx = RpcContext::new(0, None, deadline, scope.request(), token.clone());
+        let timing = Timing {
+            deadline,
+            grace_period: self.grace_period,
+            token: token.clone(),
+        };
+        let fut = match scope.enter(|| call(method, rpc_ctx, request)) {
+            HandlerOutput::Unary(fut) => fut,
+            HandlerOutput::Stream(_) => {
+                let err = Error::ExecutionError(
+                    "Streaming RPC methods cannot be called by the server".into(),
+                );
+                return ctx
+                    .broker
+                    .send(ClientBrokerItem::OutboundResponse {
+                        id,
+                        result: Err(err),
+                        metadata: MetadataMap::new(),
+                    })
+                    .map_err(|err| err.into());
+            }
+        };
+        task::spawn(async move {
+            let result = execute_timed_call(id, timing, scope.wrap(fut)).await;
+            // The server is no longer waiting for the response of a canceled call
+            if let Err(Error::Canceled(_)) = result {
+                return;
+            }
+            let metadata = scope.take_response();
+            broker
+                .send_async(ClientBrokerItem::OutboundResponse {
+                    id,
+                    result,
+                    metadata,
+                })
+                .await
+                .unwrap_or_else(|e| log::error!("{}", e));
+        });
+        self.executions.insert(id, token);
+        Ok(())
+    }
+
+    async fn handle_outbound_response<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+        result: HandlerResult,
+        metadata: MetadataMap,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        self.executions.remove(&id);
+        writer
+            .send(ClientWriterItem::Response(id, result, metadata))
+            .await
+            .map_err(|_| {
+                Error::IoError(IoError::new(
+                    std::io::ErrorKind::Other,
+                    "Writer is disconnected",
+                ))
+            })
+    }
+
+    fn handle_inbound_cancel(&mut self, id: MessageId) -> Result<(), Error> {
+        if let Some(token) = self.executions.remove(&id) {
+            token.cancel();
+        }
+        Ok(())
+    }
+
+    /// Cancels the reverse calls that are still being executed
+    fn cancel_executions(&mut self) {
+        for (_, token) in self.executions.drain() {
+            log::debug!("Stopping execution as server is disconnected");
+            token.cancel();
+        }
+    }
+
     fn handle_inbound_ack(&mut self, id: MessageId) -> Result<(), Error> {
         if let Some(tx) = self.pending_acks.remove(&id) {
+            self.ids.release(id);
             tx.send(()).map_err(|_| {
                 Error::Internal("InternalError: Failed to send Ack to Ack timeout task".into())
             })
@@ -424,29 +1160,86 @@ impl<AckMode, C> ClientBroker<AckMode, C> {
             })
     }
 
-    fn handle_subscription_inner(
+    async fn handle_subscription_inner(
         &mut self,
         topic: String,
         item: SubscriptionItem,
     ) -> Result<(), Error> {
         if let Some(sub) = self.subscriptions.get(&topic) {
-            match sub.try_send(item) {
+            match sub.push(item).await {
                 Ok(_) => Ok(()),
-                Err(err) => match err {
-                    flume::TrySendError::Disconnected(_) => {
-                        self.subscriptions.remove(&topic);
-                        Err(Error::Internal(
-                            "Subscription recver is Disconnected".into(),
-                        ))
-                    }
-                    _ => Ok(()),
-                },
+                Err(PushError::Full) => {
+                    // the subscriber stream ends once the sender is dropped
+                    self.subscriptions.remove(&topic);
+                    Err(Error::Internal(
+                        "Subscriber is too slow, dropping the subscription".into(),
+                    ))
+                }
+                Err(PushError::Closed) => {
+                    self.subscriptions.remove(&topic);
+                    Err(Error::Internal(
+                        "Subscription recver is Disconnected".into(),
+                    ))
+                }
             }
         } else {
             Err(Error::Internal("Topic is not found locally".into()))
         }
     }
 
+    async fn handle_ping<'w, W>(&'w mut self, writer: &'w mut W) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        // No reply other than the Pong is expected, and thus the id does not need to be kept live
+        let id = self.ids.next();
+        writer.send(ClientWriterItem::Ping(id)).await.map_err(|_| {
+            Error::IoError(IoError::new(
+                std::io::ErrorKind::Other,
+                "Writer is disconnected",
+            ))
+        })
+    }
+
+    async fn handle_inbound_ping<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        writer.send(ClientWriterItem::Pong(id)).await.map_err(|_| {
+            Error::IoError(IoError::new(
+                std::io::ErrorKind::Other,
+                "Writer is disconnected",
+            ))
+        })
+    }
+
+    /// Fails all the pending calls, streams and extension messages with the error
+    fn fail_pending(&mut self, err: impl Fn() -> Error) {
+        for (id, tx) in self.pending.drain() {
+            self.ids.release(id);
+            let _ = tx.send(Err(err()));
+        }
+        for (id, (tx, _)) in self.pending_streams.drain() {
+            self.ids.release(id);
+            // the error is queued behind the pending items
+            let err = err();
+            task::spawn(async move {
+                let _ = tx.push(Err(err)).await;
+            });
+        }
+        for (id, tx) in self.pending_extensions.drain() {
+            self.ids.release(id);
+            let _ = tx.send(Err(err()));
+        }
+        for (id, _) in self.pending_batches.drain() {
+            self.ids.release(id);
+        }
+    }
+
     async fn handle_stopping<'w, W>(&'w mut self, writer: &'w mut W) -> Result<(), Error>
     where
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
@@ -486,7 +1279,8 @@ impl<C: Marshal + Send> ClientBroker<AckModeNone, C> {
     where
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
-        let id = self.count.fetch_add(1, Ordering::Relaxed);
+        // No Ack is expected, and thus the id does not need to be kept live
+        let id = self.ids.next();
         let body = Arc::new(C::marshal(&body)?);
         Self::handle_publish_inner(writer, id, topic, body).await
     }
@@ -504,7 +1298,7 @@ impl<C: Marshal + Send> ClientBroker<AckModeNone, C> {
         log::debug!("Handling subscription with AckModeNone");
 
         let item = SubscriptionItem::new(id, item);
-        self.handle_subscription_inner(topic, item)
+        self.handle_subscription_inner(topic, item).await
         // No Ack will be sent
     }
 }
@@ -525,8 +1319,9 @@ impl<C: Marshal + Send> ClientBroker<AckModeAuto, C> {
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
         let count = 0;
-        let id = self.count.fetch_add(1, Ordering::Relaxed);
         let body = Arc::new(C::marshal(&body)?);
+        // The id is kept live until the Ack is received or the retries are exhausted
+        let id = self.ids.acquire();
         let res = Self::handle_publish_inner(writer, id, topic.clone(), body.clone()).await;
         let broker = ctx.broker.clone();
         self.spawn_timed_task_waiting_for_ack(
@@ -551,7 +1346,7 @@ impl<C: Marshal + Send> ClientBroker<AckModeAuto, C> {
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
         let item = SubscriptionItem::new(id.clone(), item);
-        self.handle_subscription_inner(topic, item)?;
+        self.handle_subscription_inner(topic, item).await?;
         // Automatically send back Ack
         writer.send(ClientWriterItem::Ack(id)).await.map_err(|_| {
             Error::IoError(IoError::new(
@@ -578,8 +1373,9 @@ impl<C: Marshal + Send> ClientBroker<AckModeManual, C> {
         W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
         let count = 0;
-        let id = self.count.fetch_add(1, Ordering::Relaxed);
         let body = Arc::new(C::marshal(&body)?);
+        // The id is kept live until the Ack is received or the retries are exhausted
+        let id = self.ids.acquire();
         let res = Self::handle_publish_inner(writer, id, topic.clone(), body.clone()).await;
         let broker = ctx.broker.clone();
         self.spawn_timed_task_waiting_for_ack(
@@ -606,7 +1402,7 @@ impl<C: Marshal + Send> ClientBroker<AckModeManual, C> {
         log::debug!("Handling subscription with AckModeManual");
 
         let item = SubscriptionItem::new(id, item);
-        self.handle_subscription_inner(topic, item)
+        self.handle_subscription_inner(topic, item).await
         // The user needs to manually Ack
     }
 }
@@ -625,23 +1421,60 @@ macro_rules! impl_broker_for_ack_modes {
                     &mut self,
                     ctx: &Arc<Context<Self::Item>>,
                     item: Self::Item,
-                    mut writer: W,
+                    writer: W,
                 ) -> Running<Result<Self::Ok, Self::Error>, Option<Self::Error>>
                 where
                     W: Sink<Self::WriterItem, Error = flume::SendError<Self::WriterItem>> + Send + Unpin,
                 {
+                    self.queues.broker.set_depth(ctx.broker.len());
+                    let mut writer = GaugedSink::new(writer, self.queues.writer.clone());
+                    if item.writes_data() && self.queues.writer.is_full() {
+                        if let ClientBrokerState::Started = self.state {
+                            // waits for the server to read the pending messages
+                            self.queues.writer.wait_for_space().await;
+                        }
+                    }
+
                     let res = match item {
-                        ClientBrokerItem::Request {
+                        ClientBrokerItem::Request { request, resp_tx } => {
+                            self.handle_request(&mut writer, request, resp_tx).await
+                        }
+                        ClientBrokerItem::Response { id, result, metadata } => {
+                            self.handle_response(id, result, metadata).await
+                        },
+                        ClientBrokerItem::StreamRequest { request, item_tx } => {
+                            self.handle_stream_request(&mut writer, ctx, request, item_tx).await
+                        },
+                        ClientBrokerItem::Notify {
                             id,
                             service_method,
-                            duration,
                             body,
-                            resp_tx,
+                            metadata,
                         } => {
-                            self.handle_request(&mut writer, id, service_method, duration, body, resp_tx).await
-                        }
-                        ClientBrokerItem::Response { id, result } => {
-                            self.handle_response(id, result)
+                            self.handle_notify(&mut writer, id, service_method, body, metadata).await
+                        },
+                        ClientBrokerItem::Batch {
+                            id,
+                            deadline,
+                            metadata,
+                            calls,
+                        } => {
+                            self.handle_batch(&mut writer, id, deadline, metadata, calls).await
+                        },
+                        ClientBrokerItem::BatchEnd(id) => {
+                            self.handle_batch_end(id)
+                        },
+                        ClientBrokerItem::InboundStreamItem { id, result } => {
+                            self.handle_inbound_stream_item(ctx, id, result).await
+                        },
+                        ClientBrokerItem::InboundStreamEnd(id) => {
+                            self.handle_inbound_stream_end(id)
+                        },
+                        ClientBrokerItem::OutboundStreamItem { id, body } => {
+                            self.handle_outbound_stream_item(&mut writer, id, body).await
+                        },
+                        ClientBrokerItem::OutboundStreamEnd(id) => {
+                            self.handle_outbound_stream_end(&mut writer, id).await
                         },
                         ClientBrokerItem::Cancel(id) => {
                             self.handle_cancel(&mut writer, id).await
@@ -667,18 +1500,73 @@ macro_rules! impl_broker_for_ack_modes {
                         ClientBrokerItem::Subscription { id, topic, item } => {
                             self.handle_subscription(&mut writer, id, topic, item).await
                         },
+                        ClientBrokerItem::Produce { topic, tickets, body } => {
+                            self.handle_produce(&mut writer, topic, tickets, body).await
+                        },
+                        ClientBrokerItem::Consume { topic, item_sink } => {
+                            self.handle_consume(&mut writer, topic, item_sink).await
+                        },
+                        ClientBrokerItem::Delivery { id, topic, item } => {
+                            self.handle_delivery(id, topic, item)
+                        },
+                        ClientBrokerItem::Extension { id, marker, content, body, resp_tx } => {
+                            let frame = ExtensionFrame { id, marker, content, body };
+                            self.handle_extension(&mut writer, frame, resp_tx).await
+                        },
+                        ClientBrokerItem::InboundExtension { id, marker, content, body } => {
+                            self.handle_inbound_extension(&mut writer, ctx, id, marker, content, body).await
+                        },
+                        ClientBrokerItem::ExtensionReply(frame) => {
+                            self.handle_extension_reply(&mut writer, frame).await
+                        },
+                        ClientBrokerItem::InboundRequest { call, request } => {
+                            self.handle_inbound_request(ctx, call, request)
+                        },
+                        ClientBrokerItem::OutboundResponse { id, result, metadata } => {
+                            self.handle_outbound_response(&mut writer, id, result, metadata).await
+                        },
+                        ClientBrokerItem::InboundCancel(id) => {
+                            self.handle_inbound_cancel(id)
+                        },
                         ClientBrokerItem::InboundAck(seq_id) => {
                             self.handle_inbound_ack(seq_id.0)
                         }
                         ClientBrokerItem::OutboundAck(seq_id) => {
                             self.handle_outbound_ack(&mut writer, seq_id).await
                         },
+                        ClientBrokerItem::KeepaliveTick => {
+                            if self.keepalive.tick() {
+                                self.handle_ping(&mut writer).await
+                            } else {
+                                log::error!("Server is not answering the keepalive pings");
+                                self.fail_pending(|| Error::ConnectionLost);
+                                self.cancel_executions();
+                                if let ClientBrokerState::Started = self.state {
+                                    let _ = self.handle_stopping(&mut writer).await;
+                                }
+                                if let Err(err) = writer.send(ClientWriterItem::Stop).await {
+                                    log::debug!("{}", err);
+                                }
+                                self.state = ClientBrokerState::Stopped;
+                                self.queues.broker.close();
+                                return Running::Stop(Some(Error::ConnectionLost))
+                            }
+                        },
+                        ClientBrokerItem::InboundPing(id) => {
+                            self.handle_inbound_ping(&mut writer, id).await
+                        },
+                        ClientBrokerItem::InboundPong(id) => {
+                            log::trace!("Received Pong({})", id);
+                            self.keepalive.pong();
+                            Ok(())
+                        },
                         ClientBrokerItem::Stopping => {
                             // Stopping ONLY comes from control
                             self.handle_stopping(&mut writer).await
                         },
                         ClientBrokerItem::Stop(io_err) => {
                             // Stop ONLY comes from reader
+                            self.cancel_executions();
                             match self.state {
                                 ClientBrokerState::Started => {
                                     if let Err(_) = self.handle_stopping(&mut writer).await {
@@ -698,6 +1586,7 @@ macro_rules! impl_broker_for_ack_modes {
                                 log::debug!("{}", err);
                             }
                             self.state = ClientBrokerState::Stopped;
+                            self.queues.broker.close();
                             return Running::Stop(io_err.map(Into::into))
                         }
                     };
@@ -1,15 +1,27 @@
 //! Client builder
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
 use cfg_if::cfg_if;
 
+use crate::codec::Format;
+use crate::compression::{Compression, DEFAULT_COMPRESSION_THRESHOLD};
+use crate::extension::{self, ExtensionMap, ExtensionMessage, ExtensionResult, Marker};
+use crate::keepalive::DEFAULT_KEEPALIVE_MISS_THRESHOLD;
+use crate::protocol::{DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_FRAME_SIZE};
 use crate::pubsub::{
     AckModeAuto, AckModeManual, AckModeNone, DEFAULT_PUB_RETRIES, DEFAULT_PUB_RETRY_TIMEOUT,
 };
+use crate::queue::{FullQueuePolicy, DEFAULT_QUEUE_CAPACITY};
+use crate::service::{
+    build_service, AsyncServiceMap, HandleService, HandlerOutput, RequestBody, RpcContext, Service,
+    DEFAULT_CANCELLATION_GRACE_PERIOD,
+};
+use crate::transport::{MessageSizeLimit, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::util::RegisterService;
 
 #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
-use crate::transport::ws::WebSocketConn;
+use crate::transport::ws::{ws_config, WebSocketConn};
 
 cfg_if! {
     if #[cfg(any(
@@ -19,14 +31,14 @@ cfg_if! {
         #[cfg(feature = "tls")]
         use tokio_rustls::TlsConnector;
         #[cfg(all(feature = "tls", feature = "ws_tokio"))]
-        use async_tungstenite::tokio::client_async;
+        use async_tungstenite::tokio::client_async_with_config;
         use tokio::net::TcpStream;
 
         use tokio::net::ToSocketAddrs;
         use ::tokio::io::{AsyncRead, AsyncWrite};
 
         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
-        use async_tungstenite::tokio::connect_async;
+        use async_tungstenite::tokio::connect_async_with_config;
     } else if #[cfg(any(
         feature = "docs",
         all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
@@ -34,14 +46,14 @@ cfg_if! {
         #[cfg(feature = "tls")]
         use futures_rustls::TlsConnector;
         #[cfg(all(feature = "tls", feature = "ws_async_std"))]
-        use async_tungstenite::client_async;
+        use async_tungstenite::client_async_with_config;
         use async_std::net::TcpStream;
 
         use async_std::net::ToSocketAddrs;
         use futures::{AsyncRead, AsyncWrite};
 
         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
-        use async_tungstenite::async_std::connect_async;
+        use async_tungstenite::async_std::connect_async_with_config;
     }
 }
 
@@ -57,6 +69,38 @@ pub struct ClientBuilder<AckMode> {
     /// The number of retries that a publisher will attempt if Ack is not received.
     /// This only affects when Ack is enabled (ie. AckModeAuto, AckModeManual)
     pub max_num_retries: u32,
+    /// Registered extension handlers
+    pub extensions: ExtensionMap,
+    /// Registered services, which the server calls with `ClientHandle::call`
+    pub services: AsyncServiceMap,
+    /// Period a canceled or timed out call made by the server is given to clean up
+    pub cancellation_grace_period: Duration,
+    /// Interval of the keepalive pings. Keepalive is disabled if this is `None`
+    pub keepalive_interval: Option<Duration>,
+    /// Number of consecutive keepalive pings that can be left unanswered before the
+    /// server is declared dead
+    pub keepalive_miss_threshold: u32,
+    /// Algorithm used to compress the payloads sent to the server. Payloads are not
+    /// compressed if this is `None`
+    pub compression: Option<Compression>,
+    /// Size in bytes below which payloads are sent uncompressed
+    pub compression_threshold: usize,
+    /// Max size in bytes of the payload of a frame. Larger bodies are split into chunks
+    pub max_frame_size: u32,
+    /// Time the server is given to complete the handshake
+    pub handshake_timeout: Duration,
+    /// Max size in bytes of an inbound message
+    pub max_message_size: usize,
+    /// Whether an inbound message that exceeds the max message size closes the connection
+    pub close_on_oversized_message: bool,
+    /// Max number of messages from the server that wait for the broker
+    pub broker_queue_capacity: usize,
+    /// Max number of messages that wait to be written to the server
+    pub writer_queue_capacity: usize,
+    /// What happens when the queue of a local subscriber is full
+    pub full_queue_policy: FullQueuePolicy,
+    /// Format of the connection. The format of `DefaultCodec` is used if this is `None`
+    pub format: Option<Format>,
 }
 
 impl Default for ClientBuilder<AckModeNone> {
@@ -65,6 +109,21 @@ impl Default for ClientBuilder<AckModeNone> {
             ack_mode: PhantomData,
             pub_retry_timeout: DEFAULT_PUB_RETRY_TIMEOUT,
             max_num_retries: DEFAULT_PUB_RETRIES,
+            extensions: ExtensionMap::new(),
+            services: AsyncServiceMap::new(),
+            cancellation_grace_period: DEFAULT_CANCELLATION_GRACE_PERIOD,
+            keepalive_interval: None,
+            keepalive_miss_threshold: DEFAULT_KEEPALIVE_MISS_THRESHOLD,
+            compression: None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            close_on_oversized_message: false,
+            broker_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            writer_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            full_queue_policy: FullQueuePolicy::DropNewest,
+            format: None,
         }
     }
 }
@@ -76,15 +135,244 @@ impl<AckMode> ClientBuilder<AckMode> {
             ack_mode: PhantomData,
             pub_retry_timeout: DEFAULT_PUB_RETRY_TIMEOUT,
             max_num_retries: DEFAULT_PUB_RETRIES,
+            extensions: ExtensionMap::new(),
+            services: AsyncServiceMap::new(),
+            cancellation_grace_period: DEFAULT_CANCELLATION_GRACE_PERIOD,
+            keepalive_interval: None,
+            keepalive_miss_threshold: DEFAULT_KEEPALIVE_MISS_THRESHOLD,
+            compression: None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            close_on_oversized_message: false,
+            broker_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            writer_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            full_queue_policy: FullQueuePolicy::DropNewest,
+            format: None,
         }
     }
 
+    /// Registers a handler for the extension messages (`Header::Ext`) with the `marker`
+    /// sent by the server.
+    ///
+    /// The reply of the handler is sent back to the server. A message whose marker has
+    /// no handler is answered with an error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `marker` is reserved, ie. not smaller than
+    /// `extension::RESERVED_MARKER_START`
+    pub fn register_extension<F, Fut>(mut self, marker: Marker, handler: F) -> Self
+    where
+        F: Fn(ExtensionMessage) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ExtensionResult> + Send + 'static,
+    {
+        extension::register(&mut self.extensions, marker, handler);
+        self
+    }
+
+    /// Registers a service that the server can call over the connection with
+    /// `ClientHandle::call`, in the same way as `ServerBuilder::register`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let agent = Arc::new(Agent { });
+    /// let client = Client::builder()
+    ///     .register(agent) // this will register `agent` with the default service name `Agent`
+    ///     .dial(addr)
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub fn register<S>(self, service: Arc<S>) -> Self
+    where
+        S: RegisterService + Send + Sync + 'static,
+    {
+        self.register_with_name(S::default_name(), service)
+    }
+
+    /// Registers a service with a name. This allows registering multiple instances
+    /// of the same type on the client.
+    pub fn register_with_name<S>(self, name: &'static str, service: Arc<S>) -> Self
+    where
+        S: RegisterService + Send + Sync + 'static,
+    {
+        let service = build_service(service, S::handlers());
+        self.register_service(name, service)
+    }
+
+    fn register_service<S>(self, name: &'static str, service: Service<S>) -> Self
+    where
+        S: Send + Sync + 'static,
+    {
+        let call = move |method_name: String,
+                         ctx: RpcContext,
+                         request: RequestBody|
+              -> HandlerOutput { service.call(&method_name, ctx, request) };
+
+        log::debug!("Registering service: {}", name);
+        let mut builder = self;
+        builder.services.insert(name, Arc::new(call));
+        builder
+    }
+
+    /// Sets the grace period of the calls made by the server with `ClientHandle::call`, which
+    /// are canceled by the server or reach their timeout.
+    ///
+    /// The `CancellationToken` of the `RpcContext` is canceled first, and the RPC method
+    /// keeps running until it returns or the grace period elapses. Defaults to
+    /// `DEFAULT_CANCELLATION_GRACE_PERIOD`.
+    pub fn set_cancellation_grace_period(mut self, duration: Duration) -> Self {
+        self.cancellation_grace_period = duration;
+        self
+    }
+
+    /// Enables keepalive, which sends a ping to the server every `interval`.
+    ///
+    /// If the server leaves more consecutive pings than the miss threshold unanswered, the
+    /// connection is closed and all the pending calls fail with `Error::ConnectionLost`.
+    /// Keepalive is disabled by default.
+    pub fn set_keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Sets the number of consecutive keepalive pings that can be left unanswered before the
+    /// server is declared dead. This is at least 1 and defaults to
+    /// `DEFAULT_KEEPALIVE_MISS_THRESHOLD`.
+    pub fn set_keepalive_miss_threshold(mut self, val: u32) -> Self {
+        self.keepalive_miss_threshold = val.max(1);
+        self
+    }
+
+    /// Compresses the payloads sent to the server with `compression`.
+    ///
+    /// Payloads are only compressed if the server supports the algorithm, and payloads
+    /// smaller than the compression threshold are sent uncompressed. Compression is
+    /// disabled by default.
+    pub fn set_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Sets the size in bytes below which payloads are sent uncompressed. This defaults to
+    /// `DEFAULT_COMPRESSION_THRESHOLD`.
+    pub fn set_compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Sets the max size in bytes of the payload of a frame, which defaults to
+    /// `DEFAULT_MAX_FRAME_SIZE`.
+    ///
+    /// The smaller of the sizes of the client and the server is used on the connection.
+    /// Larger bodies are split into chunks, which are interleaved with the other messages so
+    /// that a large transfer does not hold them back. Chunking is only used by the raw TCP
+    /// transport.
+    pub fn set_max_frame_size(mut self, size: u32) -> Self {
+        self.max_frame_size = size.max(1);
+        self
+    }
+
+    /// Sets the time the server is given to complete the handshake, which defaults to
+    /// `DEFAULT_HANDSHAKE_TIMEOUT`.
+    ///
+    /// Connecting fails with `Error::IoError` of kind `TimedOut` if the server does not
+    /// send its capabilities within the timeout.
+    pub fn set_handshake_timeout(mut self, duration: Duration) -> Self {
+        self.handshake_timeout = duration;
+        self
+    }
+
+    /// Sets the max size in bytes of a message received from the server, which defaults to
+    /// `DEFAULT_MAX_MESSAGE_SIZE`.
+    ///
+    /// The size is checked before the message is read into memory, and a compressed
+    /// message is checked against the size after decompression. An oversized message is
+    /// skipped and the call it belongs to fails with `Error::MessageTooLarge`, unless
+    /// `set_close_on_oversized_message` is enabled. The WebSocket transports cannot skip
+    /// a message and always close the connection.
+    pub fn set_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size.max(1);
+        self
+    }
+
+    /// Closes the connection when a message received from the server exceeds the max message
+    /// size instead of skipping the message. This is disabled by default.
+    pub fn set_close_on_oversized_message(mut self, close: bool) -> Self {
+        self.close_on_oversized_message = close;
+        self
+    }
+
+    /// Sets the max number of messages from the server that wait for the broker of the
+    /// client, which defaults to `DEFAULT_QUEUE_CAPACITY`.
+    ///
+    /// Once the queue is full, the client stops reading from the connection until the broker
+    /// catches up. The items of a streaming call are queued for each stream without holding
+    /// back the broker. The queue of a stream only has the same capacity under
+    /// `FullQueuePolicy::Disconnect`, which cancels the call once it is full. A `CallSink`
+    /// and the `Call` of `Client::call` also wait while the queue is full, while
+    /// `Client::notify` and `Batch::send` fail with a `ResourceExhausted` status.
+    pub fn set_broker_queue_capacity(mut self, capacity: usize) -> Self {
+        self.broker_queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets the max number of messages that wait to be written to the server, which defaults
+    /// to `DEFAULT_QUEUE_CAPACITY`.
+    ///
+    /// Once the queue is full, the broker of the client stops taking new messages until the
+    /// server reads the pending ones.
+    pub fn set_writer_queue_capacity(mut self, capacity: usize) -> Self {
+        self.writer_queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets what happens when the queue of a local subscriber created with a capacity is
+    /// full, which defaults to `FullQueuePolicy::DropNewest`.
+    ///
+    /// - `Block` makes the client wait for the subscriber, which holds back every other
+    ///   message from the server as well
+    /// - `DropNewest` and `DropOldest` discard a publication to the subscriber
+    /// - `Disconnect` drops the subscription, and the subscriber stream ends. It also
+    ///   cancels a streaming call whose items are not read fast enough
+    pub fn set_full_queue_policy(mut self, policy: FullQueuePolicy) -> Self {
+        self.full_queue_policy = policy;
+        self
+    }
+
+    /// Sets the format of the connection, which defaults to the format of `DefaultCodec`.
+    ///
+    /// The server must use the same format on the listener or the HTTP integration the client
+    /// connects to, otherwise the handshake fails with `Error::IncompatiblePeer`. This applies
+    /// to all the `dial` methods and `with_stream`.
+    pub fn set_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
     /// Set the AckMode to None
     pub fn set_ack_mode_none(self) -> ClientBuilder<AckModeNone> {
         ClientBuilder::<AckModeNone> {
             ack_mode: PhantomData,
             pub_retry_timeout: self.pub_retry_timeout,
             max_num_retries: self.max_num_retries,
+            extensions: self.extensions,
+            services: self.services,
+            cancellation_grace_period: self.cancellation_grace_period,
+            keepalive_interval: self.keepalive_interval,
+            keepalive_miss_threshold: self.keepalive_miss_threshold,
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            max_frame_size: self.max_frame_size,
+            handshake_timeout: self.handshake_timeout,
+            max_message_size: self.max_message_size,
+            close_on_oversized_message: self.close_on_oversized_message,
+            broker_queue_capacity: self.broker_queue_capacity,
+            writer_queue_capacity: self.writer_queue_capacity,
+            full_queue_policy: self.full_queue_policy,
+            format: self.format,
         }
     }
 
@@ -94,6 +382,21 @@ impl<AckMode> ClientBuilder<AckMode> {
             ack_mode: PhantomData,
             pub_retry_timeout: self.pub_retry_timeout,
             max_num_retries: self.max_num_retries,
+            extensions: self.extensions,
+            services: self.services,
+            cancellation_grace_period: self.cancellation_grace_period,
+            keepalive_interval: self.keepalive_interval,
+            keepalive_miss_threshold: self.keepalive_miss_threshold,
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            max_frame_size: self.max_frame_size,
+            handshake_timeout: self.handshake_timeout,
+            max_message_size: self.max_message_size,
+            close_on_oversized_message: self.close_on_oversized_message,
+            broker_queue_capacity: self.broker_queue_capacity,
+            writer_queue_capacity: self.writer_queue_capacity,
+            full_queue_policy: self.full_queue_policy,
+            format: self.format,
         }
     }
 
@@ -103,6 +406,21 @@ impl<AckMode> ClientBuilder<AckMode> {
             ack_mode: PhantomData,
             pub_retry_timeout: self.pub_retry_timeout,
             max_num_retries: self.max_num_retries,
+            extensions: self.extensions,
+            services: self.services,
+            cancellation_grace_period: self.cancellation_grace_period,
+            keepalive_interval: self.keepalive_interval,
+            keepalive_miss_threshold: self.keepalive_miss_threshold,
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            max_frame_size: self.max_frame_size,
+            handshake_timeout: self.handshake_timeout,
+            max_message_size: self.max_message_size,
+            close_on_oversized_message: self.close_on_oversized_message,
+            broker_queue_capacity: self.broker_queue_capacity,
+            writer_queue_capacity: self.writer_queue_capacity,
+            full_queue_policy: self.full_queue_policy,
+            format: self.format,
         }
     }
 }
@@ -149,25 +467,14 @@ impl ClientBuilder<AckModeManual> {
 cfg_if! {
     if #[cfg(any(
         feature = "docs",
-        all(
-            feature = "serde_bincode",
-            not(any(feature = "serde_json", feature = "serde_cbor", feature = "serde_rmp"))
-        ),
-        all(
-            feature = "serde_cbor",
-            not(any(feature = "serde_json", feature = "serde_bincode", feature = "serde_rmp")),
-        ),
-        all(
-            feature = "serde_json",
-            not(any(feature = "serde_bincode", feature = "serde_cbor", feature = "serde_rmp")),
-        ),
-        all(
-            feature = "serde_rmp",
-            not(any(feature = "serde_cbor", feature = "serde_json", feature = "serde_bincode")),
-        )
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_json",
+        feature = "serde_rmp",
+        feature = "serde_gob"
     ))] {
         use std::{
-            sync::Arc, collections::HashMap, time::Duration,
+            collections::HashMap, time::Duration,
         };
 
         #[cfg(feature = "tls")]
@@ -177,8 +484,12 @@ cfg_if! {
         use crate::{
             client::Client,
             error::Error,
-            codec::{split::SplittableCodec, DefaultCodec},
-            message::AtomicMessageId,
+            codec::{split::SplittableCodec, with_format, Codec, CodecRead, CodecWrite},
+            compression::NegotiatedCompression,
+            message::MessageIdAllocator,
+            execution::timeout,
+            keepalive,
+            protocol::{handshake, Capabilities, FEATURE_CHUNKING, FEATURE_KEEPALIVE},
         };
 
         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
@@ -212,10 +523,7 @@ cfg_if! {
                                 .map_err(|_| Error::Internal(Box::new(webpki::InvalidDnsNameError)))?;
                             let tls_stream = connector.connect(domain, stream).await?;
 
-                            Ok(
-                                ClientBuilder::<$ack_mode>::new()
-                                    .with_stream(tls_stream)
-                            )
+                            self.with_stream(tls_stream).await
                         }
 
                         #[cfg(all(
@@ -246,30 +554,29 @@ cfg_if! {
                             let domain = rustls::client::ServerName::try_from(domain)
                                 .map_err(|_| Error::Internal(Box::new(webpki::InvalidDnsNameError)))?;
                             let tls_stream = connector.connect(domain, stream).await?;
-                            let (ws_stream, _) = client_async(url, tls_stream).await?;
+                            let config = ws_config(self.max_message_size);
+                            let (ws_stream, _) =
+                                client_async_with_config(url, tls_stream, Some(config)).await?;
                             let ws_stream = WebSocketConn::new(ws_stream);
-                            let codec = DefaultCodec::with_websocket(ws_stream);
-                            Ok(
-                                ClientBuilder::<$ack_mode>::new()
-                                    .with_codec(codec)
-                            )
+                            with_format!(self.format.unwrap_or_default(), F => {
+                                self.with_codec(Codec::<_, _, _, F>::with_websocket(ws_stream)).await
+                            })
                         }
 
                         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
                         async fn dial_websocket_url(self, url: url::Url) -> Result<Client<$ack_mode>, Error> {
-                            let (ws_stream, _) = connect_async(&url).await?;
+                            let config = ws_config(self.max_message_size);
+                            let (ws_stream, _) = connect_async_with_config(&url, Some(config)).await?;
                             let ws_stream = WebSocketConn::new(ws_stream);
-                            let codec = DefaultCodec::with_websocket(ws_stream);
-                            Ok(self.with_codec(codec))
+                            with_format!(self.format.unwrap_or_default(), F => {
+                                self.with_codec(Codec::<_, _, _, F>::with_websocket(ws_stream)).await
+                            })
                         }
 
                         /// Connects to an RPC server over socket at the specified network address
                         pub async fn dial(self, addr: impl ToSocketAddrs) -> Result<Client<$ack_mode>, Error> {
                             let stream = TcpStream::connect(addr).await?;
-                            Ok(
-                                ClientBuilder::<$ack_mode>::new()
-                                    .with_stream(stream)
-                            )
+                            self.with_stream(stream).await
                         }
 
                         /// Connects to an RPC server with TLS enabled
@@ -290,8 +597,7 @@ cfg_if! {
                             let mut url = url::Url::parse(addr)?.join(DEFAULT_RPC_PATH)?;
                             url.set_scheme("ws").expect("Failed to change scheme to ws");
 
-                            ClientBuilder::<$ack_mode>::new()
-                                .dial_websocket_url(url).await
+                            self.dial_websocket_url(url).await
                         }
 
                         /// Connects to an HTTP RPC server with TLS enabled
@@ -345,42 +651,103 @@ cfg_if! {
 
                         /// Creates an RPC `Client` over a stream
                         ///
+                        /// The handshake with the server is performed before the `Client` is returned.
                         #[cfg_attr(feature = "docs", doc(cfg(feature = "tokio_runtime")))]
-                        pub fn with_stream<T>(self, stream: T) -> Client<$ack_mode>
+                        pub async fn with_stream<T>(self, stream: T) -> Result<Client<$ack_mode>, Error>
                         where
                             T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
                         {
-                            let codec = DefaultCodec::new(stream);
-                            self.with_codec(codec)
+                            with_format!(self.format.unwrap_or_default(), F => {
+                                self.with_codec(Codec::<_, _, _, F>::new(stream)).await
+                            })
                         }
 
                         /// Creates an RPC 'Client` over socket with a specified codec
+                        ///
+                        /// The handshake with the server is performed before the `Client` is returned.
+                        /// `Error::IncompatiblePeer` is returned if the server is not compatible.
                         #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
                         #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
-                        pub fn with_codec<C>(self, codec: C) -> Client<$ack_mode>
+                        pub async fn with_codec<C>(self, codec: C) -> Result<Client<$ack_mode>, Error>
                         where
                             C: SplittableCodec + Send + 'static,
                         {
-                            let count = Arc::new(AtomicMessageId::new(0));
-                            let (writer, reader) = codec.split();
+                            let ids = Arc::new(MessageIdAllocator::default());
+                            let (mut writer, mut reader) = codec.split();
+                            reader.set_size_limit(MessageSizeLimit::new(
+                                self.max_message_size,
+                                self.close_on_oversized_message,
+                            ));
+
+                            let mut local = Capabilities::local(C::codec_name());
+                            local.max_frame_size = self.max_frame_size;
+                            let handshake = handshake(&mut writer, &mut reader, &local);
+                            let capabilities = match timeout(self.handshake_timeout, handshake).await {
+                                Some(result) => result?,
+                                None => {
+                                    return Err(Error::IoError(std::io::Error::new(
+                                        std::io::ErrorKind::TimedOut,
+                                        "The handshake is not completed within the timeout",
+                                    )))
+                                }
+                            };
+                            let compression = NegotiatedCompression::new(
+                                self.compression,
+                                self.compression_threshold,
+                                &capabilities,
+                            );
+                            writer.set_compression(compression);
+                            reader.set_compression(compression);
+                            if capabilities.supports(FEATURE_CHUNKING) {
+                                writer.set_max_frame_size(capabilities.max_frame_size as usize);
+                            }
 
-                            let reader = ClientReader { reader };
-                            let writer = ClientWriter { writer };
+                            let queues = broker::ClientQueues::new(
+                                self.broker_queue_capacity,
+                                self.writer_queue_capacity,
+                                self.full_queue_policy,
+                            );
+                            let reader = ClientReader {
+                                reader,
+                                services: Arc::new(self.services),
+                                broker_queue: queues.broker.clone(),
+                            };
+                            let writer = ClientWriter { writer, queue: queues.writer.clone() };
                             let broker = broker::ClientBroker::<$ack_mode, C>::new(
-                                count.clone(), self.pub_retry_timeout, self.max_num_retries
+                                ids.clone(),
+                                self.pub_retry_timeout,
+                                self.max_num_retries,
+                                Arc::new(self.extensions),
+                                self.keepalive_miss_threshold,
+                                self.cancellation_grace_period,
+                                queues.clone(),
                             );
                             let (handle, broker) = brw::spawn(broker, reader, writer);
 
-                            Client {
-                                count,
-                                default_timeout: Duration::from_secs(super::DEFAULT_TIMEOUT_SECONDS),
+                            if let Some(interval) = self.keepalive_interval {
+                                if capabilities.supports(FEATURE_KEEPALIVE) {
+                                    keepalive::spawn_ticker(interval, broker.clone(), || {
+                                        broker::ClientBrokerItem::KeepaliveTick
+                                    });
+                                } else {
+                                    log::warn!("Server does not support keepalive");
+                                }
+                            }
+
+                            Ok(Client {
+                                ids,
+                                default_timeout: Some(Duration::from_secs(super::DEFAULT_TIMEOUT_SECONDS)),
                                 next_timeout: AtomicCell::new(None),
+                                default_metadata: Default::default(),
+                                next_metadata: Default::default(),
                                 broker,
                                 broker_handle: Some(handle),
                                 subscriptions: HashMap::new(),
+                                capabilities,
+                                queues,
 
                                 ack_mode: PhantomData
-                            }
+                            })
                         }
                     }
                 )*
@@ -3,16 +3,24 @@
 use std::{
     marker::PhantomData,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
 use flume::Sender;
-use futures::{channel::oneshot, Future};
-use serde::de::DeserializeOwned;
+use futures::{channel::oneshot, Future, Sink, Stream};
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{message::MessageId, protocol::InboundBody, Error};
+use crate::{
+    error::{CallError, IoError},
+    message::{MessageId, MessageIdAllocator},
+    protocol::{MetadataMap, OutboundBody},
+    queue::{QueueGauge, QueueReceiver},
+    Error,
+};
 
-use super::{broker, ResponseResult};
+use super::{broker, ResponseReply, ResponseResult};
+use crate::execution::deserialize_response;
 
 enum CallStatus {
     Pending,
@@ -29,6 +37,13 @@ enum CallStatus {
 /// will yield a `Result<Res, toy_rpc::Error>`. If a `Call` is dropped before the value is consumed
 /// by `.await`ing, the call will be canceled.
 ///
+/// The type parameter `E` is the error type that the RPC method returns, which is set by the
+/// client stubs generated by the macros. `typed()` waits for the result and decodes the error
+/// returned by the RPC method into `E`.
+///
+/// A request that is made while the broker queue of the client is full is held in the
+/// `Call`, and is only sent once the `Call` is polled and the queue has room.
+///
 /// # Example
 ///
 /// ```rust
@@ -41,23 +56,57 @@ enum CallStatus {
 /// call.cancel();
 /// // You can still .await on the canceled `Call` but will get an error
 /// let result = call.await; // Err(Error::Canceled(Some(id)))
+///
+/// // the stubs of `Bank.withdraw`, which returns `Result<u64, BankError>`, return a
+/// // `Call<u64, BankError>`
+/// let result: Result<u64, CallError<BankError>> = client.bank().withdraw(100).typed().await;
 /// ```
 #[pin_project::pin_project(PinnedDrop)]
-pub struct Call<Res: DeserializeOwned> {
+pub struct Call<Res: DeserializeOwned, E = ()> {
     status: CallStatus,
     id: MessageId,
     cancel: Sender<broker::ClientBrokerItem>,
     #[pin]
-    done: oneshot::Receiver<Result<ResponseResult, Error>>,
+    done: oneshot::Receiver<Result<ResponseReply, Error>>,
     marker: PhantomData<Res>,
+    error_type: PhantomData<fn() -> E>,
     error: Option<Error>,
+    metadata: Option<MetadataMap>,
+    held: Option<HeldRequest>,
+}
+
+/// Request that waits for the broker queue to have room before it is sent
+pub(crate) struct HeldRequest {
+    request: broker::OutboundRequest,
+    resp_tx: oneshot::Sender<Result<ResponseReply, Error>>,
+    ids: Arc<MessageIdAllocator>,
+    broker_queue: Arc<QueueGauge>,
+    space: Pin<Box<dyn Future<Output = bool> + Send + Sync>>,
+}
+
+impl HeldRequest {
+    pub(crate) fn new(
+        request: broker::OutboundRequest,
+        resp_tx: oneshot::Sender<Result<ResponseReply, Error>>,
+        ids: Arc<MessageIdAllocator>,
+        broker_queue: Arc<QueueGauge>,
+    ) -> Self {
+        let queue = broker_queue.clone();
+        Self {
+            request,
+            resp_tx,
+            ids,
+            broker_queue,
+            space: Box::pin(async move { queue.wait_for_space().await }),
+        }
+    }
 }
 
-impl<Res: DeserializeOwned> Call<Res> {
+impl<Res: DeserializeOwned, E> Call<Res, E> {
     pub(crate) fn new(
         id: MessageId,
         cancel: Sender<broker::ClientBrokerItem>,
-        done: oneshot::Receiver<Result<ResponseResult, Error>>,
+        done: oneshot::Receiver<Result<ResponseReply, Error>>,
     ) -> Self {
         Self {
             status: CallStatus::Pending,
@@ -65,15 +114,29 @@ impl<Res: DeserializeOwned> Call<Res> {
             cancel,
             done,
             marker: PhantomData,
+            error_type: PhantomData,
             error: None,
+            metadata: None,
+            held: None,
         }
     }
 
+    /// Creates a `Call` whose request is sent once the broker queue has room
+    pub(crate) fn held(
+        cancel: Sender<broker::ClientBrokerItem>,
+        done: oneshot::Receiver<Result<ResponseReply, Error>>,
+        held: HeldRequest,
+    ) -> Self {
+        let mut call = Self::new(held.request.id, cancel, done);
+        call.held = Some(held);
+        call
+    }
+
     /// This will initialize
     pub(crate) fn with_error(
         id: MessageId,
         cancel: Sender<broker::ClientBrokerItem>,
-        done: oneshot::Receiver<Result<ResponseResult, Error>>,
+        done: oneshot::Receiver<Result<ResponseReply, Error>>,
         error: Error,
     ) -> Self {
         Self {
@@ -82,16 +145,22 @@ impl<Res: DeserializeOwned> Call<Res> {
             cancel,
             done,
             marker: PhantomData,
+            error_type: PhantomData,
             error: Some(error),
+            metadata: None,
+            held: None,
         }
     }
 }
 
 #[pin_project::pinned_drop]
-impl<Res: DeserializeOwned> PinnedDrop for Call<Res> {
+impl<Res: DeserializeOwned, E> PinnedDrop for Call<Res, E> {
     fn drop(self: Pin<&mut Self>) {
         let this = self.project();
-        if let CallStatus::Pending = this.status {
+        // The broker does not know about a request that is not sent
+        if let Some(held) = this.held.take() {
+            held.ids.release(*this.id);
+        } else if let CallStatus::Pending = this.status {
             if let Err(_) = this.cancel.send(broker::ClientBrokerItem::Cancel(*this.id)) {
                 log::error!("Failed to send cancellation message to client broker");
             }
@@ -100,11 +169,13 @@ impl<Res: DeserializeOwned> PinnedDrop for Call<Res> {
     }
 }
 
-impl<Res: DeserializeOwned> Call<Res> {
+impl<Res: DeserializeOwned, E> Call<Res, E> {
     /// Cancel the RPC call
     ///
     pub fn cancel(&mut self) {
-        if let Err(_) = self.cancel.send(broker::ClientBrokerItem::Cancel(self.id)) {
+        if let Some(held) = self.held.take() {
+            held.ids.release(self.id);
+        } else if let Err(_) = self.cancel.send(broker::ClientBrokerItem::Cancel(self.id)) {
             log::error!("Failed to send cancellation message to client broker");
         }
         self.status = CallStatus::Canceled;
@@ -112,13 +183,66 @@ impl<Res: DeserializeOwned> Call<Res> {
 
     /// Gets the ID number of the call
     ///
-    /// Each client RPC call has a monotonically increasing ID number of type `u16`
+    /// Each client RPC call has a monotonically increasing ID number of type `u64`
     pub fn id(&self) -> MessageId {
         self.id
     }
+
+    /// Gets the metadata of the response.
+    ///
+    /// Returns `None` if the response is not received yet. Because `.await`ing on
+    /// the `Call` consumes it, the `Call` should be `.await`ed by mutable reference
+    /// in order to read the metadata afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut call: Call<i32> = client.call("Arith.add", (1i32, 6i32));
+    /// let result = (&mut call).await;
+    /// println!("{:?}", call.metadata());
+    /// ```
+    pub fn metadata(&self) -> Option<&MetadataMap> {
+        self.metadata.as_ref()
+    }
+
+    /// Sets `T` as the error type that the RPC method returns, which `typed()` decodes
+    pub fn with_error_type<T>(mut self) -> Call<Res, T> {
+        // the pending call is moved to the returned `Call`, so dropping `self` is a no-op
+        let (_, done) = oneshot::channel();
+        let status = std::mem::replace(&mut self.status, CallStatus::Received);
+        Call {
+            status,
+            id: self.id,
+            cancel: self.cancel.clone(),
+            done: std::mem::replace(&mut self.done, done),
+            marker: PhantomData,
+            error_type: PhantomData,
+            error: self.error.take(),
+            metadata: self.metadata.take(),
+            held: self.held.take(),
+        }
+    }
+}
+
+impl<Res: DeserializeOwned, E: DeserializeOwned> Call<Res, E> {
+    /// Waits for the result like `.await`ing on the `Call`, and decodes the error that
+    /// the RPC method returns into `E`. Other errors are returned as `CallError::Rpc`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// match client.bank().withdraw(100).typed().await {
+    ///     Err(CallError::Typed(BankError::InsufficientFunds { balance })) => { /* ... */ }
+    ///     Err(CallError::Rpc(err)) => { /* other errors */ }
+    ///     Ok(_) => { /* ... */ }
+    /// }
+    /// ```
+    pub async fn typed(self) -> Result<Res, CallError<E>> {
+        self.await.map_err(CallError::from)
+    }
 }
 
-impl<Res> Future for Call<Res>
+impl<Res, E> Future for Call<Res, E>
 where
     Res: serde::de::DeserializeOwned,
 {
@@ -126,9 +250,28 @@ where
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        let done: Pin<
-            &mut oneshot::Receiver<Result<Result<Box<InboundBody>, Box<InboundBody>>, Error>>,
-        > = this.done;
+        // waits for the broker to catch up
+        if let Some(held) = this.held.as_mut() {
+            let has_space = futures::ready!(held.space.as_mut().poll(cx));
+            let held = this.held.take().expect("Held request is taken");
+            let sent = has_space && {
+                held.broker_queue.push();
+                let item = broker::ClientBrokerItem::Request {
+                    request: held.request,
+                    resp_tx: held.resp_tx,
+                };
+                this.cancel.send(item).is_ok()
+            };
+            if !sent {
+                held.ids.release(*this.id);
+                *this.status = CallStatus::Dropped;
+                return Poll::Ready(Err(Error::IoError(IoError::new(
+                    std::io::ErrorKind::NotConnected,
+                    "Cannot connect to client side broker",
+                ))));
+            }
+        }
+        let done: Pin<&mut oneshot::Receiver<Result<ResponseReply, Error>>> = this.done;
 
         match done.poll(cx) {
             Poll::Pending => match this.status {
@@ -156,18 +299,12 @@ where
                     Ok(val) => val,
                     Err(_canceled) => return Poll::Ready(Err(Error::Canceled(*this.id))),
                 };
-                let res = match res {
+                let (res, metadata) = match res {
                     Ok(val) => val,
                     Err(err) => return Poll::Ready(Err(err)),
                 };
-                let res = match res {
-                    Ok(mut resp_body) => erased_serde::deserialize(&mut resp_body)
-                        .map_err(|err| Error::ParseError(Box::new(err))),
-                    Err(mut err_body) => erased_serde::deserialize(&mut err_body).map_or_else(
-                        |err| Err(Error::ParseError(Box::new(err))),
-                        |msg| Err(Error::from_err_msg(msg)),
-                    ),
-                };
+                *this.metadata = Some(metadata);
+                let res = deserialize_response(res);
 
                 *this.status = CallStatus::Received;
                 Poll::Ready(res)
@@ -175,3 +312,248 @@ where
         }
     }
 }
+
+/// Call of a server-streaming RPC request. The items can be obtained by polling
+/// the `CallStream` as a `futures::Stream`. The call can be cancelled with `cancel()` method.
+///
+/// The type parameter `Res` is the `Ok` type of the items yielded by the RPC method.
+/// Each item is a `Result<Res, toy_rpc::Error>`, and the stream ends once the server
+/// has yielded all the items. If the request reaches the timeout, an `Err(Error::Timeout(id))`
+/// will be yielded before the stream ends. If a `CallStream` is dropped before the stream ends,
+/// the call will be canceled.
+///
+/// # Example
+///
+/// ```rust
+/// use futures::StreamExt;
+///
+/// let mut stream: CallStream<u32> = client.call_stream("Counter.count_to", 10u32);
+/// while let Some(item) = stream.next().await {
+///     println!("{:?}", item);
+/// }
+///
+/// // cancel the call. The canceled `CallStream` will not yield any more items
+/// let mut stream: CallStream<u32> = client.call_stream("Counter.count_forever", ());
+/// stream.cancel();
+/// assert!(stream.next().await.is_none());
+/// ```
+#[pin_project::pin_project(PinnedDrop)]
+pub struct CallStream<Res: DeserializeOwned> {
+    status: CallStatus,
+    id: MessageId,
+    cancel: Sender<broker::ClientBrokerItem>,
+    #[pin]
+    items: QueueReceiver<Result<ResponseResult, Error>>,
+    marker: PhantomData<Res>,
+    error: Option<Error>,
+}
+
+impl<Res: DeserializeOwned> CallStream<Res> {
+    pub(crate) fn new(
+        id: MessageId,
+        cancel: Sender<broker::ClientBrokerItem>,
+        items: QueueReceiver<Result<ResponseResult, Error>>,
+    ) -> Self {
+        Self {
+            status: CallStatus::Pending,
+            id,
+            cancel,
+            items,
+            marker: PhantomData,
+            error: None,
+        }
+    }
+
+    pub(crate) fn with_error(
+        id: MessageId,
+        cancel: Sender<broker::ClientBrokerItem>,
+        items: QueueReceiver<Result<ResponseResult, Error>>,
+        error: Error,
+    ) -> Self {
+        Self {
+            status: CallStatus::Dropped,
+            id,
+            cancel,
+            items,
+            marker: PhantomData,
+            error: Some(error),
+        }
+    }
+
+    /// Cancel the RPC call
+    pub fn cancel(&mut self) {
+        if let Err(_) = self.cancel.send(broker::ClientBrokerItem::Cancel(self.id)) {
+            log::error!("Failed to send cancellation message to client broker");
+        }
+        self.status = CallStatus::Canceled;
+    }
+
+    /// Gets the ID number of the call
+    pub fn id(&self) -> MessageId {
+        self.id
+    }
+}
+
+#[pin_project::pinned_drop]
+impl<Res: DeserializeOwned> PinnedDrop for CallStream<Res> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if let CallStatus::Pending = this.status {
+            if let Err(_) = this.cancel.send(broker::ClientBrokerItem::Cancel(*this.id)) {
+                log::error!("Failed to send cancellation message to client broker");
+            }
+        }
+        *this.status = CallStatus::Dropped;
+    }
+}
+
+impl<Res> Stream for CallStream<Res>
+where
+    Res: DeserializeOwned,
+{
+    type Item = Result<Res, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        match this.status {
+            CallStatus::Pending => {}
+            CallStatus::Canceled | CallStatus::Received => return Poll::Ready(None),
+            CallStatus::Dropped => return Poll::Ready(this.error.take().map(Err)),
+        }
+
+        match this.items.poll_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(None) => {
+                *this.status = CallStatus::Received;
+                Poll::Ready(None)
+            }
+            Poll::Ready(Some(res)) => {
+                let res = match res {
+                    Ok(val) => deserialize_response(val),
+                    Err(err) => Err(err),
+                };
+                Poll::Ready(Some(res))
+            }
+        }
+    }
+}
+
+/// Sink of the request items of a client-streaming or bidirectional streaming
+/// RPC call.
+///
+/// The type parameter `Req` is the type of the request items. The request stream
+/// is ended when the `CallSink` is closed or dropped. Items sent after the call is
+/// finished or canceled are discarded. The sink is not ready while the broker queue of
+/// the client is full.
+///
+/// # Example
+///
+/// ```rust
+/// use futures::SinkExt;
+///
+/// let (mut sink, call): (CallSink<i32>, Call<i32>) = client.call_sink("Arith.sum");
+/// sink.send(1).await?;
+/// sink.send(2).await?;
+/// sink.close().await?;
+/// let result = call.await; // Ok(3)
+/// ```
+pub struct CallSink<Req> {
+    id: MessageId,
+    broker: Sender<broker::ClientBrokerItem>,
+    broker_queue: Arc<QueueGauge>,
+    /// Waits for the broker queue to have room
+    space: Option<Pin<Box<dyn Future<Output = bool> + Send + Sync>>>,
+    closed: bool,
+    marker: PhantomData<fn(Req)>,
+}
+
+impl<Req> CallSink<Req> {
+    pub(crate) fn new(
+        id: MessageId,
+        broker: Sender<broker::ClientBrokerItem>,
+        broker_queue: Arc<QueueGauge>,
+    ) -> Self {
+        Self {
+            id,
+            broker,
+            broker_queue,
+            space: None,
+            closed: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Gets the ID number of the call
+    pub fn id(&self) -> MessageId {
+        self.id
+    }
+
+    fn send_to_broker(&self, item: broker::ClientBrokerItem) -> Result<(), Error> {
+        self.broker.send(item).map_err(|_| {
+            Error::IoError(IoError::new(
+                std::io::ErrorKind::NotConnected,
+                "Cannot connect to client side broker",
+            ))
+        })
+    }
+}
+
+impl<Req> Drop for CallSink<Req> {
+    fn drop(&mut self) {
+        if !self.closed {
+            let item = broker::ClientBrokerItem::OutboundStreamEnd(self.id);
+            if self.broker.send(item).is_err() {
+                log::debug!("Failed to send end of stream to client broker");
+            }
+        }
+    }
+}
+
+impl<Req> Sink<Req> for CallSink<Req>
+where
+    Req: Serialize + Send + Sync + 'static,
+{
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Err(Error::Internal("CallSink is closed".into())));
+        }
+        // waits for the broker to catch up
+        if this.space.is_none() && this.broker_queue.is_full() {
+            let queue = this.broker_queue.clone();
+            this.space = Some(Box::pin(async move { queue.wait_for_space().await }));
+        }
+        if let Some(space) = this.space.as_mut() {
+            let has_space = futures::ready!(space.as_mut().poll(cx));
+            this.space = None;
+            if !has_space {
+                return Poll::Ready(Err(Error::IoError(IoError::new(
+                    std::io::ErrorKind::NotConnected,
+                    "Cannot connect to client side broker",
+                ))));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Req) -> Result<(), Self::Error> {
+        let body = Box::new(item) as Box<OutboundBody>;
+        self.broker_queue.push();
+        self.send_to_broker(broker::ClientBrokerItem::OutboundStreamItem { id: self.id, body })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        this.closed = true;
+        Poll::Ready(this.send_to_broker(broker::ClientBrokerItem::OutboundStreamEnd(this.id)))
+    }
+}
@@ -3,20 +3,36 @@
 use cfg_if::cfg_if;
 use crossbeam::atomic::AtomicCell;
 use flume::Sender;
-use std::{any::TypeId, collections::HashMap, marker::PhantomData, sync::Arc, time::Duration};
-
-use crate::{message::AtomicMessageId, protocol::InboundBody, pubsub::AckModeNone};
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    message::MessageIdAllocator,
+    protocol::{Capabilities, InboundBody, MetadataMap},
+    pubsub::AckModeNone,
+    queue::QueueMetrics,
+};
 
 pub(crate) mod broker;
 pub mod builder;
 pub mod pubsub;
+pub mod queue;
 mod reader;
 mod writer;
 
-use broker::ClientBrokerItem;
+use broker::{ClientBrokerItem, ClientQueues, OutboundRequest};
 use builder::ClientBuilder;
+use call::HeldRequest;
+
+type ResponseResult = Result<Box<InboundBody>, Error>;
 
-type ResponseResult = Result<Box<InboundBody>, Box<InboundBody>>;
+/// Result of a response along with the metadata of the response
+type ResponseReply = (ResponseResult, MetadataMap);
 
 cfg_if! {
     if #[cfg(any(
@@ -25,8 +41,10 @@ cfg_if! {
         all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
     ))] {
         use futures::channel::oneshot;
+        use std::time::Instant;
 
-        use crate::{Error, protocol::{OutboundBody}};
+        use crate::{Error, protocol::{OutboundBody, FEATURE_NOTIFY}};
+        use crate::extension::{ExtensionMessage, Marker, RESERVED_MARKER_START};
 
         const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
     }
@@ -57,12 +75,19 @@ cfg_if! {
     allow(dead_code)
 )]
 pub struct Client<AckMode> {
-    count: Arc<AtomicMessageId>,
-    default_timeout: Duration,
-    next_timeout: AtomicCell<Option<Duration>>,
+    ids: Arc<MessageIdAllocator>,
+    /// `None` if the calls have no timeout by default
+    default_timeout: Option<Duration>,
+    /// Timeout of the next call, which overrides the default timeout if set
+    next_timeout: AtomicCell<Option<Option<Duration>>>,
+    default_metadata: MetadataMap,
+    /// Metadata of the next call, which is locked so that the keys set concurrently are kept
+    next_metadata: Mutex<MetadataMap>,
     broker: Sender<ClientBrokerItem>,
     broker_handle: Option<JoinHandle<Result<(), Error>>>,
     subscriptions: HashMap<String, TypeId>,
+    capabilities: Capabilities,
+    queues: ClientQueues,
 
     ack_mode: PhantomData<AckMode>,
 }
@@ -71,42 +96,33 @@ cfg_if! {
     if #[cfg(any(
         feature = "docs",
         any(
-            all(
-                feature = "serde_bincode",
-                not(any(feature = "serde_json", feature = "serde_cbor", feature = "serde_rmp"))
-            ),
-            all(
-                feature = "serde_cbor",
-                not(any(feature = "serde_json", feature = "serde_bincode", feature = "serde_rmp")),
-            ),
-            all(
-                feature = "serde_json",
-                not(any(feature = "serde_bincode", feature = "serde_cbor", feature = "serde_rmp")),
-            ),
-            all(
-                feature = "serde_rmp",
-                not(any(feature = "serde_cbor", feature = "serde_json", feature = "serde_bincode")),
-            )
+            feature = "serde_bincode",
+            feature = "serde_cbor",
+            feature = "serde_json",
+            feature = "serde_rmp",
+            feature = "serde_gob"
         )
     ))] {
         #[cfg(feature = "tls")]
         use rustls::{ClientConfig};
 
         /// The following impl block is controlled by feature flag. It is enabled
-        /// if and only if **exactly one** of the the following feature flag is turned on
+        /// if at least one of the the following feature flags is turned on
         /// - `serde_bincode`
         /// - `serde_json`
         /// - `serde_cbor`
         /// - `serde_rmp`
+        /// - `serde_gob`
         impl Client<AckModeNone> {
             /// Connects to an RPC server over socket at the specified network address
             ///
             /// This is enabled
-            /// if and only if **exactly one** of the the following feature flag is turned on
+            /// if at least one of the the following feature flags is turned on
             /// - `serde_bincode`
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_gob`
             ///
             /// # Example
             ///
@@ -148,11 +164,16 @@ cfg_if! {
             /// earlier than "0.5.0-beta.0".
             ///
             /// This is enabled
-            /// if and only if **only one** of the the following feature flag is turned on
+            /// if at least one of the the following feature flags is turned on
             /// - `serde_bincode`
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_gob`
+            ///
+            /// The connection uses the format of `DefaultCodec`. If more than one of the
+            /// feature flags is turned on, another `Format` can be selected with
+            /// `ClientBuilder::set_format`, which must match the format of the server.
             ///
             /// # Example
             ///
@@ -202,11 +223,12 @@ cfg_if! {
             /// append `DEFAULT_RPC_PATH="_rpc"` to the end of the addr.
             ///
             /// This is enabled
-            /// if and only if **exactly one** of the the following feature flag is turned on
+            /// if at least one of the the following feature flags is turned on
             /// - `serde_bincode`
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_gob`
             ///
             /// # Example
             ///
@@ -251,24 +273,25 @@ cfg_if! {
             /// and `tokio::io::AsyncWrite`
             ///
             /// This is enabled
-            /// if and only if **exactly one** of the the following feature flag is turned on
+            /// if at least one of the the following feature flags is turned on
             /// - `serde_bincode`
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_gob`
             ///
             /// # Example
             /// ```
             /// let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-            /// let client = Client::with_stream(stream);
+            /// let client = Client::with_stream(stream).await.unwrap();
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(feature = "tokio_runtime")))]
-            pub fn with_stream<T>(stream: T) -> Self
+            pub async fn with_stream<T>(stream: T) -> Result<Self, Error>
             where
                 T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
             {
                 let builder = ClientBuilder::default();
-                builder.with_stream(stream)
+                builder.with_stream(stream).await
             }
 
             /// Creates an RPC 'Client` over socket with a specified codec
@@ -278,8 +301,8 @@ cfg_if! {
             /// ```rust
             /// let addr = "127.0.0.1:8080";
             /// let stream = TcpStream::connect(addr).await.unwrap();
-            /// let codec = Codec::new(stream);
-            /// let client = Client::with_codec(codec);
+            /// let codec = DefaultCodec::new(stream);
+            /// let client = Client::with_codec(codec).await.unwrap();
             /// ```
             // #[cfg(any(
             //     all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
@@ -287,19 +310,21 @@ cfg_if! {
             // ))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
-            pub fn with_codec<C>(codec: C) -> Self
+            pub async fn with_codec<C>(codec: C) -> Result<Self, Error>
             where
                 C: SplittableCodec + Send + 'static,
             {
                 let builder = ClientBuilder::default();
-                builder.with_codec(codec)
+                builder.with_codec(codec).await
             }
         }
     }
 }
 
+pub mod batch;
 pub mod call;
-pub use call::Call;
+pub use batch::Batch;
+pub use call::{Call, CallSink, CallStream};
 
 // seems like it still works even without this impl
 impl<AckMode> Drop for Client<AckMode> {
@@ -339,6 +364,21 @@ impl Client<AckModeNone> {
 }
 
 impl<AckMode> Client<AckMode> {
+    /// Capabilities agreed upon by the client and the server in the handshake
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Returns the metrics of the queue of messages from the server that wait for the broker
+    pub fn broker_queue_metrics(&self) -> QueueMetrics {
+        self.queues.broker.metrics()
+    }
+
+    /// Returns the metrics of the queue of messages that wait to be written to the server
+    pub fn writer_queue_metrics(&self) -> QueueMetrics {
+        self.queues.writer.metrics()
+    }
+
     /// Closes connection with the server
     ///
     /// Dropping the client will close the connection as well
@@ -379,8 +419,6 @@ cfg_if! {
         all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
         all(feature = "tokio_runtime", not(feature = "async_std_runtime"))
     ))] {
-        use std::sync::atomic::Ordering;
-
         use crate::{codec::split::SplittableCodec};
 
         impl<AckMode> Client<AckMode> {
@@ -398,7 +436,19 @@ cfg_if! {
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
             pub fn set_default_timeout(&mut self, duration: Duration) -> &Self {
-                self.default_timeout = duration;
+                self.default_timeout = Some(duration);
+                self
+            }
+
+            /// Removes the default timeout so that the calls wait for the response indefinitely,
+            /// unless a timeout is set with `set_next_timeout`
+            ///
+            /// A call made from within a RPC method is still limited by the deadline of the
+            /// request being handled.
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn set_no_default_timeout(&mut self) -> &Self {
+                self.default_timeout = None;
                 self
             }
 
@@ -416,10 +466,94 @@ cfg_if! {
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
             pub fn set_next_timeout(&self, duration: Duration) -> &Self {
-                let _ = self.next_timeout.swap(Some(duration));
+                let _ = self.next_timeout.swap(Some(Some(duration)));
+                self
+            }
+
+            /// Makes the next RPC request wait for the response indefinitely
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// let call: Call<()> = client
+            ///     .set_next_no_timeout()
+            ///     .call("Service.wait_for_10secs", ()); // the RPC Call will not timeout
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn set_next_no_timeout(&self) -> &Self {
+                let _ = self.next_timeout.swap(Some(None));
+                self
+            }
+
+            /// Computes the deadline of the next call from its timeout. A call made from
+            /// within a RPC method does not outlive the request being handled.
+            fn next_deadline(&self) -> Option<Instant> {
+                let timeout = match self.next_timeout.swap(None) {
+                    Some(timeout) => timeout,
+                    None => self.default_timeout,
+                };
+                let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+                match (deadline, crate::service::request_deadline()) {
+                    (Some(deadline), Some(inherited)) => Some(deadline.min(inherited)),
+                    (deadline, inherited) => deadline.or(inherited),
+                }
+            }
+
+            /// Sets a key-value pair in the default metadata, which is sent with every RPC request
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// client.set_default_metadata("authorization", "Bearer some-token");
+            /// let call: Call<()> = client.call("Service.method", ());
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn set_default_metadata(&mut self, key: impl ToString, value: impl ToString) -> &Self {
+                self.default_metadata.insert(key.to_string(), value.to_string());
+                self
+            }
+
+            /// Sets a key-value pair in the metadata **ONLY** for the next RPC request.
+            /// The value overrides the default metadata with the same key.
+            ///
+            /// The metadata of the response can be read from the `Call` once the response
+            /// is received.
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// let mut call: Call<()> = client
+            ///     .set_next_metadata("trace-id", "0af7651916cd43dd")
+            ///     .call("Service.method", ());
+            /// let result = (&mut call).await;
+            /// println!("{:?}", call.metadata());
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn set_next_metadata(&self, key: impl ToString, value: impl ToString) -> &Self {
+                self.next_metadata
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .insert(key.to_string(), value.to_string());
                 self
             }
 
+            /// Gets the metadata of the next request by merging the metadata for
+            /// the next request into the default metadata
+            fn take_next_metadata(&self) -> MetadataMap {
+                let mut metadata = self.default_metadata.clone();
+                let next = std::mem::take(
+                    &mut *self
+                        .next_metadata
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner()),
+                );
+                metadata.extend(next);
+                metadata
+            }
+
             /// Invokes the named function and wait synchronously in a blocking manner.
             ///
             /// This function internally calls `task::block_on` to wait for the response.
@@ -469,7 +603,8 @@ cfg_if! {
             /// The `Call<Res>` type takes one type argument `Res` which is the type of successful RPC execution.
             /// The result can be obtained by `.await`ing on the `Call`, which returns type `Result<Res, toy_rpc::Error>`
             /// `Call` can be cancelled by calling the `cancel()` function.
-            /// The request will be sent in a background task.
+            /// The request will be sent in a background task. While the broker queue of the client
+            /// is full, the request waits in the `Call` until it is polled and the queue has room.
             ///
             /// Example
             ///
@@ -492,25 +627,302 @@ cfg_if! {
             where
                 Req: serde::Serialize + Send + Sync + 'static,
                 Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let body = Box::new(args) as Box<OutboundBody>;
+                self.send_request(service_method.to_string(), Some(body))
+            }
+
+            /// Sends a notification to the named RPC function, which is a one-way request that
+            /// expects no response
+            ///
+            /// The server executes the RPC method but does not send back its result, and the
+            /// client does not wait for one. This suits calls like telemetry and logging where
+            /// the result is not needed. The metadata set with `set_next_metadata` is sent with
+            /// the notification, while the timeout does not apply.
+            ///
+            /// An error is returned if the notification cannot be sent, ie. the connection is
+            /// closed, or if the server does not support notifications. A `ResourceExhausted`
+            /// status is returned while the broker queue of the client is full. Errors from the
+            /// execution on the server are only logged on the server.
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// client.notify("Telemetry.record", "page_view".to_string())?;
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn notify<Req>(&self, service_method: impl ToString, args: Req) -> Result<(), Error>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+            {
+                if !self.capabilities.supports(FEATURE_NOTIFY) {
+                    return Err(Error::IncompatiblePeer(
+                        "The server does not support notifications".into()
+                    ))
+                }
+                self.queues.check_broker_space()?;
+
+                let id = self.ids.acquire();
+                let metadata = self.take_next_metadata();
+                let item = ClientBrokerItem::Notify {
+                    id,
+                    service_method: service_method.to_string(),
+                    body: Box::new(args),
+                    metadata,
+                };
+                self.queues.broker.push();
+                if let Err(err) = self.broker.send(item) {
+                    log::error!("{}", err);
+                    self.ids.release(id);
+                    return Err(Error::IoError(
+                        std::io::Error::new(
+                            std::io::ErrorKind::NotConnected,
+                            "Cannot connect to client side broker"
+                        )
+                    ))
+                }
+                Ok(())
+            }
+
+            /// Creates a `Batch`, which collects calls and sends them to the server in a single
+            /// message
+            ///
+            /// The timeout and metadata set for the next request apply to all calls in the batch.
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// let mut batch = client.batch();
+            /// let sum: Call<i32> = batch.call("Arith.add", (1i32, 6i32));
+            /// let product: Call<i32> = batch.call("Arith.multiply", (2i32, 3i32));
+            /// batch.send()?;
+            /// println!("{:?} {:?}", sum.await, product.await);
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn batch(&self) -> Batch<'_, AckMode> {
+                let deadline = self.next_deadline();
+                let metadata = self.take_next_metadata();
+                Batch::new(self, deadline, metadata)
+            }
+
+            /// Invokes the named client-streaming RPC function asynchronously and returns a
+            /// `CallSink` and a cancellation `Call`
+            ///
+            /// The request items are sent through the `CallSink<Req>`, which implements
+            /// `futures::Sink<Req, Error = toy_rpc::Error>`. The request stream is ended by
+            /// closing or dropping the `CallSink`. The response can be obtained by `.await`ing
+            /// on the `Call`. Cancelling the `Call` also cancels the request stream.
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// use futures::SinkExt;
+            ///
+            /// let (mut sink, call): (CallSink<i32>, Call<i32>) = client.call_sink("SomeService.sum");
+            /// for i in 0..10 {
+            ///     sink.send(i).await?;
+            /// }
+            /// sink.close().await?;
+            /// let reply: Result<i32, toy_rpc::Error> = call.await;
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn call_sink<Req, Res>(&self, service_method: impl ToString) -> (CallSink<Req>, Call<Res>)
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let call = self.send_request(service_method.to_string(), None);
+                let sink = CallSink::new(call.id(), self.broker.clone(), self.queues.broker.clone());
+                (sink, call)
+            }
+
+            fn send_request<Res>(&self, service_method: String, body: Option<Box<OutboundBody>>) -> Call<Res>
+            where
+                Res: serde::de::DeserializeOwned + Send + 'static,
             {
                 // Prepare RPC request
-                let id = self.count.fetch_add(1, Ordering::Relaxed);
-                let service_method = service_method.to_string();
-                let duration = match self.next_timeout.swap(None) {
-                    Some(dur) => dur,
-                    None => self.default_timeout.clone()
+                let id = self.ids.acquire();
+                let deadline = self.next_deadline();
+                let metadata = self.take_next_metadata();
+                let (resp_tx, resp_rx) = oneshot::channel();
+                let request = OutboundRequest {
+                    id,
+                    service_method,
+                    deadline,
+                    body,
+                    metadata,
                 };
+
+                // The request of a `CallSink` is sent right away, and its items wait instead
+                if request.body.is_some() && self.queues.broker.is_full() {
+                    let held = HeldRequest::new(request, resp_tx, self.ids.clone(), self.queues.broker.clone());
+                    return Call::<Res>::held(self.broker.clone(), resp_rx, held)
+                }
+
+                self.queues.broker.push();
+                if let Err(err) = self.broker.send(ClientBrokerItem::Request{ request, resp_tx }) {
+                    log::error!("{}", err);
+                    // If Broker is dropped, then the connection is dropped as well
+                    let err = Error::IoError(
+                        std::io::Error::new(
+                            std::io::ErrorKind::NotConnected,
+                            "Cannot connect to client side broker"
+                        )
+                    );
+                    self.ids.release(id);
+                    return Call::<Res>::with_error(id, self.broker.clone(), resp_rx, err)
+                }
+
+                // Creates Call
+                Call::<Res>::new(id, self.broker.clone(), resp_rx)
+            }
+
+            /// Invokes the named server-streaming RPC function asynchronously and returns a `CallStream`
+            ///
+            /// The `CallStream<Res>` type takes one type argument `Res` which is the `Ok` type of the items
+            /// yielded by the RPC method. The items can be obtained by polling the `CallStream`, which
+            /// implements `futures::Stream<Item = Result<Res, toy_rpc::Error>>`. The timeout applies to the
+            /// whole stream. `CallStream` can be cancelled by calling the `cancel()` function.
+            /// The request will be sent in a background task.
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// use futures::StreamExt;
+            ///
+            /// let mut stream: CallStream<u32> = client.call_stream("SomeService.count_to", 10u32);
+            /// while let Some(item) = stream.next().await {
+            ///     println!("{:?}", item);
+            /// }
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn call_stream<Req, Res>(&self, service_method: impl ToString, args: Req) -> CallStream<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
                 let body = Box::new(args) as Box<OutboundBody>;
-                let (resp_tx, resp_rx) = oneshot::channel();
+                self.send_stream_request(service_method.to_string(), Some(body))
+            }
 
-                if let Err(err) = self.broker.send(
-                    ClientBrokerItem::Request{
+            /// Invokes the named bidirectional streaming RPC function asynchronously and returns a
+            /// `CallSink` and a `CallStream`
+            ///
+            /// The request items are sent through the `CallSink<Req>` and the response items are
+            /// yielded by the `CallStream<Res>`. Both share the same message id. The request stream is
+            /// ended by closing or dropping the `CallSink`. Cancelling the `CallStream` also cancels
+            /// the request stream.
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// use futures::{SinkExt, StreamExt};
+            ///
+            /// let (mut sink, mut stream): (CallSink<String>, CallStream<String>) =
+            ///     client.call_duplex("SomeService.chat");
+            /// sink.send("hello".to_string()).await?;
+            /// let reply = stream.next().await;
+            /// sink.close().await?;
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub fn call_duplex<Req, Res>(&self, service_method: impl ToString) -> (CallSink<Req>, CallStream<Res>)
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let stream = self.send_stream_request(service_method.to_string(), None);
+                let sink = CallSink::new(stream.id(), self.broker.clone(), self.queues.broker.clone());
+                (sink, stream)
+            }
+
+            /// Sends an extension message with `Header::Ext` and waits for the reply
+            ///
+            /// The message is handed to the extension handler that the server registered for
+            /// the `marker` with `ServerBuilder::register_extension`. The body is serialized with
+            /// the codec of the connection. If no handler is registered for the marker, this
+            /// returns `Err(Error::UnknownExtension(marker))`.
+            ///
+            /// Example
+            ///
+            /// ```rust
+            /// let reply = client.extension(1, "echo", "hello".to_string()).await?;
+            /// let body: String = reply.body()?;
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub async fn extension<T>(
+                &self,
+                marker: Marker,
+                content: impl Into<String>,
+                body: T,
+            ) -> Result<ExtensionMessage, Error>
+            where
+                T: serde::Serialize + Send + Sync + 'static,
+            {
+                if marker >= RESERVED_MARKER_START {
+                    return Err(Error::UnknownExtension(marker))
+                }
+
+                // waits for the broker to catch up
+                if !self.queues.broker.wait_for_space().await {
+                    return Err(Error::IoError(
+                        std::io::Error::new(
+                            std::io::ErrorKind::NotConnected,
+                            "Cannot connect to client side broker"
+                        )
+                    ))
+                }
+
+                let id = self.ids.acquire();
+                let (resp_tx, resp_rx) = oneshot::channel();
+                if let Err(err) = self.broker.send_async(
+                    ClientBrokerItem::Extension {
                         id,
-                        service_method,
-                        duration,
-                        body,
+                        marker,
+                        content: content.into(),
+                        body: Box::new(body),
                         resp_tx,
                     }
+                ).await {
+                    log::error!("{}", err);
+                    self.ids.release(id);
+                    return Err(Error::IoError(
+                        std::io::Error::new(
+                            std::io::ErrorKind::NotConnected,
+                            "Cannot connect to client side broker"
+                        )
+                    ))
+                }
+
+                resp_rx.await.map_err(|_| Error::Canceled(id))?
+            }
+
+            fn send_stream_request<Res>(&self, service_method: String, body: Option<Box<OutboundBody>>) -> CallStream<Res>
+            where
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                // Prepare RPC request
+                let id = self.ids.acquire();
+                let deadline = self.next_deadline();
+                let metadata = self.take_next_metadata();
+                let (item_tx, item_rx) = crate::queue::stream_channel(self.queues.broker.capacity(), self.queues.policy);
+
+                if let Err(err) = self.broker.send(
+                    ClientBrokerItem::StreamRequest{
+                        request: OutboundRequest {
+                            id,
+                            service_method,
+                            deadline,
+                            body,
+                            metadata,
+                        },
+                        item_tx,
+                    }
                 ) {
                     log::error!("{}", err);
                     // If Broker is dropped, then the connection is dropped as well
@@ -520,11 +932,12 @@ cfg_if! {
                             "Cannot connect to client side broker"
                         )
                     );
-                    return Call::<Res>::with_error(id, self.broker.clone(), resp_rx, err)
+                    self.ids.release(id);
+                    return CallStream::<Res>::with_error(id, self.broker.clone(), item_rx, err)
                 }
 
-                // Creates Call
-                Call::<Res>::new(id, self.broker.clone(), resp_rx)
+                // Creates CallStream
+                CallStream::<Res>::new(id, self.broker.clone(), item_rx)
             }
         }
     }
@@ -1,7 +1,7 @@
 //! PubSub impl on the client side
 
-use flume::r#async::{RecvStream, SendSink};
-use flume::{Receiver, Sender};
+use flume::r#async::SendSink;
+use flume::Sender;
 use futures::{Sink, Stream};
 use pin_project::pin_project;
 use std::any::TypeId;
@@ -12,6 +12,7 @@ use std::task::{Context, Poll};
 
 use super::{broker::ClientBrokerItem, Client};
 use crate::pubsub::{AckModeAuto, AckModeManual, AckModeNone, SeqId};
+use crate::queue::{self, QueueMetrics, QueueReceiver};
 use crate::{
     error::Error,
     protocol::{InboundBody, OutboundBody},
@@ -26,7 +27,7 @@ pub struct Delivery<Item> {
 }
 
 impl<Item> Delivery<Item> {
-    fn new(seq_id: SeqId, sender: Sender<ClientBrokerItem>, item: Item) -> Self {
+    pub(crate) fn new(seq_id: SeqId, sender: Sender<ClientBrokerItem>, item: Item) -> Self {
         Self {
             seq_id,
             ack_sender: sender,
@@ -102,21 +103,26 @@ impl SubscriptionItem {
 #[pin_project]
 pub struct Subscriber<T: Topic, AckMode> {
     #[pin]
-    inner: RecvStream<'static, SubscriptionItem>,
+    inner: QueueReceiver<SubscriptionItem>,
     broker: Sender<ClientBrokerItem>,
     marker: PhantomData<T>,
     ack_mode: PhantomData<AckMode>,
 }
 
 impl<T: Topic, AckMode> Subscriber<T, AckMode> {
-    fn new(broker: Sender<ClientBrokerItem>, rx: Receiver<SubscriptionItem>) -> Self {
+    fn new(broker: Sender<ClientBrokerItem>, rx: QueueReceiver<SubscriptionItem>) -> Self {
         Self {
-            inner: rx.into_stream(),
+            inner: rx,
             broker,
             marker: PhantomData,
             ack_mode: PhantomData,
         }
     }
+
+    /// Returns the metrics of the queue of the subscriber
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.inner.metrics()
+    }
 }
 
 impl<T: Topic> Stream for Subscriber<T, AckModeNone> {
@@ -208,11 +214,8 @@ impl<AckMode> Client<AckMode> {
     fn create_subscriber_rx<T: Topic + 'static>(
         &mut self,
         cap: Option<NonZeroUsize>,
-    ) -> Result<Receiver<SubscriptionItem>, Error> {
-        let (tx, rx) = match cap {
-            Some(n) => flume::bounded(n.get()),
-            None => flume::unbounded(),
-        };
+    ) -> Result<QueueReceiver<SubscriptionItem>, Error> {
+        let (tx, rx) = queue::channel(cap.map(NonZeroUsize::get), self.queues.policy);
         let topic = T::topic();
 
         // Check if there is an existing subscriber
@@ -237,15 +240,12 @@ impl<AckMode> Client<AckMode> {
     fn replace_local_subscriber_rx<T: Topic + 'static>(
         &mut self,
         cap: Option<NonZeroUsize>,
-    ) -> Result<Receiver<SubscriptionItem>, Error> {
+    ) -> Result<QueueReceiver<SubscriptionItem>, Error> {
         let topic = T::topic();
         match self.subscriptions.get(&topic) {
             Some(entry) => match &TypeId::of::<T>() == entry {
                 true => {
-                    let (tx, rx) = match cap {
-                        Some(n) => flume::bounded(n.get()),
-                        None => flume::unbounded(),
-                    };
+                    let (tx, rx) = queue::channel(cap.map(NonZeroUsize::get), self.queues.policy);
                     if let Err(err) = self.broker.send(ClientBrokerItem::NewLocalSubscriber {
                         topic,
                         new_item_sink: tx,
@@ -0,0 +1,159 @@
+//! Work queue impl on the client side
+
+use flume::r#async::SendSink;
+use flume::Sender;
+use futures::channel::oneshot;
+use futures::{Future, Sink, Stream};
+use pin_project::pin_project;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::pubsub::{Delivery, SubscriptionItem};
+use super::{broker::ClientBrokerItem, Client};
+use crate::pubsub::DEFAULT_TICKETS;
+use crate::{error::Error, protocol::OutboundBody, pubsub::Topic};
+
+/// Producer of a work queue on the client side
+///
+/// Each message is delivered to at most `tickets` consumers
+#[pin_project]
+pub struct Producer<T: Topic> {
+    #[pin]
+    inner: SendSink<'static, ClientBrokerItem>,
+    tickets: u32,
+    marker: PhantomData<T>,
+}
+
+impl<T: Topic> From<Sender<ClientBrokerItem>> for Producer<T> {
+    fn from(inner: Sender<ClientBrokerItem>) -> Self {
+        Self {
+            inner: inner.into_sink(),
+            tickets: DEFAULT_TICKETS,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Topic> Producer<T> {
+    /// Sets the number of consumers each produced message is delivered to
+    pub fn with_tickets(mut self, tickets: u32) -> Self {
+        self.tickets = tickets;
+        self
+    }
+}
+
+impl<T: Topic> Sink<T::Item> for Producer<T> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_ready(cx).map_err(|err| err.into())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T::Item) -> Result<(), Self::Error> {
+        let this = self.project();
+        let topic = T::topic();
+        let body = Box::new(item) as Box<OutboundBody>;
+        let item = ClientBrokerItem::Produce {
+            topic,
+            tickets: *this.tickets,
+            body,
+        };
+        this.inner.start_send(item).map_err(|err| err.into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_flush(cx).map_err(|err| err.into())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_close(cx).map_err(|err| err.into())
+    }
+}
+
+/// Consumer of a work queue on the client side
+///
+/// The consumer pulls one message at a time from the server. Each message must be
+/// acked with `Delivery::ack`, otherwise it is delivered again after the visibility
+/// timeout of the server.
+#[pin_project]
+pub struct Consumer<T: Topic> {
+    broker: Sender<ClientBrokerItem>,
+    pull: Option<oneshot::Receiver<SubscriptionItem>>,
+    marker: PhantomData<T>,
+}
+
+impl<T: Topic> From<Sender<ClientBrokerItem>> for Consumer<T> {
+    fn from(broker: Sender<ClientBrokerItem>) -> Self {
+        Self {
+            broker,
+            pull: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Topic> Stream for Consumer<T> {
+    type Item = Result<Delivery<T::Item>, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if this.pull.is_none() {
+            let (item_sink, rx) = oneshot::channel();
+            let topic = T::topic();
+            if let Err(err) = this
+                .broker
+                .send(ClientBrokerItem::Consume { topic, item_sink })
+            {
+                return Poll::Ready(Some(Err(err.into())));
+            }
+            *this.pull = Some(rx);
+        }
+
+        let pull = match this.pull.as_mut() {
+            Some(pull) => pull,
+            None => return Poll::Pending,
+        };
+        match Pin::new(pull).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(res) => {
+                *this.pull = None;
+                match res {
+                    Ok(mut item) => {
+                        let sender = this.broker.clone();
+                        let result =
+                            erased_serde::deserialize(&mut item.body).map_err(|err| err.into());
+                        let result =
+                            result.map(|content| Delivery::new(item.seq_id, sender, content));
+                        Poll::Ready(Some(result))
+                    }
+                    // The connection is closed
+                    Err(_) => Poll::Ready(None),
+                }
+            }
+        }
+    }
+}
+
+impl<AckMode> Client<AckMode> {
+    /// Creates a new producer on a work queue.
+    ///
+    /// Multiple local producers on the same topic are allowed.
+    pub fn producer<T: Topic>(&self) -> Producer<T> {
+        let tx = self.broker.clone();
+        Producer::from(tx)
+    }
+
+    /// Creates a new consumer on a work queue.
+    ///
+    /// Multiple local consumers on the same topic are allowed, and they share
+    /// the messages. The client counts as one consumer on the server, so at most
+    /// one ticket of each message is delivered to the client.
+    pub fn consumer<T: Topic>(&self) -> Consumer<T> {
+        let tx = self.broker.clone();
+        Consumer::from(tx)
+    }
+}
@@ -2,16 +2,24 @@ use async_trait::async_trait;
 use brw::Running;
 use futures::Sink;
 use futures::SinkExt;
+use std::sync::Arc;
 
 use super::broker::ClientBrokerItem;
 use crate::error::CodecError;
 use crate::error::IoError;
-use crate::protocol::{Header, InboundBody};
+use crate::execution::{deadline, deserialize_error, handle_cancel, service, InboundCall};
+use crate::protocol::{BatchReply, Header, InboundBody, MetadataMap};
 use crate::pubsub::SeqId;
+use crate::queue::{GaugedSink, QueueGauge};
+use crate::service::{AsyncServiceMap, RequestBody};
+use crate::transport::is_message_too_large;
 use crate::{codec::CodecRead, Error};
 
 pub(crate) struct ClientReader<R> {
     pub reader: R,
+    /// Services that the server can call
+    pub services: Arc<AsyncServiceMap>,
+    pub broker_queue: Arc<QueueGauge>,
 }
 
 #[async_trait]
@@ -22,17 +30,24 @@ impl<R: CodecRead> brw::Reader for ClientReader<R> {
 
     async fn op<B>(
         &mut self,
-        mut broker: B,
+        broker: B,
     ) -> Running<Result<Self::Ok, Self::Error>, Option<Self::Error>>
     where
         B: Sink<Self::BrokerItem, Error = flume::SendError<Self::BrokerItem>> + Send + Unpin,
     {
+        // stops reading from the connection until the broker catches up
+        if !self.broker_queue.wait_for_space().await {
+            return Running::Stop(None);
+        }
+        let mut broker = GaugedSink::new(broker, self.broker_queue.clone());
+
         if let Some(header) = self.reader.read_header().await {
             let header: Header = match header {
                 Ok(header) => header,
                 Err(err) => {
                     match err {
-                        CodecError::IoError(e) => {
+                        // an oversized header is skipped unless the connection is closed
+                        CodecError::IoError(e) if !is_message_too_large(&e) => {
                             // pass back IoError
                             match broker.send(ClientBrokerItem::Stop(Some(e))).await {
                                 Ok(_) => return Running::Stop(None),
@@ -46,12 +61,27 @@ impl<R: CodecRead> brw::Reader for ClientReader<R> {
             log::debug!("{:?}", &header);
 
             match header {
-                Header::Response { id, is_ok } => {
+                Header::Response {
+                    id,
+                    status,
+                    metadata,
+                } => {
                     // Ack will not come with a body
                     let deserializer: Box<InboundBody> = match self.reader.read_body().await {
                         Some(res) => match res {
                             Ok(de) => de,
-                            Err(err) => return Running::Continue(Err(err.into())),
+                            Err(err) => {
+                                // the call fails instead of waiting for a body that is
+                                // skipped, ie. because it exceeds the max message size
+                                let item = ClientBrokerItem::Response {
+                                    id,
+                                    result: Err(err.into()),
+                                    metadata,
+                                };
+                                return Running::Continue(
+                                    broker.send(item).await.map_err(|err| err.into()),
+                                );
+                            }
                         },
                         None => {
                             let err = IoError::new(
@@ -64,16 +94,187 @@ impl<R: CodecRead> brw::Reader for ClientReader<R> {
                             }
                         }
                     };
-                    let result = match is_ok {
+                    let result = match status.is_ok() {
                         true => Ok(deserializer),
-                        false => Err(deserializer),
+                        false => Err(deserialize_error::<R>(id, deserializer)),
                     };
 
-                    if let Err(err) = broker.send(ClientBrokerItem::Response { id, result }).await {
+                    let item = ClientBrokerItem::Response {
+                        id,
+                        result,
+                        metadata,
+                    };
+                    if let Err(err) = broker.send(item).await {
                         return Running::Continue(Err(err.into()));
                     }
                     Running::Continue(Ok(()))
                 }
+                Header::BatchResponse(batch_id) => {
+                    let replies = match self.reader.read_body().await {
+                        Some(res) => res.map_err(Into::into).and_then(|mut de| {
+                            erased_serde::deserialize::<Vec<BatchReply>>(&mut de)
+                                .map_err(|err| Error::ParseError(Box::new(err)))
+                        }),
+                        None => {
+                            let err = IoError::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "Expecting batch response body",
+                            );
+                            match broker.send(ClientBrokerItem::Stop(Some(err))).await {
+                                Ok(_) => return Running::Stop(None),
+                                Err(e) => return Running::Stop(Some(e.into())),
+                            }
+                        }
+                    };
+                    let replies = match replies {
+                        Ok(replies) => replies,
+                        Err(err) => {
+                            // the calls of the batch fail instead of waiting for their results
+                            let item = ClientBrokerItem::Response {
+                                id: batch_id,
+                                result: Err(err),
+                                metadata: MetadataMap::new(),
+                            };
+                            return Running::Continue(
+                                broker.send(item).await.map_err(|err| err.into()),
+                            );
+                        }
+                    };
+                    // Each result is handled as if it were a response of its own
+                    for reply in replies {
+                        let BatchReply {
+                            id,
+                            status,
+                            metadata,
+                            body,
+                        } = reply;
+                        let deserializer = R::from_bytes(body);
+                        let result = match status.is_ok() {
+                            true => Ok(deserializer),
+                            false => Err(deserialize_error::<R>(id, deserializer)),
+                        };
+                        let item = ClientBrokerItem::Response {
+                            id,
+                            result,
+                            metadata,
+                        };
+                        if let Err(err) = broker.send(item).await {
+                            return Running::Continue(Err(err.into()));
+                        }
+                    }
+                    let item = ClientBrokerItem::BatchEnd(batch_id);
+                    Running::Continue(broker.send(item).await.map_err(|err| err.into()))
+                }
+                Header::Request {
+                    id,
+                    service_method,
+                    timeout,
+                    metadata,
+                } => {
+                    // Reverse call made by the server
+                    let deserializer: Box<InboundBody> = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => {
+                            let err = IoError::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "Expecting request body",
+                            );
+                            match broker.send(ClientBrokerItem::Stop(Some(err))).await {
+                                Ok(_) => return Running::Stop(None),
+                                Err(e) => return Running::Stop(Some(e.into())),
+                            }
+                        }
+                    };
+                    let item = match service(&self.services, service_method) {
+                        Ok((call, method)) => ClientBrokerItem::InboundRequest {
+                            call: InboundCall {
+                                call,
+                                id,
+                                method,
+                                deadline: deadline(timeout),
+                                metadata,
+                            },
+                            request: RequestBody::Unary(deserializer),
+                        },
+                        Err(err) => {
+                            log::error!("{}", &err);
+                            ClientBrokerItem::OutboundResponse {
+                                id,
+                                result: Err(err),
+                                metadata: MetadataMap::new(),
+                            }
+                        }
+                    };
+                    Running::Continue(broker.send(item).await.map_err(|err| err.into()))
+                }
+                Header::Cancel(id) => {
+                    let deserializer: Box<InboundBody> = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => {
+                            let err = IoError::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "Expecting cancel body",
+                            );
+                            match broker.send(ClientBrokerItem::Stop(Some(err))).await {
+                                Ok(_) => return Running::Stop(None),
+                                Err(e) => return Running::Stop(Some(e.into())),
+                            }
+                        }
+                    };
+                    match handle_cancel(id, deserializer) {
+                        Ok(_) => Running::Continue(
+                            broker
+                                .send(ClientBrokerItem::InboundCancel(id))
+                                .await
+                                .map_err(|err| err.into()),
+                        ),
+                        Err(err) => Running::Continue(Err(err)),
+                    }
+                }
+                Header::StreamItem { id, is_ok } => {
+                    let deserializer: Box<InboundBody> = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => {
+                            let err = IoError::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "Expecting stream item body",
+                            );
+                            match broker.send(ClientBrokerItem::Stop(Some(err))).await {
+                                Ok(_) => return Running::Stop(None),
+                                Err(e) => return Running::Stop(Some(e.into())),
+                            }
+                        }
+                    };
+                    let result = match is_ok {
+                        true => Ok(deserializer),
+                        false => Err(deserialize_error::<R>(id, deserializer)),
+                    };
+
+                    Running::Continue(
+                        broker
+                            .send(ClientBrokerItem::InboundStreamItem { id, result })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
+                Header::StreamEnd(id) => {
+                    // There is no body frame for end of stream message
+                    Running::Continue(
+                        broker
+                            .send(ClientBrokerItem::InboundStreamEnd(id))
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
                 Header::Publish { id, topic } => {
                     let deserializer: Box<InboundBody> = match self.reader.read_body().await {
                         Some(res) => match res {
@@ -102,6 +303,67 @@ impl<R: CodecRead> brw::Reader for ClientReader<R> {
                             .map_err(|err| err.into()),
                     )
                 }
+                Header::Consume { id, topic } => {
+                    let deserializer: Box<InboundBody> = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => {
+                            let err = IoError::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "Expecting Consume body",
+                            );
+                            match broker.send(ClientBrokerItem::Stop(Some(err))).await {
+                                Ok(_) => return Running::Stop(None),
+                                Err(e) => return Running::Stop(Some(e.into())),
+                            }
+                        }
+                    };
+                    Running::Continue(
+                        broker
+                            .send(ClientBrokerItem::Delivery {
+                                id: SeqId::new(id),
+                                topic,
+                                item: deserializer,
+                            })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
+                Header::Ext {
+                    id,
+                    content,
+                    marker,
+                } => {
+                    let body: Box<InboundBody> = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => {
+                            let err = IoError::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "Expecting Ext body",
+                            );
+                            match broker.send(ClientBrokerItem::Stop(Some(err))).await {
+                                Ok(_) => return Running::Stop(None),
+                                Err(e) => return Running::Stop(Some(e.into())),
+                            }
+                        }
+                    };
+                    Running::Continue(
+                        broker
+                            .send(ClientBrokerItem::InboundExtension {
+                                id,
+                                marker,
+                                content,
+                                body,
+                            })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
                 Header::Ack(id) => {
                     let seq_id = SeqId::new(id);
                     Running::Continue(
@@ -111,6 +373,18 @@ impl<R: CodecRead> brw::Reader for ClientReader<R> {
                             .map_err(|err| err.into()),
                     )
                 }
+                Header::Ping(id) => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::InboundPing(id))
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                Header::Pong(id) => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::InboundPong(id))
+                        .await
+                        .map_err(|err| err.into()),
+                ),
                 _ => Running::Continue(Err(Error::Internal("Unexpected Header type".into()))),
             }
         } else {
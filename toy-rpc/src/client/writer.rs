@@ -10,37 +10,63 @@ cfg_if! {
         all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
         all(feature = "tokio_runtime", not(feature = "async_std_runtime"))
     ))] {
-        use std::time::Duration;
+        use std::time::{Duration, Instant};
         use async_trait::async_trait;
         use brw::Running;
 
         use crate::{
             Error, codec::CodecWrite,
+            extension::ExtensionFrame,
             message::{
                 Metadata, CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM, MessageId
             },
+            message::ErrorMessage,
             protocol::{
-                Header, OutboundBody
+                BatchRequest, Header, MetadataMap, OutboundBody
             },
+            queue::QueueGauge,
+            service::HandlerResult,
+            status::Status,
             util:: GracefulShutdown
         };
 
         pub enum ClientWriterItem {
-            Request(MessageId, String, Duration, Box<OutboundBody>),
+            Request(MessageId, String, Option<Instant>, MetadataMap, Box<OutboundBody>),
+            // Opens a client-streaming call, the items are sent with `StreamItem`
+            OpenStream(MessageId, String, Option<Instant>, MetadataMap),
+            // One-way request that expects no response
+            Notify(MessageId, String, MetadataMap, Box<OutboundBody>),
+            // Batch of requests sent in a single message
+            Batch(MessageId, Option<Instant>, MetadataMap, Vec<(MessageId, String, Box<OutboundBody>)>),
+            StreamItem(MessageId, Box<OutboundBody>),
+            StreamEnd(MessageId),
             Publish(MessageId, String, Arc<Vec<u8>>),
             Subscribe(MessageId, String),
             Unsubscribe(MessageId, String),
+            Produce(MessageId, String, u32, Arc<Vec<u8>>),
+            Consume(MessageId, String),
+            Extension(ExtensionFrame),
 
             // Client will respond to Publish message sent from the server
             // Thus needs to reply with the seq_id
             Ack(SeqId),
             Cancel(MessageId),
+            // Result of a reverse call made by the server
+            Response(MessageId, HandlerResult, MetadataMap),
+            Ping(MessageId),
+            Pong(MessageId),
             Stopping,
             Stop,
         }
 
         pub struct ClientWriter<W> {
-            pub writer: W
+            pub writer: W,
+            pub queue: Arc<QueueGauge>,
+        }
+
+        /// Time left until the deadline, which is sent to the server as the timeout
+        fn remaining(deadline: Option<Instant>) -> Option<Duration> {
+            deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
         }
 
         impl<W: CodecWrite> ClientWriter<W> {
@@ -55,6 +81,22 @@ cfg_if! {
                 Ok(())
             }
 
+            /// Writes a batch of requests, whose arguments are serialized with the codec
+            pub async fn write_batch(
+                &mut self,
+                header: Header,
+                calls: Vec<(MessageId, String, Box<OutboundBody>)>,
+            ) -> Result<(), Error> {
+                let requests = calls
+                    .into_iter()
+                    .map(|(id, service_method, body)| {
+                        let args = W::marshal(&body)?;
+                        Ok(BatchRequest { id, service_method, args })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                self.write_request(header, &requests).await
+            }
+
             pub async fn write_publish_item(
                 &mut self,
                 header: Header,
@@ -75,11 +117,40 @@ cfg_if! {
 
             async fn op(&mut self, item: Self::Item) -> Running<Result<Self::Ok, Self::Error>, Option<Self::Error>> {
                 let res = match item {
-                    ClientWriterItem::Request(id, service_method, duration, body) => {
-                        let header = Header::Request{id, service_method, timeout: duration};
+                    ClientWriterItem::Request(id, service_method, deadline, metadata, body) => {
+                        let header = Header::Request{id, service_method, timeout: remaining(deadline), metadata};
                         log::debug!("{:?}", &header);
                         self.write_request(header, &body).await
                     },
+                    ClientWriterItem::OpenStream(id, service_method, deadline, metadata) => {
+                        let header = Header::OpenStream{id, service_method, timeout: remaining(deadline), metadata};
+                        log::debug!("{:?}", &header);
+                        // There is no body frame for OpenStream message
+                        self.writer.write_header(header).await
+                            .map_err(Into::into)
+                    },
+                    ClientWriterItem::Notify(id, service_method, metadata, body) => {
+                        let header = Header::Notify{id, service_method, metadata};
+                        log::debug!("{:?}", &header);
+                        self.write_request(header, &body).await
+                    },
+                    ClientWriterItem::Batch(id, deadline, metadata, calls) => {
+                        let header = Header::Batch{id, timeout: remaining(deadline), metadata};
+                        log::debug!("{:?}", &header);
+                        self.write_batch(header, calls).await
+                    },
+                    ClientWriterItem::StreamItem(id, body) => {
+                        let header = Header::StreamItem{id, is_ok: true};
+                        log::debug!("{:?}", &header);
+                        self.write_request(header, &body).await
+                    },
+                    ClientWriterItem::StreamEnd(id) => {
+                        let header = Header::StreamEnd(id);
+                        log::debug!("{:?}", &header);
+                        // There is no body frame for StreamEnd message
+                        self.writer.write_header(header).await
+                            .map_err(Into::into)
+                    },
                     ClientWriterItem::Cancel(id) => {
                         let header = Header::Cancel(id);
                         log::debug!("{:?}", &header);
@@ -103,6 +174,22 @@ cfg_if! {
                         log::debug!("{:?}", &header);
                         self.write_request(header, &()).await
                     },
+                    ClientWriterItem::Produce(id, topic, tickets, body) => {
+                        let header = Header::Produce{id, topic, tickets};
+                        log::debug!("{:?}", &header);
+                        self.write_publish_item(header, &body).await
+                    },
+                    ClientWriterItem::Consume(id, topic) => {
+                        let header = Header::Consume{id, topic};
+                        log::debug!("{:?}", &header);
+                        self.write_request(header, &()).await
+                    },
+                    ClientWriterItem::Extension(frame) => {
+                        let ExtensionFrame { id, marker, content, body } = frame;
+                        let header = Header::Ext{id, content, marker};
+                        log::debug!("{:?}", &header);
+                        self.write_request(header, &body).await
+                    },
                     ClientWriterItem::Ack(seq_id) => {
                         let header = Header::Ack(seq_id.0);
                         log::debug!("{:?}", &header);
@@ -110,6 +197,43 @@ cfg_if! {
                         self.writer.write_header(header).await
                             .map_err(Into::into)
                     },
+                    ClientWriterItem::Response(id, result, metadata) => {
+                        match result {
+                            Ok(body) => {
+                                let header = Header::Response{id, status: Status::ok(), metadata};
+                                log::debug!("{:?}", &header);
+                                self.write_request(header, &body).await
+                            },
+                            Err(err) => {
+                                let status = err.status();
+                                match ErrorMessage::from_err::<W>(err) {
+                                    Ok(msg) => {
+                                        let header = Header::Response{id, status, metadata};
+                                        log::debug!("{:?}", &header);
+                                        self.write_request(header, &msg).await
+                                    },
+                                    Err(err) => {
+                                        log::debug!("Non-sendable error: {}", err);
+                                        Ok(())
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    ClientWriterItem::Ping(id) => {
+                        let header = Header::Ping(id);
+                        log::debug!("{:?}", &header);
+                        // There is no body frame for Ping message
+                        self.writer.write_header(header).await
+                            .map_err(Into::into)
+                    },
+                    ClientWriterItem::Pong(id) => {
+                        let header = Header::Pong(id);
+                        log::debug!("{:?}", &header);
+                        // There is no body frame for Pong message
+                        self.writer.write_header(header).await
+                            .map_err(Into::into)
+                    },
                     ClientWriterItem::Stopping => {
                         Ok(self.writer.close().await)
                     },
@@ -117,8 +241,11 @@ cfg_if! {
                         return Running::Stop(None)
                     }
                 };
+                // a header without a body is held back until the message ends
+                let ended = self.writer.end_message().await.map_err(Into::into);
+                self.queue.pop();
 
-                Running::Continue(res)
+                Running::Continue(res.and(ended))
             }
 
             async fn handle_result(res: Result<Self::Ok, Self::Error>) -> Running<(), Option<Self::Error>> {
@@ -1,70 +1,62 @@
 //! Impplementation of `CodecRead`, `CodecWrite`, `Marshal`, `Unmarshal` and `EraseDeserializer` traits with `bincode`
 
-use cfg_if::cfg_if;
+use bincode::{DefaultOptions, Options};
+use erased_serde as erased;
+use serde::de::Visitor;
+use std::io::Cursor; // serde doesn't support AsyncRead
 
+use super::{DeserializerOwned, EraseDeserializer, Marshal, Unmarshal};
 use crate::error::ParseError;
+use crate::macros::impl_inner_deserializer;
 
-cfg_if! {
-    if #[cfg(feature = "serde_json")] {
+/// The `bincode` format, which is sent over the frame transport
+pub struct Bincode {}
 
-    } else if #[cfg(feature = "serde_cbor")] {
+impl<'de, R, O> serde::Deserializer<'de> for DeserializerOwned<bincode::Deserializer<R, O>>
+where
+    R: bincode::BincodeRead<'de>,
+    O: bincode::Options,
+{
+    type Error = <&'de mut bincode::Deserializer<R, O> as serde::Deserializer<'de>>::Error;
 
-    } else if #[cfg(feature = "serde_rmp")] {
-
-    } else {
-        use bincode::{DefaultOptions, Options};
-        use erased_serde as erased;
-        use serde::de::Visitor;
-        use std::io::Cursor; // serde doesn't support AsyncRead
-
-        use super::{Codec, DeserializerOwned, Marshal, Unmarshal};
-        use crate::macros::impl_inner_deserializer;
-
-        use super::EraseDeserializer;
-
-        impl<'de, R, O> serde::Deserializer<'de> for DeserializerOwned<bincode::Deserializer<R, O>>
-        where
-            R: bincode::BincodeRead<'de>,
-            O: bincode::Options,
-        {
-            type Error = <&'de mut bincode::Deserializer<R, O> as serde::Deserializer<'de>>::Error;
-
-            // use a macro to generate the code
-            impl_inner_deserializer!();
-        }
+    // use a macro to generate the code
+    impl_inner_deserializer!();
+}
 
-        impl<R, W, C> Marshal for Codec<R, W, C> {
-            fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
-                DefaultOptions::new()
-                    // .with_fixint_encoding()
-                    .with_varint_encoding() // FIXME: varint has problem with i16
-                    .serialize(&val)
-                    .map_err(|err| err.into())
-            }
-        }
+impl Marshal for Bincode {
+    fn codec_name() -> &'static str {
+        "bincode"
+    }
 
-        impl<R, W, C> Unmarshal for Codec<R, W, C> {
-            fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
-                DefaultOptions::new()
-                    // .with_fixint_encoding()
-                    .with_varint_encoding() // FIXME: varint has problem with i16
-                    .deserialize(buf)
-                    .map_err(|err| err.into())
-            }
-        }
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        DefaultOptions::new()
+            // .with_fixint_encoding()
+            .with_varint_encoding() // FIXME: varint has problem with i16
+            .serialize(&val)
+            .map_err(|err| err.into())
+    }
+}
 
-        impl<R, W, C> EraseDeserializer for Codec<R, W, C> {
-            fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
-                let de = bincode::Deserializer::with_reader(
-                    Cursor::new(buf),
-                    bincode::DefaultOptions::new()
-                        // .with_fixint_encoding()
-                        .with_varint_encoding() // FIXME: varint has problem with i16
-                );
+impl Unmarshal for Bincode {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        DefaultOptions::new()
+            // .with_fixint_encoding()
+            .with_varint_encoding() // FIXME: varint has problem with i16
+            .deserialize(buf)
+            .map_err(|err| err.into())
+    }
+}
 
-                let de_owned = DeserializerOwned::new(de);
-                Box::new(<dyn erased::Deserializer>::erase(de_owned))
-            }
-        }
+impl EraseDeserializer for Bincode {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        let de = bincode::Deserializer::with_reader(
+            Cursor::new(buf),
+            bincode::DefaultOptions::new()
+                // .with_fixint_encoding()
+                .with_varint_encoding(), // FIXME: varint has problem with i16
+        );
+
+        let de_owned = DeserializerOwned::new(de);
+        Box::new(<dyn erased::Deserializer>::erase(de_owned))
     }
 }
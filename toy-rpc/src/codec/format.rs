@@ -0,0 +1,213 @@
+//! Serialization formats that can be selected at runtime
+
+use cfg_if::cfg_if;
+
+/// Serialization format of a connection
+///
+/// The formats are enabled with the `serde_bincode`, `serde_json`, `serde_cbor`,
+/// `serde_rmp` and `serde_gob` feature flags, and any number of them can be enabled at the same time. Both
+/// ends of a connection must use the same format, which is checked in the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// `bincode`, which is provided by `codec::bincode::Bincode`
+    #[cfg(feature = "serde_bincode")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_bincode")))]
+    Bincode,
+    /// `serde_json`, which is provided by `codec::json::Json`. Messages are line-delimited
+    /// on the raw TCP transport
+    #[cfg(feature = "serde_json")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_json")))]
+    Json,
+    /// `serde_cbor`, which is provided by `codec::cbor::Cbor`
+    #[cfg(feature = "serde_cbor")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_cbor")))]
+    Cbor,
+    /// `rmp-serde`, which is provided by `codec::rmp::Rmp`
+    #[cfg(feature = "serde_rmp")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_rmp")))]
+    Rmp,
+    /// MessagePack-RPC, which is provided by `codec::msgpack_rpc::MsgpackRpc`. There is no
+    /// handshake, and only unary calls and notifications are supported
+    #[cfg(feature = "serde_rmp")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_rmp")))]
+    MsgpackRpc,
+    /// Go's `net/rpc/jsonrpc` protocol, which is provided by `codec::go_jsonrpc::GoJsonRpc`.
+    /// There is no handshake, and only unary calls and notifications are supported
+    #[cfg(feature = "serde_json")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_json")))]
+    GoJsonRpc,
+    /// JSON-RPC 2.0, which is provided by `codec::jsonrpc::JsonRpc`. There is no handshake,
+    /// and only unary calls and notifications are supported. Batches are answered in one
+    /// array, and the objects are sent as text messages on WebSocket
+    #[cfg(feature = "serde_json")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_json")))]
+    JsonRpc,
+    /// Go's `encoding/gob`, which is provided by `codec::gob::Gob`
+    #[cfg(feature = "serde_gob")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_gob")))]
+    Gob,
+    /// Go's `net/rpc` protocol, which is provided by `codec::go_rpc::GoRpc`. There is no
+    /// handshake, and only unary calls are supported
+    #[cfg(feature = "serde_gob")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "serde_gob")))]
+    GoRpc,
+}
+
+impl Format {
+    /// Formats enabled by the feature flags
+    pub fn available() -> impl Iterator<Item = Format> {
+        [
+            "bincode",
+            "cbor",
+            "rmp",
+            "json",
+            "gob",
+            "jsonrpc",
+            "msgpack-rpc",
+            "go-jsonrpc",
+            "go-rpc",
+        ]
+        .iter()
+        .filter_map(|name| Self::from_name(name))
+    }
+
+    /// Name of the format, which is exchanged with the peer in the handshake
+    pub fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "serde_bincode")]
+            Format::Bincode => "bincode",
+            #[cfg(feature = "serde_json")]
+            Format::Json => "json",
+            #[cfg(feature = "serde_cbor")]
+            Format::Cbor => "cbor",
+            #[cfg(feature = "serde_rmp")]
+            Format::Rmp => "rmp",
+            #[cfg(feature = "serde_rmp")]
+            Format::MsgpackRpc => "msgpack-rpc",
+            #[cfg(feature = "serde_json")]
+            Format::GoJsonRpc => "go-jsonrpc",
+            #[cfg(feature = "serde_json")]
+            Format::JsonRpc => "jsonrpc",
+            #[cfg(feature = "serde_gob")]
+            Format::Gob => "gob",
+            #[cfg(feature = "serde_gob")]
+            Format::GoRpc => "go-rpc",
+        }
+    }
+
+    /// Finds the enabled format with the name, ie. `"json"`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            #[cfg(feature = "serde_bincode")]
+            "bincode" => Some(Format::Bincode),
+            #[cfg(feature = "serde_json")]
+            "json" => Some(Format::Json),
+            #[cfg(feature = "serde_cbor")]
+            "cbor" => Some(Format::Cbor),
+            #[cfg(feature = "serde_rmp")]
+            "rmp" => Some(Format::Rmp),
+            #[cfg(feature = "serde_rmp")]
+            "msgpack-rpc" => Some(Format::MsgpackRpc),
+            #[cfg(feature = "serde_json")]
+            "go-jsonrpc" => Some(Format::GoJsonRpc),
+            #[cfg(feature = "serde_json")]
+            "jsonrpc" => Some(Format::JsonRpc),
+            #[cfg(feature = "serde_gob")]
+            "gob" => Some(Format::Gob),
+            #[cfg(feature = "serde_gob")]
+            "go-rpc" => Some(Format::GoRpc),
+            _ => None,
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_json",
+        feature = "serde_rmp",
+        feature = "serde_gob"
+    ))] {
+        /// The format of `DefaultCodec`, which is the first enabled format in the order of
+        /// `bincode`, `cbor`, `rmp`, `json` and `gob`
+        impl Default for Format {
+            fn default() -> Self {
+                Self::available()
+                    .next()
+                    .expect("At least one format is enabled")
+            }
+        }
+    }
+}
+
+/// Evaluates `$body` with `$f` as an alias of the marker type of `$format`, which turns
+/// the format selected at runtime into the type parameter of `Codec`
+#[cfg(any(feature = "server", feature = "client"))]
+macro_rules! with_format {
+    ($format:expr, $f:ident => $body:expr) => {
+        match $format {
+            #[cfg(feature = "serde_bincode")]
+            $crate::codec::Format::Bincode => {
+                type $f = $crate::codec::bincode::Bincode;
+                $body
+            }
+            #[cfg(feature = "serde_json")]
+            $crate::codec::Format::Json => {
+                type $f = $crate::codec::json::Json;
+                $body
+            }
+            #[cfg(feature = "serde_cbor")]
+            $crate::codec::Format::Cbor => {
+                type $f = $crate::codec::cbor::Cbor;
+                $body
+            }
+            #[cfg(feature = "serde_rmp")]
+            $crate::codec::Format::Rmp => {
+                type $f = $crate::codec::rmp::Rmp;
+                $body
+            }
+            #[cfg(feature = "serde_rmp")]
+            $crate::codec::Format::MsgpackRpc => {
+                type $f = $crate::codec::msgpack_rpc::MsgpackRpc;
+                $body
+            }
+            #[cfg(feature = "serde_json")]
+            $crate::codec::Format::GoJsonRpc => {
+                type $f = $crate::codec::go_jsonrpc::GoJsonRpc;
+                $body
+            }
+            #[cfg(feature = "serde_json")]
+            $crate::codec::Format::JsonRpc => {
+                type $f = $crate::codec::jsonrpc::JsonRpc;
+                $body
+            }
+            #[cfg(feature = "serde_gob")]
+            $crate::codec::Format::Gob => {
+                type $f = $crate::codec::gob::Gob;
+                $body
+            }
+            #[cfg(feature = "serde_gob")]
+            $crate::codec::Format::GoRpc => {
+                type $f = $crate::codec::go_rpc::GoRpc;
+                $body
+            }
+        }
+    };
+}
+
+#[cfg(any(feature = "server", feature = "client"))]
+pub(crate) use with_format;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_names() {
+        for format in Format::available() {
+            assert_eq!(Format::from_name(format.name()), Some(format));
+        }
+        assert_eq!(Format::from_name("custom"), None);
+    }
+}
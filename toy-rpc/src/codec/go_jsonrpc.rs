@@ -0,0 +1,322 @@
+//! Wire compatibility with Go's `net/rpc/jsonrpc`
+//!
+//! Go's `net/rpc/jsonrpc` speaks JSON-RPC 1.0. A request is an object with the `method`,
+//! `params` and `id` members, where `params` is an array holding the argument, and a
+//! response is an object with the `id`, `result` and `error` members, where `error` is
+//! either `null` or a string. The objects are written one after another, and there is no
+//! handshake.
+//!
+//! The reading and writing halves translate these objects from and into the messages of
+//! `toy-rpc`, so that a `Client` can call a Go server and a `Server` can serve Go clients.
+//! The method is named "{Service}.{Method}" on both sides. Only unary calls and
+//! notifications (requests with a `null` id) can be carried, and the other messages, ie.
+//! streaming calls, pub/sub and extensions, fail to be written.
+
+use erased_serde as erased;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+
+use super::json::Json;
+use super::split::SplittableCodec;
+use super::translate::{self, Protocol, Translate, TranslateReadHalf, TranslateWriteHalf};
+use super::{Codec, Marshal, Unmarshal};
+use crate::error::{CodecError, IoError, ParseError};
+use crate::message::{ErrorMessage, MessageId};
+use crate::protocol::{Header, InboundBody, MetadataMap};
+use crate::status::Status;
+
+/// Go's `net/rpc/jsonrpc` protocol. The objects are serialized with `serde_json` and are
+/// line-delimited on the raw TCP transport
+pub struct GoJsonRpc {}
+
+impl Marshal for GoJsonRpc {
+    fn codec_name() -> &'static str {
+        "go-jsonrpc"
+    }
+
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        Json::marshal(val)
+    }
+}
+
+impl Unmarshal for GoJsonRpc {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        Json::unmarshal(buf)
+    }
+}
+
+/// State of a connection, which is shared by the reading and writing halves
+#[derive(Default)]
+pub(crate) struct Exchange {
+    /// Ids of the requests from the peer by the message ids that they are served with. The
+    /// id of a JSON-RPC request can be any JSON value, and it is sent back as is
+    ids: HashMap<MessageId, Value>,
+    next_id: MessageId,
+}
+
+/// Request or response object of JSON-RPC 1.0
+#[derive(Deserialize)]
+struct InboundObject {
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Value,
+}
+
+/// Request object of JSON-RPC 1.0
+#[derive(Serialize)]
+struct RequestObject<'a> {
+    method: &'a str,
+    params: [&'a (dyn erased::Serialize + Send + Sync); 1],
+    id: Option<MessageId>,
+}
+
+/// Response object of JSON-RPC 1.0
+#[derive(Serialize)]
+struct ResponseObject<'a> {
+    id: Value,
+    result: Option<&'a (dyn erased::Serialize + Send + Sync)>,
+    error: Option<String>,
+}
+
+fn parse_error(err: serde_json::Error) -> CodecError {
+    CodecError::ParseError(Box::new(err))
+}
+
+/// Converts the error string of a Go server into the status and the body of a response
+fn go_error(error: Value) -> (Status, ErrorMessage) {
+    let error = match error {
+        Value::String(error) => error,
+        error => format!("invalid error {}", error),
+    };
+    ErrorMessage::from_go_error(error)
+}
+
+impl Protocol for Exchange {
+    type Format = GoJsonRpc;
+
+    const NAME: &'static str = "Go's net/rpc/jsonrpc";
+
+    const FEATURES: &'static [&'static str] = &[];
+
+    fn encode(
+        &mut self,
+        header: Header,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<Option<Vec<u8>>, CodecError> {
+        let buf = match header {
+            Header::Request {
+                id, service_method, ..
+            } => GoJsonRpc::marshal(&RequestObject {
+                method: &service_method,
+                params: [body],
+                id: Some(id),
+            })?,
+            Header::Notify { service_method, .. } => GoJsonRpc::marshal(&RequestObject {
+                method: &service_method,
+                params: [body],
+                id: None,
+            })?,
+            Header::Response { id, status, .. } => {
+                let id = self.ids.remove(&id).unwrap_or_else(|| id.into());
+                let object = match status.is_ok() {
+                    true => ResponseObject {
+                        id,
+                        result: Some(body),
+                        error: None,
+                    },
+                    // Go only carries the message of an error
+                    false => ResponseObject {
+                        id,
+                        result: None,
+                        error: Some(match status.message() {
+                            Some(message) => message.into(),
+                            None => status.code().to_string(),
+                        }),
+                    },
+                };
+                GoJsonRpc::marshal(&object)?
+            }
+            header => return Err(translate::unsupported::<Self>(&header)),
+        };
+        Ok(Some(buf))
+    }
+
+    fn encode_bytes(&mut self, header: Header, body: &[u8]) -> Result<Option<Vec<u8>>, CodecError> {
+        let body: Value = serde_json::from_slice(body).map_err(parse_error)?;
+        self.encode(header, &body)
+    }
+}
+
+impl Translate for Exchange {
+    type Values = Json;
+
+    type Body = Value;
+
+    fn translate(&mut self, buf: &[u8]) -> Result<Vec<(Header, Value)>, CodecError> {
+        let InboundObject {
+            method,
+            params,
+            id,
+            result,
+            error,
+        } = GoJsonRpc::unmarshal(buf)?;
+
+        let service_method = match method {
+            Some(service_method) => service_method,
+            None => {
+                // only the responses to the requests of this side carry no method
+                let id: MessageId = serde_json::from_value(id).map_err(parse_error)?;
+                let (status, body) = match error {
+                    Value::Null => (Status::ok(), result),
+                    error => {
+                        let (status, msg) = go_error(error);
+                        (status, serde_json::to_value(&msg).map_err(parse_error)?)
+                    }
+                };
+                let header = Header::Response {
+                    id,
+                    status,
+                    metadata: MetadataMap::new(),
+                };
+                return Ok(vec![(header, body)]);
+            }
+        };
+
+        // the argument is the only element of `params`
+        let body = match params {
+            Value::Array(params) => params.into_iter().next().unwrap_or_default(),
+            params => params,
+        };
+        let seq = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let metadata = MetadataMap::new();
+        let header = match id {
+            // a request without id is a notification, which expects no response
+            Value::Null => Header::Notify {
+                id: seq,
+                service_method,
+                metadata,
+            },
+            id => {
+                self.ids.insert(seq, id);
+                Header::Request {
+                    id: seq,
+                    service_method,
+                    timeout: None,
+                    metadata,
+                }
+            }
+        };
+        Ok(vec![(header, body)])
+    }
+
+    fn deserializer(body: Value) -> Result<Box<InboundBody>, CodecError> {
+        Ok(Box::new(<dyn erased::Deserializer>::erase(body)))
+    }
+
+    fn into_bytes(body: Value) -> Result<Vec<u8>, IoError> {
+        serde_json::to_vec(&body).map_err(|err| IoError::new(ErrorKind::InvalidData, err))
+    }
+}
+
+/// The objects are carried by the transports of the `json` format
+macro_rules! impl_splittable_codec_for_conn_types {
+    ($($conn_type:ty),*) => {
+        $(
+            impl<R, W> SplittableCodec for Codec<R, W, $conn_type, GoJsonRpc>
+            where
+                Codec<R, W, $conn_type, Json>: SplittableCodec,
+            {
+                type Writer = TranslateWriteHalf<
+                    <Codec<R, W, $conn_type, Json> as SplittableCodec>::Writer,
+                    Exchange,
+                >;
+                type Reader = TranslateReadHalf<
+                    <Codec<R, W, $conn_type, Json> as SplittableCodec>::Reader,
+                    Exchange,
+                >;
+
+                fn split(self) -> (Self::Writer, Self::Reader) {
+                    let codec = Codec::<R, W, $conn_type, Json> {
+                        reader: self.reader,
+                        writer: self.writer,
+                        conn_type: PhantomData,
+                        format: PhantomData,
+                    };
+                    let (writer, reader) = codec.split();
+                    translate::split(writer, reader)
+                }
+            }
+        )*
+    };
+}
+
+impl_splittable_codec_for_conn_types!(super::ConnTypeReadWrite);
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+impl_splittable_codec_for_conn_types!(super::ConnTypePayload);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(exchange: &mut Exchange, object: &str) -> (Header, Value) {
+        let mut messages = exchange.translate(object.as_bytes()).unwrap();
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    #[test]
+    fn translate_go_objects() {
+        let mut exchange = Exchange::default();
+        let (header, body) = translate(
+            &mut exchange,
+            r#"{"method":"Arith.Multiply","params":[{"A":7,"B":8}],"id":"a"}"#,
+        );
+        match header {
+            Header::Request {
+                id, service_method, ..
+            } => {
+                assert_eq!(service_method, "Arith.Multiply");
+                assert_eq!(exchange.ids[&id], Value::from("a"));
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+        assert_eq!(body, serde_json::json!({"A": 7, "B": 8}));
+
+        let (header, _) = translate(
+            &mut exchange,
+            r#"{"method":"Log.Write","params":["hi"],"id":null}"#,
+        );
+        assert!(matches!(header, Header::Notify { .. }));
+
+        let (header, body) = translate(&mut exchange, r#"{"id":3,"result":56,"error":null}"#);
+        match header {
+            Header::Response { id, status, .. } => {
+                assert_eq!(id, 3);
+                assert!(status.is_ok());
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+        assert_eq!(body, Value::from(56));
+
+        let (header, body) = translate(
+            &mut exchange,
+            r#"{"id":4,"result":null,"error":"rpc: can't find service Arith.Divide"}"#,
+        );
+        match header {
+            Header::Response { status, .. } => assert!(!status.is_ok()),
+            header => panic!("Unexpected header {:?}", header),
+        }
+        let msg: ErrorMessage = serde_json::from_value(body).unwrap();
+        assert!(matches!(msg, ErrorMessage::ServiceNotFound));
+    }
+}
@@ -0,0 +1,157 @@
+//! Implements the gob stream of Go's `net/rpc` with `async_std` runtime
+
+use async_trait::async_trait;
+use erased_serde as erased;
+use futures::io::{AsyncBufRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+
+use super::{split, Exchange, GoRpc, GoRpcReadHalf};
+use crate::codec::gob::{count_len, message_len, Gob};
+use crate::codec::split::SplittableCodec;
+use crate::codec::split::{CodecReadHalf, CodecWriteHalf};
+use crate::codec::translate::TranslateWriteHalf;
+use crate::codec::{
+    Codec, CodecRead, CodecWrite, ConnTypeReadWrite, EraseDeserializer, Marshal, Unmarshal,
+};
+use crate::error::{CodecError, IoError};
+use crate::message::{MessageId, Metadata};
+use crate::protocol::Header;
+use crate::transport::MessageSizeLimit;
+use crate::util::GracefulShutdown;
+
+/// Reading half of the raw TCP transport, which reads one gob message at a time
+pub(crate) struct StreamReader<R>(R);
+
+#[async_trait]
+impl<R, C> CodecRead for CodecReadHalf<StreamReader<R>, C, ConnTypeReadWrite>
+where
+    R: AsyncBufRead + Send + Unpin,
+    C: Unmarshal + EraseDeserializer + Send,
+{
+    /// Reads a message together with its count
+    async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
+        if self.closed {
+            return None;
+        }
+        let mut buf = vec![0u8; 1];
+        match self.reader.0.read_exact(&mut buf).await {
+            Ok(_) => (),
+            // EOF, probably client closed connection
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        }
+        buf.resize(1 + count_len(buf[0]).min(8), 0);
+        if let Err(err) = self.reader.0.read_exact(&mut buf[1..]).await {
+            return Some(Err(err));
+        }
+        let len = match message_len(&buf) {
+            Ok(Some((_, len))) => len,
+            Ok(None) => unreachable!("the count is complete"),
+            Err(err) => return Some(Err(IoError::new(ErrorKind::InvalidData, err))),
+        };
+
+        // an oversized message is discarded without holding it in memory
+        let limit = self.size_limit;
+        if len > limit.max {
+            if limit.close {
+                self.closed = true;
+                return Some(Err(limit.error()));
+            }
+            let mut rest = (&mut self.reader.0).take(len as u64);
+            return match futures::io::copy(&mut rest, &mut futures::io::sink()).await {
+                Ok(_) => Some(Err(limit.error())),
+                Err(err) => Some(Err(err)),
+            };
+        }
+        let start = buf.len();
+        buf.resize(start + len, 0);
+        match self.reader.0.read_exact(&mut buf[start..]).await {
+            Ok(_) => Some(Ok(buf)),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+        self.size_limit = limit;
+    }
+}
+
+/// Writing half of the raw TCP transport, which writes the gob messages as they are
+pub(crate) struct StreamWriter<W>(W);
+
+#[async_trait]
+impl<W, C> CodecWrite for CodecWriteHalf<StreamWriter<W>, C, ConnTypeReadWrite>
+where
+    W: AsyncWrite + Send + Unpin,
+    C: Marshal + Send,
+{
+    async fn write_header(&mut self, header: Header) -> Result<(), CodecError> {
+        let buf = Self::marshal(&header)?;
+        self.write_body_bytes(header.id(), &buf).await?;
+        Ok(())
+    }
+
+    async fn write_body(
+        &mut self,
+        id: MessageId,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<(), CodecError> {
+        let buf = Self::marshal(&body)?;
+        self.write_body_bytes(id, &buf).await?;
+        Ok(())
+    }
+
+    async fn write_body_bytes(&mut self, _: MessageId, bytes: &[u8]) -> Result<(), IoError> {
+        self.writer.0.write_all(bytes).await?;
+        self.writer.0.flush().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<W> GracefulShutdown for StreamWriter<W>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    async fn close(&mut self) {
+        match self.0.flush().await {
+            Ok(()) => (),
+            Err(e) => log::error!("Error closing connection: {}", e),
+        }
+
+        match AsyncWriteExt::close(&mut self.0).await {
+            Ok(()) => (),
+            Err(e) => log::error!("Error closing connection: {}", e),
+        }
+    }
+}
+
+impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite, GoRpc>
+where
+    R: AsyncBufRead + Send + Unpin,
+    W: AsyncWrite + Send + Unpin,
+{
+    type Writer =
+        TranslateWriteHalf<CodecWriteHalf<StreamWriter<W>, Gob, ConnTypeReadWrite>, Exchange>;
+    type Reader = GoRpcReadHalf<CodecReadHalf<StreamReader<R>, Gob, ConnTypeReadWrite>>;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        split(
+            CodecWriteHalf {
+                writer: StreamWriter(self.writer),
+                marker: PhantomData,
+                conn_type: PhantomData,
+                compression: Default::default(),
+            },
+            CodecReadHalf {
+                reader: StreamReader(self.reader),
+                marker: PhantomData,
+                conn_type: PhantomData,
+                compression: Default::default(),
+                size_limit: Default::default(),
+                closed: false,
+            },
+        )
+    }
+}
@@ -0,0 +1,510 @@
+//! Wire compatibility with Go's `net/rpc`
+//!
+//! Go's `net/rpc` encodes its messages with `encoding/gob` by default. A request is a
+//! `Request{ServiceMethod, Seq}` followed by the argument, and a response is a
+//! `Response{ServiceMethod, Seq, Error}` followed by the reply, where a failed call
+//! carries the error string and an empty struct in place of the reply. The messages in
+//! each direction form a single gob stream, in which a type is defined once, and there
+//! is no handshake.
+//!
+//! The reading and writing halves translate these envelopes from and into the messages of
+//! `toy-rpc`, so that a `Client` can call a Go `rpc.Server` and a `Server` can serve Go
+//! clients. The method is named "{Service}.{Method}" on both sides, and the arguments and
+//! replies are encoded as described in `codec::gob`. Only unary calls can be carried,
+//! and the other messages, ie. notifications, streaming calls, pub/sub and extensions,
+//! fail to be written.
+
+use async_trait::async_trait;
+use cfg_if::cfg_if;
+use erased_serde as erased;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+
+use super::gob::{next_message, Decoder, Encoder, Gob, GobError, Item, Value};
+use super::translate::{self, Protocol, TranslateWriteHalf};
+use super::{CodecRead, EraseDeserializer, Marshal, Unmarshal};
+use crate::compression::NegotiatedCompression;
+use crate::error::{CodecError, IoError, ParseError};
+use crate::message::{ErrorMessage, MessageId};
+use crate::protocol::{Header, InboundBody, MetadataMap};
+use crate::status::Status;
+use crate::transport::MessageSizeLimit;
+
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+use super::{split::SplittableCodec, Codec};
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+use std::marker::PhantomData;
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "http_tide"
+    ))] {
+        mod async_std;
+    } else if #[cfg(any(
+        feature = "tokio_runtime",
+        feature = "http_warp",
+    ))] {
+        mod tokio;
+    }
+}
+
+/// Go's `net/rpc` protocol. The messages are encoded with `gob::Gob` and are written
+/// one after another on the raw TCP transport
+pub struct GoRpc {}
+
+impl Marshal for GoRpc {
+    fn codec_name() -> &'static str {
+        "go-rpc"
+    }
+
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        Gob::marshal(val)
+    }
+}
+
+impl Unmarshal for GoRpc {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        Gob::unmarshal(buf)
+    }
+}
+
+/// `rpc.Request` of Go
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Request<'a> {
+    service_method: &'a str,
+    seq: u64,
+}
+
+/// `rpc.Response` of Go
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Response<'a> {
+    service_method: &'a str,
+    seq: u64,
+    error: &'a str,
+}
+
+/// Either envelope, which are told apart by the `Error` field of the response
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Envelope {
+    service_method: String,
+    seq: u64,
+    error: Option<String>,
+}
+
+fn parse_error(err: GobError) -> CodecError {
+    CodecError::ParseError(Box::new(err))
+}
+
+/// Reading half of a `Codec` with the `GoRpc` format
+pub(crate) struct GoRpcReadHalf<R> {
+    inner: R,
+    /// Bytes of the stream that are not decoded yet
+    buf: Vec<u8>,
+    decoder: Decoder,
+    /// Error of the response whose header is read last, which replaces its body
+    error: Option<ErrorMessage>,
+    exchange: Arc<Mutex<Exchange>>,
+}
+
+impl<R: CodecRead> GoRpcReadHalf<R> {
+    /// Reads the next value of the stream, and decodes the definitions of types on the
+    /// way. The messages are appended to `raw` as they are read
+    async fn read_value(
+        &mut self,
+        mut raw: Option<&mut Vec<u8>>,
+    ) -> Option<Result<Value, CodecError>> {
+        loop {
+            match next_message(&self.buf) {
+                Ok(Some(msg)) => {
+                    let result = self.decoder.decode_message(&self.buf[msg.clone()]);
+                    let consumed = self.buf.drain(..msg.end);
+                    if let Some(raw) = raw.as_mut() {
+                        raw.extend(consumed);
+                    }
+                    match result {
+                        Ok(Some(value)) => return Some(Ok(value)),
+                        Ok(None) => continue,
+                        Err(err) => return Some(Err(parse_error(err))),
+                    }
+                }
+                Ok(None) => (),
+                // the messages that follow cannot be found
+                Err(err) => return Some(Err(IoError::new(ErrorKind::InvalidData, err).into())),
+            }
+            match self.inner.read_bytes().await? {
+                Ok(bytes) => self.buf.extend_from_slice(&bytes),
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+impl<R> GoRpcReadHalf<R> {
+    fn translate(&mut self, value: Value) -> Result<Header, CodecError> {
+        let is_response = match &value {
+            Value::Struct(fields) => fields.iter().any(|(name, _)| name == "Error"),
+            _ => false,
+        };
+        let Envelope {
+            service_method,
+            seq,
+            error,
+        } = Envelope::deserialize(self.decoder.deserializer(value)).map_err(parse_error)?;
+        let metadata = MetadataMap::new();
+
+        if is_response {
+            let status = match error {
+                Some(error) if !error.is_empty() => {
+                    let (status, msg) = ErrorMessage::from_go_error(error);
+                    self.error = Some(msg);
+                    status
+                }
+                _ => Status::ok(),
+            };
+            return Ok(Header::Response {
+                id: seq,
+                status,
+                metadata,
+            });
+        }
+
+        self.exchange
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .service_methods
+            .insert(seq, service_method.clone());
+        Ok(Header::Request {
+            id: seq,
+            service_method,
+            timeout: None,
+            metadata,
+        })
+    }
+}
+
+impl<R> Unmarshal for GoRpcReadHalf<R> {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        GoRpc::unmarshal(buf)
+    }
+}
+
+impl<R> EraseDeserializer for GoRpcReadHalf<R> {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        Gob::from_bytes(buf)
+    }
+}
+
+#[async_trait]
+impl<R: CodecRead> CodecRead for GoRpcReadHalf<R> {
+    async fn read_header(&mut self) -> Option<Result<Header, CodecError>> {
+        let value = match self.read_value(None).await? {
+            Ok(value) => value,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(self.translate(value))
+    }
+
+    async fn read_body(&mut self) -> Option<Result<Box<InboundBody>, CodecError>> {
+        let value = match self.read_value(None).await? {
+            Ok(value) => value,
+            Err(err) => return Some(Err(err)),
+        };
+        match self.error.take() {
+            // the body of a failed call is an empty struct
+            Some(msg) => match Gob::marshal(&msg) {
+                Ok(buf) => Some(Ok(Gob::from_bytes(buf))),
+                Err(err) => Some(Err(err.into())),
+            },
+            None => {
+                let de = self.decoder.deserializer(value);
+                Some(Ok(Box::new(<dyn erased::Deserializer>::erase(de))))
+            }
+        }
+    }
+
+    /// Reads the messages up to the next value as they are. They rely on the definitions
+    /// that are read before them
+    async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
+        let mut raw = Vec::new();
+        match self.read_value(Some(&mut raw)).await? {
+            Ok(_) => Some(Ok(raw)),
+            Err(CodecError::IoError(err)) => Some(Err(err)),
+            Err(CodecError::ParseError(err)) => {
+                Some(Err(IoError::new(ErrorKind::InvalidData, err)))
+            }
+        }
+    }
+
+    fn set_compression(&mut self, compression: NegotiatedCompression) {
+        self.inner.set_compression(compression);
+    }
+
+    fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+        self.inner.set_size_limit(limit);
+    }
+}
+
+/// State of a connection, which is shared by the reading and writing halves
+pub(crate) struct Exchange {
+    encoder: Encoder,
+    /// Service methods of the requests from the peer by their sequence numbers, which are
+    /// sent back in the responses as Go's server does
+    service_methods: HashMap<MessageId, String>,
+}
+
+impl Exchange {
+    fn new() -> Self {
+        Self {
+            encoder: Encoder::new(),
+            service_methods: HashMap::new(),
+        }
+    }
+}
+
+impl Protocol for Exchange {
+    type Format = GoRpc;
+
+    const NAME: &'static str = "Go's net/rpc";
+
+    const FEATURES: &'static [&'static str] = &[];
+
+    fn supports(header: &Header) -> bool {
+        matches!(
+            header,
+            Header::Request { .. } | Header::Response { .. } | Header::Cancel(_)
+        )
+    }
+
+    fn encode(
+        &mut self,
+        header: Header,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<Option<Vec<u8>>, CodecError> {
+        // both are serialized before either is encoded, which keeps the stream intact if
+        // one of them fails
+        let (envelope, body) = match header {
+            Header::Request {
+                id, service_method, ..
+            } => {
+                let request = Request {
+                    service_method: &service_method,
+                    seq: id,
+                };
+                (Item::new(&request), Item::new(body))
+            }
+            Header::Response { id, status, .. } => {
+                let service_method = self.service_methods.remove(&id).unwrap_or_default();
+                match status.is_ok() {
+                    true => {
+                        let response = Response {
+                            service_method: &service_method,
+                            seq: id,
+                            error: "",
+                        };
+                        (Item::new(&response), Item::new(body))
+                    }
+                    // Go only carries the message of an error
+                    false => {
+                        let error = match status.message() {
+                            Some(message) => message.to_string(),
+                            None => status.code().to_string(),
+                        };
+                        let response = Response {
+                            service_method: &service_method,
+                            seq: id,
+                            error: &error,
+                        };
+                        (Item::new(&response), Item::new(&()))
+                    }
+                }
+            }
+            header => return Err(translate::unsupported::<Self>(&header)),
+        };
+        let envelope = envelope.map_err(parse_error)?;
+        let body = body.map_err(parse_error)?;
+
+        let mut buf = Vec::new();
+        self.encoder.encode(&envelope, &mut buf);
+        self.encoder.encode(&body, &mut buf);
+        Ok(Some(buf))
+    }
+
+    /// Bodies that are already serialized are only written by pub/sub, which Go does not
+    /// speak
+    fn encode_bytes(&mut self, _: Header, _: &[u8]) -> Result<Option<Vec<u8>>, CodecError> {
+        Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Serialized bodies are not supported by Go's net/rpc",
+        )
+        .into())
+    }
+}
+
+/// Wraps the halves of a transport that carry a gob stream in each direction
+fn split<W, R>(writer: W, reader: R) -> (TranslateWriteHalf<W, Exchange>, GoRpcReadHalf<R>) {
+    let exchange = Arc::new(Mutex::new(Exchange::new()));
+    (
+        TranslateWriteHalf::new(writer, exchange.clone()),
+        GoRpcReadHalf {
+            inner: reader,
+            buf: Vec::new(),
+            decoder: Decoder::new(),
+            error: None,
+            exchange,
+        },
+    )
+}
+
+/// Each write is carried by a message of the WebSocket transport of the `gob` format
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+impl<R, W> SplittableCodec for Codec<R, W, super::ConnTypePayload, GoRpc>
+where
+    Codec<R, W, super::ConnTypePayload, Gob>: SplittableCodec,
+{
+    type Writer = TranslateWriteHalf<
+        <Codec<R, W, super::ConnTypePayload, Gob> as SplittableCodec>::Writer,
+        Exchange,
+    >;
+    type Reader =
+        GoRpcReadHalf<<Codec<R, W, super::ConnTypePayload, Gob> as SplittableCodec>::Reader>;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        let codec = Codec::<R, W, super::ConnTypePayload, Gob> {
+            reader: self.reader,
+            writer: self.writer,
+            conn_type: PhantomData,
+            format: PhantomData,
+        };
+        let (writer, reader) = codec.split();
+        split(writer, reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Messages that Go's `rpc.Client` writes on a new connection for
+    /// `client.Call("Arith.Multiply", &Args{7, 8}, &reply)`, where `Args` is
+    /// `struct{ A, B int }`
+    pub(crate) const GO_REQUEST: &[u8] = b"\
+        \x2f\xff\x81\x03\x01\x01\x07Request\x01\xff\x82\x00\x01\x02\
+        \x01\x0dServiceMethod\x01\x0c\x00\x01\x03Seq\x01\x06\x00\x00\x00\
+        \x13\xff\x82\x01\x0eArith.Multiply\x00\
+        \x1e\xff\x83\x03\x01\x01\x04Args\x01\xff\x84\x00\x01\x02\
+        \x01\x01A\x01\x04\x00\x01\x01B\x01\x04\x00\x00\x00\
+        \x07\xff\x84\x01\x0e\x01\x10\x00";
+
+    /// Messages that Go's `rpc.Server` writes on a new connection in reply to
+    /// `GO_REQUEST` with `56`, and then to a call of the missing `Arith.Divide` with
+    /// sequence number 1
+    pub(crate) const GO_RESPONSES: &[u8] = b"\
+        \x3a\xff\x81\x03\x01\x01\x08Response\x01\xff\x82\x00\x01\x03\
+        \x01\x0dServiceMethod\x01\x0c\x00\x01\x03Seq\x01\x06\x00\x01\x05Error\x01\x0c\x00\
+        \x00\x00\
+        \x13\xff\x82\x01\x0eArith.Multiply\x00\
+        \x03\x04\x00\x70\
+        \x38\xff\x82\x01\x0cArith.Divide\x01\x01\
+        \x01\x23rpc: can't find method Arith.Divide\x00\
+        \x0a\xff\x83\x03\x01\x02\xff\x84\x00\x00\x00\
+        \x03\xff\x84\x00";
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Args {
+        a: i64,
+        b: i64,
+    }
+
+    fn read_half() -> GoRpcReadHalf<()> {
+        split((), ()).1
+    }
+
+    /// Decodes the values of a stream, and translates every other value as an envelope
+    fn translate(half: &mut GoRpcReadHalf<()>, mut stream: &[u8]) -> Vec<(Header, Value)> {
+        let mut values = Vec::new();
+        while let Some(msg) = next_message(stream).unwrap() {
+            if let Some(value) = half.decoder.decode_message(&stream[msg.clone()]).unwrap() {
+                values.push(value);
+            }
+            stream = &stream[msg.end..];
+        }
+        assert!(stream.is_empty());
+
+        let mut values = values.into_iter();
+        let mut messages = Vec::new();
+        while let (Some(envelope), Some(body)) = (values.next(), values.next()) {
+            messages.push((half.translate(envelope).unwrap(), body));
+        }
+        messages
+    }
+
+    #[test]
+    fn encode_go_request() {
+        let mut exchange = Exchange::new();
+        let request = Item::new(&Request {
+            service_method: "Arith.Multiply",
+            seq: 0,
+        })
+        .unwrap();
+        let args = Item::new(&Args { a: 7, b: 8 }).unwrap();
+        let mut buf = Vec::new();
+        exchange.encoder.encode(&request, &mut buf);
+        exchange.encoder.encode(&args, &mut buf);
+        assert_eq!(buf, GO_REQUEST);
+    }
+
+    #[test]
+    fn translate_go_request() {
+        let mut half = read_half();
+        let mut messages = translate(&mut half, GO_REQUEST);
+        assert_eq!(messages.len(), 1);
+        let (header, body) = messages.remove(0);
+        match header {
+            Header::Request {
+                id, service_method, ..
+            } => {
+                assert_eq!(id, 0);
+                assert_eq!(service_method, "Arith.Multiply");
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+        let args = Args::deserialize(half.decoder.deserializer(body)).unwrap();
+        assert_eq!(args, Args { a: 7, b: 8 });
+        let exchange = half.exchange.lock().unwrap();
+        assert_eq!(exchange.service_methods[&0], "Arith.Multiply");
+    }
+
+    #[test]
+    fn translate_go_responses() {
+        let mut half = read_half();
+        let mut messages = translate(&mut half, GO_RESPONSES).into_iter();
+
+        let (header, body) = messages.next().unwrap();
+        match header {
+            Header::Response { id, status, .. } => {
+                assert_eq!(id, 0);
+                assert!(status.is_ok());
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+        assert_eq!(body, Value::Int(56));
+
+        let (header, _) = messages.next().unwrap();
+        match header {
+            Header::Response { id, status, .. } => {
+                assert_eq!(id, 1);
+                assert!(!status.is_ok());
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+        assert!(matches!(half.error, Some(ErrorMessage::MethodNotFound)));
+        assert!(messages.next().is_none());
+    }
+}
@@ -0,0 +1,631 @@
+//! Decoding of gob messages into serde values
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{
+    next_message, GobError, Reader, TypeId, BOOL_ID, BYTES_ID, COMPLEX_ID, FLOAT_ID, INTERFACE_ID,
+    INT_ID, STRING_ID, UINT_ID, WIRE_ARRAY, WIRE_BINARY_MARSHALER, WIRE_GOB_ENCODER, WIRE_MAP,
+    WIRE_SLICE, WIRE_STRUCT, WIRE_TEXT_MARSHALER,
+};
+
+/// Values can be nested up to this depth, which keeps a hostile message from
+/// overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+/// A decoded value
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    /// A nil `interface{}`
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    Bytes(Vec<u8>),
+    String(String),
+    Slice(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Struct(Vec<(String, Value)>),
+    /// A field that is not sent, which is the zero value of its type
+    Zero(TypeId),
+}
+
+/// Type defined in a stream
+#[derive(Debug, Clone)]
+enum WireType {
+    /// Slices and arrays, which are sent in the same way
+    Slice(TypeId),
+    Struct(Vec<(String, TypeId)>),
+    Map(TypeId, TypeId),
+    /// Types that marshal themselves into bytes
+    Bytes,
+    /// Types that marshal themselves into text
+    Text,
+}
+
+type Types = HashMap<TypeId, WireType>;
+
+/// Decoder of a gob stream, which keeps the types defined in the stream
+pub(crate) struct Decoder {
+    types: Arc<Types>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            types: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Decodes a message without its count. This returns `None` if the message is a
+    /// definition of a type
+    pub fn decode_message(&mut self, msg: &[u8]) -> Result<Option<Value>, GobError> {
+        let mut reader = Reader::new(msg);
+        let id = reader.int()?;
+        if id < 0 {
+            let ty = read_wire_type(&mut reader)?;
+            if !reader.is_empty() {
+                return Err(GobError::new("extra data in the definition of a type"));
+            }
+            Arc::make_mut(&mut self.types).insert(-id, ty);
+            return Ok(None);
+        }
+
+        let value = match self.types.get(&id) {
+            Some(WireType::Struct(fields)) => self.read_struct(&mut reader, fields, 0)?,
+            _ => {
+                // any other value is sent as the only field of a struct
+                if reader.uint()? != 0 {
+                    return Err(GobError::new(
+                        "non-zero delta for a value that is not a struct",
+                    ));
+                }
+                self.read_value(&mut reader, id, 0)?
+            }
+        };
+        Ok(Some(value))
+    }
+
+    /// Decodes the messages at the start of a stream up to the first value
+    pub fn decode_value(&mut self, mut buf: &[u8]) -> Result<Value, GobError> {
+        loop {
+            let msg =
+                next_message(buf)?.ok_or_else(|| GobError::new("unexpected end of stream"))?;
+            if let Some(value) = self.decode_message(&buf[msg.clone()])? {
+                return Ok(value);
+            }
+            buf = &buf[msg.end..];
+        }
+    }
+
+    /// Deserializer of a value, which refers to the types defined so far
+    pub fn deserializer(&self, value: Value) -> ValueDeserializer {
+        ValueDeserializer {
+            value,
+            types: self.types.clone(),
+        }
+    }
+
+    fn read_value(&self, reader: &mut Reader, id: TypeId, depth: usize) -> Result<Value, GobError> {
+        if depth > MAX_DEPTH {
+            return Err(GobError::new("values are nested too deeply"));
+        }
+        let value = match id {
+            BOOL_ID => Value::Bool(reader.uint()? != 0),
+            INT_ID => Value::Int(reader.int()?),
+            UINT_ID => Value::Uint(reader.uint()?),
+            FLOAT_ID => Value::Float(reader.float()?),
+            BYTES_ID => Value::Bytes(reader.bytes()?.to_vec()),
+            STRING_ID => read_string(reader)?,
+            COMPLEX_ID => Value::Slice(vec![
+                Value::Float(reader.float()?),
+                Value::Float(reader.float()?),
+            ]),
+            INTERFACE_ID => self.read_interface(reader, depth)?,
+            id => match self.types.get(&id) {
+                Some(WireType::Slice(elem)) => {
+                    let len = reader.len()?;
+                    let items = (0..len)
+                        .map(|_| self.read_value(reader, *elem, depth + 1))
+                        .collect::<Result<_, _>>()?;
+                    Value::Slice(items)
+                }
+                Some(WireType::Map(key, elem)) => {
+                    let len = reader.len()?;
+                    let entries = (0..len)
+                        .map(|_| {
+                            Ok((
+                                self.read_value(reader, *key, depth + 1)?,
+                                self.read_value(reader, *elem, depth + 1)?,
+                            ))
+                        })
+                        .collect::<Result<_, GobError>>()?;
+                    Value::Map(entries)
+                }
+                Some(WireType::Struct(fields)) => self.read_struct(reader, fields, depth + 1)?,
+                Some(WireType::Bytes) => Value::Bytes(reader.bytes()?.to_vec()),
+                Some(WireType::Text) => read_string(reader)?,
+                None => return Err(GobError::new(format!("type id {} is not defined", id))),
+            },
+        };
+        Ok(value)
+    }
+
+    fn read_struct(
+        &self,
+        reader: &mut Reader,
+        fields: &[(String, TypeId)],
+        depth: usize,
+    ) -> Result<Value, GobError> {
+        let mut values: Vec<Option<Value>> = vec![None; fields.len()];
+        let mut num = -1i64;
+        loop {
+            let delta = reader.uint()?;
+            if delta == 0 {
+                break;
+            }
+            num = num.saturating_add(delta as i64);
+            let (slot, (_, id)) = values
+                .iter_mut()
+                .zip(fields)
+                .nth(num as usize)
+                .ok_or_else(|| GobError::new("field number is out of range"))?;
+            *slot = Some(self.read_value(reader, *id, depth)?);
+        }
+        let fields = fields
+            .iter()
+            .zip(values)
+            .map(|((name, id), value)| (name.clone(), value.unwrap_or(Value::Zero(*id))))
+            .collect();
+        Ok(Value::Struct(fields))
+    }
+
+    /// Reads an `interface{}`, which is the name of the concrete type, its id, and the
+    /// value as if it were a message of its own
+    fn read_interface(&self, reader: &mut Reader, depth: usize) -> Result<Value, GobError> {
+        if reader.string()?.is_empty() {
+            return Ok(Value::Nil);
+        }
+        let id = reader.int()?;
+        let mut inner = Reader::new(reader.bytes()?);
+        match self.types.get(&id) {
+            Some(WireType::Struct(fields)) => self.read_struct(&mut inner, fields, depth + 1),
+            _ => {
+                if inner.uint()? != 0 {
+                    return Err(GobError::new(
+                        "non-zero delta for a value that is not a struct",
+                    ));
+                }
+                self.read_value(&mut inner, id, depth + 1)
+            }
+        }
+    }
+}
+
+/// Strings that are not UTF-8 are left as bytes, which Go allows
+fn read_string(reader: &mut Reader) -> Result<Value, GobError> {
+    let bytes = reader.bytes()?.to_vec();
+    Ok(String::from_utf8(bytes)
+        .map(Value::String)
+        .unwrap_or_else(|err| Value::Bytes(err.into_bytes())))
+}
+
+/// Reads the fields of a struct that defines a type
+fn read_fields(
+    reader: &mut Reader,
+    mut field: impl FnMut(&mut Reader, i64) -> Result<(), GobError>,
+) -> Result<(), GobError> {
+    let mut num = -1i64;
+    loop {
+        let delta = reader.uint()?;
+        if delta == 0 {
+            return Ok(());
+        }
+        num = num.saturating_add(delta as i64);
+        field(reader, num)?;
+    }
+}
+
+fn unexpected_field(num: i64) -> GobError {
+    GobError::new(format!("unexpected field {} in a type definition", num))
+}
+
+/// Reads the `CommonType` of a definition, of which only the id is kept
+fn read_common_type(reader: &mut Reader) -> Result<(), GobError> {
+    read_fields(reader, |reader, num| match num {
+        0 => reader.string().map(|_| ()),
+        1 => reader.int().map(|_| ()),
+        num => Err(unexpected_field(num)),
+    })
+}
+
+/// Reads a `wireType`, which holds the definition of one kind of type
+fn read_wire_type(reader: &mut Reader) -> Result<WireType, GobError> {
+    let mut ty = None;
+    read_fields(reader, |reader, kind| {
+        let mut elem = 0;
+        let mut key = 0;
+        let mut fields = Vec::new();
+        read_fields(reader, |reader, num| match (kind, num) {
+            (_, 0) => read_common_type(reader),
+            (WIRE_ARRAY, 1) | (WIRE_SLICE, 1) | (WIRE_MAP, 2) => {
+                elem = reader.int()?;
+                Ok(())
+            }
+            (WIRE_MAP, 1) => {
+                key = reader.int()?;
+                Ok(())
+            }
+            // the length of an array is sent with each value
+            (WIRE_ARRAY, 2) => reader.int().map(|_| ()),
+            (WIRE_STRUCT, 1) => {
+                let len = reader.len()?;
+                for _ in 0..len {
+                    let mut name = String::new();
+                    let mut id = 0;
+                    read_fields(reader, |reader, num| {
+                        match num {
+                            0 => name = reader.string()?,
+                            1 => id = reader.int()?,
+                            num => return Err(unexpected_field(num)),
+                        }
+                        Ok(())
+                    })?;
+                    fields.push((name, id));
+                }
+                Ok(())
+            }
+            (_, num) => Err(unexpected_field(num)),
+        })?;
+        ty = Some(match kind {
+            WIRE_ARRAY | WIRE_SLICE => WireType::Slice(elem),
+            WIRE_STRUCT => WireType::Struct(fields),
+            WIRE_MAP => WireType::Map(key, elem),
+            WIRE_GOB_ENCODER | WIRE_BINARY_MARSHALER => WireType::Bytes,
+            WIRE_TEXT_MARSHALER => WireType::Text,
+            kind => return Err(unexpected_field(kind)),
+        });
+        Ok(())
+    })?;
+    ty.ok_or_else(|| GobError::new("empty type definition"))
+}
+
+/// Deserializer of a decoded value, which owns the value
+pub(crate) struct ValueDeserializer {
+    value: Value,
+    types: Arc<Types>,
+}
+
+impl ValueDeserializer {
+    fn with(&self, value: Value) -> Self {
+        Self {
+            value,
+            types: self.types.clone(),
+        }
+    }
+
+    /// Turns a field that is not sent into the zero value of its type
+    fn resolve(self) -> Self {
+        let id = match self.value {
+            Value::Zero(id) => id,
+            _ => return self,
+        };
+        let value = match id {
+            BOOL_ID => Value::Bool(false),
+            INT_ID => Value::Int(0),
+            UINT_ID => Value::Uint(0),
+            FLOAT_ID => Value::Float(0.0),
+            BYTES_ID => Value::Bytes(Vec::new()),
+            STRING_ID => Value::String(String::new()),
+            COMPLEX_ID => Value::Slice(vec![Value::Float(0.0), Value::Float(0.0)]),
+            id => match self.types.get(&id) {
+                Some(WireType::Slice(_)) => Value::Slice(Vec::new()),
+                Some(WireType::Map(..)) => Value::Map(Vec::new()),
+                Some(WireType::Struct(fields)) => Value::Struct(
+                    fields
+                        .iter()
+                        .map(|(name, id)| (name.clone(), Value::Zero(*id)))
+                        .collect(),
+                ),
+                Some(WireType::Bytes) => Value::Bytes(Vec::new()),
+                Some(WireType::Text) => Value::String(String::new()),
+                None => Value::Nil,
+            },
+        };
+        self.with(value)
+    }
+
+    fn visit_seq<'de, V: Visitor<'de>>(
+        self,
+        items: Vec<Value>,
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        let types = self.types;
+        let mut seq = SeqDeserializer::new(items.into_iter().map(move |value| Self {
+            value,
+            types: types.clone(),
+        }));
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn visit_map<'de, V: Visitor<'de>>(
+        self,
+        entries: Vec<(Value, Value)>,
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        let types = self.types;
+        let mut map = MapDeserializer::new(entries.into_iter().map(move |(key, value)| {
+            let key = Self {
+                value: key,
+                types: types.clone(),
+            };
+            let value = Self {
+                value,
+                types: types.clone(),
+            };
+            (key, value)
+        }));
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+}
+
+fn struct_entries(fields: Vec<(String, Value)>) -> Vec<(Value, Value)> {
+    fields
+        .into_iter()
+        .map(|(name, value)| (Value::String(name), value))
+        .collect()
+}
+
+impl<'de> IntoDeserializer<'de, GobError> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = GobError;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, GobError> {
+        let mut this = self.resolve();
+        match std::mem::replace(&mut this.value, Value::Nil) {
+            Value::Nil | Value::Zero(_) => visitor.visit_unit(),
+            Value::Bool(val) => visitor.visit_bool(val),
+            Value::Int(val) => visitor.visit_i64(val),
+            Value::Uint(val) => visitor.visit_u64(val),
+            Value::Float(val) => visitor.visit_f64(val),
+            Value::Bytes(val) => visitor.visit_byte_buf(val),
+            Value::String(val) => visitor.visit_string(val),
+            Value::Slice(items) => this.visit_seq(items, visitor),
+            Value::Map(entries) => this.visit_map(entries, visitor),
+            Value::Struct(fields) => this.visit_map(struct_entries(fields), visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, GobError> {
+        match self.value {
+            Value::Nil | Value::Zero(_) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, GobError> {
+        match self.value {
+            Value::Nil | Value::Zero(_) | Value::Struct(_) => visitor.visit_unit(),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, GobError> {
+        let mut this = self.resolve();
+        match std::mem::replace(&mut this.value, Value::Nil) {
+            Value::Nil => this.visit_seq(Vec::new(), visitor),
+            Value::Bytes(bytes) => {
+                let items = bytes.into_iter().map(|b| Value::Uint(b as u64)).collect();
+                this.visit_seq(items, visitor)
+            }
+            value => this.with(value).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        let mut this = self.resolve();
+        match std::mem::replace(&mut this.value, Value::Nil) {
+            // the elements of different types are the fields of a struct, where the ones
+            // that are left out of the type are nil
+            Value::Struct(fields) => {
+                let mut items = vec![Value::Nil; len];
+                for (name, value) in fields {
+                    let pos = name
+                        .strip_prefix('F')
+                        .and_then(|pos| pos.parse::<usize>().ok());
+                    match pos {
+                        Some(pos) if pos < len => items[pos] = value,
+                        _ => {
+                            return Err(GobError::new(format!(
+                                "unexpected field {} of a tuple",
+                                name
+                            )))
+                        }
+                    }
+                }
+                this.visit_seq(items, visitor)
+            }
+            value => this.with(value).deserialize_seq(visitor),
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, GobError> {
+        match self.value {
+            Value::Nil => self.visit_map(Vec::new(), visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        let mut this = self.resolve();
+        let mut entries = match std::mem::replace(&mut this.value, Value::Nil) {
+            Value::Struct(entries) => entries,
+            Value::Nil => Vec::new(),
+            value => return this.with(value).deserialize_any(visitor),
+        };
+        // the fields that are left out of the type are nil, ie. `None`
+        for field in fields {
+            if !entries.iter().any(|(name, _)| name == field) {
+                entries.push((field.to_string(), Value::Nil));
+            }
+        }
+        this.visit_map(struct_entries(entries), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        let mut this = self.resolve();
+        match std::mem::replace(&mut this.value, Value::Nil) {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            Value::Struct(fields) => {
+                // the variant is the only field that is sent
+                let mut sent = fields
+                    .into_iter()
+                    .filter(|(_, value)| !matches!(value, Value::Zero(_)));
+                match (sent.next(), sent.next()) {
+                    (Some((variant, value)), None) => visitor.visit_enum(Enum {
+                        variant,
+                        value: this.with(value),
+                    }),
+                    _ => Err(GobError::new(format!(
+                        "expecting a single variant of {}",
+                        name
+                    ))),
+                }
+            }
+            value => Err(GobError::new(format!(
+                "expecting a variant of {}, found {:?}",
+                name, value
+            ))),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, GobError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf identifier
+    }
+}
+
+/// A variant of an enum and its value
+struct Enum {
+    variant: String,
+    value: ValueDeserializer,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = GobError;
+    type Variant = ValueDeserializer;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, ValueDeserializer), GobError> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for ValueDeserializer {
+    type Error = GobError;
+
+    fn unit_variant(self) -> Result<(), GobError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, GobError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, GobError> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, GobError> {
+        de::Deserializer::deserialize_struct(self, "", fields, visitor)
+    }
+}
+
+/// Deserializer of a body that cannot be decoded, which fails with the error
+pub(crate) struct Failed(pub GobError);
+
+impl<'de> de::Deserializer<'de> for Failed {
+    type Error = GobError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, GobError> {
+        Err(self.0)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
@@ -0,0 +1,376 @@
+//! Impplementation of `Marshal`, `Unmarshal` and `EraseDeserializer` traits with the gob
+//! encoding of Go's `encoding/gob`
+//!
+//! A gob stream is a sequence of messages, and each message is its length followed by a
+//! type id. A negative id introduces the definition of a type, and a positive id
+//! introduces a value of the type. The types of the values are inferred from what is
+//! serialized, so the definitions are derived from the values:
+//!
+//! - signed integers are `int`, unsigned integers are `uint`, and floats are `float64`
+//! - sequences are slices, and sequences of `u8` are `[]byte`
+//! - tuples are slices if their elements are of the same type, and otherwise structs
+//!     whose fields are named `F0`, `F1` and so on
+//! - structs keep the names of their fields, so a struct that is exchanged with Go
+//!     usually needs `#[serde(rename_all = "PascalCase")]`
+//! - `None` and empty sequences or maps of which the element type cannot be inferred are
+//!     left out of the structs, and are a nil `interface{}` elsewhere
+//! - unit and unit structs are empty structs, and newtype structs are their content
+//! - Go has no enums. A unit variant is the string of its name, and the other variants
+//!     are a struct with the variant as the only field
+//!
+//! Fields that are not sent are the zero values of their types, as they are in Go, and
+//! `Marshal` leaves out the fields with zero values as Go does. Each call of `marshal`
+//! is a complete stream, which includes the definitions of the types that it uses.
+
+use erased_serde as erased;
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::{EraseDeserializer, Marshal, Unmarshal};
+use crate::error::ParseError;
+
+mod de;
+mod ser;
+
+pub(crate) use de::{Decoder, Value};
+pub(crate) use ser::{Encoder, Item};
+
+/// Id of a type in a gob stream
+pub(crate) type TypeId = i64;
+
+// ids of the types that are predefined by the gob encoding
+const BOOL_ID: TypeId = 1;
+const INT_ID: TypeId = 2;
+const UINT_ID: TypeId = 3;
+const FLOAT_ID: TypeId = 4;
+const BYTES_ID: TypeId = 5;
+const STRING_ID: TypeId = 6;
+const COMPLEX_ID: TypeId = 7;
+const INTERFACE_ID: TypeId = 8;
+/// Id of the first type that is defined in a stream
+const FIRST_USER_ID: TypeId = 65;
+
+// field numbers of the `wireType` struct that defines a type
+const WIRE_ARRAY: i64 = 0;
+const WIRE_SLICE: i64 = 1;
+const WIRE_STRUCT: i64 = 2;
+const WIRE_MAP: i64 = 3;
+const WIRE_GOB_ENCODER: i64 = 4;
+const WIRE_BINARY_MARSHALER: i64 = 5;
+const WIRE_TEXT_MARSHALER: i64 = 6;
+
+/// The gob encoding of Go's `encoding/gob`, which is sent over the frame transport
+pub struct Gob {}
+
+/// Error of encoding or decoding a gob stream
+#[derive(Debug)]
+pub(crate) struct GobError(String);
+
+impl GobError {
+    fn new(msg: impl fmt::Display) -> Self {
+        Self(format!("gob: {}", msg))
+    }
+}
+
+impl fmt::Display for GobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for GobError {}
+
+impl serde::ser::Error for GobError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::new(msg)
+    }
+}
+
+impl serde::de::Error for GobError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::new(msg)
+    }
+}
+
+/// Appends an unsigned integer, which is a single byte below 128, and otherwise the
+/// negated count of the big-endian bytes that follow
+fn encode_uint(buf: &mut Vec<u8>, val: u64) {
+    if val < 0x80 {
+        buf.push(val as u8);
+        return;
+    }
+    let bytes = val.to_be_bytes();
+    let skip = (val.leading_zeros() / 8) as usize;
+    buf.push((skip as u8).wrapping_sub(8));
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+/// Appends a signed integer, whose sign is moved into the lowest bit
+fn encode_int(buf: &mut Vec<u8>, val: i64) {
+    let bits = match val < 0 {
+        true => (!(val as u64) << 1) | 1,
+        false => (val as u64) << 1,
+    };
+    encode_uint(buf, bits);
+}
+
+/// Appends a float, whose bytes are reversed so that the exponent comes first
+fn encode_float(buf: &mut Vec<u8>, val: f64) {
+    encode_uint(buf, val.to_bits().swap_bytes());
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, val: &[u8]) {
+    encode_uint(buf, val.len() as u64);
+    buf.extend_from_slice(val);
+}
+
+/// Number of the bytes that follow the first byte of an unsigned integer
+pub(crate) fn count_len(first: u8) -> usize {
+    match first < 0x80 {
+        true => 0,
+        false => first.wrapping_neg() as usize,
+    }
+}
+
+/// Reads the count of the message at the start of `buf`. This returns the length of the
+/// count and of the message, or `None` if the count is not complete
+pub(crate) fn message_len(buf: &[u8]) -> Result<Option<(usize, usize)>, GobError> {
+    match buf.first() {
+        None => return Ok(None),
+        Some(first) if count_len(*first) <= 8 && buf.len() <= count_len(*first) => return Ok(None),
+        Some(_) => (),
+    }
+    let mut reader = Reader::new(buf);
+    let len = reader.uint()?;
+    let len = usize::try_from(len).map_err(|_| GobError::new("message is too long"))?;
+    Ok(Some((reader.pos, len)))
+}
+
+/// Finds the complete message at the start of `buf`, and returns the range of the
+/// message without its count
+pub(crate) fn next_message(buf: &[u8]) -> Result<Option<std::ops::Range<usize>>, GobError> {
+    match message_len(buf)? {
+        Some((start, len)) if buf.len() - start >= len => Ok(Some(start..start + len)),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the primitives of the encoding from a message
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], GobError> {
+        match self.buf.len() - self.pos >= len {
+            true => {
+                self.pos += len;
+                Ok(&self.buf[self.pos - len..self.pos])
+            }
+            false => Err(GobError::new("unexpected end of message")),
+        }
+    }
+
+    fn uint(&mut self) -> Result<u64, GobError> {
+        let first = self.take(1)?[0];
+        if first < 0x80 {
+            return Ok(first as u64);
+        }
+        let len = count_len(first);
+        if len > 8 {
+            return Err(GobError::new("unsigned integer is out of range"));
+        }
+        let val = self
+            .take(len)?
+            .iter()
+            .fold(0u64, |val, byte| (val << 8) | *byte as u64);
+        Ok(val)
+    }
+
+    fn int(&mut self) -> Result<i64, GobError> {
+        let bits = self.uint()?;
+        match bits & 1 {
+            1 => Ok(!(bits >> 1) as i64),
+            _ => Ok((bits >> 1) as i64),
+        }
+    }
+
+    fn float(&mut self) -> Result<f64, GobError> {
+        Ok(f64::from_bits(self.uint()?.swap_bytes()))
+    }
+
+    fn len(&mut self) -> Result<usize, GobError> {
+        let len = self.uint()?;
+        // every element takes at least a byte
+        match len <= (self.buf.len() - self.pos) as u64 {
+            true => Ok(len as usize),
+            false => Err(GobError::new("length is out of range")),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], GobError> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, GobError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(GobError::new)
+    }
+}
+
+impl Marshal for Gob {
+    fn codec_name() -> &'static str {
+        "gob"
+    }
+
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        let item = Item::new(val)?;
+        let mut buf = Vec::new();
+        Encoder::new().encode(&item, &mut buf);
+        Ok(buf)
+    }
+}
+
+impl Unmarshal for Gob {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        let mut decoder = Decoder::new();
+        let value = decoder.decode_value(buf)?;
+        D::deserialize(decoder.deserializer(value)).map_err(|err| err.into())
+    }
+}
+
+impl EraseDeserializer for Gob {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        let mut decoder = Decoder::new();
+        match decoder.decode_value(&buf) {
+            Ok(value) => Box::new(<dyn erased::Deserializer>::erase(
+                decoder.deserializer(value),
+            )),
+            Err(err) => Box::new(<dyn erased::Deserializer>::erase(de::Failed(err))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// `Point` of the documentation of `encoding/gob`
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    /// `gob.NewEncoder(w).Encode(Point{22, 33})`, as shown by the documentation of
+    /// `encoding/gob`
+    const POINT: &[u8] = b"\x1f\xff\x81\x03\x01\x01\x05Point\x01\xff\x82\x00\x01\x02\x01\x01X\x01\x04\x00\x01\x01Y\x01\x04\x00\x00\x00\x07\xff\x82\x01\x2c\x01\x42\x00";
+
+    #[test]
+    fn primitives() {
+        let mut buf = Vec::new();
+        for val in [0, 7, 0x7f, 0x80, 0x1234, u64::MAX] {
+            encode_uint(&mut buf, val);
+        }
+        for val in [0, -1, 1, -129, i64::MIN, i64::MAX] {
+            encode_int(&mut buf, val);
+        }
+        encode_float(&mut buf, 17.0);
+        assert_eq!(
+            &buf[..13],
+            b"\x00\x07\x7f\xff\x80\xfe\x12\x34\xf8\xff\xff\xff\xff"
+        );
+
+        let mut reader = Reader::new(&buf);
+        for val in [0, 7, 0x7f, 0x80, 0x1234, u64::MAX] {
+            assert_eq!(reader.uint().unwrap(), val);
+        }
+        for val in [0, -1, 1, -129, i64::MIN, i64::MAX] {
+            assert_eq!(reader.int().unwrap(), val);
+        }
+        // 17.0 is sent as 0x3140 in the documentation
+        assert_eq!(&buf[buf.len() - 3..], b"\xfe\x31\x40");
+        assert_eq!(reader.float().unwrap(), 17.0);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn go_point() {
+        let point = Point { x: 22, y: 33 };
+        assert_eq!(Gob::marshal(&point).unwrap(), POINT);
+        assert_eq!(Gob::unmarshal::<Point>(POINT).unwrap(), point);
+        assert_eq!(message_len(&POINT[..10]).unwrap(), Some((1, 0x1f)));
+        assert_eq!(next_message(POINT).unwrap(), Some(1..0x20));
+        assert_eq!(next_message(&POINT[..10]).unwrap(), None);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Polygon { points: Vec<(i32, i32)> },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        name: String,
+        shapes: Vec<Shape>,
+        tags: HashMap<String, u32>,
+        data: Vec<u8>,
+        scale: Option<f32>,
+        layer: u8,
+        unit: (),
+    }
+
+    #[test]
+    fn round_trip() {
+        let drawing = Drawing {
+            name: "gob".into(),
+            shapes: vec![
+                Shape::Circle(1.5),
+                Shape::Polygon {
+                    points: vec![(0, 0), (-3, 4)],
+                },
+                Shape::Polygon { points: vec![] },
+            ],
+            tags: vec![("a".to_string(), 1)].into_iter().collect(),
+            data: vec![0, 1, 255],
+            scale: None,
+            layer: 0,
+            unit: (),
+        };
+        let buf = Gob::marshal(&drawing).unwrap();
+        assert_eq!(Gob::unmarshal::<Drawing>(&buf).unwrap(), drawing);
+
+        let buf = Gob::marshal(&Shape::Empty).unwrap();
+        assert_eq!(Gob::unmarshal::<Shape>(&buf).unwrap(), Shape::Empty);
+        let buf = Gob::marshal(&Option::<u64>::None).unwrap();
+        assert_eq!(Gob::unmarshal::<Option<u64>>(&buf).unwrap(), None);
+        let buf = Gob::marshal(&Vec::<String>::new()).unwrap();
+        assert!(Gob::unmarshal::<Vec<String>>(&buf).unwrap().is_empty());
+        let tuple = (Option::<String>::None, 7u64, true);
+        let buf = Gob::marshal(&tuple).unwrap();
+        assert_eq!(
+            Gob::unmarshal::<(Option<String>, u64, bool)>(&buf).unwrap(),
+            tuple
+        );
+
+        // the shapes have different fields, and cannot share one type
+        let mixed = vec![Shape::Empty, Shape::Circle(1.0)];
+        assert!(Gob::marshal(&mixed).is_err());
+    }
+}
@@ -0,0 +1,781 @@
+//! Encoding of serde values into gob messages
+
+use serde::ser::{self, Serialize};
+use std::collections::HashMap;
+
+use super::{
+    encode_bytes, encode_float, encode_int, encode_uint, GobError, TypeId, BOOL_ID, BYTES_ID,
+    FIRST_USER_ID, FLOAT_ID, INTERFACE_ID, INT_ID, STRING_ID, UINT_ID, WIRE_MAP, WIRE_SLICE,
+    WIRE_STRUCT,
+};
+
+/// Value that is serialized, from which its type is inferred
+#[derive(Debug)]
+enum Ser {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Byte(u8),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Seq(Vec<Ser>),
+    Map(Vec<(Ser, Ser)>),
+    Struct {
+        name: &'static str,
+        fields: Vec<(&'static str, Ser)>,
+        /// The field of a variant is sent even if it is zero, which tells the variant
+        variant: bool,
+    },
+}
+
+impl Ser {
+    fn unit() -> Self {
+        // Go names an anonymous `struct {}` with an empty string
+        Ser::Struct {
+            name: "",
+            fields: Vec::new(),
+            variant: false,
+        }
+    }
+
+    fn variant(name: &'static str, variant: &'static str, value: Ser) -> Self {
+        Ser::Struct {
+            name,
+            fields: vec![(variant, value)],
+            variant: true,
+        }
+    }
+
+    /// Whether the value is the zero value of its type, which is not sent as a field
+    fn is_zero(&self) -> bool {
+        match self {
+            Ser::Nil => true,
+            Ser::Bool(val) => !val,
+            Ser::Int(val) => *val == 0,
+            Ser::Uint(val) => *val == 0,
+            Ser::Byte(val) => *val == 0,
+            Ser::Float(val) => *val == 0.0,
+            Ser::String(val) => val.is_empty(),
+            Ser::Bytes(val) => val.is_empty(),
+            Ser::Seq(val) => val.is_empty(),
+            Ser::Map(val) => val.is_empty(),
+            Ser::Struct { .. } => false,
+        }
+    }
+
+    fn ty(&self) -> Result<Type, GobError> {
+        let ty = match self {
+            Ser::Nil => Type::Unknown,
+            Ser::Bool(_) => Type::Bool,
+            Ser::Int(_) => Type::Int,
+            Ser::Uint(_) => Type::Uint,
+            Ser::Byte(_) => Type::Byte,
+            Ser::Float(_) => Type::Float,
+            Ser::String(_) => Type::String,
+            Ser::Bytes(_) => Type::Bytes,
+            Ser::Seq(items) => {
+                let elem = items
+                    .iter()
+                    .try_fold(Type::Unknown, |ty, item| ty.unify(item.ty()?))?;
+                match elem {
+                    Type::Byte => Type::Bytes,
+                    elem => Type::Slice(Box::new(elem)),
+                }
+            }
+            Ser::Map(entries) => {
+                let (key, elem) = entries.iter().try_fold(
+                    (Type::Unknown, Type::Unknown),
+                    |(key, elem), (k, v)| {
+                        Ok::<_, GobError>((key.unify(k.ty()?)?, elem.unify(v.ty()?)?))
+                    },
+                )?;
+                Type::Map(Box::new(key), Box::new(elem))
+            }
+            Ser::Struct { name, fields, .. } => {
+                let mut types = Vec::with_capacity(fields.len());
+                for (field, value) in fields {
+                    let ty = value.ty()?;
+                    // a field whose type is unknown is only defined if it has to be sent
+                    if !(ty.is_partial() && value.is_zero()) {
+                        types.push((*field, ty));
+                    }
+                }
+                Type::Struct(name, types)
+            }
+        };
+        Ok(ty)
+    }
+}
+
+/// Type of a value, which is defined once in a stream
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Type {
+    /// The type of `None`, which is sent as `interface{}`
+    Unknown,
+    Bool,
+    Int,
+    Uint,
+    Byte,
+    Float,
+    String,
+    Bytes,
+    Slice(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Struct(&'static str, Vec<(&'static str, Type)>),
+}
+
+impl Type {
+    fn is_partial(&self) -> bool {
+        match self {
+            Type::Unknown => true,
+            Type::Slice(elem) => elem.is_partial(),
+            Type::Map(key, elem) => key.is_partial() || elem.is_partial(),
+            _ => false,
+        }
+    }
+
+    /// Finds the type of which both types are, which is the type of the elements of a
+    /// sequence. Structs of the same name have the fields of both
+    fn unify(self, other: Type) -> Result<Type, GobError> {
+        let ty = match (self, other) {
+            (Type::Unknown, ty) | (ty, Type::Unknown) => ty,
+            (Type::Byte, Type::Uint) | (Type::Uint, Type::Byte) => Type::Uint,
+            (Type::Bytes, Type::Slice(elem)) | (Type::Slice(elem), Type::Bytes)
+                if *elem == Type::Unknown =>
+            {
+                Type::Bytes
+            }
+            (Type::Slice(a), Type::Slice(b)) => Type::Slice(Box::new(a.unify(*b)?)),
+            (Type::Map(ka, ea), Type::Map(kb, eb)) => {
+                Type::Map(Box::new(ka.unify(*kb)?), Box::new(ea.unify(*eb)?))
+            }
+            (Type::Struct(name, mut fields), Type::Struct(other_name, other_fields))
+                if name == other_name =>
+            {
+                for (field, ty) in other_fields {
+                    match fields.iter_mut().find(|(name, _)| *name == field) {
+                        Some((_, existing)) => {
+                            let prev = std::mem::replace(existing, Type::Unknown);
+                            *existing = prev.unify(ty)?;
+                        }
+                        None => fields.push((field, ty)),
+                    }
+                }
+                Type::Struct(name, fields)
+            }
+            (a, b) if a == b => a,
+            (a, b) => {
+                return Err(GobError::new(format!(
+                    "values of {:?} and {:?} cannot be elements of the same sequence",
+                    a, b
+                )))
+            }
+        };
+        Ok(ty)
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Type::Unknown => "interface {}".into(),
+            Type::Bool => "bool".into(),
+            Type::Int => "int".into(),
+            Type::Uint | Type::Byte => "uint".into(),
+            Type::Float => "float64".into(),
+            Type::String => "string".into(),
+            Type::Bytes => "[]uint8".into(),
+            Type::Slice(elem) => format!("[]{}", elem.name()),
+            Type::Map(key, elem) => format!("map[{}]{}", key.name(), elem.name()),
+            Type::Struct(name, _) => name.to_string(),
+        }
+    }
+}
+
+/// Names of the fields of a tuple whose elements are of different types
+const TUPLE_FIELDS: [&str; 32] = [
+    "F0", "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12", "F13", "F14",
+    "F15", "F16", "F17", "F18", "F19", "F20", "F21", "F22", "F23", "F24", "F25", "F26", "F27",
+    "F28", "F29", "F30", "F31",
+];
+
+/// A tuple is a slice if its elements are of the same type, ie. an array, and otherwise a
+/// struct whose fields are named by their positions
+fn tuple(name: &'static str, items: Vec<Ser>) -> Result<Ser, GobError> {
+    let items = match Ser::Seq(items) {
+        seq if seq.ty().is_ok() => return Ok(seq),
+        Ser::Seq(items) => items,
+        _ => unreachable!(),
+    };
+    if items.len() > TUPLE_FIELDS.len() {
+        return Err(GobError::new("tuples of different types are too long"));
+    }
+    Ok(Ser::Struct {
+        name,
+        fields: TUPLE_FIELDS.iter().copied().zip(items).collect(),
+        variant: false,
+    })
+}
+
+/// A value that is serialized and whose type is inferred, which is ready to be encoded
+pub(crate) struct Item {
+    value: Ser,
+    ty: Type,
+}
+
+impl Item {
+    /// Serializes a value. The encoder is not touched until the value is encoded, so a
+    /// value that cannot be serialized leaves no definition behind
+    pub fn new<S: Serialize + ?Sized>(val: &S) -> Result<Self, GobError> {
+        let value = val.serialize(Serializer)?;
+        let ty = value.ty()?;
+        Ok(Self { value, ty })
+    }
+}
+
+/// Writes the fields of a struct, which are numbered from 0 and are sent as the delta
+/// from the previous field. The struct ends with a zero delta
+struct Fields<'a> {
+    buf: &'a mut Vec<u8>,
+    last: i64,
+}
+
+impl<'a> Fields<'a> {
+    fn new(buf: &'a mut Vec<u8>) -> Self {
+        Self { buf, last: -1 }
+    }
+
+    fn field(&mut self, num: i64) -> &mut Vec<u8> {
+        encode_uint(self.buf, (num - self.last) as u64);
+        self.last = num;
+        self.buf
+    }
+
+    fn end(self) {
+        self.buf.push(0);
+    }
+}
+
+/// Writes the `CommonType` of a definition
+fn common_type(buf: &mut Vec<u8>, name: &str, id: TypeId) {
+    let mut fields = Fields::new(buf);
+    if !name.is_empty() {
+        encode_bytes(fields.field(0), name.as_bytes());
+    }
+    encode_int(fields.field(1), id);
+    fields.end();
+}
+
+/// Encoder of a gob stream, which defines each type once
+pub(crate) struct Encoder {
+    ids: HashMap<Type, TypeId>,
+    next_id: TypeId,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            next_id: FIRST_USER_ID,
+        }
+    }
+
+    /// Appends the definitions of the types that are new to the stream and the message of
+    /// the value to `buf`
+    pub fn encode(&mut self, item: &Item, buf: &mut Vec<u8>) {
+        let id = self.type_id(&item.ty, buf);
+        let mut msg = Vec::new();
+        encode_int(&mut msg, id);
+        match (&item.value, &item.ty) {
+            (
+                Ser::Struct {
+                    fields, variant, ..
+                },
+                Type::Struct(_, types),
+            ) => encode_struct(&mut msg, fields, *variant, types),
+            (value, ty) => {
+                // a value that is not a struct is sent as the only field of a struct
+                msg.push(0);
+                encode_value(&mut msg, value, ty);
+            }
+        }
+        encode_bytes(buf, &msg);
+    }
+
+    fn type_id(&mut self, ty: &Type, buf: &mut Vec<u8>) -> TypeId {
+        match ty {
+            Type::Unknown => return INTERFACE_ID,
+            Type::Bool => return BOOL_ID,
+            Type::Int => return INT_ID,
+            Type::Uint | Type::Byte => return UINT_ID,
+            Type::Float => return FLOAT_ID,
+            Type::String => return STRING_ID,
+            Type::Bytes => return BYTES_ID,
+            _ => (),
+        }
+        if let Some(id) = self.ids.get(ty) {
+            return *id;
+        }
+
+        // the types of the elements are defined first
+        let mut def = Vec::new();
+        let id = match ty {
+            Type::Slice(elem) => {
+                let elem = self.type_id(elem, buf);
+                let id = self.next_id;
+                let mut wire = Fields::new(&mut def);
+                let mut slice = Fields::new(wire.field(WIRE_SLICE));
+                common_type(slice.field(0), &ty.name(), id);
+                encode_int(slice.field(1), elem);
+                slice.end();
+                wire.end();
+                id
+            }
+            Type::Map(key, elem) => {
+                let key = self.type_id(key, buf);
+                let elem = self.type_id(elem, buf);
+                let id = self.next_id;
+                let mut wire = Fields::new(&mut def);
+                let mut map = Fields::new(wire.field(WIRE_MAP));
+                common_type(map.field(0), &ty.name(), id);
+                encode_int(map.field(1), key);
+                encode_int(map.field(2), elem);
+                map.end();
+                wire.end();
+                id
+            }
+            Type::Struct(name, fields) => {
+                let ids: Vec<_> = fields.iter().map(|(_, ty)| self.type_id(ty, buf)).collect();
+                let id = self.next_id;
+                let mut wire = Fields::new(&mut def);
+                let mut st = Fields::new(wire.field(WIRE_STRUCT));
+                common_type(st.field(0), name, id);
+                if !fields.is_empty() {
+                    let list = st.field(1);
+                    encode_uint(list, fields.len() as u64);
+                    for ((field, _), id) in fields.iter().zip(ids) {
+                        let mut field_type = Fields::new(list);
+                        encode_bytes(field_type.field(0), field.as_bytes());
+                        encode_int(field_type.field(1), id);
+                        field_type.end();
+                    }
+                }
+                st.end();
+                wire.end();
+                id
+            }
+            _ => unreachable!("Predefined types are not defined"),
+        };
+        self.next_id += 1;
+        self.ids.insert(ty.clone(), id);
+
+        let mut msg = Vec::with_capacity(def.len() + 10);
+        encode_int(&mut msg, -id);
+        msg.extend_from_slice(&def);
+        encode_bytes(buf, &msg);
+        id
+    }
+}
+
+fn encode_struct(
+    buf: &mut Vec<u8>,
+    values: &[(&'static str, Ser)],
+    variant: bool,
+    types: &[(&'static str, Type)],
+) {
+    let mut fields = Fields::new(buf);
+    for (num, (field, ty)) in types.iter().enumerate() {
+        let value = match values.iter().find(|(name, _)| name == field) {
+            Some((_, value)) if variant || !value.is_zero() => value,
+            _ => continue,
+        };
+        encode_value(fields.field(num as i64), value, ty);
+    }
+    fields.end();
+}
+
+fn encode_value(buf: &mut Vec<u8>, value: &Ser, ty: &Type) {
+    match (value, ty) {
+        (Ser::Bool(val), _) => encode_uint(buf, *val as u64),
+        (Ser::Int(val), _) => encode_int(buf, *val),
+        (Ser::Uint(val), _) => encode_uint(buf, *val),
+        (Ser::Byte(val), _) => encode_uint(buf, *val as u64),
+        (Ser::Float(val), _) => encode_float(buf, *val),
+        (Ser::String(val), _) => encode_bytes(buf, val.as_bytes()),
+        (Ser::Bytes(val), _) => encode_bytes(buf, val),
+        (Ser::Seq(items), Type::Bytes) => {
+            let bytes: Vec<u8> = items
+                .iter()
+                .map(|item| match item {
+                    Ser::Byte(byte) => *byte,
+                    _ => 0,
+                })
+                .collect();
+            encode_bytes(buf, &bytes)
+        }
+        (Ser::Seq(items), Type::Slice(elem)) => {
+            encode_uint(buf, items.len() as u64);
+            for item in items {
+                encode_value(buf, item, elem);
+            }
+        }
+        (Ser::Map(entries), Type::Map(key, elem)) => {
+            encode_uint(buf, entries.len() as u64);
+            for (k, v) in entries {
+                encode_value(buf, k, key);
+                encode_value(buf, v, elem);
+            }
+        }
+        (
+            Ser::Struct {
+                fields, variant, ..
+            },
+            Type::Struct(_, types),
+        ) => encode_struct(buf, fields, *variant, types),
+        // the zero value of every type, including a nil `interface{}`, is a zero byte
+        _ => buf.push(0),
+    }
+}
+
+/// Serializes a value into `Ser`
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Ser;
+    type Error = GobError;
+
+    type SerializeSeq = SerializeSeq;
+    type SerializeTuple = SerializeTuple;
+    type SerializeTupleStruct = SerializeTuple;
+    type SerializeTupleVariant = SerializeTupleVariant;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeStruct;
+    type SerializeStructVariant = SerializeStructVariant;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<Ser, GobError> {
+        Ok(Ser::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Ser, GobError> {
+        Ok(Ser::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Ser, GobError> {
+        Ok(Ser::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Ser, GobError> {
+        Ok(Ser::Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Ser, GobError> {
+        Ok(Ser::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Ser, GobError> {
+        Ok(Ser::Byte(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Ser, GobError> {
+        Ok(Ser::Uint(v as u64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Ser, GobError> {
+        Ok(Ser::Uint(v as u64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Ser, GobError> {
+        Ok(Ser::Uint(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Ser, GobError> {
+        Ok(Ser::Float(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Ser, GobError> {
+        Ok(Ser::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Ser, GobError> {
+        Ok(Ser::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Ser, GobError> {
+        Ok(Ser::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Ser, GobError> {
+        Ok(Ser::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Ser, GobError> {
+        Ok(Ser::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Ser, GobError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Ser, GobError> {
+        Ok(Ser::unit())
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Ser, GobError> {
+        Ok(Ser::Struct {
+            name,
+            fields: Vec::new(),
+            variant: false,
+        })
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Ser, GobError> {
+        Ok(Ser::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Ser, GobError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Ser, GobError> {
+        Ok(Ser::variant(name, variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeSeq, GobError> {
+        Ok(SerializeSeq(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeTuple, GobError> {
+        Ok(SerializeTuple {
+            name: "",
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<SerializeTuple, GobError> {
+        Ok(SerializeTuple {
+            name,
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeTupleVariant, GobError> {
+        Ok(SerializeTupleVariant {
+            name,
+            variant,
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeMap, GobError> {
+        Ok(SerializeMap {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<SerializeStruct, GobError> {
+        Ok(SerializeStruct {
+            name,
+            fields: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeStructVariant, GobError> {
+        Ok(SerializeStructVariant {
+            name,
+            variant,
+            fields: Vec::with_capacity(len),
+        })
+    }
+}
+
+struct SerializeSeq(Vec<Ser>);
+
+impl ser::SerializeSeq for SerializeSeq {
+    type Ok = Ser;
+    type Error = GobError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), GobError> {
+        self.0.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ser, GobError> {
+        Ok(Ser::Seq(self.0))
+    }
+}
+
+struct SerializeTuple {
+    name: &'static str,
+    items: Vec<Ser>,
+}
+
+impl ser::SerializeTuple for SerializeTuple {
+    type Ok = Ser;
+    type Error = GobError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), GobError> {
+        self.items.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ser, GobError> {
+        tuple(self.name, self.items)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeTuple {
+    type Ok = Ser;
+    type Error = GobError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), GobError> {
+        ser::SerializeTuple::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Ser, GobError> {
+        ser::SerializeTuple::end(self)
+    }
+}
+
+struct SerializeTupleVariant {
+    name: &'static str,
+    variant: &'static str,
+    items: Vec<Ser>,
+}
+
+impl ser::SerializeTupleVariant for SerializeTupleVariant {
+    type Ok = Ser;
+    type Error = GobError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), GobError> {
+        self.items.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ser, GobError> {
+        Ok(Ser::variant(
+            self.name,
+            self.variant,
+            tuple("", self.items)?,
+        ))
+    }
+}
+
+struct SerializeMap {
+    entries: Vec<(Ser, Ser)>,
+    key: Option<Ser>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Ser;
+    type Error = GobError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), GobError> {
+        self.key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), GobError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| GobError::new("value of a map is serialized before its key"))?;
+        self.entries.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ser, GobError> {
+        Ok(Ser::Map(self.entries))
+    }
+}
+
+struct SerializeStruct {
+    name: &'static str,
+    fields: Vec<(&'static str, Ser)>,
+}
+
+impl ser::SerializeStruct for SerializeStruct {
+    type Ok = Ser;
+    type Error = GobError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), GobError> {
+        self.fields.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ser, GobError> {
+        Ok(Ser::Struct {
+            name: self.name,
+            fields: self.fields,
+            variant: false,
+        })
+    }
+}
+
+struct SerializeStructVariant {
+    name: &'static str,
+    variant: &'static str,
+    fields: Vec<(&'static str, Ser)>,
+}
+
+impl ser::SerializeStructVariant for SerializeStructVariant {
+    type Ok = Ser;
+    type Error = GobError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), GobError> {
+        self.fields.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ser, GobError> {
+        let value = Ser::Struct {
+            name: self.variant,
+            fields: self.fields,
+            variant: false,
+        };
+        Ok(Ser::variant(self.name, self.variant, value))
+    }
+}
@@ -1,6 +1,13 @@
 //! Impplementation of `CodecRead`, `CodecWrite`, `Marshal`, `Unmarshal` and `EraseDeserializer` traits with `serde_json`
 
 use cfg_if::cfg_if;
+use erased_serde as erased;
+use serde::de::Visitor;
+use std::io::Cursor; // serde doesn't support AsyncRead
+
+use super::{DeserializerOwned, EraseDeserializer, Marshal, Unmarshal};
+use crate::error::ParseError;
+use crate::macros::impl_inner_deserializer;
 
 cfg_if! {
     if #[cfg(any(
@@ -16,62 +23,46 @@ cfg_if! {
     }
 }
 
-cfg_if! {
-    if #[cfg(feature = "serde_bincode")] {
-
-    } else if #[cfg(feature = "serde_cbor")] {
-
-    } else if #[cfg(feature = "serde_rmp")] {
-
-    } else {
-        use erased_serde as erased;
-        use serde::de::Visitor;
-        use std::io::Cursor; // serde doesn't support AsyncRead
-
-        use super::{
-            Codec, CodecRead, CodecWrite, DeserializerOwned, EraseDeserializer, Marshal, Unmarshal,
-        };
-        use crate::error::ParseError;
-        use crate::macros::impl_inner_deserializer;
-        use crate::message::{MessageId, Metadata};
+/// The `serde_json` format. Messages are line-delimited on the raw TCP transport
+pub struct Json {}
 
-        use super::ConnTypeReadWrite;
+impl<'de, R> serde::Deserializer<'de> for DeserializerOwned<serde_json::Deserializer<R>>
+where
+    R: serde_json::de::Read<'de>,
+{
+    type Error = <&'de mut serde_json::Deserializer<R> as serde::Deserializer<'de>>::Error;
 
-        impl<'de, R> serde::Deserializer<'de> for DeserializerOwned<serde_json::Deserializer<R>>
-        where
-            R: serde_json::de::Read<'de>,
-        {
-            type Error = <&'de mut serde_json::Deserializer<R> as serde::Deserializer<'de>>::Error;
+    // the rest is simply calling self.inner.deserialize_xxx()
+    // use a macro to generate the code
+    impl_inner_deserializer!();
+}
 
-            // the rest is simply calling self.inner.deserialize_xxx()
-            // use a macro to generate the code
-            impl_inner_deserializer!();
-        }
+impl Marshal for Json {
+    fn codec_name() -> &'static str {
+        "json"
+    }
 
-        impl<R, W, C> Marshal for Codec<R, W, C> {
-            fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
-                serde_json::to_vec(val)
-                    .map(|mut v| {
-                        v.push(b'\n');
-                        v
-                    })
-                    .map_err(|e| e.into())
-            }
-        }
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        serde_json::to_vec(val)
+            .map(|mut v| {
+                v.push(b'\n');
+                v
+            })
+            .map_err(|e| e.into())
+    }
+}
 
-        impl<R, W, C> Unmarshal for Codec<R, W, C> {
-            fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
-                serde_json::from_slice(buf).map_err(|e| e.into())
-            }
-        }
+impl Unmarshal for Json {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        serde_json::from_slice(buf).map_err(|e| e.into())
+    }
+}
 
-        impl<R, W, C> EraseDeserializer for Codec<R, W, C> {
-            fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
-                let de = serde_json::Deserializer::from_reader(Cursor::new(buf));
+impl EraseDeserializer for Json {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        let de = serde_json::Deserializer::from_reader(Cursor::new(buf));
 
-                let de_owned = DeserializerOwned::new(de);
-                Box::new(<dyn erased::Deserializer>::erase(de_owned))
-            }
-        }
+        let de_owned = DeserializerOwned::new(de);
+        Box::new(<dyn erased::Deserializer>::erase(de_owned))
     }
 }
@@ -1,165 +1,201 @@
 //! Implements json codec with `tokio` runtime
 
-use cfg_if::cfg_if;
-
 use crate::error::{CodecError, IoError};
 
-cfg_if! {
-    if #[cfg(feature = "serde_bincode")] {
-
-    } else if #[cfg(feature = "serde_cbor")] {
-
-    } else if #[cfg(feature = "serde_rmp")] {
-
-    } else {
-        use ::tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
-        use std::marker::PhantomData;
-        use async_trait::async_trait;
-
-        use super::*;
-        use crate::codec::split::{SplittableCodec};
-        use crate::codec::split::{CodecReadHalf, CodecWriteHalf};
-        use crate::util::GracefulShutdown;
-
-        #[async_trait]
-        impl<R, C> CodecRead for CodecReadHalf<R, C, ConnTypeReadWrite>
-        where
-            R: AsyncBufRead + Send + Unpin,
-            C: Unmarshal + EraseDeserializer + Send,
-        {
-            // async fn read_header<H>(&mut self) -> Option<Result<H, Error>>
-            // where
-            //     H: serde::de::DeserializeOwned,
-            // {
-            //     let mut buf = String::new();
-            //     match self.reader.read_line(&mut buf).await {
-            //         Ok(n) => {
-            //             if n == 0 {
-            //                 // EOF, probably end of connection
-            //                 return None;
-            //             }
-
-            //             Some(Self::unmarshal(buf.as_bytes()))
-            //         }
-            //         Err(err) => {
-            //             Some(Err(err.into()))
-            //         }
-            //     }
-            // }
-
-            // async fn read_body(
-            //     &mut self
-            // ) -> Option<Result<Box<InboundBody>, Error>> {
-            //     let mut buf = String::new();
-            //     match self.reader.read_line(&mut buf).await {
-            //         Ok(n) => {
-            //             if n == 0 {
-            //                 // EOF, probably client closed connection
-            //                 return None;
-            //             }
-
-            //             let de = Self::from_bytes(buf.into_bytes());
-            //             Some(Ok(de))
-            //         }
-            //         Err(err) => return Some(Err(err.into())),
-            //     }
-            // }
-
-            async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
-                let mut buf = String::new();
-                match self.reader.read_line(&mut buf).await {
-                    Ok(n) => {
-                        if n == 0 {
-                            // EOF, probably client closed connection
-                            return None;
-                        }
-
-                        Some(Ok(buf.into_bytes()))
-                    }
-                    Err(err) => return Some(Err(err.into())),
+use ::tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use async_trait::async_trait;
+use erased_serde as erased;
+use std::marker::PhantomData;
+
+use super::Json;
+use crate::codec::split::SplittableCodec;
+use crate::codec::split::{CodecReadHalf, CodecWriteHalf};
+use crate::codec::{
+    Codec, CodecRead, CodecWrite, ConnTypeReadWrite, EraseDeserializer, Marshal, Unmarshal,
+};
+use crate::message::{MessageId, Metadata};
+use crate::protocol::Header;
+use crate::transport::MessageSizeLimit;
+use crate::util::GracefulShutdown;
+
+#[async_trait]
+impl<R, C> CodecRead for CodecReadHalf<R, C, ConnTypeReadWrite>
+where
+    R: AsyncBufRead + Send + Unpin,
+    C: Unmarshal + EraseDeserializer + Send,
+{
+    // async fn read_header<H>(&mut self) -> Option<Result<H, Error>>
+    // where
+    //     H: serde::de::DeserializeOwned,
+    // {
+    //     let mut buf = String::new();
+    //     match self.reader.read_line(&mut buf).await {
+    //         Ok(n) => {
+    //             if n == 0 {
+    //                 // EOF, probably end of connection
+    //                 return None;
+    //             }
+
+    //             Some(Self::unmarshal(buf.as_bytes()))
+    //         }
+    //         Err(err) => {
+    //             Some(Err(err.into()))
+    //         }
+    //     }
+    // }
+
+    // async fn read_body(
+    //     &mut self
+    // ) -> Option<Result<Box<InboundBody>, Error>> {
+    //     let mut buf = String::new();
+    //     match self.reader.read_line(&mut buf).await {
+    //         Ok(n) => {
+    //             if n == 0 {
+    //                 // EOF, probably client closed connection
+    //                 return None;
+    //             }
+
+    //             let de = Self::from_bytes(buf.into_bytes());
+    //             Some(Ok(de))
+    //         }
+    //         Err(err) => return Some(Err(err.into())),
+    //     }
+    // }
+
+    async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
+        if self.closed {
+            return None;
+        }
+        let limit = self.size_limit;
+        let mut buf = Vec::new();
+        let mut oversized = false;
+        loop {
+            let available = match self.reader.fill_buf().await {
+                Ok(available) => available,
+                Err(err) => return Some(Err(err)),
+            };
+            if available.is_empty() {
+                if buf.is_empty() && !oversized {
+                    // EOF, probably client closed connection
+                    return None;
+                }
+                break;
+            }
+            let (len, done) = match available.iter().position(|b| *b == b'\n') {
+                Some(pos) => (pos + 1, true),
+                None => (available.len(), false),
+            };
+
+            // the rest of an oversized line is discarded without holding it in memory
+            if !oversized && buf.len() + len > limit.max {
+                if limit.close {
+                    self.closed = true;
+                    return Some(Err(limit.error()));
                 }
+                oversized = true;
+                buf = Vec::new();
+            }
+            if !oversized {
+                buf.extend_from_slice(&available[..len]);
+            }
+            AsyncBufReadExt::consume(&mut self.reader, len);
+            if done {
+                break;
             }
         }
+        match oversized {
+            true => Some(Err(limit.error())),
+            false => Some(Ok(buf)),
+        }
+    }
 
-        #[async_trait]
-        impl<W, C> CodecWrite for CodecWriteHalf<W, C, ConnTypeReadWrite>
-        where
-            W: AsyncWrite + Send + Unpin,
-            C: Marshal + Send,
-        {
-            async fn write_header<H>(&mut self, header: H) -> Result<(), CodecError>
-            where
-                H: serde::Serialize + Metadata + Send,
-            {
-                let _ = header.id();
-                let buf = Self::marshal(&header)?;
-
-                let _ = self.writer.write(&buf).await?;
-                self.writer.flush().await?;
-
-                Ok(())
-            }
+    fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+        self.size_limit = limit;
+    }
+}
 
-            async fn write_body(
-                &mut self,
-                _id: MessageId,
-                body: &(dyn erased::Serialize + Send + Sync),
-            ) -> Result<(), CodecError> {
-                let buf = Self::marshal(&body)?;
+#[async_trait]
+impl<W, C> CodecWrite for CodecWriteHalf<LineWriter<W>, C, ConnTypeReadWrite>
+where
+    W: AsyncWrite + Send + Unpin,
+    C: Marshal + Send,
+{
+    async fn write_header(&mut self, header: Header) -> Result<(), CodecError> {
+        let _ = header.id();
+        let buf = Self::marshal(&header)?;
 
-                let _ = self.writer.write(&buf).await?;
-                self.writer.flush().await?;
+        self.writer.0.write_all(&buf).await?;
+        self.writer.0.flush().await?;
 
-                Ok(())
-            }
+        Ok(())
+    }
 
-            async fn write_body_bytes(&mut self, _: MessageId, bytes: &[u8]) -> Result<(), IoError> {
-                let _ = self.writer.write(bytes).await?;
-                self.writer.flush().await?;
-                Ok(())
-            }
-        }
+    async fn write_body(
+        &mut self,
+        _id: MessageId,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<(), CodecError> {
+        let buf = Self::marshal(&body)?;
 
-        impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite>
-        where
-            R: AsyncBufRead + Send + Unpin,
-            W: AsyncWrite + GracefulShutdown + Send + Unpin,
-        {
-            type Reader = CodecReadHalf::<R, Self, ConnTypeReadWrite>;
-            type Writer = CodecWriteHalf::<W, Self, ConnTypeReadWrite>;
-
-            fn split(self) -> (Self::Writer, Self::Reader) {
-                (
-                    CodecWriteHalf::<W, Self, ConnTypeReadWrite> {
-                        writer: self.writer,
-                        marker: PhantomData,
-                        conn_type: PhantomData,
-                    },
-                    CodecReadHalf::<R, Self, ConnTypeReadWrite> {
-                        reader: self.reader,
-                        marker: PhantomData,
-                        conn_type: PhantomData
-                    }
-                )
-            }
-        }
+        self.writer.0.write_all(&buf).await?;
+        self.writer.0.flush().await?;
 
-        #[async_trait]
-        impl<T> GracefulShutdown for T
-        where
-            T: AsyncWrite + Send + Unpin,
-        {
-            async fn close(&mut self) {
-                match self.flush().await {
-                    Ok(()) => (),
-                    Err(e) => log::error!("Error closing connection: {}", e),
-                }
+        Ok(())
+    }
 
-                match AsyncWriteExt::shutdown(self).await {
-                    Ok(()) => (),
-                    Err(e) => log::error!("Error closing connection: {}", e),
-                }
-            }
+    async fn write_body_bytes(&mut self, _: MessageId, bytes: &[u8]) -> Result<(), IoError> {
+        self.writer.0.write_all(bytes).await?;
+        self.writer.0.flush().await?;
+        Ok(())
+    }
+}
+
+impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite, Json>
+where
+    R: AsyncBufRead + Send + Unpin,
+    W: AsyncWrite + Send + Unpin,
+{
+    type Reader = CodecReadHalf<R, Self, ConnTypeReadWrite>;
+    type Writer = CodecWriteHalf<LineWriter<W>, Self, ConnTypeReadWrite>;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        (
+            CodecWriteHalf::<LineWriter<W>, Self, ConnTypeReadWrite> {
+                writer: LineWriter(self.writer),
+                marker: PhantomData,
+                conn_type: PhantomData,
+                compression: Default::default(),
+            },
+            CodecReadHalf::<R, Self, ConnTypeReadWrite> {
+                reader: self.reader,
+                marker: PhantomData,
+                conn_type: PhantomData,
+                compression: Default::default(),
+                size_limit: Default::default(),
+                closed: false,
+            },
+        )
+    }
+}
+
+/// Writing half of the line-delimited transport, which is shut down without the trailer
+/// frame of the frame transport
+pub(crate) struct LineWriter<W>(W);
+
+#[async_trait]
+impl<W> GracefulShutdown for LineWriter<W>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    async fn close(&mut self) {
+        match self.0.flush().await {
+            Ok(()) => (),
+            Err(e) => log::error!("Error closing connection: {}", e),
+        }
+
+        match AsyncWriteExt::shutdown(&mut self.0).await {
+            Ok(()) => (),
+            Err(e) => log::error!("Error closing connection: {}", e),
         }
     }
 }
@@ -0,0 +1,907 @@
+//! JSON-RPC 2.0
+//!
+//! A request is an object with the `jsonrpc`, `method`, `params` and `id` members, and a
+//! request without `id` is a notification, which is not answered. A response is an object
+//! with the `jsonrpc` and `id` members and either a `result` or an `error` member. Several
+//! requests can be sent in an array, which is a batch, and the responses to a batch are
+//! sent back in one array once all of them are ready. There is no handshake.
+//!
+//! The reading and writing halves translate these objects from and into the messages of
+//! `toy-rpc`, so that JSON-RPC clients can call the services of a `Server` and a `Client`
+//! can call a JSON-RPC server. The method is named "{Service}.{Method}" on both sides. The
+//! argument of a method is `params`, where an array holding a single element is unwrapped
+//! unless the method takes a sequence, so that both `"params": [7]` and `"params": 7` can
+//! call a method that takes an integer. Likewise, a `Client` sends an argument that is
+//! neither an array nor an object as the only element of `params`.
+//!
+//! The errors are sent as the error objects of the specification:
+//!
+//! - a missing service or method is -32601 (method not found)
+//! - an argument that cannot be deserialized is -32602 (invalid params)
+//! - an internal error is -32603 (internal error)
+//! - an object that is not a request is -32600 (invalid request) and a message that is
+//!   not JSON is -32700 (parse error)
+//! - any other error is -32000 with the message of the error, and the payload of a typed
+//!   error is sent as `data`
+//!
+//! Only unary calls and notifications can be carried, and the other messages, ie.
+//! streaming calls, pub/sub and extensions, fail to be written. The objects are
+//! line-delimited on the raw TCP transport, so a batch is written on a single line, and
+//! they are sent as text messages on WebSocket.
+
+use erased_serde as erased;
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+
+use super::json::Json;
+use super::split::SplittableCodec;
+use super::translate::{self, Protocol, Translate, TranslateReadHalf, TranslateWriteHalf};
+use super::{Codec, ConnTypeReadWrite, Marshal, Unmarshal};
+use crate::error::{CodecError, Error, IoError, ParseError};
+use crate::message::{ErrorMessage, MessageId};
+use crate::protocol::{Header, InboundBody, MetadataMap, FEATURE_NOTIFY};
+use crate::status::{Code, Status};
+
+/// The JSON-RPC 2.0 protocol. The objects are serialized with `serde_json`
+pub struct JsonRpc {}
+
+impl Marshal for JsonRpc {
+    fn codec_name() -> &'static str {
+        "jsonrpc"
+    }
+
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        Json::marshal(val)
+    }
+}
+
+impl Unmarshal for JsonRpc {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        Json::unmarshal(buf)
+    }
+}
+
+/// Value of the `jsonrpc` member
+const VERSION: &str = "2.0";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Code of the errors that are defined by the server
+const SERVER_ERROR: i64 = -32000;
+
+/// Error object of JSON-RPC 2.0
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorObject {
+    code: i64,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl ErrorObject {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Converts the message of a failed response. The status is only used if the message
+    /// carries no more than the status itself
+    fn from_err_msg(msg: ErrorMessage, status: &Status) -> Self {
+        match msg {
+            ErrorMessage::InvalidArgument => Self::new(INVALID_PARAMS, "Invalid params"),
+            ErrorMessage::ServiceNotFound | ErrorMessage::MethodNotFound => {
+                Self::new(METHOD_NOT_FOUND, "Method not found")
+            }
+            ErrorMessage::ExecutionError(message) => Self::new(SERVER_ERROR, message),
+            ErrorMessage::Typed { message, payload } => Self {
+                code: SERVER_ERROR,
+                message,
+                data: serde_json::from_slice(&payload).ok(),
+            },
+            ErrorMessage::Internal(message) => Self::new(INTERNAL_ERROR, message),
+            ErrorMessage::Canceled | ErrorMessage::Timeout | ErrorMessage::Status(_) => {
+                Self::from_status(status)
+            }
+        }
+    }
+
+    fn from_status(status: &Status) -> Self {
+        let code = match status.code() {
+            Code::Unimplemented => METHOD_NOT_FOUND,
+            Code::InvalidArgument => INVALID_PARAMS,
+            Code::Internal => INTERNAL_ERROR,
+            _ => SERVER_ERROR,
+        };
+        let message = match status.message() {
+            Some(message) => message.into(),
+            None => status.code().to_string(),
+        };
+        Self::new(code, message)
+    }
+
+    /// Converts the error object of a JSON-RPC server into the status and the body of a
+    /// response
+    fn into_err_msg(self) -> (Status, ErrorMessage) {
+        let Self {
+            code,
+            message,
+            data,
+        } = self;
+        match (code, data) {
+            (METHOD_NOT_FOUND, _) => (Error::MethodNotFound.status(), ErrorMessage::MethodNotFound),
+            (INVALID_PARAMS, _) => (
+                Error::InvalidArgument.status(),
+                ErrorMessage::InvalidArgument,
+            ),
+            (PARSE_ERROR, _) | (INVALID_REQUEST, _) | (INTERNAL_ERROR, _) => (
+                Status::new(Code::Internal, message.clone()),
+                ErrorMessage::Internal(message),
+            ),
+            (_, Some(data)) => (
+                Status::new(Code::Unknown, message.clone()),
+                ErrorMessage::Typed {
+                    message,
+                    payload: serde_json::to_vec(&data).unwrap_or_default(),
+                },
+            ),
+            (_, None) => (
+                Error::ExecutionError(message.clone()).status(),
+                ErrorMessage::ExecutionError(message),
+            ),
+        }
+    }
+}
+
+/// Request of the peer that is served with a message id of this side
+struct InboundRequest {
+    /// Id of the request, which can be any JSON value and is sent back as is
+    id: Value,
+    /// Batch that the request belongs to
+    batch: Option<u64>,
+    /// Error that is sent instead of the response of the server, which is only told that
+    /// the request is invalid
+    error: Option<ErrorObject>,
+}
+
+/// Responses to a batch, which are sent together once all of them are written
+struct Batch {
+    remaining: usize,
+    responses: Vec<Value>,
+}
+
+/// State of the requests from the peer, which is shared by the reading and writing halves
+#[derive(Default)]
+pub(crate) struct Exchange {
+    requests: HashMap<MessageId, InboundRequest>,
+    batches: HashMap<u64, Batch>,
+    next_batch: u64,
+    next_id: MessageId,
+}
+
+/// Request or response object of JSON-RPC 2.0
+#[derive(Deserialize)]
+struct InboundObject {
+    jsonrpc: Option<String>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    #[serde(default, deserialize_with = "present")]
+    result: Option<Value>,
+    error: Option<ErrorObject>,
+}
+
+/// Tells a member that is `null` from a missing one
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// Request object of JSON-RPC 2.0
+#[derive(Serialize)]
+struct RequestObject<'a> {
+    jsonrpc: &'static str,
+    method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<MessageId>,
+}
+
+/// Response object of JSON-RPC 2.0
+#[derive(Serialize)]
+struct ResponseObject {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorObject>,
+    id: Value,
+}
+
+fn parse_error(err: serde_json::Error) -> CodecError {
+    CodecError::ParseError(Box::new(err))
+}
+
+/// Body of an object whose header is read
+pub(crate) enum ObjectBody {
+    /// `params` of a request
+    Params(Value),
+    /// `result` of a response or the message of its error
+    Value(Value),
+}
+
+/// Deserializer of the argument of a method from `params`. An array holding a single
+/// element is unwrapped unless a sequence is expected, and an empty `params` is taken as
+/// the unit type or `None`
+struct Params(Value);
+
+impl Params {
+    fn unwrap(self) -> Value {
+        match self.0 {
+            Value::Array(mut items) if items.len() == 1 => items.remove(0),
+            value => value,
+        }
+    }
+
+    /// Unwraps an array holding a single object, because an array can also be deserialized
+    /// into a struct
+    fn unwrap_object(self) -> Value {
+        match self.0 {
+            Value::Array(mut items) if items.len() == 1 && items[0].is_object() => items.remove(0),
+            value => value,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.0 {
+            Value::Null => true,
+            Value::Array(items) => items.iter().all(Value::is_null),
+            _ => false,
+        }
+    }
+}
+
+macro_rules! forward_unwrapped {
+    ($($method:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.unwrap().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Params {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_any(visitor)
+    }
+
+    forward_unwrapped!(
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_identifier
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.is_empty() {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.is_empty() {
+            true => visitor.visit_unit(),
+            false => self.0.deserialize_unit(visitor),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.unwrap_object().deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.unwrap_object()
+            .deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.unwrap().deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_ignored_any(visitor)
+    }
+}
+
+impl Exchange {
+    /// Translates a message, which is an object or a batch of objects
+    fn translate_message(&mut self, buf: &[u8]) -> Vec<(Header, ObjectBody)> {
+        match serde_json::from_slice(buf) {
+            Err(err) => vec![self.invalid(Value::Null, None, PARSE_ERROR, err.to_string())],
+            Ok(Value::Array(objects)) if objects.is_empty() => {
+                vec![self.invalid(Value::Null, None, INVALID_REQUEST, "Empty batch")]
+            }
+            Ok(Value::Array(objects)) => {
+                let batch = self.next_batch;
+                self.next_batch = batch.wrapping_add(1);
+                let messages: Vec<_> = objects
+                    .into_iter()
+                    .filter_map(|object| self.translate(object, Some(batch)))
+                    .collect();
+                // the responses are sent once the server answers all requests in the batch
+                let remaining = messages
+                    .iter()
+                    .filter(|(header, _)| matches!(header, Header::Request { .. }))
+                    .count();
+                if remaining > 0 {
+                    self.batches.insert(
+                        batch,
+                        Batch {
+                            remaining,
+                            responses: Vec::new(),
+                        },
+                    );
+                }
+                messages
+            }
+            Ok(object) => self.translate(object, None).into_iter().collect(),
+        }
+    }
+
+    /// Translates an object into a header and its body. A response to an unknown request
+    /// is dropped
+    fn translate(&mut self, object: Value, batch: Option<u64>) -> Option<(Header, ObjectBody)> {
+        let object: InboundObject = match serde_json::from_value(object) {
+            Ok(object) => object,
+            Err(err) => {
+                return Some(self.invalid(Value::Null, batch, INVALID_REQUEST, err.to_string()))
+            }
+        };
+        let InboundObject {
+            jsonrpc,
+            method,
+            params,
+            id,
+            result,
+            error,
+        } = object;
+
+        let service_method = match (method, &result, &error) {
+            (Some(service_method), _, _) if jsonrpc.as_deref() == Some(VERSION) => service_method,
+            // only the responses to the requests of this side carry no method
+            (None, Some(_), _) | (None, _, Some(_)) => {
+                let id = match id.as_ref().and_then(Value::as_u64) {
+                    Some(id) => id,
+                    None => {
+                        log::error!("Dropping JSON-RPC response with unknown id {:?}", id);
+                        return None;
+                    }
+                };
+                let (status, body) = match error {
+                    None => (Status::ok(), result.unwrap_or_default()),
+                    Some(error) => {
+                        let (status, msg) = error.into_err_msg();
+                        (status, serde_json::to_value(&msg).unwrap_or_default())
+                    }
+                };
+                let header = Header::Response {
+                    id,
+                    status,
+                    metadata: MetadataMap::new(),
+                };
+                return Some((header, ObjectBody::Value(body)));
+            }
+            _ => {
+                let id = id.unwrap_or_default();
+                return Some(self.invalid(id, batch, INVALID_REQUEST, "Invalid request"));
+            }
+        };
+
+        let seq = self.next_seq();
+        let metadata = MetadataMap::new();
+        let header = match id {
+            // a request without id is a notification, which expects no response
+            None => Header::Notify {
+                id: seq,
+                service_method,
+                metadata,
+            },
+            Some(id) => {
+                self.requests.insert(
+                    seq,
+                    InboundRequest {
+                        id,
+                        batch,
+                        error: None,
+                    },
+                );
+                Header::Request {
+                    id: seq,
+                    service_method,
+                    timeout: None,
+                    metadata,
+                }
+            }
+        };
+        Some((header, ObjectBody::Params(params)))
+    }
+
+    /// The server is asked for a method without name, which fails, and the error of an
+    /// invalid request is sent instead of its response
+    fn invalid(
+        &mut self,
+        id: Value,
+        batch: Option<u64>,
+        code: i64,
+        message: impl Into<String>,
+    ) -> (Header, ObjectBody) {
+        let seq = self.next_seq();
+        self.requests.insert(
+            seq,
+            InboundRequest {
+                id,
+                batch,
+                error: Some(ErrorObject::new(code, message)),
+            },
+        );
+        let header = Header::Request {
+            id: seq,
+            service_method: String::new(),
+            timeout: None,
+            metadata: MetadataMap::new(),
+        };
+        (header, ObjectBody::Value(Value::Null))
+    }
+
+    fn next_seq(&mut self) -> MessageId {
+        let seq = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        seq
+    }
+
+    /// Builds the response object, which is held back until the rest of its batch is
+    /// answered. Returns the message that is ready to be written
+    fn respond(
+        &mut self,
+        id: MessageId,
+        status: Status,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<Option<Value>, CodecError> {
+        let (request_id, batch, error) = match self.requests.remove(&id) {
+            Some(request) => (request.id, request.batch, request.error),
+            None => (id.into(), None, None),
+        };
+        let body = serde_json::to_value(body).map_err(parse_error)?;
+        let (result, error) = match (error, status.is_ok()) {
+            (Some(error), _) => (None, Some(error)),
+            (None, true) => (Some(body), None),
+            (None, false) => {
+                let error = match serde_json::from_value(body) {
+                    Ok(msg) => ErrorObject::from_err_msg(msg, &status),
+                    Err(_) => ErrorObject::from_status(&status),
+                };
+                (None, Some(error))
+            }
+        };
+        let object = serde_json::to_value(ResponseObject {
+            jsonrpc: VERSION,
+            result,
+            error,
+            id: request_id,
+        })
+        .map_err(parse_error)?;
+
+        let batch_id = match batch {
+            Some(batch_id) => batch_id,
+            None => return Ok(Some(object)),
+        };
+        let batch = match self.batches.get_mut(&batch_id) {
+            Some(batch) => batch,
+            None => return Ok(Some(object)),
+        };
+        batch.responses.push(object);
+        batch.remaining -= 1;
+        match batch.remaining {
+            0 => Ok(self
+                .batches
+                .remove(&batch_id)
+                .map(|batch| Value::Array(batch.responses))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Converts the argument of a call into `params`, which must be an array or an object
+fn params(body: &(dyn erased::Serialize + Send + Sync)) -> Result<Option<Value>, CodecError> {
+    match serde_json::to_value(body).map_err(parse_error)? {
+        Value::Null => Ok(None),
+        params @ Value::Array(_) | params @ Value::Object(_) => Ok(Some(params)),
+        param => Ok(Some(Value::Array(vec![param]))),
+    }
+}
+
+impl Protocol for Exchange {
+    type Format = JsonRpc;
+
+    const NAME: &'static str = "JSON-RPC 2.0";
+
+    const FEATURES: &'static [&'static str] = &[FEATURE_NOTIFY];
+
+    fn encode(
+        &mut self,
+        header: Header,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<Option<Vec<u8>>, CodecError> {
+        let buf = match header {
+            Header::Request {
+                id, service_method, ..
+            } => JsonRpc::marshal(&RequestObject {
+                jsonrpc: VERSION,
+                method: &service_method,
+                params: params(body)?,
+                id: Some(id),
+            })?,
+            Header::Notify { service_method, .. } => JsonRpc::marshal(&RequestObject {
+                jsonrpc: VERSION,
+                method: &service_method,
+                params: params(body)?,
+                id: None,
+            })?,
+            Header::Response { id, status, .. } => match self.respond(id, status, body)? {
+                Some(message) => JsonRpc::marshal(&message)?,
+                None => return Ok(None),
+            },
+            header => return Err(translate::unsupported::<Self>(&header)),
+        };
+        Ok(Some(buf))
+    }
+
+    fn encode_bytes(&mut self, header: Header, body: &[u8]) -> Result<Option<Vec<u8>>, CodecError> {
+        let body: Value = serde_json::from_slice(body).map_err(parse_error)?;
+        self.encode(header, &body)
+    }
+}
+
+impl Translate for Exchange {
+    type Values = Json;
+
+    type Body = ObjectBody;
+
+    fn translate(&mut self, buf: &[u8]) -> Result<Vec<(Header, ObjectBody)>, CodecError> {
+        Ok(self.translate_message(buf))
+    }
+
+    fn deserializer(body: ObjectBody) -> Result<Box<InboundBody>, CodecError> {
+        match body {
+            ObjectBody::Params(params) => {
+                Ok(Box::new(<dyn erased::Deserializer>::erase(Params(params))))
+            }
+            ObjectBody::Value(body) => Ok(Box::new(<dyn erased::Deserializer>::erase(body))),
+        }
+    }
+
+    fn into_bytes(body: ObjectBody) -> Result<Vec<u8>, IoError> {
+        match body {
+            ObjectBody::Params(body) | ObjectBody::Value(body) => {
+                serde_json::to_vec(&body).map_err(|err| IoError::new(ErrorKind::InvalidData, err))
+            }
+        }
+    }
+}
+
+/// The objects are carried by the raw TCP transport of the `json` format
+impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite, JsonRpc>
+where
+    Codec<R, W, ConnTypeReadWrite, Json>: SplittableCodec,
+{
+    type Writer = TranslateWriteHalf<
+        <Codec<R, W, ConnTypeReadWrite, Json> as SplittableCodec>::Writer,
+        Exchange,
+    >;
+    type Reader = TranslateReadHalf<
+        <Codec<R, W, ConnTypeReadWrite, Json> as SplittableCodec>::Reader,
+        Exchange,
+    >;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        let codec = Codec::<R, W, ConnTypeReadWrite, Json> {
+            reader: self.reader,
+            writer: self.writer,
+            conn_type: PhantomData,
+            format: PhantomData,
+        };
+        let (writer, reader) = codec.split();
+        translate::split(writer, reader)
+    }
+}
+
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+mod ws {
+    use super::*;
+    use crate::codec::ConnTypePayload;
+    use crate::transport::PayloadWrite;
+    use crate::util::GracefulShutdown;
+    use async_trait::async_trait;
+
+    /// Writes the payload of the WebSocket transport as text messages, which is what
+    /// JSON-RPC clients in the browser expect
+    pub(crate) struct TextWriter<W>(W);
+
+    #[async_trait]
+    impl<W: PayloadWrite + Send> PayloadWrite for TextWriter<W> {
+        async fn write_payload(&mut self, payload: &[u8]) -> Result<(), IoError> {
+            let text = std::str::from_utf8(payload)
+                .map_err(|err| IoError::new(ErrorKind::InvalidData, err))?;
+            self.0.write_text(text).await
+        }
+    }
+
+    #[async_trait]
+    impl<W: GracefulShutdown + Send> GracefulShutdown for TextWriter<W> {
+        async fn close(&mut self) {
+            self.0.close().await;
+        }
+    }
+
+    impl<R, W> SplittableCodec for Codec<R, W, ConnTypePayload, JsonRpc>
+    where
+        Codec<R, TextWriter<W>, ConnTypePayload, Json>: SplittableCodec,
+    {
+        type Writer = TranslateWriteHalf<
+            <Codec<R, TextWriter<W>, ConnTypePayload, Json> as SplittableCodec>::Writer,
+            Exchange,
+        >;
+        type Reader = TranslateReadHalf<
+            <Codec<R, TextWriter<W>, ConnTypePayload, Json> as SplittableCodec>::Reader,
+            Exchange,
+        >;
+
+        fn split(self) -> (Self::Writer, Self::Reader) {
+            let codec = Codec::<R, TextWriter<W>, ConnTypePayload, Json> {
+                reader: self.reader,
+                writer: TextWriter(self.writer),
+                conn_type: PhantomData,
+                format: PhantomData,
+            };
+            let (writer, reader) = codec.split();
+            translate::split(writer, reader)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(exchange: &mut Exchange, message: &str) -> Vec<(Header, ObjectBody)> {
+        exchange.translate_message(message.as_bytes())
+    }
+
+    #[test]
+    fn translate_requests() {
+        let mut exchange = Exchange::default();
+        let mut messages = translate(
+            &mut exchange,
+            r#"[
+                {"jsonrpc":"2.0","method":"Arith.Multiply","params":[7,8],"id":"a"},
+                {"jsonrpc":"2.0","method":"Log.Write","params":["hi"]},
+                {"jsonrpc":"1.0","method":"Arith.Multiply","id":1},
+                3
+            ]"#,
+        )
+        .into_iter();
+
+        match messages.next() {
+            Some((
+                Header::Request {
+                    id, service_method, ..
+                },
+                ObjectBody::Params(params),
+            )) => {
+                assert_eq!(service_method, "Arith.Multiply");
+                assert_eq!(params, serde_json::json!([7, 8]));
+                assert_eq!(exchange.requests[&id].id, Value::from("a"));
+                assert_eq!(exchange.requests[&id].batch, Some(0));
+            }
+            message => panic!("Unexpected message {:?}", message.map(|m| m.0)),
+        }
+        assert!(matches!(messages.next(), Some((Header::Notify { .. }, _))));
+        for _ in 0..2 {
+            match messages.next() {
+                Some((
+                    Header::Request {
+                        id, service_method, ..
+                    },
+                    _,
+                )) => {
+                    assert_eq!(service_method, "");
+                    let code = exchange.requests[&id].error.as_ref().unwrap().code;
+                    assert_eq!(code, INVALID_REQUEST);
+                }
+                message => panic!("Unexpected message {:?}", message.map(|m| m.0)),
+            }
+        }
+        assert!(messages.next().is_none());
+        assert_eq!(exchange.batches[&0].remaining, 3);
+
+        match translate(&mut exchange, "{").pop() {
+            Some((Header::Request { id, .. }, _)) => {
+                assert_eq!(exchange.requests[&id].id, Value::Null);
+                let code = exchange.requests[&id].error.as_ref().unwrap().code;
+                assert_eq!(code, PARSE_ERROR);
+            }
+            message => panic!("Unexpected message {:?}", message.map(|m| m.0)),
+        }
+    }
+
+    #[test]
+    fn translate_responses() {
+        let mut exchange = Exchange::default();
+        match translate(&mut exchange, r#"{"jsonrpc":"2.0","result":56,"id":3}"#).pop() {
+            Some((Header::Response { id, status, .. }, ObjectBody::Value(body))) => {
+                assert_eq!(id, 3);
+                assert!(status.is_ok());
+                assert_eq!(body, Value::from(56));
+            }
+            message => panic!("Unexpected message {:?}", message.map(|m| m.0)),
+        }
+
+        let message =
+            r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"Method not found"},"id":4}"#;
+        match translate(&mut exchange, message).pop() {
+            Some((Header::Response { status, .. }, ObjectBody::Value(body))) => {
+                assert_eq!(status.code(), Code::Unimplemented);
+                let msg: ErrorMessage = serde_json::from_value(body).unwrap();
+                assert!(matches!(msg, ErrorMessage::MethodNotFound));
+            }
+            message => panic!("Unexpected message {:?}", message.map(|m| m.0)),
+        }
+
+        let message =
+            r#"{"jsonrpc":"2.0","error":{"code":-32000,"message":"no","data":[1]},"id":5}"#;
+        match translate(&mut exchange, message).pop() {
+            Some((_, ObjectBody::Value(body))) => {
+                let msg: ErrorMessage = serde_json::from_value(body).unwrap();
+                match msg {
+                    ErrorMessage::Typed { message, payload } => {
+                        assert_eq!(message, "no");
+                        assert_eq!(payload, b"[1]");
+                    }
+                    _ => panic!("Unexpected error message"),
+                }
+            }
+            message => panic!("Unexpected message {:?}", message.map(|m| m.0)),
+        }
+
+        // a response without numeric id cannot be routed
+        let message =
+            r#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"Parse error"},"id":null}"#;
+        assert!(translate(&mut exchange, message).is_empty());
+    }
+
+    #[test]
+    fn deserialize_params() {
+        fn de<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, serde_json::Error> {
+            T::deserialize(Params(params))
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Args {
+            a: i32,
+            b: i32,
+        }
+
+        assert_eq!(de::<u32>(serde_json::json!([7])).unwrap(), 7);
+        assert_eq!(de::<u32>(serde_json::json!(7)).unwrap(), 7);
+        assert_eq!(de::<String>(serde_json::json!(["go"])).unwrap(), "go");
+        assert_eq!(de::<Vec<u32>>(serde_json::json!([7])).unwrap(), vec![7]);
+        assert_eq!(de::<(u32, u32)>(serde_json::json!([7, 8])).unwrap(), (7, 8));
+        assert_eq!(de::<Option<u32>>(serde_json::json!([])).unwrap(), None);
+        assert_eq!(de::<Option<u32>>(serde_json::json!([7])).unwrap(), Some(7));
+        de::<()>(Value::Null).unwrap();
+        let args = Args { a: 7, b: 8 };
+        assert_eq!(
+            de::<Args>(serde_json::json!({"a": 7, "b": 8})).unwrap(),
+            args
+        );
+        assert_eq!(
+            de::<Args>(serde_json::json!([{"a": 7, "b": 8}])).unwrap(),
+            args
+        );
+        assert_eq!(de::<Args>(serde_json::json!([7, 8])).unwrap(), args);
+        assert!(de::<u32>(serde_json::json!(["go"])).is_err());
+    }
+
+    #[test]
+    fn error_objects() {
+        let status = Error::InvalidArgument.status();
+        let error = ErrorObject::from_err_msg(ErrorMessage::InvalidArgument, &status);
+        assert_eq!(error.code, INVALID_PARAMS);
+        let status = Error::ServiceNotFound.status();
+        let error = ErrorObject::from_err_msg(ErrorMessage::ServiceNotFound, &status);
+        assert_eq!(error.code, METHOD_NOT_FOUND);
+        let status = Status::new(Code::PermissionDenied, "denied");
+        let error = ErrorObject::from_err_msg(ErrorMessage::Status(status.clone()), &status);
+        assert_eq!(
+            (error.code, error.message.as_str()),
+            (SERVER_ERROR, "denied")
+        );
+    }
+}
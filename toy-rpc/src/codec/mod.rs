@@ -1,19 +1,34 @@
 //! `SplittibleCodec` is defined in this module, and they are implemented
 //! for the `DefaultCodec`
 //! Default codec implementations are feature gated behind the following features
-//! `serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`.
+//! `serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`, `serde_gob`.
+//!
+//! The features are additive. Each of them enables a serialization format, ie.
+//! `bincode::Bincode`, which is the last type parameter of `Codec`. When more than one
+//! format is enabled, the format used on a connection is selected at runtime with
+//! `Format`, which is set with `set_format` on the server and client builders, and
+//! `DefaultCodec` uses the first enabled format in the order of `bincode`, `cbor`, `rmp`,
+//! `json` and `gob`.
 
 use async_trait::async_trait;
 use cfg_if::cfg_if;
 use erased_serde as erased;
 use std::marker::PhantomData;
 
+use crate::compression::NegotiatedCompression;
 use crate::error::{CodecError, IoError, ParseError};
-use crate::message::{MessageId, Metadata};
-use crate::protocol::InboundBody;
+use crate::message::MessageId;
+use crate::protocol::{Capabilities, Header, InboundBody};
+use crate::transport::MessageSizeLimit;
 
 pub mod split;
 
+mod format;
+pub use format::Format;
+
+#[cfg(any(feature = "server", feature = "client"))]
+pub(crate) use format::with_format;
+
 cfg_if! {
     if #[cfg(feature = "http_tide")] {
         use tide_websockets as tide_ws;
@@ -54,33 +69,38 @@ cfg_if! {
 
 cfg_if! {
     if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "tokio_runtime",
         feature = "docs",
-        all(
+    ))] {
+        cfg_if! {
+            if #[cfg(feature = "serde_bincode")] {
+                /// Format used by `DefaultCodec`
+                pub type DefaultFormat = bincode::Bincode;
+            } else if #[cfg(feature = "serde_cbor")] {
+                /// Format used by `DefaultCodec`
+                pub type DefaultFormat = cbor::Cbor;
+            } else if #[cfg(feature = "serde_rmp")] {
+                /// Format used by `DefaultCodec`
+                pub type DefaultFormat = rmp::Rmp;
+            } else if #[cfg(feature = "serde_json")] {
+                /// Format used by `DefaultCodec`
+                pub type DefaultFormat = json::Json;
+            } else if #[cfg(feature = "serde_gob")] {
+                /// Format used by `DefaultCodec`
+                pub type DefaultFormat = gob::Gob;
+            }
+        }
+
+        /// `Codec` with the default format
+        #[cfg(any(
             feature = "serde_bincode",
-            not(feature = "serde_json"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
             feature = "serde_cbor",
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
             feature = "serde_json",
-            not(feature = "serde_bincode"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
             feature = "serde_rmp",
-            not(feature = "serde_cbor"),
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-        )
-    ))] {
-        pub use Codec as DefaultCodec;
+            feature = "serde_gob"
+        ))]
+        pub type DefaultCodec<R, W, C> = Codec<R, W, C, DefaultFormat>;
     }
 }
 
@@ -90,73 +110,71 @@ cfg_if! {
         feature = "tokio_runtime",
         feature = "docs",
     ))] {
-        #[cfg(all(
-            feature = "serde_bincode",
-            not(feature = "serde_json"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ))]
+        #[cfg(feature = "serde_bincode")]
         #[cfg_attr(
             doc,
-            doc(cfg(all(
-                feature = "serde_bincode",
-                not(feature = "serde_json"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            )))
+            doc(cfg(feature = "serde_bincode"))
         )]
         pub mod bincode;
 
-        #[cfg(all(
-            feature = "serde_json",
-            not(feature = "serde_bincode"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ))]
+        #[cfg(feature = "serde_json")]
         #[cfg_attr(
             doc,
-            doc(cfg(all(
-                feature = "serde_json",
-                not(feature = "serde_bincode"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            )))
+            doc(cfg(feature = "serde_json"))
         )]
         pub mod json;
 
-        #[cfg(all(
-            feature = "serde_cbor",
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-            not(feature = "serde_rmp"),
-        ))]
+        #[cfg(any(feature = "serde_json", feature = "serde_rmp", feature = "serde_gob"))]
+        mod translate;
+
+        #[cfg(feature = "serde_json")]
         #[cfg_attr(
             doc,
-            doc(cfg(all(
-                feature = "serde_cbor",
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-                not(feature = "serde_rmp"),
-            )))
+            doc(cfg(feature = "serde_json"))
+        )]
+        pub mod go_jsonrpc;
+
+        #[cfg(feature = "serde_json")]
+        #[cfg_attr(
+            doc,
+            doc(cfg(feature = "serde_json"))
+        )]
+        pub mod jsonrpc;
+
+        #[cfg(feature = "serde_cbor")]
+        #[cfg_attr(
+            doc,
+            doc(cfg(feature = "serde_cbor"))
         )]
         pub mod cbor;
 
-        #[cfg(all(
-            feature = "serde_rmp",
-            not(feature = "serde_cbor"),
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-        ))]
+        #[cfg(feature = "serde_rmp")]
         #[cfg_attr(
             doc,
-            doc(cfg(all(
-                feature = "serde_rmp",
-                not(feature = "serde_cbor"),
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-            )))
+            doc(cfg(feature = "serde_rmp"))
         )]
         pub mod rmp;
+
+        #[cfg(feature = "serde_rmp")]
+        #[cfg_attr(
+            feature = "docs",
+            doc(cfg(feature = "serde_rmp"))
+        )]
+        pub mod msgpack_rpc;
+
+        #[cfg(feature = "serde_gob")]
+        #[cfg_attr(
+            doc,
+            doc(cfg(feature = "serde_gob"))
+        )]
+        pub mod gob;
+
+        #[cfg(feature = "serde_gob")]
+        #[cfg_attr(
+            doc,
+            doc(cfg(feature = "serde_gob"))
+        )]
+        pub mod go_rpc;
     }
 }
 
@@ -171,8 +189,9 @@ pub(crate) struct ConnTypePayload {}
 /// Reserved type state for Reader/Writer for Codec
 pub struct Reserved {}
 
-/// Default codec. `Codec` is re-exported as `DefaultCodec` when one of these feature
-/// flags is toggled (`serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`")
+/// Codec that reads and writes messages in the format `F` over a connection of type `C`.
+/// `DefaultCodec` is the `Codec` with the default format when one of these feature
+/// flags is toggled (`serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`, `serde_gob`)
 #[cfg_attr(
     not(all(
         any( // there has to be a runtime
@@ -184,14 +203,38 @@ pub struct Reserved {}
             feature = "serde_json",
             feature = "serde_cbor",
             feature = "serde_rmp",
+            feature = "serde_gob",
         )
     )),
     allow(dead_code)
 )]
-pub struct Codec<R, W, C> {
+pub struct Codec<R, W, C, F> {
     reader: R,
     writer: W,
     conn_type: PhantomData<C>,
+    format: PhantomData<F>,
+}
+
+impl<R, W, C, F: Marshal> Marshal for Codec<R, W, C, F> {
+    fn codec_name() -> &'static str {
+        F::codec_name()
+    }
+
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        F::marshal(val)
+    }
+}
+
+impl<R, W, C, F: Unmarshal> Unmarshal for Codec<R, W, C, F> {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        F::unmarshal(buf)
+    }
+}
+
+impl<R, W, C, F: EraseDeserializer> EraseDeserializer for Codec<R, W, C, F> {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        F::from_bytes(buf)
+    }
 }
 
 cfg_if! {
@@ -203,11 +246,12 @@ cfg_if! {
         use crate::transport::ws::{CanSink, SinkHalf, StreamHalf, WebSocketConn};
 
         /// WebSocket integration for async_tungstenite, tokio_tungstenite
-        impl<S, E>
+        impl<S, E, F>
             Codec<
                 StreamHalf<SplitStream<S>, CanSink>,
                 SinkHalf<SplitSink<S, Message>, CanSink>,
                 ConnTypePayload,
+                F,
             >
         where
             S: Stream<Item = Result<Message, E>> + Sink<Message> + Send + Sync + Unpin,
@@ -223,6 +267,7 @@ cfg_if! {
                     reader,
                     writer,
                     conn_type: PhantomData,
+                    format: PhantomData,
                 }
             }
         }
@@ -231,11 +276,12 @@ cfg_if! {
 
 #[cfg(all(feature = "http_tide"))]
 /// WebSocket integration with `tide`
-impl
+impl<F>
     Codec<
         StreamHalf<tide_ws::WebSocketConnection, CannotSink>,
         SinkHalf<tide_ws::WebSocketConnection, CannotSink>,
         ConnTypePayload,
+        F,
     >
 {
     /// Creates a `Codec` with a WebSocket connection implemented in the `tide` HTTP server.
@@ -249,17 +295,19 @@ impl
             reader,
             writer,
             conn_type: PhantomData,
+            format: PhantomData,
         }
     }
 }
 
 #[cfg(all(feature = "http_warp"))]
 // warp websocket
-impl<S, E>
+impl<S, E, F>
     Codec<
         StreamHalf<SplitStream<S>, CanSink>,
         SinkHalf<SplitSink<S, warp::ws::Message>, CanSink>,
         ConnTypePayload,
+        F,
     >
 where
     S: Stream<Item = Result<warp::ws::Message, E>> + Sink<warp::ws::Message>,
@@ -283,16 +331,18 @@ where
             reader,
             writer,
             conn_type: PhantomData,
+            format: PhantomData,
         }
     }
 }
 
 #[cfg(all(feature = "http_axum"))]
-impl<S, E>
+impl<S, E, F>
     Codec<
         StreamHalf<SplitStream<S>, CanSink>,
         SinkHalf<SplitSink<S, axum::extract::ws::Message>, CanSink>,
         ConnTypePayload,
+        F,
     >
 where
     S: Stream<Item = Result<axum::extract::ws::Message, E>> + Sink<axum::extract::ws::Message>,
@@ -316,6 +366,7 @@ where
             reader,
             writer,
             conn_type: PhantomData,
+            format: PhantomData,
         }
     }
 }
@@ -324,10 +375,7 @@ where
 #[async_trait]
 pub trait CodecRead: Send + Unmarshal + EraseDeserializer {
     /// Reads the header of the message.
-    async fn read_header<H>(&mut self) -> Option<Result<H, CodecError>>
-    where
-        H: serde::de::DeserializeOwned,
-    {
+    async fn read_header(&mut self) -> Option<Result<Header, CodecError>> {
         Some(
             self.read_bytes()
                 .await?
@@ -349,15 +397,21 @@ pub trait CodecRead: Send + Unmarshal + EraseDeserializer {
 
     /// Reads the frame body as raw bytes
     async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>>;
+
+    /// Sets the compression agreed upon in the handshake. Codecs that do not support
+    /// compression ignore it
+    fn set_compression(&mut self, _compression: NegotiatedCompression) {}
+
+    /// Sets the limit on the size of the inbound messages, which the transport enforces
+    /// before a message is held in memory
+    fn set_size_limit(&mut self, _limit: MessageSizeLimit) {}
 }
 
 /// A codec that can write the header and body of a message
 #[async_trait]
 pub trait CodecWrite: Send + Marshal {
     /// Writes the header of the message
-    async fn write_header<H>(&mut self, header: H) -> Result<(), CodecError>
-    where
-        H: serde::Serialize + Metadata + Send;
+    async fn write_header(&mut self, header: Header) -> Result<(), CodecError>;
 
     /// Writes the body of the message
     async fn write_body(
@@ -368,6 +422,26 @@ pub trait CodecWrite: Send + Marshal {
 
     /// Writes body as raw bytes
     async fn write_body_bytes(&mut self, id: MessageId, bytes: &[u8]) -> Result<(), IoError>;
+
+    /// Ends the message that is written. The frame transport holds back the header of a
+    /// message until its body is written or the message ends
+    async fn end_message(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+
+    /// Sets the compression agreed upon in the handshake. Codecs that do not support
+    /// compression ignore it
+    fn set_compression(&mut self, _compression: NegotiatedCompression) {}
+
+    /// Sets the max frame size agreed upon in the handshake. Codecs that support chunking
+    /// split larger bodies into chunks, and the other codecs ignore it
+    fn set_max_frame_size(&mut self, _size: usize) {}
+
+    /// Capabilities of a peer that speaks a protocol without the handshake, ie. Go's
+    /// `net/rpc/jsonrpc`. The handshake is skipped if this returns `Some`
+    fn implied_capabilities() -> Option<Capabilities> {
+        None
+    }
 }
 
 cfg_if! {
@@ -379,30 +453,10 @@ cfg_if! {
             feature = "http_warp",
         ),
         any(
-            all(
-                feature = "serde_bincode",
-                not(feature = "serde_json"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_cbor",
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_json",
-                not(feature = "serde_bincode"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_rmp",
-                not(feature = "serde_cbor"),
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-            )
+            feature = "serde_bincode",
+            feature = "serde_cbor",
+            feature = "serde_json",
+            feature = "serde_rmp"
         )
     ))] {
         /// A wrapper for erased serde deserializers to allow transfer of ownership
@@ -420,6 +474,11 @@ cfg_if! {
 
 /// This trait should be implemented by serializer (Codec) to serialize messages into bytes
 pub trait Marshal {
+    /// Name of the serialization format, which is exchanged with the peer during the handshake
+    fn codec_name() -> &'static str {
+        "custom"
+    }
+
     /// Marshals/serializes an object into `Vec<u8>`
     fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError>;
 }
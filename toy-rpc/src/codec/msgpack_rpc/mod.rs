@@ -0,0 +1,857 @@
+//! MessagePack-RPC
+//!
+//! A request is the array `[0, msgid, method, params]`, a response is the array
+//! `[1, msgid, error, result]`, where `error` is `nil` if the call succeeded, and a
+//! notification is the array `[2, method, params]`, which is not answered. `params` is an
+//! array. The messages are MessagePack values that are written one after another, and
+//! there is no handshake.
+//!
+//! The reading and writing halves translate these arrays from and into the messages of
+//! `toy-rpc`, so that MessagePack-RPC clients can call the services of a `Server` and a
+//! `Client` can call a MessagePack-RPC server. The method is named "{Service}.{Method}"
+//! on both sides, and the values are encoded with `rmp-serde`, which encodes a struct as
+//! an array of its fields. The argument of a method is `params`, where an array holding a
+//! single element is unwrapped unless the method takes a sequence or a struct, so that
+//! `[7]` can call a method that takes an integer and `[7, 8]` can call a method that
+//! takes a tuple or a struct of two fields. Likewise, a `Client` sends an argument that is
+//! not an array as the only element of `params`.
+//!
+//! The error of a failed call is sent as a string, which is the message of the error, ie.
+//! "MethodNotFound" for a missing method, and the payload of a typed error is not sent.
+//! Only unary calls and notifications can be carried, and the other messages, ie.
+//! streaming calls, pub/sub and extensions, fail to be written.
+
+use cfg_if::cfg_if;
+use erased_serde as erased;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Cursor;
+use std::ops::Range;
+
+use super::rmp::Rmp;
+use super::translate::{self, Protocol, Translate};
+use super::{EraseDeserializer, Marshal, Unmarshal};
+use crate::error::{CodecError, Error, IoError, ParseError};
+use crate::message::{ErrorMessage, MessageId};
+use crate::protocol::{Header, InboundBody, MetadataMap, FEATURE_NOTIFY};
+use crate::status::Status;
+
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+use super::translate::{TranslateReadHalf, TranslateWriteHalf};
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+use super::{split::SplittableCodec, Codec};
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+use std::marker::PhantomData;
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "http_tide"
+    ))] {
+        mod async_std;
+    } else if #[cfg(any(
+        feature = "tokio_runtime",
+        feature = "http_warp",
+    ))] {
+        mod tokio;
+    }
+}
+
+/// The MessagePack-RPC protocol. The values are encoded with `rmp::Rmp` and are written
+/// one after another on the raw TCP transport
+pub struct MsgpackRpc {}
+
+impl Marshal for MsgpackRpc {
+    fn codec_name() -> &'static str {
+        "msgpack-rpc"
+    }
+
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        Rmp::marshal(val)
+    }
+}
+
+impl Unmarshal for MsgpackRpc {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+        Rmp::unmarshal(buf)
+    }
+}
+
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+const NOTIFICATION: u8 = 2;
+
+const NIL: u8 = 0xc0;
+
+/// Length of the first value in `buf`, or `None` if the value is not complete
+pub(crate) fn value_len(buf: &[u8]) -> Result<Option<usize>, &'static str> {
+    ValueScan::default().resume(buf)
+}
+
+/// Walks the markers of a value, which is resumed where it stopped as the bytes of the
+/// value arrive
+#[derive(Debug)]
+pub(crate) struct ValueScan {
+    /// Position of the next marker
+    pos: usize,
+    /// Values that are yet to be read, including the elements of the arrays and maps
+    remaining: u64,
+}
+
+impl Default for ValueScan {
+    fn default() -> Self {
+        Self {
+            pos: 0,
+            remaining: 1,
+        }
+    }
+}
+
+impl ValueScan {
+    /// Length of the first value in `buf`, or `None` if the value is not complete.
+    ///
+    /// `buf` must start with the bytes that were scanned before.
+    pub(crate) fn resume(&mut self, buf: &[u8]) -> Result<Option<usize>, &'static str> {
+        while self.remaining > 0 {
+            let pos = self.pos;
+            let marker = match buf.get(pos) {
+                Some(marker) => *marker,
+                None => return Ok(None),
+            };
+            // the size of the value without its elements, and the number of its elements
+            let (size, elements) = match marker {
+                0x00..=0x7f | 0xe0..=0xff | 0xc0 | 0xc2 | 0xc3 => (1, 0),
+                0x80..=0x8f => (1, 2 * u64::from(marker & 0x0f)),
+                0x90..=0x9f => (1, u64::from(marker & 0x0f)),
+                0xa0..=0xbf => (1 + u64::from(marker & 0x1f), 0),
+                0xc1 => return Err("the marker 0xc1 is never used"),
+                0xc4 | 0xd9 => match len_at(buf, pos + 1, 1) {
+                    Some(len) => (2 + len, 0),
+                    None => return Ok(None),
+                },
+                0xc5 | 0xda => match len_at(buf, pos + 1, 2) {
+                    Some(len) => (3 + len, 0),
+                    None => return Ok(None),
+                },
+                0xc6 | 0xdb => match len_at(buf, pos + 1, 4) {
+                    Some(len) => (5 + len, 0),
+                    None => return Ok(None),
+                },
+                0xc7 => match len_at(buf, pos + 1, 1) {
+                    Some(len) => (3 + len, 0),
+                    None => return Ok(None),
+                },
+                0xc8 => match len_at(buf, pos + 1, 2) {
+                    Some(len) => (4 + len, 0),
+                    None => return Ok(None),
+                },
+                0xc9 => match len_at(buf, pos + 1, 4) {
+                    Some(len) => (6 + len, 0),
+                    None => return Ok(None),
+                },
+                0xca => (5, 0),
+                0xcb => (9, 0),
+                0xcc | 0xd0 => (2, 0),
+                0xcd | 0xd1 => (3, 0),
+                0xce | 0xd2 => (5, 0),
+                0xcf | 0xd3 => (9, 0),
+                0xd4 => (3, 0),
+                0xd5 => (4, 0),
+                0xd6 => (6, 0),
+                0xd7 => (10, 0),
+                0xd8 => (18, 0),
+                0xdc | 0xde => match len_at(buf, pos + 1, 2) {
+                    Some(len) if marker == 0xde => (3, 2 * len),
+                    Some(len) => (3, len),
+                    None => return Ok(None),
+                },
+                0xdd | 0xdf => match len_at(buf, pos + 1, 4) {
+                    Some(len) if marker == 0xdf => (5, 2 * len),
+                    Some(len) => (5, len),
+                    None => return Ok(None),
+                },
+            };
+            self.pos = match usize::try_from(size)
+                .ok()
+                .and_then(|size| pos.checked_add(size))
+            {
+                Some(pos) => pos,
+                None => return Err("the value is too large"),
+            };
+            self.remaining = self.remaining - 1 + elements;
+        }
+        match self.pos <= buf.len() {
+            true => Ok(Some(self.pos)),
+            false => Ok(None),
+        }
+    }
+}
+
+/// Reads a big-endian length of `n` bytes at `pos`
+fn len_at(buf: &[u8], pos: usize, n: usize) -> Option<u64> {
+    let bytes = buf.get(pos..pos + n)?;
+    Some(bytes.iter().fold(0, |len, b| len << 8 | u64::from(*b)))
+}
+
+fn is_array(marker: u8) -> bool {
+    matches!(marker, 0x90..=0x9f | 0xdc | 0xdd)
+}
+
+fn is_map(marker: u8) -> bool {
+    matches!(marker, 0x80..=0x8f | 0xde | 0xdf)
+}
+
+/// Ranges of the elements of the array that makes up `buf`
+fn elements(buf: &[u8]) -> Result<Vec<Range<usize>>, &'static str> {
+    let (mut pos, len) = match buf.first() {
+        Some(marker @ 0x90..=0x9f) => (1, usize::from(marker & 0x0f)),
+        Some(0xdc) if buf.len() >= 3 => (3, usize::from(u16::from_be_bytes([buf[1], buf[2]]))),
+        Some(0xdd) if buf.len() >= 5 => {
+            let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]);
+            (
+                5,
+                usize::try_from(len).map_err(|_| "the array is too long")?,
+            )
+        }
+        _ => return Err("a message must be an array"),
+    };
+    let mut elements = Vec::new();
+    for _ in 0..len {
+        let end = match value_len(&buf[pos..])? {
+            Some(len) => pos + len,
+            None => return Err("the message is not complete"),
+        };
+        elements.push(pos..end);
+        pos = end;
+    }
+    Ok(elements)
+}
+
+fn parse_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> CodecError {
+    CodecError::ParseError(err.into())
+}
+
+/// Converts the message of a failed response into the error that is sent
+fn error_text(msg: Option<ErrorMessage>, status: &Status) -> String {
+    match msg {
+        Some(ErrorMessage::InvalidArgument) => Error::InvalidArgument.to_string(),
+        Some(ErrorMessage::ServiceNotFound) => Error::ServiceNotFound.to_string(),
+        Some(ErrorMessage::MethodNotFound) => Error::MethodNotFound.to_string(),
+        Some(ErrorMessage::ExecutionError(message))
+        | Some(ErrorMessage::Typed { message, .. })
+        | Some(ErrorMessage::Internal(message)) => message,
+        _ => match status.message() {
+            Some(message) => message.into(),
+            None => status.code().to_string(),
+        },
+    }
+}
+
+/// Converts the error of a response into its status and body. The errors of `toy-rpc`
+/// that carry no message are recognized by their names
+fn from_error_text(error: String) -> (Status, ErrorMessage) {
+    let err = match error.as_str() {
+        "InvalidArgument" => Error::InvalidArgument,
+        "ServiceNotFound" => Error::ServiceNotFound,
+        "MethodNotFound" => Error::MethodNotFound,
+        _ => Error::ExecutionError(error),
+    };
+    let status = err.status();
+    let msg = match err {
+        Error::InvalidArgument => ErrorMessage::InvalidArgument,
+        Error::ServiceNotFound => ErrorMessage::ServiceNotFound,
+        Error::MethodNotFound => ErrorMessage::MethodNotFound,
+        Error::ExecutionError(error) => ErrorMessage::ExecutionError(error),
+        _ => unreachable!(),
+    };
+    (status, msg)
+}
+
+/// The error of a response as text. It is usually a string, but it can be any value
+struct ErrorText(String);
+
+impl<'de> Deserialize<'de> for ErrorText {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TextVisitor).map(ErrorText)
+    }
+}
+
+struct TextVisitor;
+
+impl<'de> Visitor<'de> for TextVisitor {
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<String, E> {
+        Ok(v.into())
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<String, E> {
+        Ok(String::from_utf8_lossy(v).into_owned())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<String, E> {
+        Ok("nil".into())
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<String, E> {
+        self.visit_unit()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<String, A::Error> {
+        let mut items = Vec::new();
+        while let Some(ErrorText(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(format!("[{}]", items.join(", ")))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<String, A::Error> {
+        let mut entries = Vec::new();
+        while let Some((ErrorText(key), ErrorText(value))) = map.next_entry()? {
+            entries.push(format!("{}: {}", key, value));
+        }
+        Ok(format!("{{{}}}", entries.join(", ")))
+    }
+}
+
+type ValueDeserializer = rmp_serde::Deserializer<rmp_serde::decode::ReadReader<Cursor<Vec<u8>>>>;
+
+fn deserializer(buf: Vec<u8>) -> ValueDeserializer {
+    rmp_serde::Deserializer::new(Cursor::new(buf))
+}
+
+/// Deserializer of the argument of a method from `params`. An array holding a single
+/// element is unwrapped unless a sequence is expected, and an empty `params` is taken as
+/// the unit type or `None`
+struct Params {
+    /// Encoded array
+    buf: Vec<u8>,
+    elements: Vec<Range<usize>>,
+}
+
+impl Params {
+    fn new(buf: Vec<u8>) -> Result<Self, CodecError> {
+        let elements = elements(&buf).map_err(parse_error)?;
+        Ok(Self { buf, elements })
+    }
+
+    fn unwrap(self) -> ValueDeserializer {
+        match self.elements.as_slice() {
+            [element] => deserializer(self.buf[element.clone()].to_vec()),
+            _ => deserializer(self.buf),
+        }
+    }
+
+    /// Unwraps an array holding a single array or map, because an array can also be
+    /// deserialized into a struct
+    fn unwrap_container(self) -> ValueDeserializer {
+        match self.elements.as_slice() {
+            [element] if is_array(self.buf[element.start]) || is_map(self.buf[element.start]) => {
+                deserializer(self.buf[element.clone()].to_vec())
+            }
+            _ => deserializer(self.buf),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.elements
+            .iter()
+            .all(|element| self.buf[element.clone()] == [NIL])
+    }
+}
+
+macro_rules! forward_unwrapped {
+    ($($method:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.unwrap().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Params {
+    type Error = rmp_serde::decode::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        deserializer(self.buf).deserialize_any(visitor)
+    }
+
+    forward_unwrapped!(
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_identifier
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.is_empty() {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.is_empty() {
+            true => visitor.visit_unit(),
+            false => deserializer(self.buf).deserialize_unit(visitor),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        deserializer(self.buf).deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        deserializer(self.buf).deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        deserializer(self.buf).deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.unwrap_container().deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.unwrap_container()
+            .deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.unwrap().deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        deserializer(self.buf).deserialize_ignored_any(visitor)
+    }
+}
+
+/// State of a connection, which is shared by the reading and writing halves
+#[derive(Default)]
+pub(crate) struct Exchange {
+    /// Ids of the requests from the peer by the message ids that they are served with. The
+    /// message ids of this side are told apart from the notifications, which carry no id
+    ids: HashMap<MessageId, u64>,
+    next_id: MessageId,
+}
+
+/// Body of a message whose header is read
+pub(crate) enum MessageBody {
+    /// `params` of a request or a notification
+    Params(Vec<u8>),
+    /// `result` of a response or the message of its error
+    Value(Vec<u8>),
+}
+
+impl Exchange {
+    fn translate_message(&mut self, buf: &[u8]) -> Result<(Header, MessageBody), CodecError> {
+        let elements = elements(buf).map_err(parse_error)?;
+        let element = |index: usize| &buf[elements[index].clone()];
+        let kind: u8 = match elements.first() {
+            Some(_) => Rmp::unmarshal(element(0))?,
+            None => return Err(parse_error("a message cannot be empty")),
+        };
+        let metadata = MetadataMap::new();
+
+        match (kind, elements.len()) {
+            (REQUEST, 4) => {
+                let msgid: u64 = Rmp::unmarshal(element(1))?;
+                let service_method = Rmp::unmarshal(element(2))?;
+                let id = self.next_seq();
+                self.ids.insert(id, msgid);
+                let header = Header::Request {
+                    id,
+                    service_method,
+                    timeout: None,
+                    metadata,
+                };
+                Ok((header, MessageBody::Params(element(3).to_vec())))
+            }
+            (RESPONSE, 4) => {
+                let id = Rmp::unmarshal(element(1))?;
+                let (status, body) = match element(2) {
+                    [NIL] => (Status::ok(), element(3).to_vec()),
+                    error => {
+                        let ErrorText(error) = Rmp::unmarshal(error)?;
+                        let (status, msg) = from_error_text(error);
+                        (status, Rmp::marshal(&msg)?)
+                    }
+                };
+                let header = Header::Response {
+                    id,
+                    status,
+                    metadata,
+                };
+                Ok((header, MessageBody::Value(body)))
+            }
+            (NOTIFICATION, 3) => {
+                let service_method = Rmp::unmarshal(element(1))?;
+                let header = Header::Notify {
+                    id: self.next_seq(),
+                    service_method,
+                    metadata,
+                };
+                Ok((header, MessageBody::Params(element(2).to_vec())))
+            }
+            (kind, len) => Err(parse_error(format!(
+                "Invalid MessagePack-RPC message of type {} with {} elements",
+                kind, len
+            ))),
+        }
+    }
+
+    fn next_seq(&mut self) -> MessageId {
+        let seq = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        seq
+    }
+}
+
+/// Converts the encoded argument of a call into `params`, which must be an array
+fn params(body: &[u8]) -> Vec<u8> {
+    match body.first() {
+        Some(marker) if is_array(*marker) => body.to_vec(),
+        None | Some(&NIL) => vec![0x90],
+        Some(_) => {
+            let mut params = vec![0x91];
+            params.extend_from_slice(body);
+            params
+        }
+    }
+}
+
+impl Protocol for Exchange {
+    type Format = MsgpackRpc;
+
+    const NAME: &'static str = "MessagePack-RPC";
+
+    const FEATURES: &'static [&'static str] = &[FEATURE_NOTIFY];
+
+    fn encode(
+        &mut self,
+        header: Header,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<Option<Vec<u8>>, CodecError> {
+        let body = Rmp::marshal(&body)?;
+        self.encode_bytes(header, &body)
+    }
+
+    fn encode_bytes(&mut self, header: Header, body: &[u8]) -> Result<Option<Vec<u8>>, CodecError> {
+        let mut buf = Vec::new();
+        match header {
+            Header::Request {
+                id, service_method, ..
+            } => {
+                buf.push(0x94);
+                buf.extend(Rmp::marshal(&REQUEST)?);
+                buf.extend(Rmp::marshal(&id)?);
+                buf.extend(Rmp::marshal(&service_method)?);
+                buf.extend(params(body));
+            }
+            Header::Notify { service_method, .. } => {
+                buf.push(0x93);
+                buf.extend(Rmp::marshal(&NOTIFICATION)?);
+                buf.extend(Rmp::marshal(&service_method)?);
+                buf.extend(params(body));
+            }
+            Header::Response { id, status, .. } => {
+                let msgid = self.ids.remove(&id).unwrap_or(id);
+                buf.push(0x94);
+                buf.extend(Rmp::marshal(&RESPONSE)?);
+                buf.extend(Rmp::marshal(&msgid)?);
+                match status.is_ok() {
+                    true => {
+                        buf.push(NIL);
+                        buf.extend_from_slice(body);
+                    }
+                    false => {
+                        let error = error_text(Rmp::unmarshal(body).ok(), &status);
+                        buf.extend(Rmp::marshal(&error)?);
+                        buf.push(NIL);
+                    }
+                }
+            }
+            header => return Err(translate::unsupported::<Self>(&header)),
+        }
+        Ok(Some(buf))
+    }
+}
+
+impl Translate for Exchange {
+    type Values = Rmp;
+
+    type Body = MessageBody;
+
+    fn translate(&mut self, buf: &[u8]) -> Result<Vec<(Header, MessageBody)>, CodecError> {
+        self.translate_message(buf).map(|message| vec![message])
+    }
+
+    fn deserializer(body: MessageBody) -> Result<Box<InboundBody>, CodecError> {
+        match body {
+            MessageBody::Params(buf) => {
+                let params = Params::new(buf)?;
+                Ok(Box::new(<dyn erased::Deserializer>::erase(params)))
+            }
+            MessageBody::Value(buf) => Ok(Rmp::from_bytes(buf)),
+        }
+    }
+
+    fn into_bytes(body: MessageBody) -> Result<Vec<u8>, IoError> {
+        match body {
+            MessageBody::Params(buf) | MessageBody::Value(buf) => Ok(buf),
+        }
+    }
+}
+
+/// Each message is carried by a message of the WebSocket transport of the `rmp` format
+#[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+impl<R, W> SplittableCodec for Codec<R, W, super::ConnTypePayload, MsgpackRpc>
+where
+    Codec<R, W, super::ConnTypePayload, Rmp>: SplittableCodec,
+{
+    type Writer = TranslateWriteHalf<
+        <Codec<R, W, super::ConnTypePayload, Rmp> as SplittableCodec>::Writer,
+        Exchange,
+    >;
+    type Reader = TranslateReadHalf<
+        <Codec<R, W, super::ConnTypePayload, Rmp> as SplittableCodec>::Reader,
+        Exchange,
+    >;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        let codec = Codec::<R, W, super::ConnTypePayload, Rmp> {
+            reader: self.reader,
+            writer: self.writer,
+            conn_type: PhantomData,
+            format: PhantomData,
+        };
+        let (writer, reader) = codec.split();
+        translate::split(writer, reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_lengths() {
+        let values = [
+            rmp_serde::to_vec(&(0u8, 7u32, "Arith.Multiply", [7, 8])).unwrap(),
+            rmp_serde::to_vec(&vec![u64::MAX; 20]).unwrap(),
+            rmp_serde::to_vec(&"a".repeat(300)).unwrap(),
+            rmp_serde::to_vec(&vec![1u8; 70000]).unwrap(),
+            rmp_serde::to_vec(
+                &[("a", 1), ("b", 2)]
+                    .iter()
+                    .cloned()
+                    .collect::<HashMap<_, _>>(),
+            )
+            .unwrap(),
+            rmp_serde::to_vec(&(-1i8, -200i16, 1.5f32, 2.5f64, Option::<u8>::None, true)).unwrap(),
+        ];
+        for value in values.iter() {
+            assert_eq!(value_len(value), Ok(Some(value.len())));
+            assert_eq!(value_len(&value[..value.len() - 1]), Ok(None));
+            let mut stream = value.clone();
+            stream.extend_from_slice(value);
+            assert_eq!(value_len(&stream), Ok(Some(value.len())));
+
+            // the scan resumes where it stopped as the bytes arrive
+            let mut scan = ValueScan::default();
+            for end in 0..value.len() {
+                assert_eq!(scan.resume(&value[..end]), Ok(None));
+            }
+            assert_eq!(scan.resume(value), Ok(Some(value.len())));
+        }
+        assert!(value_len(&[0xc1]).is_err());
+    }
+
+    #[test]
+    fn translate_messages() {
+        let mut exchange = Exchange::default();
+        let buf = rmp_serde::to_vec(&(0u8, 9u32, "Arith.Multiply", (7, 8))).unwrap();
+        let (header, body) = exchange.translate_message(&buf).unwrap();
+        match header {
+            Header::Request {
+                id, service_method, ..
+            } => {
+                assert_eq!(service_method, "Arith.Multiply");
+                assert_eq!(exchange.ids[&id], 9);
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+        match body {
+            MessageBody::Params(buf) => {
+                let params = Params::new(buf).unwrap();
+                assert_eq!(<(i32, i32)>::deserialize(params).unwrap(), (7, 8));
+            }
+            _ => panic!("Expecting params"),
+        }
+
+        let buf = rmp_serde::to_vec(&(2u8, "Log.Write", ["hi"])).unwrap();
+        assert!(matches!(
+            exchange.translate_message(&buf).unwrap(),
+            (Header::Notify { .. }, _)
+        ));
+
+        let buf = rmp_serde::to_vec(&(1u8, 3u32, (), 56)).unwrap();
+        let (header, body) = exchange.translate_message(&buf).unwrap();
+        match header {
+            Header::Response { id, status, .. } => {
+                assert_eq!(id, 3);
+                assert!(status.is_ok());
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+        match body {
+            MessageBody::Value(buf) => assert_eq!(Rmp::unmarshal::<i32>(&buf).unwrap(), 56),
+            _ => panic!("Expecting a result"),
+        }
+
+        let buf = rmp_serde::to_vec(&(1u8, 4u32, "MethodNotFound", ())).unwrap();
+        let (header, body) = exchange.translate_message(&buf).unwrap();
+        match header {
+            Header::Response { status, .. } => assert!(!status.is_ok()),
+            header => panic!("Unexpected header {:?}", header),
+        }
+        match body {
+            MessageBody::Value(buf) => {
+                let msg: ErrorMessage = Rmp::unmarshal(&buf).unwrap();
+                assert!(matches!(msg, ErrorMessage::MethodNotFound));
+            }
+            _ => panic!("Expecting an error"),
+        }
+
+        // any value can be an error
+        let buf = rmp_serde::to_vec(&(1u8, 5u32, (1, "no"), ())).unwrap();
+        match exchange.translate_message(&buf).unwrap() {
+            (_, MessageBody::Value(buf)) => match Rmp::unmarshal(&buf).unwrap() {
+                ErrorMessage::ExecutionError(error) => assert_eq!(error, "[1, no]"),
+                _ => panic!("Expecting an execution error"),
+            },
+            _ => panic!("Expecting an error"),
+        }
+
+        let buf = rmp_serde::to_vec(&(3u8, "Log.Write")).unwrap();
+        assert!(exchange.translate_message(&buf).is_err());
+    }
+
+    #[test]
+    fn deserialize_params() {
+        fn de<T: de::DeserializeOwned, P: serde::Serialize>(params: P) -> Option<T> {
+            let buf = rmp_serde::to_vec(&params).unwrap();
+            T::deserialize(Params::new(buf).unwrap()).ok()
+        }
+
+        #[derive(Debug, PartialEq, Deserialize, serde::Serialize)]
+        struct Args {
+            a: i32,
+            b: i32,
+        }
+
+        assert_eq!(de::<u32, _>([7]), Some(7));
+        assert_eq!(de::<String, _>(["go"]), Some("go".into()));
+        assert_eq!(de::<Vec<u32>, _>([7]), Some(vec![7]));
+        assert_eq!(de::<(u32, u32), _>([7, 8]), Some((7, 8)));
+        assert_eq!(de::<Option<u32>, _>(Vec::<u32>::new()), Some(None));
+        assert_eq!(de::<Option<u32>, _>([7]), Some(Some(7)));
+        assert_eq!(de::<(), _>(Vec::<u32>::new()), Some(()));
+        let args = Args { a: 7, b: 8 };
+        assert_eq!(de::<Args, _>([7, 8]), Some(Args { a: 7, b: 8 }));
+        assert_eq!(de::<Args, _>([&args]), Some(Args { a: 7, b: 8 }));
+        assert_eq!(de::<u32, _>(["go"]), None);
+    }
+
+    #[test]
+    fn wrap_params() {
+        let body = Rmp::marshal(&7u32).unwrap();
+        assert_eq!(params(&body), rmp_serde::to_vec(&[7u32]).unwrap());
+        let body = Rmp::marshal(&(7u32, 8u32)).unwrap();
+        assert_eq!(params(&body), body);
+        let body = Rmp::marshal(&()).unwrap();
+        assert_eq!(
+            params(&body),
+            rmp_serde::to_vec(&Vec::<u32>::new()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Implements the MessagePack stream of MessagePack-RPC with `tokio` runtime
+
+use ::tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use async_trait::async_trait;
+use erased_serde as erased;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+
+use super::{Exchange, MsgpackRpc, ValueScan};
+use crate::codec::rmp::Rmp;
+use crate::codec::split::SplittableCodec;
+use crate::codec::split::{CodecReadHalf, CodecWriteHalf};
+use crate::codec::translate::{self, TranslateReadHalf, TranslateWriteHalf};
+use crate::codec::{
+    Codec, CodecRead, CodecWrite, ConnTypeReadWrite, EraseDeserializer, Marshal, Unmarshal,
+};
+use crate::error::{CodecError, IoError};
+use crate::message::{MessageId, Metadata};
+use crate::protocol::Header;
+use crate::transport::MessageSizeLimit;
+use crate::util::GracefulShutdown;
+
+/// Reading half of the raw TCP transport, which reads one MessagePack value at a time
+pub(crate) struct StreamReader<R> {
+    inner: R,
+    /// Bytes of the value that is being read
+    buf: Vec<u8>,
+    /// Where the markers of the value are walked up to
+    scan: ValueScan,
+}
+
+impl<R> StreamReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            scan: ValueScan::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl<R, C> CodecRead for CodecReadHalf<StreamReader<R>, C, ConnTypeReadWrite>
+where
+    R: AsyncBufRead + Send + Unpin,
+    C: Unmarshal + EraseDeserializer + Send,
+{
+    /// Reads a value, which is found by walking its markers as the bytes arrive
+    async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
+        if self.closed {
+            return None;
+        }
+        let limit = self.size_limit;
+        let reader = &mut self.reader;
+        loop {
+            let available = match reader.inner.fill_buf().await {
+                Ok(available) => available,
+                Err(err) => return Some(Err(err)),
+            };
+            if available.is_empty() {
+                // EOF, probably client closed connection
+                return match reader.buf.is_empty() {
+                    true => None,
+                    false => Some(Err(ErrorKind::UnexpectedEof.into())),
+                };
+            }
+            let start = reader.buf.len();
+            reader.buf.extend_from_slice(available);
+            match reader.scan.resume(&reader.buf) {
+                Ok(Some(len)) => {
+                    reader.inner.consume(len - start);
+                    reader.scan = ValueScan::default();
+                    let mut buf = std::mem::take(&mut reader.buf);
+                    buf.truncate(len);
+                    if len > limit.max {
+                        self.closed = limit.close;
+                        return Some(Err(limit.error()));
+                    }
+                    return Some(Ok(buf));
+                }
+                Ok(None) => {
+                    let consumed = reader.buf.len() - start;
+                    reader.inner.consume(consumed);
+                    // an oversized message is not held in memory, and the rest of the
+                    // stream cannot be told apart from it
+                    if reader.buf.len() > limit.max {
+                        self.closed = true;
+                        return Some(Err(limit.error()));
+                    }
+                }
+                Err(err) => {
+                    self.closed = true;
+                    return Some(Err(IoError::new(ErrorKind::InvalidData, err)));
+                }
+            }
+        }
+    }
+
+    fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+        self.size_limit = limit;
+    }
+}
+
+/// Writing half of the raw TCP transport, which writes the MessagePack values as they are
+pub(crate) struct StreamWriter<W>(W);
+
+#[async_trait]
+impl<W, C> CodecWrite for CodecWriteHalf<StreamWriter<W>, C, ConnTypeReadWrite>
+where
+    W: AsyncWrite + Send + Unpin,
+    C: Marshal + Send,
+{
+    async fn write_header(&mut self, header: Header) -> Result<(), CodecError> {
+        let buf = Self::marshal(&header)?;
+        self.write_body_bytes(header.id(), &buf).await?;
+        Ok(())
+    }
+
+    async fn write_body(
+        &mut self,
+        id: MessageId,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<(), CodecError> {
+        let buf = Self::marshal(&body)?;
+        self.write_body_bytes(id, &buf).await?;
+        Ok(())
+    }
+
+    async fn write_body_bytes(&mut self, _: MessageId, bytes: &[u8]) -> Result<(), IoError> {
+        self.writer.0.write_all(bytes).await?;
+        self.writer.0.flush().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<W> GracefulShutdown for StreamWriter<W>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    async fn close(&mut self) {
+        match self.0.flush().await {
+            Ok(()) => (),
+            Err(e) => log::error!("Error closing connection: {}", e),
+        }
+
+        match AsyncWriteExt::shutdown(&mut self.0).await {
+            Ok(()) => (),
+            Err(e) => log::error!("Error closing connection: {}", e),
+        }
+    }
+}
+
+impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite, MsgpackRpc>
+where
+    R: AsyncBufRead + Send + Unpin,
+    W: AsyncWrite + Send + Unpin,
+{
+    type Writer =
+        TranslateWriteHalf<CodecWriteHalf<StreamWriter<W>, Rmp, ConnTypeReadWrite>, Exchange>;
+    type Reader =
+        TranslateReadHalf<CodecReadHalf<StreamReader<R>, Rmp, ConnTypeReadWrite>, Exchange>;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        translate::split(
+            CodecWriteHalf {
+                writer: StreamWriter(self.writer),
+                marker: PhantomData,
+                conn_type: PhantomData,
+                compression: Default::default(),
+            },
+            CodecReadHalf {
+                reader: StreamReader::new(self.reader),
+                marker: PhantomData,
+                conn_type: PhantomData,
+                compression: Default::default(),
+                size_limit: Default::default(),
+                closed: false,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::tokio::io::BufReader;
+
+    #[test]
+    fn read_value_byte_by_byte() {
+        let value = rmp_serde::to_vec(&vec![u64::MAX; 100_000]).unwrap();
+        let mut stream = value.clone();
+        stream.extend_from_slice(&value);
+        // the buffer holds a single byte, so the values arrive one byte at a time
+        let mut half = CodecReadHalf::<_, Rmp, ConnTypeReadWrite> {
+            reader: StreamReader::new(BufReader::with_capacity(1, &stream[..])),
+            marker: PhantomData,
+            conn_type: PhantomData,
+            compression: Default::default(),
+            size_limit: Default::default(),
+            closed: false,
+        };
+        futures::executor::block_on(async {
+            for _ in 0..2 {
+                let buf = half.read_bytes().await.unwrap().unwrap();
+                assert_eq!(buf, value);
+            }
+            assert!(half.read_bytes().await.is_none());
+        });
+    }
+}
@@ -4,6 +4,10 @@
 use async_trait::async_trait;
 use std::marker::PhantomData;
 
+use crate::compression::NegotiatedCompression;
+#[cfg(any(feature = "tokio_runtime", feature = "async_std_runtime"))]
+use crate::message::Metadata;
+use crate::transport::MessageSizeLimit;
 use crate::util::GracefulShutdown;
 
 use super::*;
@@ -13,6 +17,10 @@ pub(crate) struct CodecReadHalf<R, C, CT> {
     pub reader: R,
     pub marker: PhantomData<C>,
     pub conn_type: PhantomData<CT>,
+    pub compression: NegotiatedCompression,
+    pub size_limit: MessageSizeLimit,
+    /// Set once an oversized message closes the connection
+    pub closed: bool,
 }
 
 #[allow(dead_code)]
@@ -20,12 +28,17 @@ pub(crate) struct CodecWriteHalf<W, C, CT> {
     pub writer: W,
     pub marker: PhantomData<C>,
     pub conn_type: PhantomData<CT>,
+    pub compression: NegotiatedCompression,
 }
 
 impl<W, C, CT> Marshal for CodecWriteHalf<W, C, CT>
 where
     C: Marshal,
 {
+    fn codec_name() -> &'static str {
+        C::codec_name()
+    }
+
     fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
         C::marshal(val)
     }
@@ -67,62 +80,61 @@ cfg_if! {
     if #[cfg(all(
         any(feature = "async_std_runtime", feature = "tokio_runtime"),
         any(
-            all(
-                feature = "serde_bincode",
-                not(feature = "serde_json"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_cbor",
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_rmp",
-                not(feature = "serde_cbor"),
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-            )
+            feature = "serde_bincode",
+            feature = "serde_cbor",
+            feature = "serde_rmp",
+            feature = "serde_gob"
         )
     ))] {
-        use crate::transport::frame::{PayloadType, FrameRead, FrameWrite, FrameHeader};
+        use crate::transport::frame::{FrameRead, FrameWrite};
+        use crate::transport::chunk::{ChunkedReader, ChunkedWriter, Payload};
+        use crate::compression::decompress;
         use crate::error::IoError;
+        use crate::transport::is_message_too_large;
 
         #[async_trait]
-        impl<R, C> CodecRead for CodecReadHalf<R, C, ConnTypeReadWrite>
+        impl<R, C> CodecRead for CodecReadHalf<ChunkedReader<R>, C, ConnTypeReadWrite>
         where
             R: FrameRead + Send + Unpin,
             C: Unmarshal + EraseDeserializer + Send
         {
             async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
-                self.reader.read_frame().await
-                    .map(|res| {
-                        res.map(|f| f.payload)
-                            .map_err(Into::into)
-                    })
+                if self.closed {
+                    return None;
+                }
+                let max = self.size_limit.max;
+                let res = self.reader.read_frame().await?
+                    .and_then(|f| decompress(f.compression, f.payload, max));
+                if let Err(err) = &res {
+                    self.closed = self.size_limit.close && is_message_too_large(err);
+                }
+                Some(res)
+            }
+
+            fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+                self.size_limit = limit;
+                self.reader.set_size_limit(limit);
+            }
+        }
+
+        impl<C> CodecWriteHalf<ChunkedWriter, C, ConnTypeReadWrite> {
+            /// Compresses the payload unless it is smaller than the compression threshold
+            fn payload(&self, buf: &[u8]) -> Result<Payload, IoError> {
+                let (mark, buf) = self.compression.compress(buf)?;
+                Ok(Payload::new(mark, buf.into_owned()))
             }
         }
 
         #[async_trait]
-        impl<W, C> CodecWrite for CodecWriteHalf<W, C, ConnTypeReadWrite>
+        impl<C> CodecWrite for CodecWriteHalf<ChunkedWriter, C, ConnTypeReadWrite>
         where
-            W: FrameWrite + Send + Unpin,
             C: Marshal + Send,
         {
-            async fn write_header<H>(&mut self, header: H) -> Result<(), CodecError>
-            where
-                H: serde::Serialize + Metadata + Send,
-            {
-                let writer = &mut self.writer;
-
+            async fn write_header(&mut self, header: Header) -> Result<(), CodecError> {
                 let id = header.id();
                 let buf = Self::marshal(&header)?;
-                // let frame = Frame::new(id, 0, PayloadType::Header, buf);
-                let frame_header = FrameHeader::new(id, 0, PayloadType::Header, buf.len() as u32);
-
-                writer.write_frame(frame_header, &buf).await?;
+                let payload = self.payload(&buf)?;
+                self.writer.write_header(id, payload).await?;
                 Ok(())
             }
 
@@ -131,45 +143,73 @@ cfg_if! {
                 id: MessageId,
                 body: &(dyn erased::Serialize + Send + Sync),
             ) -> Result<(), CodecError> {
-                let writer = &mut self.writer;
                 let buf = Self::marshal(&body)?;
-                // let frame = Frame::new(id.to_owned(), 1, PayloadType::Data, buf.to_owned());
-                let frame_header = FrameHeader::new(id, 1, PayloadType::Data, buf.len() as u32);
-                writer.write_frame(frame_header, &buf).await?;
+                let payload = self.payload(&buf)?;
+                self.writer.write_body(id, payload).await?;
                 Ok(())
             }
 
             async fn write_body_bytes(&mut self, id: MessageId, bytes: &[u8]) -> Result<(), IoError> {
-                // let frame = Frame::new(*id, 1, PayloadType::Data, bytes);
-                let frame_header = FrameHeader::new(id, 1, PayloadType::Data, bytes.len() as u32);
-                self.writer.write_frame(frame_header, bytes).await?;
-                Ok(())
+                let payload = self.payload(bytes)?;
+                self.writer.write_body(id, payload).await
+            }
+
+            async fn end_message(&mut self) -> Result<(), IoError> {
+                self.writer.end_message().await
+            }
+
+            fn set_compression(&mut self, compression: NegotiatedCompression) {
+                self.compression = compression;
+            }
+
+            fn set_max_frame_size(&mut self, size: usize) {
+                self.writer.set_chunk_size(size);
             }
         }
 
-        impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite>
-        where
-            R: FrameRead + Send + Unpin,
-            W: FrameWrite + GracefulShutdown + Send + Unpin
-        {
-            type Writer = CodecWriteHalf::<W, Self, ConnTypeReadWrite>;
-            type Reader = CodecReadHalf::<R, Self, ConnTypeReadWrite>;
+        /// The formats other than `json` are sent over the frame transport on raw TCP
+        macro_rules! impl_splittable_codec_for_frame_formats {
+            ($($format:ty),*) => {
+                $(
+                    impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite, $format>
+                    where
+                        R: FrameRead + Send + Unpin,
+                        W: FrameWrite + GracefulShutdown + Send + Unpin + 'static
+                    {
+                        type Writer = CodecWriteHalf::<ChunkedWriter, Self, ConnTypeReadWrite>;
+                        type Reader = CodecReadHalf::<ChunkedReader<R>, Self, ConnTypeReadWrite>;
 
-            fn split(self) -> (Self::Writer, Self::Reader) {
-                (
-                    CodecWriteHalf::<W, Self, ConnTypeReadWrite> {
-                        writer: self.writer,
-                        marker: PhantomData,
-                        conn_type: PhantomData,
-                    },
-                    CodecReadHalf::<R, Self, ConnTypeReadWrite> {
-                        reader: self.reader,
-                        marker: PhantomData,
-                        conn_type: PhantomData
+                        fn split(self) -> (Self::Writer, Self::Reader) {
+                            (
+                                CodecWriteHalf::<ChunkedWriter, Self, ConnTypeReadWrite> {
+                                    writer: ChunkedWriter::new(self.writer),
+                                    marker: PhantomData,
+                                    conn_type: PhantomData,
+                                    compression: Default::default(),
+                                },
+                                CodecReadHalf::<ChunkedReader<R>, Self, ConnTypeReadWrite> {
+                                    reader: ChunkedReader::new(self.reader),
+                                    marker: PhantomData,
+                                    conn_type: PhantomData,
+                                    compression: Default::default(),
+                                    size_limit: Default::default(),
+                                    closed: false,
+                                }
+                            )
+                        }
                     }
-                )
-            }
+                )*
+            };
         }
+
+        #[cfg(feature = "serde_bincode")]
+        impl_splittable_codec_for_frame_formats!(super::bincode::Bincode);
+        #[cfg(feature = "serde_cbor")]
+        impl_splittable_codec_for_frame_formats!(super::cbor::Cbor);
+        #[cfg(feature = "serde_rmp")]
+        impl_splittable_codec_for_frame_formats!(super::rmp::Rmp);
+        #[cfg(feature = "serde_gob")]
+        impl_splittable_codec_for_frame_formats!(super::gob::Gob);
     }
 }
 
@@ -183,30 +223,11 @@ cfg_if! {
             feature = "tokio_runtime",
         ),
         any(
-            all(
-                feature = "serde_bincode",
-                not(feature = "serde_json"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_cbor",
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_json",
-                not(feature = "serde_bincode"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_rmp",
-                not(feature = "serde_cbor"),
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-            )
+            feature = "serde_bincode",
+            feature = "serde_cbor",
+            feature = "serde_json",
+            feature = "serde_rmp",
+            feature = "serde_gob"
         )
     ))] {
         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
@@ -220,8 +241,33 @@ cfg_if! {
             C: Unmarshal + EraseDeserializer + Send
         {
             async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
-                self.reader.read_payload().await
-                    .map(|res| res.map_err(Into::into))
+                if self.closed {
+                    return None;
+                }
+                let max = self.size_limit.max;
+                let res = match self.reader.read_payload().await? {
+                    // `tide` does not let the size be limited before the message is read
+                    Ok(payload) if payload.len() > max => Err(self.size_limit.error()),
+                    Ok(payload) => self.compression.unmark_payload(payload, max),
+                    Err(err) => {
+                        // the WebSocket stream cannot skip the rest of an oversized message
+                        self.closed = crate::transport::is_message_too_large(&err);
+                        return Some(Err(err));
+                    }
+                };
+                if let Err(err) = &res {
+                    self.closed =
+                        self.size_limit.close && crate::transport::is_message_too_large(err);
+                }
+                Some(res)
+            }
+
+            fn set_compression(&mut self, compression: NegotiatedCompression) {
+                self.compression = compression;
+            }
+
+            fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+                self.size_limit = limit;
             }
         }
 
@@ -232,13 +278,10 @@ cfg_if! {
             W: PayloadWrite + Send,
             C: Marshal + Send,
         {
-            async fn write_header<H>(&mut self, header: H) -> Result<(), CodecError>
-            where
-                H: serde::Serialize + Metadata + Send,
-            {
-                let writer = &mut self.writer;
+            async fn write_header(&mut self, header: Header) -> Result<(), CodecError> {
                 let buf = Self::marshal(&header)?;
-                writer.write_payload(&buf).await?;
+                let buf = self.compression.mark_payload(&buf)?;
+                self.writer.write_payload(&buf).await?;
                 Ok(())
             }
 
@@ -248,15 +291,20 @@ cfg_if! {
                 body: &(dyn erased::Serialize + Send + Sync),
             ) -> Result<(), CodecError> {
                 let buf = Self::marshal(&body)?;
-                let writer = &mut self.writer;
-                writer.write_payload(&buf).await?;
+                let buf = self.compression.mark_payload(&buf)?;
+                self.writer.write_payload(&buf).await?;
                 Ok(())
             }
 
             async fn write_body_bytes(&mut self, _: MessageId, bytes: &[u8]) -> Result<(), IoError> {
-                self.writer.write_payload(bytes).await?;
+                let buf = self.compression.mark_payload(bytes)?;
+                self.writer.write_payload(&buf).await?;
                 Ok(())
             }
+
+            fn set_compression(&mut self, compression: NegotiatedCompression) {
+                self.compression = compression;
+            }
         }
 
         #[async_trait]
@@ -272,10 +320,11 @@ cfg_if! {
         }
 
         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
-        impl<R, W> SplittableCodec for Codec<R, W, ConnTypePayload>
+        impl<R, W, F> SplittableCodec for Codec<R, W, ConnTypePayload, F>
         where
             R: PayloadRead + Send,
             W: PayloadWrite + GracefulShutdown + Send,
+            F: Marshal + Unmarshal + EraseDeserializer + Send,
         {
             type Writer = CodecWriteHalf::<W, Self, ConnTypePayload>;
             type Reader = CodecReadHalf::<R, Self, ConnTypePayload>;
@@ -286,11 +335,15 @@ cfg_if! {
                         writer: self.writer,
                         marker: PhantomData,
                         conn_type: PhantomData,
+                        compression: Default::default(),
                     },
                     CodecReadHalf::<R, Self, ConnTypePayload> {
                         reader: self.reader,
                         marker: PhantomData,
-                        conn_type: PhantomData
+                        conn_type: PhantomData,
+                        compression: Default::default(),
+                        size_limit: Default::default(),
+                        closed: false,
                     }
                 )
             }
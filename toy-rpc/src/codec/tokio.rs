@@ -9,7 +9,7 @@ use crate::util::GracefulShutdown;
 
 use super::*;
 
-impl<R, W> Codec<R, W, ConnTypeReadWrite>
+impl<R, W, F> Codec<R, W, ConnTypeReadWrite, F>
 where
     R: AsyncRead + Send + Unpin,
     W: AsyncWrite + Send + Unpin,
@@ -23,11 +23,12 @@ where
             reader,
             writer,
             conn_type: PhantomData,
+            format: PhantomData,
         }
     }
 }
 
-impl<T> Codec<BufReader<ReadHalf<T>>, BufWriter<WriteHalf<T>>, ConnTypeReadWrite>
+impl<T, F> Codec<BufReader<ReadHalf<T>>, BufWriter<WriteHalf<T>>, ConnTypeReadWrite, F>
 where
     T: AsyncRead + AsyncWrite + Send + Unpin,
 {
@@ -42,10 +43,11 @@ where
 }
 
 #[async_trait]
-impl<R, W> GracefulShutdown for Codec<R, W, ConnTypeReadWrite>
+impl<R, W, F> GracefulShutdown for Codec<R, W, ConnTypeReadWrite, F>
 where
     R: AsyncRead + Send + Sync + Unpin,
     W: AsyncWrite + Send + Sync + Unpin,
+    F: Send,
 {
     async fn close(&mut self) {
         match self.writer.flush().await {
@@ -62,10 +64,11 @@ where
 
 #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
 #[async_trait::async_trait]
-impl<R, W> GracefulShutdown for Codec<R, W, ConnTypePayload>
+impl<R, W, F> GracefulShutdown for Codec<R, W, ConnTypePayload, F>
 where
     R: Send,
     W: GracefulShutdown + Send,
+    F: Send,
 {
     async fn close(&mut self) {
         self.writer.close().await;
@@ -0,0 +1,305 @@
+//! Translation of the messages of other RPC protocols
+//!
+//! The protocols of other RPC frameworks, ie. JSON-RPC, carry a header and its body in one
+//! message and have no handshake. The writing half holds a header back until its body is
+//! written, and both are encoded into one message of the protocol. The reading half
+//! translates each message of the peer into the headers of `toy-rpc` and holds the body
+//! of a header until it is read. The state of a connection, ie. the ids of the requests
+//! from the peer that are sent back in the responses, is shared by both halves.
+
+use async_trait::async_trait;
+use cfg_if::cfg_if;
+use erased_serde as erased;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{CodecWrite, Marshal, Unmarshal};
+use crate::compression::NegotiatedCompression;
+use crate::error::{CodecError, IoError, ParseError};
+use crate::message::MessageId;
+use crate::protocol::{Capabilities, Header, DEFAULT_MAX_FRAME_SIZE, PROTOCOL_VERSION};
+use crate::util::GracefulShutdown;
+
+/// A protocol whose messages are encoded from the messages of `toy-rpc`
+pub(crate) trait Protocol: Send + 'static {
+    /// Format of the protocol, which names the codec
+    type Format: Marshal + Unmarshal;
+
+    /// Name of the protocol in the errors
+    const NAME: &'static str;
+
+    /// Features of `toy-rpc` that the peer is assumed to support
+    const FEATURES: &'static [&'static str];
+
+    /// Whether the messages of the header can be written. A cancellation is accepted but
+    /// never sent
+    fn supports(header: &Header) -> bool {
+        matches!(
+            header,
+            Header::Request { .. }
+                | Header::Notify { .. }
+                | Header::Response { .. }
+                | Header::Cancel(_)
+        )
+    }
+
+    /// Encodes a header and its body into a message. Returns `None` if there is nothing
+    /// to write yet
+    fn encode(
+        &mut self,
+        header: Header,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<Option<Vec<u8>>, CodecError>;
+
+    /// Encodes a header and its body, which is serialized with the format, into a message
+    fn encode_bytes(&mut self, header: Header, body: &[u8]) -> Result<Option<Vec<u8>>, CodecError>;
+}
+
+/// Error of a header whose messages cannot be written with `P`
+pub(crate) fn unsupported<P: Protocol>(header: &Header) -> CodecError {
+    CodecError::ParseError(format!("{:?} is not supported by {}", header, P::NAME).into())
+}
+
+fn lock<P>(protocol: &Mutex<P>) -> MutexGuard<'_, P> {
+    protocol.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn into_io_error(err: CodecError) -> IoError {
+    match err {
+        CodecError::IoError(err) => err,
+        CodecError::ParseError(err) => IoError::new(ErrorKind::InvalidData, err),
+    }
+}
+
+/// Writing half of a `Codec` that translates the messages into the messages of `P`
+pub(crate) struct TranslateWriteHalf<W, P> {
+    inner: W,
+    /// Header that is written together with its body in one message
+    pending: Option<Header>,
+    protocol: Arc<Mutex<P>>,
+}
+
+impl<W, P> TranslateWriteHalf<W, P> {
+    pub(crate) fn new(inner: W, protocol: Arc<Mutex<P>>) -> Self {
+        Self {
+            inner,
+            pending: None,
+            protocol,
+        }
+    }
+}
+
+impl<W, P: Protocol> TranslateWriteHalf<W, P> {
+    /// Takes the header of the body that is written, or `None` if nothing is written
+    fn take_pending(&mut self) -> Result<Option<Header>, CodecError> {
+        match self.pending.take() {
+            // the peer cannot cancel a call, which is only given up on by this side
+            Some(Header::Cancel(_)) => Ok(None),
+            Some(header) => Ok(Some(header)),
+            None => Err(CodecError::ParseError(
+                format!("Expecting the header of a {} message", P::NAME).into(),
+            )),
+        }
+    }
+}
+
+impl<W, P: Protocol> Marshal for TranslateWriteHalf<W, P> {
+    fn codec_name() -> &'static str {
+        P::Format::codec_name()
+    }
+
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, ParseError> {
+        P::Format::marshal(val)
+    }
+}
+
+#[async_trait]
+impl<W: CodecWrite, P: Protocol> CodecWrite for TranslateWriteHalf<W, P> {
+    async fn write_header(&mut self, header: Header) -> Result<(), CodecError> {
+        match P::supports(&header) {
+            true => {
+                self.pending = Some(header);
+                Ok(())
+            }
+            false => Err(unsupported::<P>(&header)),
+        }
+    }
+
+    async fn write_body(
+        &mut self,
+        id: MessageId,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<(), CodecError> {
+        let header = match self.take_pending()? {
+            Some(header) => header,
+            None => return Ok(()),
+        };
+        let message = lock(&self.protocol).encode(header, body)?;
+        if let Some(buf) = message {
+            self.inner.write_body_bytes(id, &buf).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_body_bytes(&mut self, id: MessageId, bytes: &[u8]) -> Result<(), IoError> {
+        let header = match self.take_pending().map_err(into_io_error)? {
+            Some(header) => header,
+            None => return Ok(()),
+        };
+        let message = lock(&self.protocol)
+            .encode_bytes(header, bytes)
+            .map_err(into_io_error)?;
+        match message {
+            Some(buf) => self.inner.write_body_bytes(id, &buf).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn end_message(&mut self) -> Result<(), IoError> {
+        self.inner.end_message().await
+    }
+
+    fn set_compression(&mut self, compression: NegotiatedCompression) {
+        self.inner.set_compression(compression);
+    }
+
+    fn implied_capabilities() -> Option<Capabilities> {
+        Some(Capabilities {
+            version: PROTOCOL_VERSION,
+            codec: P::Format::codec_name().into(),
+            features: P::FEATURES
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        })
+    }
+}
+
+#[async_trait]
+impl<W, P> GracefulShutdown for TranslateWriteHalf<W, P>
+where
+    W: GracefulShutdown + Send,
+    P: Send,
+{
+    async fn close(&mut self) {
+        self.inner.close().await;
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(feature = "serde_json", feature = "serde_rmp"))] {
+        use std::collections::VecDeque;
+
+        use super::{CodecRead, EraseDeserializer};
+        use crate::protocol::InboundBody;
+        use crate::transport::MessageSizeLimit;
+
+        /// A protocol whose messages are each read as a whole and translated into the
+        /// messages of `toy-rpc`
+        pub(crate) trait Translate: Protocol + Default {
+            /// Format of the values that are carried by the messages
+            type Values: EraseDeserializer;
+
+            /// Body of a translated message, which is held until it is read
+            type Body: Send;
+
+            /// Translates a message of the peer into headers and their bodies. A message
+            /// can carry more than one, ie. a batch, or none at all
+            fn translate(&mut self, buf: &[u8]) -> Result<Vec<(Header, Self::Body)>, CodecError>;
+
+            /// Deserializer of a body
+            fn deserializer(body: Self::Body) -> Result<Box<InboundBody>, CodecError>;
+
+            /// Serialized body, which is encoded with the format
+            fn into_bytes(body: Self::Body) -> Result<Vec<u8>, IoError>;
+        }
+
+        /// Reading half of a `Codec` that translates the messages of `P`
+        pub(crate) struct TranslateReadHalf<R, P: Translate> {
+            inner: R,
+            /// Translated messages that are not read yet
+            queue: VecDeque<(Header, P::Body)>,
+            /// Body of the message whose header is read last
+            body: Option<P::Body>,
+            protocol: Arc<Mutex<P>>,
+        }
+
+        impl<R, P: Translate> Unmarshal for TranslateReadHalf<R, P> {
+            fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, ParseError> {
+                P::Format::unmarshal(buf)
+            }
+        }
+
+        impl<R, P: Translate> EraseDeserializer for TranslateReadHalf<R, P> {
+            fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+                P::Values::from_bytes(buf)
+            }
+        }
+
+        #[async_trait]
+        impl<R: CodecRead, P: Translate> CodecRead for TranslateReadHalf<R, P> {
+            async fn read_header(&mut self) -> Option<Result<Header, CodecError>> {
+                let (header, body) = loop {
+                    if let Some(message) = self.queue.pop_front() {
+                        break message;
+                    }
+                    let buf = match self.inner.read_bytes().await? {
+                        Ok(buf) => buf,
+                        Err(err) => return Some(Err(err.into())),
+                    };
+                    let messages = lock(&self.protocol).translate(&buf);
+                    match messages {
+                        Ok(messages) => self.queue.extend(messages),
+                        Err(err) => return Some(Err(err)),
+                    }
+                };
+                self.body = Some(body);
+                Some(Ok(header))
+            }
+
+            async fn read_body(&mut self) -> Option<Result<Box<InboundBody>, CodecError>> {
+                match self.body.take() {
+                    Some(body) => Some(P::deserializer(body)),
+                    None => Some(Err(IoError::new(
+                        ErrorKind::InvalidData,
+                        format!("Expecting the header of a {} message", P::NAME),
+                    )
+                    .into())),
+                }
+            }
+
+            async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, IoError>> {
+                match self.body.take() {
+                    Some(body) => Some(P::into_bytes(body)),
+                    None => self.inner.read_bytes().await,
+                }
+            }
+
+            fn set_compression(&mut self, compression: NegotiatedCompression) {
+                self.inner.set_compression(compression);
+            }
+
+            fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+                self.inner.set_size_limit(limit);
+            }
+        }
+
+        /// Wraps the halves of a transport that carry one message of `P` at a time
+        pub(crate) fn split<W, R, P: Translate>(
+            writer: W,
+            reader: R,
+        ) -> (TranslateWriteHalf<W, P>, TranslateReadHalf<R, P>) {
+            let protocol = Arc::new(Mutex::new(P::default()));
+            (
+                TranslateWriteHalf::new(writer, protocol.clone()),
+                TranslateReadHalf {
+                    inner: reader,
+                    queue: VecDeque::new(),
+                    body: None,
+                    protocol,
+                },
+            )
+        }
+    }
+}
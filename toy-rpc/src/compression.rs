@@ -0,0 +1,384 @@
+//! Compression of the payloads sent over a connection
+//!
+//! Compression is enabled with `ClientBuilder::set_compression` on the client side and with
+//! `ServerBuilder::set_compression` on the server side. Each side advertises the algorithms it
+//! can decompress in the handshake (ie. `"compression/deflate"`), and a side only compresses
+//! its payloads if the peer supports its algorithm. Payloads smaller than the compression
+//! threshold are sent uncompressed.
+//!
+//! Whether a payload is compressed is marked per frame. The frame transport marks it in the
+//! `payload_type` of the frame header, and a WebSocket message carries a leading byte with the
+//! mark once both sides support compression. The line-delimited JSON transport over raw TCP
+//! does not support compression.
+//!
+//! The algorithms are enabled with the `compression_deflate` and `compression_lz4` feature flags.
+
+use cfg_if::cfg_if;
+
+use crate::protocol::FEATURE_COMPRESSION;
+
+#[cfg(any(feature = "server", feature = "client"))]
+use crate::protocol::Capabilities;
+
+/// Default size in bytes below which payloads are sent uncompressed
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Mark of a payload that is not compressed
+pub(crate) const UNCOMPRESSED: u8 = 0;
+
+/// Largest mark of a compressed payload. The frame header has four bits for the mark
+const MAX_MARK: u8 = 0x0F;
+
+/// Compression algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// DEFLATE, which is provided by `flate2`
+    #[cfg(feature = "compression_deflate")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "compression_deflate")))]
+    Deflate,
+    /// LZ4, which is provided by `lz4_flex`
+    #[cfg(feature = "compression_lz4")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "compression_lz4")))]
+    Lz4,
+}
+
+impl Compression {
+    /// Algorithms enabled by the feature flags
+    pub fn available() -> impl Iterator<Item = Compression> {
+        (UNCOMPRESSED + 1..=MAX_MARK).filter_map(Self::from_mark)
+    }
+
+    /// Name of the algorithm
+    pub fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "compression_deflate")]
+            Compression::Deflate => "deflate",
+            #[cfg(feature = "compression_lz4")]
+            Compression::Lz4 => "lz4",
+        }
+    }
+
+    /// Optional feature advertised in the handshake by a peer that can decompress payloads
+    /// compressed with this algorithm, ie. `"compression/deflate"`
+    pub fn feature(&self) -> String {
+        format!("{}/{}", FEATURE_COMPRESSION, self.name())
+    }
+
+    fn from_mark(mark: u8) -> Option<Self> {
+        match mark {
+            #[cfg(feature = "compression_deflate")]
+            1 => Some(Compression::Deflate),
+            #[cfg(feature = "compression_lz4")]
+            2 => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// Compression of a connection that is agreed upon in the handshake
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NegotiatedCompression {
+    outbound: Option<Compression>,
+    threshold: usize,
+    marked: bool,
+}
+
+impl NegotiatedCompression {
+    /// The local algorithm is only used if the peer supports it. The mark is carried by the
+    /// payloads in both directions if the peers have any algorithm in common.
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub(crate) fn new(
+        local: Option<Compression>,
+        threshold: usize,
+        capabilities: &Capabilities,
+    ) -> Self {
+        let outbound = local.filter(|compression| {
+            let supported = capabilities.supports(&compression.feature());
+            if !supported {
+                log::warn!(
+                    "Peer does not support {} compression, payloads are sent uncompressed",
+                    compression.name()
+                );
+            }
+            supported
+        });
+        let marked = Compression::available().any(|c| capabilities.supports(&c.feature()));
+        Self {
+            outbound,
+            threshold,
+            marked,
+        }
+    }
+
+    /// Algorithm of the outbound payloads. `None` if they are not compressed
+    pub fn outbound(&self) -> Option<Compression> {
+        self.outbound
+    }
+
+    /// Size in bytes below which the outbound payloads are sent uncompressed
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Whether the payloads without a frame header carry a leading byte with the mark
+    pub fn is_marked(&self) -> bool {
+        self.marked
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*              // Payloads of the frame and WebSocket transports             */
+/* -------------------------------------------------------------------------- */
+cfg_if! {
+    if #[cfg(any(
+        feature = "ws_tokio",
+        feature = "ws_async_std",
+        all(
+            any(feature = "async_std_runtime", feature = "tokio_runtime"),
+            any(
+                feature = "serde_bincode",
+                feature = "serde_cbor",
+                feature = "serde_rmp",
+                feature = "serde_gob"
+            )
+        )
+    ))] {
+        use std::borrow::Cow;
+        use std::io::ErrorKind;
+
+        use crate::error::IoError;
+        #[cfg(any(feature = "compression_deflate", feature = "compression_lz4"))]
+        use crate::transport::message_too_large;
+
+        impl Compression {
+            fn mark(&self) -> u8 {
+                match *self {
+                    #[cfg(feature = "compression_deflate")]
+                    Compression::Deflate => 1,
+                    #[cfg(feature = "compression_lz4")]
+                    Compression::Lz4 => 2,
+                }
+            }
+
+            fn compress(&self, payload: &[u8]) -> Result<Vec<u8>, IoError> {
+                // the arguments are matched along with the algorithm so that they are still
+                // used when no algorithm is enabled
+                match (*self, payload) {
+                    #[cfg(feature = "compression_deflate")]
+                    (Compression::Deflate, payload) => {
+                        use std::io::Write;
+
+                        let mut encoder = flate2::write::DeflateEncoder::new(
+                            Vec::new(),
+                            flate2::Compression::default(),
+                        );
+                        encoder.write_all(payload)?;
+                        encoder.finish()
+                    }
+                    #[cfg(feature = "compression_lz4")]
+                    (Compression::Lz4, payload) => Ok(lz4_flex::compress_prepend_size(payload)),
+                }
+            }
+
+            fn decompress(&self, payload: &[u8], max: usize) -> Result<Vec<u8>, IoError> {
+                match (*self, payload, max) {
+                    #[cfg(feature = "compression_deflate")]
+                    (Compression::Deflate, payload, max) => {
+                        use std::io::Read;
+
+                        // one byte past the limit is enough to tell that the payload is too large
+                        let mut buf = Vec::new();
+                        flate2::read::DeflateDecoder::new(payload)
+                            .take((max as u64).saturating_add(1))
+                            .read_to_end(&mut buf)?;
+                        match buf.len() > max {
+                            true => Err(message_too_large(max)),
+                            false => Ok(buf),
+                        }
+                    }
+                    #[cfg(feature = "compression_lz4")]
+                    (Compression::Lz4, payload, max) => {
+                        let to_io_err = |err: lz4_flex::block::DecompressError| {
+                            IoError::new(ErrorKind::InvalidData, err.to_string())
+                        };
+                        // the size is checked before the output is allocated
+                        let (size, _) =
+                            lz4_flex::block::uncompressed_size(payload).map_err(to_io_err)?;
+                        if size > max {
+                            return Err(message_too_large(max));
+                        }
+                        lz4_flex::decompress_size_prepended(payload).map_err(to_io_err)
+                    }
+                }
+            }
+        }
+
+        impl NegotiatedCompression {
+            /// Compresses the payload unless it is smaller than the threshold. Returns the mark
+            /// of the payload along with the payload
+            pub(crate) fn compress<'a>(
+                &self,
+                payload: &'a [u8],
+            ) -> Result<(u8, Cow<'a, [u8]>), IoError> {
+                if let Some(compression) = self.outbound {
+                    if payload.len() >= self.threshold {
+                        let compressed = compression.compress(payload)?;
+                        // a payload that does not get smaller is sent as it is
+                        if compressed.len() < payload.len() {
+                            return Ok((compression.mark(), Cow::Owned(compressed)));
+                        }
+                    }
+                }
+                Ok((UNCOMPRESSED, Cow::Borrowed(payload)))
+            }
+
+            /// Compresses a payload that has no frame header and prepends the mark
+            #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+            pub(crate) fn mark_payload<'a>(
+                &self,
+                payload: &'a [u8],
+            ) -> Result<Cow<'a, [u8]>, IoError> {
+                if !self.marked {
+                    return Ok(Cow::Borrowed(payload));
+                }
+                let (mark, payload) = self.compress(payload)?;
+                let mut buf = Vec::with_capacity(payload.len() + 1);
+                buf.push(mark);
+                buf.extend_from_slice(&payload);
+                Ok(Cow::Owned(buf))
+            }
+
+            /// Removes the mark from a payload that has no frame header and decompresses it.
+            /// The decompressed payload may not be larger than `max` bytes
+            #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+            pub(crate) fn unmark_payload(
+                &self,
+                mut payload: Vec<u8>,
+                max: usize,
+            ) -> Result<Vec<u8>, IoError> {
+                if !self.marked {
+                    return Ok(payload);
+                }
+                if payload.is_empty() {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidData,
+                        "Expecting the compression mark of the payload",
+                    ));
+                }
+                let mark = payload.remove(0);
+                decompress(mark, payload, max)
+            }
+        }
+
+        /// Decompresses a payload with the mark. The decompressed payload may not be larger
+        /// than `max` bytes
+        pub(crate) fn decompress(
+            mark: u8,
+            payload: Vec<u8>,
+            max: usize,
+        ) -> Result<Vec<u8>, IoError> {
+            if mark == UNCOMPRESSED {
+                return Ok(payload);
+            }
+            match Compression::from_mark(mark) {
+                Some(compression) => compression.decompress(&payload, max),
+                None => Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Unsupported compression of the payload: {}", mark),
+                )),
+            }
+        }
+    }
+}
+
+// The tests use the negotiation of the handshake and the payloads of the WebSocket transport
+#[cfg(all(
+    test,
+    any(feature = "compression_deflate", feature = "compression_lz4"),
+    any(feature = "server", feature = "client"),
+    any(feature = "ws_tokio", feature = "ws_async_std")
+))]
+mod tests {
+    use super::*;
+
+    fn negotiated(compression: Compression, threshold: usize) -> NegotiatedCompression {
+        let capabilities = Capabilities::local("bincode");
+        NegotiatedCompression::new(Some(compression), threshold, &capabilities)
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let payload = b"toy-rpc ".repeat(512);
+        for compression in Compression::available() {
+            let negotiated = negotiated(compression, 64);
+            let (mark, compressed) = negotiated.compress(&payload).unwrap();
+            assert_eq!(mark, compression.mark());
+            assert!(compressed.len() < payload.len());
+            assert_eq!(
+                decompress(mark, compressed.into_owned(), usize::MAX).unwrap(),
+                payload
+            );
+
+            let marked = negotiated.mark_payload(&payload).unwrap().into_owned();
+            assert_eq!(marked[0], compression.mark());
+            assert_eq!(
+                negotiated.unmark_payload(marked, usize::MAX).unwrap(),
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn compression_threshold() {
+        let payload = b"toy-rpc ".repeat(8);
+        for compression in Compression::available() {
+            let negotiated = negotiated(compression, payload.len() + 1);
+            let (mark, buf) = negotiated.compress(&payload).unwrap();
+            assert_eq!(mark, UNCOMPRESSED);
+            assert_eq!(&buf[..], &payload[..]);
+
+            let marked = negotiated.mark_payload(&payload).unwrap().into_owned();
+            assert_eq!(marked[0], UNCOMPRESSED);
+            assert_eq!(
+                negotiated.unmark_payload(marked, usize::MAX).unwrap(),
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn compression_not_supported_by_peer() {
+        let mut capabilities = Capabilities::local("bincode");
+        capabilities
+            .features
+            .retain(|f| !f.starts_with(FEATURE_COMPRESSION));
+        let payload = b"toy-rpc ".repeat(512);
+        for compression in Compression::available() {
+            let negotiated = NegotiatedCompression::new(Some(compression), 0, &capabilities);
+            let (mark, _) = negotiated.compress(&payload).unwrap();
+            assert_eq!(mark, UNCOMPRESSED);
+            // payloads without a frame header are not marked either
+            assert_eq!(
+                &negotiated.mark_payload(&payload).unwrap()[..],
+                &payload[..]
+            );
+        }
+        assert!(decompress(MAX_MARK, payload, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn decompressed_size_is_limited() {
+        let payload = vec![0u8; 1024 * 1024];
+        for compression in Compression::available() {
+            let negotiated = negotiated(compression, 0);
+            let (mark, compressed) = negotiated.compress(&payload).unwrap();
+            let compressed = compressed.into_owned();
+            let err = decompress(mark, compressed.clone(), payload.len() - 1).unwrap_err();
+            assert!(crate::transport::is_message_too_large(&err));
+            assert_eq!(
+                decompress(mark, compressed, payload.len()).unwrap(),
+                payload
+            );
+        }
+    }
+}
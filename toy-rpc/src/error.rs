@@ -1,8 +1,21 @@
 //! Custom errors
 
-use std::fmt::Debug;
-
-use crate::message::{ErrorMessage, MessageId};
+use cfg_if::cfg_if;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::{self, Debug};
+
+use crate::message::MessageId;
+use crate::status::{Code, Status};
+
+cfg_if! {
+    if #[cfg(all(
+        any(feature = "async_std_runtime", feature = "tokio_runtime"),
+        any(feature = "server", feature = "client")
+    ))] {
+        use crate::message::ErrorMessage;
+        use crate::protocol::{InboundBody, OutboundBody};
+    }
+}
 
 pub(crate) type IoError = std::io::Error;
 pub(crate) type ParseError = Box<dyn std::error::Error + Send + Sync>;
@@ -31,7 +44,7 @@ pub enum CodecError {
 impl From<CodecError> for Error {
     fn from(err: CodecError) -> Self {
         match err {
-            CodecError::IoError(err) => Error::IoError(err),
+            CodecError::IoError(err) => err.into(),
             CodecError::ParseError(err) => Error::ParseError(err),
         }
     }
@@ -48,7 +61,7 @@ pub enum Error {
     ///
     /// This is expected to see changes in version 0.9.
     #[error("{0:?}")]
-    IoError(#[from] std::io::Error),
+    IoError(#[source] std::io::Error),
 
     /// Errors with serialization/deserialization
     #[error("{0}")]
@@ -74,6 +87,13 @@ pub enum Error {
     #[error("{0}")]
     ExecutionError(String),
 
+    /// Typed execution error returned by RPC method, see `Error::typed`.
+    ///
+    /// The error value is serialized with the codec of the connection and can be
+    /// recovered on the other end with `Error::payload`.
+    #[error("{0}")]
+    Typed(TypedError),
+
     /// Cancellation error when an RPC call is cancelled
     #[error("Request ({0}) is canceled")]
     Canceled(MessageId),
@@ -90,28 +110,329 @@ pub enum Error {
     /// Maximum number of retries is reached before an Ack is received
     #[error("Maximum number of retries is reached for message {0}")]
     MaxRetriesReached(MessageId),
+
+    /// The peer failed the handshake, ie. it speaks a different protocol version,
+    /// uses a different codec or is not a `toy-rpc` peer at all
+    #[error("Incompatible peer: {0}")]
+    IncompatiblePeer(String),
+
+    /// The peer has no extension handler registered for the marker
+    #[error("Unknown extension marker: {0}")]
+    UnknownExtension(u32),
+
+    /// The peer stopped answering the keepalive pings and the connection is closed
+    #[error("Connection to the peer is lost")]
+    ConnectionLost,
+
+    /// An inbound message exceeds the max message size, which is carried by the error
+    #[error("Message exceeds the max message size of {0} bytes")]
+    MessageTooLarge(usize),
+
+    /// Status returned by RPC method, or a failure that the peer reported with a status
+    /// but has no more specific variant
+    #[error("{0}")]
+    Status(Status),
 }
 
 impl Error {
-    pub(crate) fn from_err_msg(msg: ErrorMessage) -> Self {
+    /// Creates an error that carries a typed value to the caller.
+    ///
+    /// Methods exported with the macros that return `Result<T, E>` convert `E` with
+    /// `From<E> for toy_rpc::Error` if there is such an impl, and otherwise with this
+    /// function if `E` implements `Serialize`. The message of the error is the `Debug`
+    /// representation of `E`, or the name of the type if `E` does not implement `Debug`.
+    /// The caller gets back `Error::Typed` and can recover the value with `Error::payload`,
+    /// or with `Call::typed` on the client stubs generated by the macros.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// #[derive(Debug, Serialize, Deserialize)]
+    /// pub enum BankError {
+    ///     InsufficientFunds { balance: u64 },
+    /// }
+    ///
+    /// #[export_impl]
+    /// impl Bank {
+    ///     #[export_method]
+    ///     async fn withdraw(&self, amount: u64) -> Result<u64, BankError> {
+    ///         // ...
+    ///     }
+    /// }
+    ///
+    /// // on the client
+    /// match client.bank().withdraw(100).typed().await {
+    ///     Err(CallError::Typed(BankError::InsufficientFunds { balance })) => { /* ... */ }
+    ///     Err(CallError::Rpc(err)) => { /* other errors */ }
+    ///     Ok(_) => { /* ... */ }
+    /// }
+    /// ```
+    pub fn typed<E>(err: E) -> Self
+    where
+        E: Serialize + Debug + Send + Sync + 'static,
+    {
+        let message = format!("{:?}", &err);
+        Self::typed_with_message(message, err)
+    }
+
+    #[cfg_attr(
+        not(any(feature = "server", feature = "client")),
+        allow(unused_variables)
+    )]
+    fn typed_with_message<E>(message: String, err: E) -> Self
+    where
+        E: Serialize + Send + Sync + 'static,
+    {
+        Self::Typed(TypedError {
+            message,
+            repr: TypedRepr::Local {
+                #[cfg(any(feature = "server", feature = "client"))]
+                value: Box::new(err),
+            },
+        })
+    }
+
+    /// Returns the typed value if this is an `Error::Typed` received from the peer and it
+    /// deserializes into `E`
+    pub fn payload<E: DeserializeOwned>(&self) -> Option<E> {
+        match self {
+            Self::Typed(err) => err.decode().ok(),
+            _ => None,
+        }
+    }
+
+    /// Status code of the error
+    pub fn code(&self) -> Code {
+        match self {
+            Self::IoError(_) => Code::Unavailable,
+            Self::ParseError(_) => Code::Internal,
+            Self::Internal(_) => Code::Internal,
+            Self::InvalidArgument => Code::InvalidArgument,
+            Self::ServiceNotFound => Code::Unimplemented,
+            Self::MethodNotFound => Code::Unimplemented,
+            Self::ExecutionError(_) => Code::Unknown,
+            Self::Typed(_) => Code::Unknown,
+            Self::Canceled(_) => Code::Cancelled,
+            Self::Timeout(_) => Code::DeadlineExceeded,
+            Self::MaxRetriesReached(_) => Code::Unavailable,
+            Self::IncompatiblePeer(_) => Code::FailedPrecondition,
+            Self::UnknownExtension(_) => Code::Unimplemented,
+            Self::ConnectionLost => Code::Unavailable,
+            Self::MessageTooLarge(_) => Code::ResourceExhausted,
+            Self::Status(status) => status.code(),
+        }
+    }
+
+    /// Status that is sent to the peer in the header of the response
+    pub fn status(&self) -> Status {
+        match self {
+            Self::Status(status) => status.clone(),
+            err => Status::new(err.code(), err.to_string()),
+        }
+    }
+
+    /// Converts the `ErrorMessage` received from the peer. The payload of a typed error
+    /// is decoded lazily with `decoder`, which is provided by the codec of the connection
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub(crate) fn from_err_msg(id: MessageId, msg: ErrorMessage, decoder: PayloadDecoder) -> Self {
         match msg {
             ErrorMessage::InvalidArgument => Self::InvalidArgument,
             ErrorMessage::ServiceNotFound => Self::ServiceNotFound,
             ErrorMessage::MethodNotFound => Self::MethodNotFound,
             ErrorMessage::ExecutionError(s) => Self::ExecutionError(s),
+            ErrorMessage::Typed { message, payload } => Self::Typed(TypedError {
+                message,
+                repr: TypedRepr::Remote {
+                    bytes: payload,
+                    decoder,
+                },
+            }),
+            ErrorMessage::Canceled => Self::Canceled(id),
+            ErrorMessage::Timeout => Self::Timeout(id),
+            ErrorMessage::Internal(s) => Self::Internal(s.into()),
+            ErrorMessage::Status(status) => Self::Status(status),
         }
     }
 }
 
-impl<T: 'static> From<flume::SendError<T>> for Error {
-    fn from(_: flume::SendError<T>) -> Self {
-        Self::Internal(format!("Cannot send internal message").into())
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        // the transports report an oversized message as an `IoError` that wraps the error
+        let too_large = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<Error>())
+            .and_then(|inner| match inner {
+                Error::MessageTooLarge(max) => Some(*max),
+                _ => None,
+            });
+        match too_large {
+            Some(max) => Error::MessageTooLarge(max),
+            None => Error::IoError(err),
+        }
+    }
+}
+
+/// Creates a deserializer from the bytes of a typed error payload
+#[cfg(any(feature = "server", feature = "client"))]
+pub(crate) type PayloadDecoder = fn(Vec<u8>) -> Box<InboundBody>;
+
+/// Typed error returned by an RPC method, which is carried by `Error::Typed`
+pub struct TypedError {
+    message: String,
+    repr: TypedRepr,
+}
+
+pub(crate) enum TypedRepr {
+    /// The error value before it is sent to the peer, which is only kept if it can be sent
+    Local {
+        #[cfg(any(feature = "server", feature = "client"))]
+        value: Box<OutboundBody>,
+    },
+    /// The serialized error value received from the peer
+    #[cfg(any(feature = "server", feature = "client"))]
+    Remote {
+        bytes: Vec<u8>,
+        decoder: PayloadDecoder,
+    },
+}
+
+impl TypedError {
+    /// `Debug` representation of the error value
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Deserializes the error value.
+    ///
+    /// Only errors received from the peer can be deserialized. An error that has not
+    /// been sent yet returns `Error::Internal`.
+    pub fn decode<E: DeserializeOwned>(&self) -> Result<E, Error> {
+        match &self.repr {
+            TypedRepr::Local { .. } => Err(Error::Internal(
+                "Typed error is not received from the peer".into(),
+            )),
+            #[cfg(any(feature = "server", feature = "client"))]
+            TypedRepr::Remote { bytes, decoder } => {
+                let mut de = decoder(bytes.clone());
+                erased_serde::deserialize(&mut de).map_err(|err| Error::ParseError(Box::new(err)))
+            }
+        }
+    }
+
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub(crate) fn into_parts(self) -> (String, TypedRepr) {
+        (self.message, self.repr)
+    }
+}
+
+impl Debug for TypedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedError")
+            .field("message", &self.message)
+            .finish()
+    }
+}
+
+impl fmt::Display for TypedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Error of a `Call` whose RPC method returns the error type `E`, see `Call::typed`
+#[derive(Debug)]
+pub enum CallError<E> {
+    /// Error returned by the RPC method
+    Typed(E),
+    /// Any other error, ie. the connection is lost or the method is not found
+    Rpc(Error),
+}
+
+impl<E: DeserializeOwned> From<Error> for CallError<E> {
+    fn from(err: Error) -> Self {
+        match err.payload::<E>() {
+            Some(payload) => Self::Typed(payload),
+            None => Self::Rpc(err),
+        }
+    }
+}
+
+/// Conversion of the errors returned by the methods that are exported with the macros.
+///
+/// The error is converted with `Into<Error>` if it implements it, and is otherwise
+/// sent as `Error::Typed` if it implements `Serialize`. The generated handlers pick
+/// the conversion with `(&&Wrap(&err)).error_kind()`, where method resolution prefers
+/// the impl for `&Wrap` over the impl for `&&Wrap`, and the impl for `&&Wrap` over
+/// the impl for `Wrap`.
+#[doc(hidden)]
+pub mod conversion {
+    use super::*;
+
+    pub struct Wrap<'a, E>(pub &'a E);
+
+    pub struct IntoTag;
+    pub struct TypedDebugTag;
+    pub struct TypedTag;
+
+    pub trait IntoKind {
+        fn error_kind(&self) -> IntoTag {
+            IntoTag
+        }
+    }
+
+    impl<'a, E: Into<Error>> IntoKind for &Wrap<'a, E> {}
+
+    pub trait TypedDebugKind {
+        fn error_kind(&self) -> TypedDebugTag {
+            TypedDebugTag
+        }
+    }
+
+    impl<'a, E: Serialize + Debug + Send + Sync + 'static> TypedDebugKind for &&Wrap<'a, E> {}
+
+    pub trait TypedKind {
+        fn error_kind(&self) -> TypedTag {
+            TypedTag
+        }
+    }
+
+    impl<'a, E: Serialize + Send + Sync + 'static> TypedKind for Wrap<'a, E> {}
+
+    impl IntoTag {
+        pub fn into_error<E: Into<Error>>(self, err: E) -> Error {
+            err.into()
+        }
+    }
+
+    impl TypedDebugTag {
+        pub fn into_error<E>(self, err: E) -> Error
+        where
+            E: Serialize + Debug + Send + Sync + 'static,
+        {
+            Error::typed(err)
+        }
+    }
+
+    impl TypedTag {
+        pub fn into_error<E>(self, err: E) -> Error
+        where
+            E: Serialize + Send + Sync + 'static,
+        {
+            let message = std::any::type_name::<E>().to_string();
+            Error::typed_with_message(message, err)
+        }
     }
 }
 
-impl From<ErrorMessage> for Error {
-    fn from(msg: ErrorMessage) -> Self {
-        Self::from_err_msg(msg)
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        Self::Status(status)
+    }
+}
+
+impl<T: 'static> From<flume::SendError<T>> for Error {
+    fn from(_: flume::SendError<T>) -> Self {
+        Self::Internal("Cannot send internal message".to_string().into())
     }
 }
 
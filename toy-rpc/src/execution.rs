@@ -0,0 +1,245 @@
+//! Execution of RPC requests, which is shared by the server and the client
+//!
+//! The server executes the requests from its clients, and a client executes the reverse
+//! calls made by the server over the same connection.
+
+use cfg_if::cfg_if;
+use serde::de::DeserializeOwned;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    codec::EraseDeserializer,
+    error::Error,
+    message::{ErrorMessage, MessageId, CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM},
+    protocol::{InboundBody, MetadataMap},
+    service::{ArcAsyncServiceCall, AsyncServiceMap},
+};
+
+/// A call from the peer to the service of an RPC method
+pub(crate) struct InboundCall {
+    pub call: ArcAsyncServiceCall,
+    pub id: MessageId,
+    pub method: String,
+    /// `None` if the request has no timeout
+    pub deadline: Option<Instant>,
+    pub metadata: MetadataMap,
+}
+
+/// Looks up the service of `service_method` and returns the service along with the name
+/// of the method
+pub(crate) fn service(
+    services: &Arc<AsyncServiceMap>,
+    service_method: String,
+) -> Result<(ArcAsyncServiceCall, String), Error> {
+    // split service and method
+    let args: Vec<&str> = service_method.split('.').collect();
+    let (service, method) = match args[..] {
+        [s, m] => (s, m),
+        _ => {
+            // Method not found
+            return Err(Error::MethodNotFound);
+        }
+    };
+
+    // look up the service
+    match services.get(service) {
+        Some(call) => Ok((call.clone(), method.into())),
+        None => Err(Error::ServiceNotFound),
+    }
+}
+
+/// Maps the error of parsing a request to `Error::InvalidArgument`, which is the code
+/// the caller gets back
+pub(crate) fn map_parse_error(err: Error) -> Error {
+    match err {
+        // if serde cannot parse request, the argument is likely mistaken
+        Error::ParseError(e) => {
+            log::error!("ParseError {:?}", e);
+            Error::InvalidArgument
+        }
+        e => e,
+    }
+}
+
+pub(crate) fn handle_cancel(
+    id: MessageId,
+    mut deserializer: Box<InboundBody>,
+) -> Result<(), Error> {
+    let token: String = erased_serde::deserialize(&mut deserializer)?;
+    if is_correct_cancellation_token(id, &token) {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
+
+fn is_correct_cancellation_token(id: MessageId, token: &str) -> bool {
+    match token.find(CANCELLATION_TOKEN_DELIM) {
+        Some(ind) => {
+            let base = &token[..ind];
+            let id_str = &token[ind + 1..];
+            let _id: MessageId = match id_str.parse() {
+                Ok(num) => num,
+                Err(_) => return false,
+            };
+            base == CANCELLATION_TOKEN && _id == id
+        }
+        None => false,
+    }
+}
+
+/// Deadline of a request that is received with `timeout` left. The deadline is taken
+/// when the request is read so that the time it spends queued counts against it
+pub(crate) fn deadline(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.and_then(|timeout| Instant::now().checked_add(timeout))
+}
+
+/// Deserializes the body of a successful response
+pub(crate) fn deserialize_response<Res: DeserializeOwned>(
+    res: Result<Box<InboundBody>, Error>,
+) -> Result<Res, Error> {
+    let mut resp_body = res?;
+    erased_serde::deserialize(&mut resp_body).map_err(|err| Error::ParseError(Box::new(err)))
+}
+
+/// Deserializes the `ErrorMessage` in the body of an error response. The payload of a
+/// typed error is decoded later with the codec `R` of the connection
+pub(crate) fn deserialize_error<R: EraseDeserializer>(
+    id: MessageId,
+    mut err_body: Box<InboundBody>,
+) -> Error {
+    match erased_serde::deserialize::<ErrorMessage>(&mut err_body) {
+        Ok(msg) => Error::from_err_msg(id, msg, R::from_bytes),
+        Err(err) => Error::ParseError(Box::new(err)),
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))] {
+        use futures::future::{self, Either, FutureExt};
+        use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+
+        use crate::service::{CancellationToken, HandlerResult};
+
+        /// Deadline and cancellation of an execution
+        pub(crate) struct Timing {
+            /// `None` if the execution has no timeout
+            pub deadline: Option<Instant>,
+            pub grace_period: Duration,
+            pub token: CancellationToken,
+        }
+
+        /// Returns `None` if the future does not complete within the duration
+        pub(crate) async fn timeout<F: Future>(duration: Duration, fut: F) -> Option<F::Output> {
+            #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+            let result = ::async_std::future::timeout(duration, fut).await.ok();
+            #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
+            let result = ::tokio::time::timeout(duration, fut).await.ok();
+            result
+        }
+
+        /// Returns `None` if the future does not complete before the deadline. Without a
+        /// deadline, the future is simply awaited
+        pub(crate) async fn timeout_at<F: Future>(
+            deadline: Option<Instant>,
+            fut: F,
+        ) -> Option<F::Output> {
+            match deadline {
+                Some(deadline) => {
+                    timeout(deadline.saturating_duration_since(Instant::now()), fut).await
+                }
+                None => Some(fut.await),
+            }
+        }
+
+        /// Waits for `fut` until it completes, reaches the timeout or is canceled. In the
+        /// latter two cases, the token is canceled and `fut` is given the grace period to
+        /// clean up before it is dropped.
+        pub(crate) async fn run_until_canceled<F: Future>(
+            id: MessageId,
+            timing: &Timing,
+            fut: F,
+        ) -> Result<F::Output, Error> {
+            futures::pin_mut!(fut);
+            let err = {
+                let timed = timeout_at(timing.deadline, &mut fut);
+                futures::pin_mut!(timed);
+                match future::select(timed, timing.token.cancelled()).await {
+                    // The RPC method may have finished after observing the cancellation
+                    Either::Left((Some(_), _)) if timing.token.is_cancelled() => {
+                        return Err(Error::Canceled(id))
+                    }
+                    Either::Left((Some(output), _)) => return Ok(output),
+                    Either::Left((None, _)) => {
+                        log::error!("Request {} reached timeout", id);
+                        Error::Timeout(id)
+                    }
+                    Either::Right(_) => {
+                        log::debug!("Request {} is canceled", id);
+                        Error::Canceled(id)
+                    }
+                }
+            };
+
+            timing.token.cancel();
+            if timeout(timing.grace_period, fut).await.is_none() {
+                log::debug!("Request {} is dropped after the grace period", id);
+            }
+            Err(err)
+        }
+
+        /// Describes the payload of a caught panic
+        pub(crate) fn panic_message(panic: Box<dyn Any + Send>) -> String {
+            let msg = match panic.downcast::<String>() {
+                Ok(msg) => *msg,
+                Err(panic) => match panic.downcast::<&'static str>() {
+                    Ok(msg) => msg.to_string(),
+                    Err(_) => "unknown panic".to_string(),
+                },
+            };
+            format!("RPC method panicked: {}", msg)
+        }
+
+        /// Awaits the RPC method. A panic is turned into `Error::Internal` so that the
+        /// caller still gets a response
+        pub(crate) async fn execute_call(
+            id: MessageId,
+            fut: impl Future<Output = HandlerResult>,
+        ) -> HandlerResult {
+            let result = AssertUnwindSafe(fut)
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|panic| Err(Error::Internal(panic_message(panic).into())));
+            let result: HandlerResult = result.map_err(|err| {
+                log::error!(
+                    "Error found executing request id: {}, error msg: {}",
+                    &id,
+                    &err
+                );
+                map_parse_error(err)
+            });
+            result
+        }
+
+        /// Executes the call with a timeout.
+        ///
+        /// Returns `Error::Timeout` if the timeout is reached or `Error::Canceled` if the
+        /// caller canceled the call, in which case the RPC method is given the grace period
+        /// to observe the cancellation and clean up.
+        pub(crate) async fn execute_timed_call(
+            id: MessageId,
+            timing: Timing,
+            fut: impl Future<Output = HandlerResult>,
+        ) -> HandlerResult {
+            run_until_canceled(id, &timing, execute_call(id, fut))
+                .await
+                .and_then(|result| result)
+        }
+    }
+}
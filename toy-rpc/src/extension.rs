@@ -0,0 +1,192 @@
+//! Protocol extensions carried by `Header::Ext`
+//!
+//! Both the server and the client keep a registry of extension handlers, keyed by the
+//! `marker` of `Header::Ext`. An incoming extension message is handed to the handler
+//! registered for its marker, and whatever the handler replies is sent back with the same
+//! message id. A message whose marker has no handler is answered with an error, which
+//! becomes `Error::UnknownExtension` on the sending side.
+
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use crate::{
+    error::Error,
+    message::MessageId,
+    protocol::{InboundBody, OutboundBody},
+};
+
+/// Type of the `marker` of `Header::Ext`
+pub type Marker = u32;
+
+/// Markers starting from this value are reserved by `toy-rpc` and cannot be registered
+pub const RESERVED_MARKER_START: Marker = Marker::MAX - 15;
+
+/// Marker of a reply to an extension message
+pub(crate) const REPLY_MARKER: Marker = Marker::MAX;
+
+/// Marker of an error reply to an extension message
+pub(crate) const ERROR_MARKER: Marker = Marker::MAX - 1;
+
+/// Result returned by an extension handler. `Ok(None)` replies with an empty body
+pub type ExtensionResult = Result<Option<ExtensionReply>, Error>;
+
+/// Type of an extension handler
+pub type ExtensionHandler =
+    Arc<dyn Fn(ExtensionMessage) -> BoxFuture<'static, ExtensionResult> + Send + Sync>;
+
+/// Extension handlers keyed by their marker
+pub type ExtensionMap = HashMap<Marker, ExtensionHandler>;
+
+/// An extension message received from the peer
+pub struct ExtensionMessage {
+    marker: Marker,
+    content: String,
+    body: Box<InboundBody>,
+}
+
+impl ExtensionMessage {
+    pub(crate) fn new(marker: Marker, content: String, body: Box<InboundBody>) -> Self {
+        Self {
+            marker,
+            content,
+            body,
+        }
+    }
+
+    /// Marker of the message
+    pub fn marker(&self) -> Marker {
+        self.marker
+    }
+
+    /// The `content` field of `Header::Ext`
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Deserializes the body with the codec of the connection
+    pub fn body<T: DeserializeOwned>(mut self) -> Result<T, Error> {
+        erased_serde::deserialize(&mut self.body).map_err(Into::into)
+    }
+}
+
+/// Reply of an extension handler
+pub struct ExtensionReply {
+    content: String,
+    body: Box<OutboundBody>,
+}
+
+impl ExtensionReply {
+    /// Creates a reply with the body, which is serialized with the codec of the connection
+    pub fn new<T>(body: T) -> Self
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        Self {
+            content: String::new(),
+            body: Box::new(body),
+        }
+    }
+
+    /// Sets the `content` field of the reply
+    pub fn with_content(mut self, content: impl Into<String>) -> Self {
+        self.content = content.into();
+        self
+    }
+}
+
+/// Registers an extension handler
+///
+/// # Panics
+///
+/// Panics if the marker is reserved, ie. not smaller than `RESERVED_MARKER_START`
+pub(crate) fn register<F, Fut>(extensions: &mut ExtensionMap, marker: Marker, handler: F)
+where
+    F: Fn(ExtensionMessage) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ExtensionResult> + Send + 'static,
+{
+    assert!(
+        marker < RESERVED_MARKER_START,
+        "Extension marker {} is reserved",
+        marker
+    );
+    log::debug!("Registering extension: {}", marker);
+    let handler: ExtensionHandler = Arc::new(move |msg| Box::pin(handler(msg)));
+    extensions.insert(marker, handler);
+}
+
+/// Error carried by the body of an error reply
+#[derive(Serialize, Deserialize)]
+enum ExtensionErrorMessage {
+    UnknownMarker(Marker),
+    HandlerError(String),
+}
+
+/// Fields of `Header::Ext` along with the body of an outgoing extension message
+pub(crate) struct ExtensionFrame {
+    pub id: MessageId,
+    pub marker: Marker,
+    pub content: String,
+    pub body: Box<OutboundBody>,
+}
+
+impl ExtensionFrame {
+    /// Frame of a reply to the extension message `id`
+    pub fn reply(id: MessageId, result: ExtensionResult) -> Self {
+        match result {
+            Ok(Some(reply)) => Self {
+                id,
+                marker: REPLY_MARKER,
+                content: reply.content,
+                body: reply.body,
+            },
+            Ok(None) => Self {
+                id,
+                marker: REPLY_MARKER,
+                content: String::new(),
+                body: Box::new(()),
+            },
+            Err(err) => Self::error(id, ExtensionErrorMessage::HandlerError(err.to_string())),
+        }
+    }
+
+    /// Frame of the error reply to a message whose marker has no handler
+    pub fn unknown_marker(id: MessageId, marker: Marker) -> Self {
+        log::error!("No extension handler is registered for marker {}", marker);
+        Self::error(id, ExtensionErrorMessage::UnknownMarker(marker))
+    }
+
+    fn error(id: MessageId, msg: ExtensionErrorMessage) -> Self {
+        Self {
+            id,
+            marker: ERROR_MARKER,
+            content: String::new(),
+            body: Box::new(msg),
+        }
+    }
+}
+
+/// Returns `true` if the marker belongs to a reply rather than a new extension message
+pub(crate) fn is_reply(marker: Marker) -> bool {
+    marker == REPLY_MARKER || marker == ERROR_MARKER
+}
+
+/// Converts a reply from the peer into the result of the extension call
+pub(crate) fn into_reply_result(
+    marker: Marker,
+    content: String,
+    mut body: Box<InboundBody>,
+) -> Result<ExtensionMessage, Error> {
+    match marker {
+        ERROR_MARKER => {
+            let msg: ExtensionErrorMessage = erased_serde::deserialize(&mut body)?;
+            match msg {
+                ExtensionErrorMessage::UnknownMarker(marker) => {
+                    Err(Error::UnknownExtension(marker))
+                }
+                ExtensionErrorMessage::HandlerError(s) => Err(Error::ExecutionError(s)),
+            }
+        }
+        _ => Ok(ExtensionMessage::new(marker, content, body)),
+    }
+}
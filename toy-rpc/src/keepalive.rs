@@ -0,0 +1,86 @@
+//! Application-level keepalive
+//!
+//! When a keepalive interval is set, a `Header::Ping` is sent to the peer every interval and
+//! the peer answers with a `Header::Pong`. A peer that leaves more consecutive pings than the
+//! miss threshold unanswered is declared dead, and the connection is closed. On the client
+//! side, the pending calls then fail with `Error::ConnectionLost`.
+//!
+//! Keepalive is only used if both sides support `protocol::FEATURE_KEEPALIVE`. Pings from
+//! the peer are always answered, even if keepalive is not enabled locally.
+
+/// Default number of consecutive pings that can be left unanswered before the peer
+/// is declared dead
+pub const DEFAULT_KEEPALIVE_MISS_THRESHOLD: u32 = 3;
+
+/// Counts the pings that are not answered by the peer
+pub(crate) struct Keepalive {
+    miss_threshold: u32,
+    missed: u32,
+}
+
+impl Keepalive {
+    pub fn new(miss_threshold: u32) -> Self {
+        Self {
+            miss_threshold,
+            missed: 0,
+        }
+    }
+
+    /// Called when it is time to send a ping. Returns `false` if the peer has missed
+    /// too many pings and should be considered dead
+    pub fn tick(&mut self) -> bool {
+        if self.missed >= self.miss_threshold {
+            return false;
+        }
+        self.missed += 1;
+        true
+    }
+
+    /// Called when a pong is received from the peer
+    pub fn pong(&mut self) {
+        self.missed = 0;
+    }
+}
+
+/// Spawns a task that sends `tick()` to the broker every `interval`. The task
+/// finishes once the broker is stopped.
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+pub(crate) fn spawn_ticker<T, F>(interval: std::time::Duration, broker: flume::Sender<T>, tick: F)
+where
+    T: Send + 'static,
+    F: Fn() -> T + Send + 'static,
+{
+    #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+    use async_std::task::{sleep, spawn};
+    #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+    use tokio::{task::spawn, time::sleep};
+
+    spawn(async move {
+        loop {
+            sleep(interval).await;
+            if broker.send_async(tick()).await.is_err() {
+                log::debug!("Broker is stopped, stopping keepalive");
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_is_dead_after_missing_threshold_pings() {
+        let mut keepalive = Keepalive::new(2);
+        assert!(keepalive.tick());
+        assert!(keepalive.tick());
+        keepalive.pong();
+        assert!(keepalive.tick());
+        assert!(keepalive.tick());
+        assert!(!keepalive.tick());
+    }
+}
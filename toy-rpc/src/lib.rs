@@ -41,6 +41,44 @@
 //!
 //! - Cascading cancellation: whenever an unfinished `Call<Res>` is dropped, a cancellation will be sent to the server
 //! and thus propagating the cancellation.
+//! - Server-streaming: an exported method that returns a `Stream` of results can be consumed on the client
+//! side as a `CallStream<Res>`.
+//! - Client-streaming and bidirectional streaming: an exported method that takes a `RequestStream<T>`
+//! receives the items sent through a `CallSink<T>` on the client side.
+//! - Metadata: string-keyed metadata (ie. auth tokens, trace ids) can be sent with each request
+//! and read by the RPC method with `toy_rpc::service::request_metadata()`.
+//! - Call context: an exported method can take a `toy_rpc::service::RpcContext` right before its
+//! argument to get the client id, remote address, deadline, metadata and a cancellation signal.
+//! - Handshake: the client and server exchange their protocol version, codec and supported features
+//! when connected, and incompatible peers are rejected with `Error::IncompatiblePeer`.
+//! - Work queues: a message sent by a `Producer` is delivered to at most `tickets` competing
+//! `Consumer`s, which ack each message explicitly.
+//! - Extensions: handlers registered for a `marker` on the server or client receive the
+//! `Header::Ext` messages of that marker and can reply to them.
+//! - Keepalive: periodic pings detect a dead peer. Pending calls then fail with
+//! `Error::ConnectionLost`, and the server drops the client's subscriptions and queue pulls.
+//! - Reverse calls: a client can register its own services, which the server calls over the
+//! same connection with the `ClientHandle` returned by `Server::client`.
+//! - Typed errors: an RPC method can return its own error type with `Error::typed`, and the
+//! caller recovers the value with `Error::payload`.
+//! - Status codes: every response carries a gRPC-like `status::Status`, and `Error::code`
+//! classifies a failure without parsing its message.
+//! - Deadlines: RPC methods see the time left for the request, and calls made from within a
+//! RPC method inherit its deadline.
+//! - Notifications: `Client::notify` sends a one-way request, which the server executes without
+//! sending back a response.
+//! - Batches: `Client::batch` sends many calls in a single message, and the server sends back all
+//! results in a single response.
+//! - Compression: payloads above a size threshold can be compressed with DEFLATE or LZ4, which
+//! is negotiated per connection in the handshake.
+//! - Chunking: bodies larger than the max frame size are split into chunks on the raw TCP
+//! transport, which are interleaved with the other messages on the connection.
+//! - Max message size: inbound messages larger than the limit set on `ServerBuilder` and
+//! `ClientBuilder` are rejected before they are read into memory.
+//! - Backpressure: the messages read from a peer, the messages written to a peer and the
+//! publications wait in bounded queues, so a slow peer or subscriber slows down the producers.
+//! A policy decides whether a full subscriber queue blocks, drops publications or disconnects
+//! the slow client.
 //!
 //! More detailed usage can be found in the book and documentation.
 //!
@@ -72,16 +110,47 @@
 //! - `server`: enables RPC server
 //! - `client`: enables RPC client
 //!
-//! Choice of serialization/deserialzation (only one should be enabled at a time)
+//! Choice of serialization/deserialzation (any number of them can be enabled at a time)
 //!
-//! - `serde_bincode`: (default) the default codec will use `bincode`
+//! - `serde_bincode`: (default) enables `codec::Format::Bincode`, which uses `bincode`
 //!     for serialization/deserialization
-//! - `serde_json`: the default codec will use `serde_json`
-//!     for `json` serialization/deserialization
-//! - `serde_cbor`: the default codec will use `serde_cbor`
-//!     for serialization/deserialization
-//! - `serde_rmp`: the default codec will use `rmp-serde`
+//! - `serde_json`: enables `codec::Format::Json`, which uses `serde_json`
+//!     for `json` serialization/deserialization. This also enables `codec::Format::GoJsonRpc`,
+//!     which speaks the protocol of Go's `net/rpc/jsonrpc`, and `codec::Format::JsonRpc`,
+//!     which speaks JSON-RPC 2.0
+//! - `serde_cbor`: enables `codec::Format::Cbor`, which uses `serde_cbor`
 //!     for serialization/deserialization
+//! - `serde_rmp`: enables `codec::Format::Rmp`, which uses `rmp-serde`
+//!     for serialization/deserialization. This also enables `codec::Format::MsgpackRpc`,
+//!     which speaks MessagePack-RPC
+//! - `serde_gob`: enables `codec::Format::Gob`, which uses the gob encoding of Go's
+//!     `encoding/gob`. This also enables `codec::Format::GoRpc`, which speaks the protocol
+//!     of Go's `net/rpc`
+//!
+//! The default codec uses the first enabled format in the order of `bincode`, `cbor`, `rmp`,
+//! `json` and `gob`. Another format is selected with `ClientBuilder::set_format` on the client side,
+//! and with `ServerBuilder::set_format` or `Server::accept_with_format` on the server side.
+//!
+//! With `codec::Format::GoJsonRpc`, a client can call a Go server that serves with
+//! `jsonrpc.ServeConn`, and a server can serve Go clients that dial with `jsonrpc.Dial`.
+//! The services and methods are named the same way, ie. `"Arith.Multiply"`, and only unary
+//! calls are supported.
+//!
+//! With `codec::Format::JsonRpc`, a server can serve JSON-RPC 2.0 clients, ie. web
+//! frontends, on raw TCP and on WebSocket, including the `axum`, `warp` and `tide`
+//! integrations with `ServerBuilder::set_format`. The methods are named after the services,
+//! ie. `"Arith.Multiply"`, and batches and notifications are supported. See
+//! `codec::jsonrpc` for how the errors are reported.
+//!
+//! With `codec::Format::MsgpackRpc`, a server can serve the MessagePack-RPC clients of
+//! other languages, and a client can call a MessagePack-RPC server. The methods are named
+//! after the services, ie. `"Arith.Multiply"`, and the errors are sent as strings. See
+//! `codec::msgpack_rpc` for how the arguments are taken from `params`.
+//!
+//! Likewise, with `codec::Format::GoRpc`, a client can call a Go server that serves with
+//! `rpc.ServeConn`, and a server can serve Go clients that dial with `rpc.Dial`. The
+//! arguments and replies are encoded with `gob`, so the fields of a struct usually need to be
+//! renamed after the exported fields in Go, ie. with `#[serde(rename_all = "PascalCase")]`.
 //!
 //! WebSocket support (HTTP integration is implementd with WebSocket)
 //!
@@ -94,6 +163,11 @@
 //!
 //! - `tls`: enables TLS support
 //!
+//! Payload compression (any number can be enabled, the peers negotiate which ones are used)
+//!
+//! - `compression_deflate`: enables compressing the payloads with DEFLATE (`flate2`)
+//! - `compression_lz4`: enables compressing the payloads with LZ4 (`lz4_flex`)
+//!
 //! Other trivial feature flags are listed below, and they are likely of no actual usage for you.
 //! - `docs`
 //! - `std`: `serde/std`. There is no actual usage right now.
@@ -119,12 +193,22 @@
 //!
 
 pub mod codec;
+pub mod compression;
 pub mod error;
+#[cfg(any(feature = "server", feature = "client"))]
+pub(crate) mod execution;
+#[cfg(any(feature = "server", feature = "client"))]
+pub mod extension;
+#[cfg(any(feature = "server", feature = "client"))]
+pub mod keepalive;
 pub mod macros;
 pub mod message;
 pub mod protocol;
 pub mod pubsub;
+#[cfg(any(feature = "server", feature = "client"))]
+pub mod queue;
 pub mod service;
+pub mod status;
 pub mod transport;
 pub mod util;
 
@@ -154,4 +238,5 @@ pub use error::Error;
 
 // re-export
 pub use erased_serde;
+pub use futures;
 pub use serde;
@@ -10,30 +10,10 @@ pub use toy_rpc_macros::{export_impl, export_trait, export_trait_impl, Topic};
         feature = "http_warp",
     ),
     any(
-        all(
-            feature = "serde_bincode",
-            not(feature = "serde_json"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_cbor",
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_json",
-            not(feature = "serde_bincode"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_rmp",
-            not(feature = "serde_cbor"),
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-        )
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_json",
+        feature = "serde_rmp"
     )
 ))]
 pub(crate) use toy_rpc_macros::impl_inner_deserializer;
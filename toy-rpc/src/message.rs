@@ -1,13 +1,17 @@
+//! Message ids, which are allocated by `MessageIdAllocator` on a connection, and
 //! ErrorMessage from server to client
 use cfg_if::cfg_if;
-use serde::{Deserialize, Serialize};
-use std::sync::atomic::AtomicU16;
+#[cfg(any(feature = "server", feature = "client", test))]
+use std::collections::HashSet;
+use std::sync::atomic::AtomicU64;
+#[cfg(any(feature = "server", feature = "client", test))]
+use std::sync::Mutex;
 
-/// Type of message id is u16
-pub type MessageId = u16;
+/// Type of message id is u64
+pub type MessageId = u64;
 
 /// Atomic type of MessageId
-pub type AtomicMessageId = AtomicU16;
+pub type AtomicMessageId = AtomicU64;
 
 /// Returning the metadata
 pub trait Metadata {
@@ -15,45 +19,195 @@ pub trait Metadata {
     fn id(&self) -> MessageId;
 }
 
-/// The Error message that will be sent over for a error response
-#[derive(Serialize, Deserialize)]
-pub(crate) enum ErrorMessage {
-    InvalidArgument,
-    ServiceNotFound,
-    MethodNotFound,
-    ExecutionError(String),
+/// Allocates message ids on a connection.
+///
+/// An id acquired with `acquire` stays live until it is released. Live ids are skipped
+/// when the counter wraps around, so two pending entries never share the same id.
+#[cfg(any(feature = "server", feature = "client", test))]
+#[derive(Debug, Default)]
+pub(crate) struct MessageIdAllocator {
+    inner: Mutex<IdAllocatorInner>,
 }
 
+#[cfg(any(feature = "server", feature = "client", test))]
+#[derive(Debug, Default)]
+struct IdAllocatorInner {
+    next: MessageId,
+    live: HashSet<MessageId>,
+}
+
+#[cfg(any(feature = "server", feature = "client", test))]
+impl MessageIdAllocator {
+    /// Allocates an id that stays live until it is released
+    pub fn acquire(&self) -> MessageId {
+        let mut inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        loop {
+            let id = inner.next;
+            inner.next = id.wrapping_add(1);
+            if inner.live.insert(id) {
+                return id;
+            }
+        }
+    }
+
+    /// Allocates an id that is not live without keeping it live. This is used
+    /// by messages that do not wait for a reply
+    pub fn next(&self) -> MessageId {
+        let mut inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        loop {
+            let id = inner.next;
+            inner.next = id.wrapping_add(1);
+            if !inner.live.contains(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Releases a live id so that it can be allocated again
+    pub fn release(&self, id: MessageId) {
+        let mut inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        inner.live.remove(&id);
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "server",
+        feature = "client",
+        all(
+            any(feature = "async_std_runtime", feature = "tokio_runtime", feature = "docs"),
+            any(feature = "serde_json", feature = "serde_rmp", feature = "serde_gob")
+        )
+    ))] {
+        use serde::{Deserialize, Serialize};
+
+        use crate::status::Status;
+
+        /// The Error message that will be sent over for a error response
+        #[derive(Serialize, Deserialize)]
+        pub(crate) enum ErrorMessage {
+            InvalidArgument,
+            ServiceNotFound,
+            MethodNotFound,
+            ExecutionError(String),
+            /// Typed error whose payload is serialized with the codec of the connection
+            Typed {
+                message: String,
+                payload: Vec<u8>,
+            },
+            Canceled,
+            Timeout,
+            Internal(String),
+            /// Any other error, which is described by its status
+            Status(Status),
+        }
+    }
+}
+
+/// Token indicating a cancellation request
+#[cfg(any(feature = "server", feature = "client"))]
+pub(crate) const CANCELLATION_TOKEN: &str = "RPC_TASK_CANCELLATION";
+#[cfg(any(feature = "server", feature = "client"))]
+pub(crate) const CANCELLATION_TOKEN_DELIM: &str = ".";
+
 cfg_if! {
     if #[cfg(any(
         feature = "async_std_runtime",
         feature = "tokio_runtime"
     ))] {
-        /// Token indicating a cancellation request
-        #[cfg(any(feature = "server", feature = "client"))]
-        pub(crate) const CANCELLATION_TOKEN: &str = "RPC_TASK_CANCELLATION";
         #[cfg(any(feature = "server", feature = "client"))]
-        pub(crate) const CANCELLATION_TOKEN_DELIM: &str = ".";
+        use crate::{codec::Marshal, error::{Error, TypedRepr}};
 
-        #[cfg(feature = "server")]
-        use crate::{error::Error};
-
-        #[cfg(feature = "server")]
+        #[cfg(any(feature = "server", feature = "client"))]
         impl ErrorMessage {
-            pub(crate) fn from_err(err: Error) -> Result<Self, Error> {
+            /// Converts an error into a message for the peer. The payload of a typed error
+            /// is serialized with `M`
+            pub(crate) fn from_err<M: Marshal>(err: Error) -> Result<Self, Error> {
                 match err {
                     Error::InvalidArgument => Ok(Self::InvalidArgument),
                     Error::ServiceNotFound => Ok(Self::ServiceNotFound),
                     Error::MethodNotFound => Ok(Self::MethodNotFound),
                     Error::ExecutionError(s) => Ok(Self::ExecutionError(s)),
-                    e @ Error::IoError(_) => Err(e),
-                    e @ Error::ParseError(_) => Err(e),
-                    e @ Error::Internal(_) => Err(e),
-                    e @ Error::Canceled(_) => Err(e),
-                    e @ Error::Timeout(_) => Err(e),
-                    e @ Error::MaxRetriesReached(_) => Err(e),
+                    Error::Typed(err) => {
+                        let (message, repr) = err.into_parts();
+                        let payload = match repr {
+                            TypedRepr::Local { value } => M::marshal(&value)?,
+                            // A typed error received from another peer is passed on as is
+                            TypedRepr::Remote { bytes, .. } => bytes,
+                        };
+                        Ok(Self::Typed { message, payload })
+                    }
+                    Error::Canceled(_) => Ok(Self::Canceled),
+                    Error::Timeout(_) => Ok(Self::Timeout),
+                    Error::Internal(e) => Ok(Self::Internal(e.to_string())),
+                    Error::ParseError(e) => Ok(Self::Internal(e.to_string())),
+                    e @ Error::IoError(_)
+                    | e @ Error::MaxRetriesReached(_)
+                    | e @ Error::IncompatiblePeer(_)
+                    | e @ Error::UnknownExtension(_)
+                    | e @ Error::ConnectionLost
+                    | e @ Error::MessageTooLarge(_)
+                    | e @ Error::Status(_) => Ok(Self::Status(e.status())),
+                }
+            }
+        }
+
+        #[cfg(any(feature = "serde_json", feature = "serde_gob"))]
+        impl ErrorMessage {
+            /// Converts the error string of a Go server into the status and the message of a
+            /// response. The errors of a missing service or method are recognized by the
+            /// messages of Go's `net/rpc`
+            pub(crate) fn from_go_error(error: String) -> (Status, Self) {
+                use crate::error::Error;
+
+                if error.starts_with("rpc: can't find service ") {
+                    (Error::ServiceNotFound.status(), Self::ServiceNotFound)
+                } else if error.starts_with("rpc: can't find method ") {
+                    (Error::MethodNotFound.status(), Self::MethodNotFound)
+                } else {
+                    let status = Error::ExecutionError(error.clone()).status();
+                    (status, Self::ExecutionError(error))
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocator_starting_at(id: MessageId) -> MessageIdAllocator {
+        let allocator = MessageIdAllocator::default();
+        allocator.inner.lock().unwrap().next = id;
+        allocator
+    }
+
+    #[test]
+    fn message_id_wraps_around() {
+        let allocator = allocator_starting_at(MessageId::MAX - 1);
+        assert_eq!(allocator.acquire(), MessageId::MAX - 1);
+        assert_eq!(allocator.acquire(), MessageId::MAX);
+        assert_eq!(allocator.acquire(), 0);
+        assert_eq!(allocator.next(), 1);
+    }
+
+    #[test]
+    fn live_message_id_is_not_reused() {
+        let allocator = MessageIdAllocator::default();
+        let long_running = allocator.acquire();
+        let finished = allocator.acquire();
+        let publish = allocator.acquire();
+        allocator.release(finished);
+
+        // the counter wraps around while `long_running` and `publish` are still pending
+        allocator.inner.lock().unwrap().next = long_running;
+        assert_eq!(allocator.acquire(), finished);
+        assert_eq!(allocator.next(), publish + 1);
+
+        // a released id can be allocated again
+        allocator.release(long_running);
+        allocator.inner.lock().unwrap().next = long_running;
+        assert_eq!(allocator.acquire(), long_running);
+    }
+}
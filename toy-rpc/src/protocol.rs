@@ -1,8 +1,20 @@
 //! Message protocol between server and client
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::Duration;
 
+#[cfg(any(feature = "server", feature = "client"))]
+use crate::codec::{CodecRead, CodecWrite};
+#[cfg(any(feature = "server", feature = "client", test))]
+use crate::compression::Compression;
+#[cfg(any(feature = "server", feature = "client"))]
+use crate::error::CodecError;
+use crate::error::Error;
 use crate::message::{MessageId, Metadata};
+use crate::status::Status;
+
+/// String-keyed metadata (ie. auth tokens, trace ids) carried by requests and responses
+pub type MetadataMap = HashMap<String, String>;
 
 /// Header of a message
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,8 +27,11 @@ pub enum Header {
         id: MessageId,
         /// RPC service and method in the format of "{Service}.{method}"
         service_method: String,
-        /// RPC timeout, all requests will have timeouts
-        timeout: Duration,
+        /// Time left until the deadline of the request when it is sent, or `None` if the
+        /// request has no timeout
+        timeout: Option<Duration>,
+        /// Metadata of the request
+        metadata: MetadataMap,
     },
 
     /// Header of a response
@@ -25,8 +40,11 @@ pub enum Header {
     Response {
         /// Message id
         id: MessageId,
-        /// Whether the result is Ok
-        is_ok: bool,
+        /// Status of the response. The body contains the result if the status is
+        /// `Code::Ok` and the error otherwise
+        status: Status,
+        /// Metadata of the response
+        metadata: MetadataMap,
     },
 
     /// Header of a cancellation message
@@ -82,19 +100,23 @@ pub enum Header {
     /// There will be no body message in order to reduce traffic
     Ack(MessageId),
 
-    /// Reserved for a potential message queue like design
-    /// Produce a message to be consumed
+    /// Header of a produce message
+    ///
+    /// The body contains the message that is put into the work queue
     Produce {
         /// Message id
         id: MessageId,
         /// Topic of the queue
         topic: String,
-        /// Number of times this message can be consumed
+        /// Number of consumers this message can be delivered to
         tickets: u32,
     },
 
-    /// Reserved for a potential message queue like design
-    /// Consumes a message by pulling message from broker/server
+    /// Header of a consume message
+    ///
+    /// A consumer pulls one message from the work queue by sending a consume message
+    /// without body. The server delivers the message with a consume message whose
+    /// id is the sequence id that the consumer Acks, and the body contains the message.
     Consume {
         /// Message id
         id: MessageId,
@@ -102,38 +124,308 @@ pub enum Header {
         topic: String,
     },
 
-    /// Reserved for further extension to the message protocol
+    /// Message of a protocol extension
+    ///
+    /// The body is handed to the extension handler registered for `marker`.
+    /// A reply shares the message id of the message and carries one of the
+    /// markers reserved by `toy-rpc` (see `extension::RESERVED_MARKER_START`).
     Ext {
         /// Message id
         id: MessageId,
-        /// Reserved for content of extension
+        /// Free-form content defined by the extension
         content: String,
-        /// Reserved for some numerical/enum content
+        /// Identifies the extension the message belongs to
         marker: u32,
     },
+
+    /// Header of a keepalive ping
+    ///
+    /// The peer answers with a `Pong` carrying the same id.
+    /// There will be no body message in order to reduce traffic
+    Ping(MessageId),
+
+    /// Header of the answer to a keepalive ping
+    ///
+    /// There will be no body message in order to reduce traffic
+    Pong(MessageId),
+
+    /// Header of an item yielded by a streaming RPC method
+    ///
+    /// The body contains the content of the item. All items of the same
+    /// stream share the message id of the request.
+    StreamItem {
+        /// Message id
+        id: MessageId,
+        /// Whether the item is Ok
+        is_ok: bool,
+    },
+
+    /// Header indicating the end of a stream
+    ///
+    /// There will be no body message in order to reduce traffic
+    StreamEnd(MessageId),
+
+    /// Header of a client-streaming or bidirectional streaming request
+    ///
+    /// There will be no body message. The request items are sent afterwards
+    /// as `StreamItem`s sharing the same message id, and the client terminates
+    /// the request stream with a `StreamEnd`.
+    OpenStream {
+        /// Message id
+        id: MessageId,
+        /// Name of the requested service and method
+        service_method: String,
+        /// Time left until the deadline of the call when it is sent, or `None` if the
+        /// call has no timeout
+        timeout: Option<Duration>,
+        /// Metadata of the request
+        metadata: MetadataMap,
+    },
+
+    /// Header of a notification, which is a one-way request
+    ///
+    /// The body contains the content of the request. The method is executed
+    /// without producing a response, and the message id is only used to frame
+    /// the body.
+    Notify {
+        /// Message id
+        id: MessageId,
+        /// RPC service and method in the format of "{Service}.{method}"
+        service_method: String,
+        /// Metadata of the request
+        metadata: MetadataMap,
+    },
+
+    /// Header of a batch of requests
+    ///
+    /// The body contains a list of `BatchRequest`s. The requests are executed
+    /// concurrently, and all the results are sent back in a single `BatchResponse`.
+    Batch {
+        /// Message id
+        id: MessageId,
+        /// Time left until the deadline of the requests when they are sent, or `None`
+        /// if the requests have no timeout
+        timeout: Option<Duration>,
+        /// Metadata shared by all requests in the batch
+        metadata: MetadataMap,
+    },
+
+    /// Header of the response to a batch of requests
+    ///
+    /// The body contains a list of `BatchReply`s, one for each request in the batch
+    BatchResponse(MessageId),
 }
 
 impl Metadata for Header {
     fn id(&self) -> MessageId {
         match self {
-            Self::Request { id, .. } => id.clone(),
-            Self::Response { id, .. } => id.clone(),
-            Self::Cancel(id) => id.clone(),
-            Self::Publish { id, .. } => id.clone(),
-            Self::Subscribe { id, .. } => id.clone(),
-            Self::Unsubscribe { id, .. } => id.clone(),
-            // Self::Subscription { id, .. } => id.clone(),
-            Self::Ack(id) => id.clone(),
-            Self::Produce { id, .. } => id.clone(),
-            Self::Consume { id, .. } => id.clone(),
-            Self::Ext { id, .. } => id.clone(),
+            Self::Request { id, .. } => *id,
+            Self::Response { id, .. } => *id,
+            Self::Cancel(id) => *id,
+            Self::Publish { id, .. } => *id,
+            Self::Subscribe { id, .. } => *id,
+            Self::Unsubscribe { id, .. } => *id,
+            // Self::Subscription { id, .. } => *id,
+            Self::Ack(id) => *id,
+            Self::Produce { id, .. } => *id,
+            Self::Consume { id, .. } => *id,
+            Self::Ext { id, .. } => *id,
+            Self::Ping(id) => *id,
+            Self::Pong(id) => *id,
+            Self::StreamItem { id, .. } => *id,
+            Self::StreamEnd(id) => *id,
+            Self::OpenStream { id, .. } => *id,
+            Self::Notify { id, .. } => *id,
+            Self::Batch { id, .. } => *id,
+            Self::BatchResponse(id) => *id,
         }
     }
 }
 
 pub(crate) type OutboundBody = dyn erased_serde::Serialize + Send + Sync;
+
+/// A request in the body of a `Header::Batch` message
+#[cfg(any(feature = "server", feature = "client"))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BatchRequest {
+    /// Message id of the request
+    pub id: MessageId,
+    /// RPC service and method in the format of "{Service}.{method}"
+    pub service_method: String,
+    /// Argument of the request serialized with the codec of the connection
+    pub args: Vec<u8>,
+}
+
+/// The result of a request in the body of a `Header::BatchResponse` message
+#[cfg(any(feature = "server", feature = "client"))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BatchReply {
+    /// Message id of the request
+    pub id: MessageId,
+    /// Status of the result
+    pub status: Status,
+    /// Metadata of the response
+    pub metadata: MetadataMap,
+    /// The result if the status is `Code::Ok` and the error otherwise, serialized with
+    /// the codec of the connection
+    pub body: Vec<u8>,
+}
 pub(crate) type InboundBody = dyn erased_serde::Deserializer<'static> + Send;
 
+/// Version of the message protocol. Peers with different versions cannot talk to each other
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Default max frame size, which is the largest payload length a frame can carry. Larger
+/// bodies are split into chunks if both sides support chunking
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 64 * 1024;
+
+/// Optional feature: compression of the payload. Each supported algorithm is advertised
+/// as `"compression/{algorithm}"`, see `Compression::feature`
+pub const FEATURE_COMPRESSION: &str = "compression";
+
+/// Optional feature: server-streaming, client-streaming and bidirectional streaming calls
+pub const FEATURE_STREAMING: &str = "streaming";
+
+/// Optional feature: metadata carried by requests and responses
+pub const FEATURE_METADATA: &str = "metadata";
+
+/// Optional feature: keepalive pings and pongs
+pub const FEATURE_KEEPALIVE: &str = "keepalive";
+
+/// Optional feature: one-way requests that expect no response
+pub const FEATURE_NOTIFY: &str = "notify";
+
+/// Optional feature: batches of requests sent in a single message
+pub const FEATURE_BATCH: &str = "batch";
+
+/// Optional feature: bodies larger than the max frame size are split into chunks, which are
+/// interleaved with the frames of the other messages
+pub const FEATURE_CHUNKING: &str = "chunking";
+
+/// Capabilities exchanged in the handshake right after a connection is established
+///
+/// Each side sends its own capabilities, and the capabilities agreed upon by both
+/// sides are available from `Client::capabilities` on the client side and from the
+/// connection hook on the server side.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Version of the message protocol
+    pub version: u32,
+    /// Name of the codec, ie. "bincode", "json", "cbor" or "rmp"
+    pub codec: String,
+    /// Supported optional features
+    pub features: Vec<String>,
+    /// Max size of the payload of a frame in bytes
+    pub max_frame_size: u32,
+}
+
+impl Capabilities {
+    /// Capabilities of this end of the connection using the specified codec
+    #[cfg(any(feature = "server", feature = "client", test))]
+    pub(crate) fn local(codec: &str) -> Self {
+        let mut features: Vec<String> = vec![
+            FEATURE_STREAMING.into(),
+            FEATURE_METADATA.into(),
+            FEATURE_KEEPALIVE.into(),
+            FEATURE_NOTIFY.into(),
+            FEATURE_BATCH.into(),
+            FEATURE_CHUNKING.into(),
+        ];
+        // payloads compressed with any of the enabled algorithms can be decompressed
+        features.extend(Compression::available().map(|c| c.feature()));
+        Self {
+            version: PROTOCOL_VERSION,
+            codec: codec.into(),
+            features,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Whether the optional feature is supported
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Agrees on the capabilities with the peer.
+    ///
+    /// The protocol version and the codec must be the same on both sides. Only the
+    /// optional features supported by both sides are kept, and the smaller max frame
+    /// size is used.
+    pub fn negotiate(&self, peer: &Capabilities) -> Result<Capabilities, Error> {
+        if self.version != peer.version {
+            return Err(Error::IncompatiblePeer(format!(
+                "protocol version mismatch, local: {}, peer: {}",
+                self.version, peer.version
+            )));
+        }
+        if self.codec != peer.codec {
+            return Err(Error::IncompatiblePeer(format!(
+                "codec mismatch, local: {}, peer: {}",
+                self.codec, peer.codec
+            )));
+        }
+
+        let features = self
+            .features
+            .iter()
+            .filter(|f| peer.supports(f))
+            .cloned()
+            .collect();
+        Ok(Capabilities {
+            version: self.version,
+            codec: self.codec.clone(),
+            features,
+            max_frame_size: self.max_frame_size.min(peer.max_frame_size),
+        })
+    }
+}
+
+/// Default time the handshake is given to complete before the connection is dropped
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Exchanges the capabilities with the peer. Both sides write their own capabilities
+/// before reading the peer's. A peer without the handshake is assumed to have the
+/// capabilities implied by the codec.
+#[cfg(any(feature = "server", feature = "client"))]
+pub(crate) async fn handshake<W, R>(
+    writer: &mut W,
+    reader: &mut R,
+    local: &Capabilities,
+) -> Result<Capabilities, Error>
+where
+    W: CodecWrite,
+    R: CodecRead,
+{
+    if let Some(peer) = W::implied_capabilities() {
+        return Ok(peer);
+    }
+
+    let buf = W::marshal(local)?;
+    writer.write_body_bytes(0, &buf).await?;
+
+    // the handshake is the only message that is not a header and its body
+    let peer = match reader.read_bytes().await {
+        Some(Ok(buf)) => R::unmarshal::<Capabilities>(&buf).map_err(CodecError::from),
+        Some(Err(err)) => Err(err.into()),
+        None => {
+            return Err(Error::IoError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Connection closed before the handshake is completed",
+            )))
+        }
+    };
+    let peer = match peer {
+        Ok(peer) => peer,
+        Err(err) => {
+            return Err(Error::IncompatiblePeer(format!(
+                "invalid handshake from peer, the peer may be using a different protocol, version or codec ({})",
+                err
+            )))
+        }
+    };
+    local.negotiate(&peer)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -152,12 +444,17 @@ mod tests {
         let header = Header::Request {
             id: 3000,
             service_method: "".into(),
-            timeout: Duration::from_secs(10),
+            timeout: Some(Duration::from_secs(10)),
+            metadata: MetadataMap::new(),
         };
         let size = bincode_opt.serialized_size(&header).unwrap();
         println!("Header::Request size: {:?}", size);
 
-        let header = Header::Response { id: 0, is_ok: true };
+        let header = Header::Response {
+            id: 0,
+            status: Status::ok(),
+            metadata: MetadataMap::new(),
+        };
         let size = bincode_opt.serialized_size(&header).unwrap();
         println!("Header::Response size: {:?}", size);
 
@@ -187,4 +484,102 @@ mod tests {
         let size = bincode_opt.serialized_size(&opt).unwrap();
         println!("size: {:?}", size);
     }
+
+    #[test]
+    fn negotiate_capabilities() {
+        let local = Capabilities::local("bincode");
+        let peer = Capabilities {
+            features: vec![FEATURE_METADATA.into(), FEATURE_COMPRESSION.into()],
+            max_frame_size: 1024,
+            ..local.clone()
+        };
+        let agreed = local.negotiate(&peer).unwrap();
+        assert_eq!(agreed.features, vec![FEATURE_METADATA.to_string()]);
+        assert_eq!(agreed.max_frame_size, 1024);
+        assert!(!agreed.supports(FEATURE_STREAMING));
+
+        let peer = Capabilities::local("json");
+        assert!(matches!(
+            local.negotiate(&peer),
+            Err(Error::IncompatiblePeer(_))
+        ));
+
+        let peer = Capabilities {
+            version: PROTOCOL_VERSION + 1,
+            ..local.clone()
+        };
+        assert!(matches!(
+            local.negotiate(&peer),
+            Err(Error::IncompatiblePeer(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    fn metadata_round_trip() {
+        #[cfg(feature = "serde_bincode")]
+        metadata_round_trip_with::<crate::codec::bincode::Bincode>();
+        #[cfg(feature = "serde_json")]
+        metadata_round_trip_with::<crate::codec::json::Json>();
+        #[cfg(feature = "serde_cbor")]
+        metadata_round_trip_with::<crate::codec::cbor::Cbor>();
+        #[cfg(feature = "serde_rmp")]
+        metadata_round_trip_with::<crate::codec::rmp::Rmp>();
+    }
+
+    #[cfg(all(
+        any(feature = "async_std_runtime", feature = "tokio_runtime"),
+        any(
+            feature = "serde_bincode",
+            feature = "serde_json",
+            feature = "serde_cbor",
+            feature = "serde_rmp"
+        )
+    ))]
+    fn metadata_round_trip_with<C: crate::codec::Marshal + crate::codec::Unmarshal>() {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("authorization".into(), "Bearer token".into());
+        metadata.insert("trace-id".into(), "0af7651916cd43dd".into());
+
+        let header = Header::Request {
+            id: 7,
+            service_method: "Foo.bar".into(),
+            timeout: Some(Duration::from_secs(10)),
+            metadata: metadata.clone(),
+        };
+        let buf = C::marshal(&header).unwrap();
+        match C::unmarshal(&buf).unwrap() {
+            Header::Request {
+                id,
+                service_method,
+                metadata: m,
+                ..
+            } => {
+                assert_eq!(id, 7);
+                assert_eq!(service_method, "Foo.bar");
+                assert_eq!(m, metadata);
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+
+        let status = Status::permission_denied("denied").with_details(vec![1, 2, 3]);
+        let header = Header::Response {
+            id: 7,
+            status: status.clone(),
+            metadata: metadata.clone(),
+        };
+        let buf = C::marshal(&header).unwrap();
+        match C::unmarshal(&buf).unwrap() {
+            Header::Response {
+                id,
+                status: s,
+                metadata: m,
+            } => {
+                assert_eq!(id, 7);
+                assert_eq!(s, status);
+                assert_eq!(m, metadata);
+            }
+            header => panic!("Unexpected header {:?}", header),
+        }
+    }
 }
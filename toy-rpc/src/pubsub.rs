@@ -1,4 +1,4 @@
-//! PubSub support
+//! PubSub and work queue support
 use std::time::Duration;
 
 use serde::{de::DeserializeOwned, Serialize};
@@ -11,7 +11,14 @@ pub const DEFAULT_PUB_RETRY_TIMEOUT: Duration = Duration::from_secs(10);
 /// Default number of retries
 pub const DEFAULT_PUB_RETRIES: u32 = 5;
 
-/// Trait for PubSub Topic
+/// Default duration a consumer has to Ack a work queue message before the message
+/// is delivered again
+pub const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default number of consumers a work queue message is delivered to
+pub const DEFAULT_TICKETS: u32 = 1;
+
+/// Trait for PubSub Topic. A topic is also used as the name of a work queue
 pub trait Topic {
     /// Message type of the topic
     type Item: Serialize + DeserializeOwned + Send + Sync + 'static;
@@ -20,7 +27,8 @@ pub trait Topic {
     fn topic() -> String;
 }
 
-/// PubSub Sequence ID that is tracked by the PubSub server
+/// PubSub Sequence ID that is tracked by the PubSub server. Each delivery of a work
+/// queue message has its own sequence ID as well
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SeqId(pub MessageId);
 
@@ -0,0 +1,445 @@
+//! Bounded queues between the tasks of a connection
+//!
+//! Every connection has a broker queue, which holds the messages waiting for the broker, and
+//! a writer queue, which holds the messages waiting to be written to the peer. The reader
+//! stops reading from the connection while the broker queue is full, and the broker holds
+//! back the requests, responses and publications to the peer while the writer queue is full,
+//! so a slow peer slows down the other side of the connection instead of making the queues
+//! grow without bound. Control messages, such as cancellations and keepalive pings, are not
+//! held back.
+//!
+//! The channels of the broker and the writer are created unbounded by `brw`, and the queues
+//! are bounded by the producers that wait on a `QueueGauge` of the channel. Besides the
+//! reader, these are the `CallSink`, the `Call` of `Client::call`, the `extension` methods
+//! of `Client` and `ClientHandle`, and the reverse calls made with `ClientHandle::call`.
+//! `Client::notify` and `Batch::send` return without waiting, so they fail with a
+//! `ResourceExhausted` status while the broker queue is full instead.
+//!
+//! Publications are queued separately for each subscriber. What happens when the queue of a
+//! subscriber is full is decided by the `FullQueuePolicy`. RPC messages are never dropped,
+//! and only wait or disconnect the peer.
+//!
+//! The items of a streaming call are queued separately for each stream as well. The broker
+//! never waits for a stream to be read, which would hold back every other call of the
+//! connection, so the queue of a stream is only bounded under `FullQueuePolicy::Disconnect`.
+//! The server then closes the connection of a client that sends faster than a stream is
+//! read, and the client cancels a call whose stream is not read fast enough.
+
+use flume::{Receiver, Sender, TrySendError};
+use futures::future::{self, Either};
+use futures::{Sink, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Default number of messages a queue holds
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// What happens when a bounded queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FullQueuePolicy {
+    /// The producer waits until the queue has room. This is the default
+    #[default]
+    Block,
+    /// The new publication is discarded
+    DropNewest,
+    /// The oldest queued publication is discarded to make room for the new one
+    DropOldest,
+    /// The slow peer is disconnected
+    Disconnect,
+}
+
+/// Snapshot of the state of a queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueMetrics {
+    depth: usize,
+    capacity: usize,
+    high_water_mark: usize,
+    dropped: u64,
+}
+
+impl QueueMetrics {
+    /// Number of messages in the queue
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Max number of messages the queue holds. This is `usize::MAX` if the queue is unbounded
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Largest depth the queue has reached
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+    /// Number of messages discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Keeps track of the depth of a queue whose messages are carried by an unbounded channel,
+/// and lets the producers wait until the queue has room
+pub(crate) struct QueueGauge {
+    capacity: usize,
+    depth: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    space_tx: Sender<()>,
+    space_rx: Receiver<()>,
+}
+
+impl QueueGauge {
+    pub fn new(capacity: usize) -> Self {
+        let (space_tx, space_rx) = flume::bounded(1);
+        Self {
+            capacity: capacity.max(1),
+            depth: AtomicUsize::new(0),
+            high_water_mark: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            space_tx,
+            space_rx,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.depth.load(Ordering::Relaxed) >= self.capacity
+    }
+
+    /// Sets the depth of a queue whose length is known
+    pub fn set_depth(&self, depth: usize) {
+        self.depth.store(depth, Ordering::Relaxed);
+        self.high_water_mark.fetch_max(depth, Ordering::Relaxed);
+        if depth < self.capacity {
+            self.notify();
+        }
+    }
+
+    /// Counts a message that is added to the queue
+    pub fn push(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.high_water_mark.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    /// Counts a message that is taken from the queue
+    pub fn pop(&self) {
+        let _ = self
+            .depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                Some(depth.saturating_sub(1))
+            });
+        self.notify();
+    }
+
+    pub fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks the consumer as stopped, which wakes up the waiting producers
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.notify();
+    }
+
+    /// Waits until the queue has room. Returns `false` if the consumer is stopped
+    pub async fn wait_for_space(&self) -> bool {
+        loop {
+            if self.closed.load(Ordering::Relaxed) {
+                // passes the wake-up on to the other producers
+                self.notify();
+                return false;
+            }
+            if !self.is_full() {
+                return true;
+            }
+            if self.space_rx.recv_async().await.is_err() {
+                return false;
+            }
+            if !self.is_full() {
+                // passes the wake-up on to the other producers
+                self.notify();
+                return true;
+            }
+        }
+    }
+
+    fn notify(&self) {
+        let _ = self.space_tx.try_send(());
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            depth: self.depth.load(Ordering::Relaxed),
+            capacity: self.capacity,
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A sink that counts the messages sent through it into a `QueueGauge`
+pub(crate) struct GaugedSink<S> {
+    inner: S,
+    gauge: Arc<QueueGauge>,
+}
+
+impl<S> GaugedSink<S> {
+    pub fn new(inner: S, gauge: Arc<QueueGauge>) -> Self {
+        Self { inner, gauge }
+    }
+}
+
+impl<S, T> Sink<T> for GaugedSink<S>
+where
+    S: Sink<T> + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), S::Error> {
+        // counted before it is sent, otherwise the consumer may set the depth without the
+        // message before it is counted
+        self.gauge.push();
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Reason a message is not added to a queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PushError {
+    /// The queue is full and the policy is `FullQueuePolicy::Disconnect`
+    Full,
+    /// The receiving half is dropped
+    Closed,
+}
+
+/// Creates a queue that holds at most `capacity` messages, or an unbounded queue
+/// if `capacity` is `None`
+pub(crate) fn channel<T>(
+    capacity: Option<usize>,
+    policy: FullQueuePolicy,
+) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (tx, rx) = match capacity {
+        Some(capacity) => flume::bounded(capacity.max(1)),
+        None => flume::unbounded(),
+    };
+    let (alive, watch) = flume::bounded(1);
+    let gauge = Arc::new(QueueGauge::new(capacity.unwrap_or(usize::MAX)));
+    let sender = QueueSender {
+        tx,
+        rx: rx.clone(),
+        watch,
+        policy,
+        gauge: gauge.clone(),
+    };
+    let receiver = QueueReceiver {
+        inner: rx.clone().into_stream(),
+        rx,
+        _alive: alive,
+        gauge,
+    };
+    (sender, receiver)
+}
+
+/// Creates the queue of the items of a stream, which holds at most `capacity` items under
+/// `FullQueuePolicy::Disconnect` and is unbounded otherwise, as the items are never dropped
+pub(crate) fn stream_channel<T>(
+    capacity: usize,
+    policy: FullQueuePolicy,
+) -> (QueueSender<T>, QueueReceiver<T>) {
+    match policy {
+        FullQueuePolicy::Disconnect => channel(Some(capacity), policy),
+        _ => channel(None, FullQueuePolicy::Block),
+    }
+}
+
+/// Sending half of a queue, which applies the `FullQueuePolicy` once the queue is full
+pub(crate) struct QueueSender<T> {
+    tx: Sender<T>,
+    /// Used to discard the oldest message
+    rx: Receiver<T>,
+    /// Disconnected once all the receiving halves are dropped
+    watch: Receiver<()>,
+    policy: FullQueuePolicy,
+    gauge: Arc<QueueGauge>,
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            watch: self.watch.clone(),
+            policy: self.policy,
+            gauge: self.gauge.clone(),
+        }
+    }
+}
+
+impl<T> QueueSender<T> {
+    /// Returns `true` once all the receiving halves are dropped
+    pub fn is_closed(&self) -> bool {
+        self.watch.is_disconnected()
+    }
+
+    pub async fn push(&self, item: T) -> Result<(), PushError> {
+        if self.is_closed() {
+            return Err(PushError::Closed);
+        }
+        let result = match self.policy {
+            FullQueuePolicy::Block => {
+                match future::select(self.tx.send_async(item), self.watch.recv_async()).await {
+                    Either::Left((result, _)) => result.map_err(|_| PushError::Closed),
+                    Either::Right(_) => Err(PushError::Closed),
+                }
+            }
+            FullQueuePolicy::DropNewest => match self.tx.try_send(item) {
+                Err(TrySendError::Full(_)) => {
+                    self.gauge.record_drop();
+                    Ok(())
+                }
+                result => result.map_err(|_| PushError::Closed),
+            },
+            FullQueuePolicy::DropOldest => {
+                let mut item = item;
+                loop {
+                    match self.tx.try_send(item) {
+                        Err(TrySendError::Full(returned)) => {
+                            if self.rx.try_recv().is_ok() {
+                                self.gauge.record_drop();
+                            }
+                            item = returned;
+                        }
+                        result => break result.map_err(|_| PushError::Closed),
+                    }
+                }
+            }
+            FullQueuePolicy::Disconnect => match self.tx.try_send(item) {
+                Err(TrySendError::Full(_)) => Err(PushError::Full),
+                result => result.map_err(|_| PushError::Closed),
+            },
+        };
+        self.gauge.set_depth(self.tx.len());
+        result
+    }
+
+    #[cfg(any(feature = "server", test))]
+    pub fn metrics(&self) -> QueueMetrics {
+        self.gauge.metrics()
+    }
+}
+
+/// Receiving half of a queue
+pub(crate) struct QueueReceiver<T: 'static> {
+    inner: flume::r#async::RecvStream<'static, T>,
+    rx: Receiver<T>,
+    /// Never used to send, the sending halves watch for it to be dropped
+    _alive: Sender<()>,
+    gauge: Arc<QueueGauge>,
+}
+
+impl<T> QueueReceiver<T> {
+    #[cfg(any(feature = "server", test))]
+    pub fn try_recv(&self) -> Option<T> {
+        let item = self.rx.try_recv().ok();
+        self.gauge.set_depth(self.rx.len());
+        item
+    }
+
+    #[cfg(feature = "server")]
+    pub fn len(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Returns `true` once all the sending halves are dropped
+    #[cfg(feature = "server")]
+    pub fn is_disconnected(&self) -> bool {
+        self.rx.is_disconnected()
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.gauge.metrics()
+    }
+}
+
+impl<T> Stream for QueueReceiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let poll = self.inner.poll_next_unpin(cx);
+        if poll.is_ready() {
+            self.gauge.set_depth(self.rx.len());
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(rx: &QueueReceiver<u32>) -> Vec<u32> {
+        std::iter::from_fn(|| rx.try_recv()).collect()
+    }
+
+    #[test]
+    fn full_queue_policies() {
+        futures::executor::block_on(async {
+            let (tx, rx) = channel(Some(2), FullQueuePolicy::DropNewest);
+            for i in 0..4 {
+                tx.push(i).await.unwrap();
+            }
+            assert_eq!(drain(&rx), vec![0, 1]);
+            assert_eq!(tx.metrics().dropped(), 2);
+            assert_eq!(tx.metrics().high_water_mark(), 2);
+            assert_eq!(rx.metrics().depth(), 0);
+
+            let (tx, rx) = channel(Some(2), FullQueuePolicy::DropOldest);
+            for i in 0..4 {
+                tx.push(i).await.unwrap();
+            }
+            assert_eq!(drain(&rx), vec![2, 3]);
+            assert_eq!(tx.metrics().dropped(), 2);
+
+            let (tx, rx) = channel(Some(2), FullQueuePolicy::Disconnect);
+            tx.push(0).await.unwrap();
+            tx.push(1).await.unwrap();
+            assert_eq!(tx.push(2).await, Err(PushError::Full));
+            drop(rx);
+            assert_eq!(tx.push(3).await, Err(PushError::Closed));
+        })
+    }
+
+    #[test]
+    fn blocked_sender_is_released_when_receiver_is_dropped() {
+        futures::executor::block_on(async {
+            let (tx, rx) = channel(Some(1), FullQueuePolicy::Block);
+            tx.push(0).await.unwrap();
+            let push = tx.push(1);
+            futures::pin_mut!(push);
+            assert!(futures::poll!(push.as_mut()).is_pending());
+            drop(rx);
+            assert_eq!(push.await, Err(PushError::Closed));
+        })
+    }
+}
@@ -1,12 +1,24 @@
 //! Broker on the server side
 
 use std::future::Future;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::protocol::InboundBody;
+use crate::execution::{
+    execute_call, execute_timed_call, panic_message, run_until_canceled, InboundCall, Timing,
+};
+use crate::extension::{self, ExtensionFrame, ExtensionMap, ExtensionMessage, Marker};
+use crate::keepalive::Keepalive;
+use crate::message::MessageIdAllocator;
+use crate::protocol::{InboundBody, MetadataMap, OutboundBody};
 use crate::pubsub::SeqId;
-use crate::service::{ArcAsyncServiceCall, HandlerResult};
+use crate::queue::{self, FullQueuePolicy, GaugedSink, PushError, QueueGauge, QueueSender};
+use crate::service::{
+    ArcAsyncServiceCall, CancellationToken, HandlerOutput, HandlerResult, MetadataScope,
+    RequestBody, RpcContext,
+};
 
 use crate::{error::Error, message::MessageId};
 
@@ -15,35 +27,67 @@ use std::marker::PhantomData;
 
 use brw::{Broker, Running};
 use flume::Sender;
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, FutureExt};
 use futures::sink::{Sink, SinkExt};
+use futures::stream::{Stream, StreamExt};
 
 use crate::pubsub::{AckModeAuto, AckModeNone};
 use crate::server::pubsub::PubSubResponder;
 
-use super::pubsub::PubSubItem;
+use super::pubsub::{
+    client_publications, ClientPublications, ClientPublicationsReceiver, Content, PubSubItem,
+    PubSubSender,
+};
 use super::writer::ServerWriterItem;
-use super::ClientId;
-
-#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-use ::async_std::task::JoinHandle;
-#[cfg(any(
-    feature = "docs",
-    all(feature = "tokio_runtime", not(feature = "async_std_runtime"),)
-))]
-use ::tokio::task::JoinHandle;
+use super::{ClientId, ConnectionConfig, QueueConfig};
 
 pub(crate) enum ServerBrokerItem {
     Request {
+        call: InboundCall,
+        request: RequestBody,
+    },
+    // A client-streaming or bidirectional streaming call is opened by the client
+    OpenStream(InboundCall),
+    // A one-way request from the client that expects no response
+    Notify {
         call: ArcAsyncServiceCall,
         id: MessageId,
         method: String,
-        duration: Duration,
-        deserializer: Box<InboundBody>,
+        request: RequestBody,
+        metadata: MetadataMap,
+    },
+    // A batch of requests from the client
+    Batch {
+        id: MessageId,
+        deadline: Option<Instant>,
+        metadata: MetadataMap,
+        calls: Vec<BatchCall>,
+    },
+    // All results of a batch
+    BatchResponse {
+        id: MessageId,
+        results: Vec<BatchResult>,
+    },
+    // An item sent by the client in a client-streaming call
+    InboundStreamItem {
+        id: MessageId,
+        body: Box<InboundBody>,
     },
+    // The client has sent all the items of a client-streaming call
+    InboundStreamEnd(MessageId),
     Response {
         id: MessageId,
         result: HandlerResult,
+        metadata: MetadataMap,
     },
+    // An item yielded by a streaming RPC method
+    OutboundStreamItem {
+        id: MessageId,
+        result: HandlerResult,
+    },
+    // A streaming RPC method has yielded all its items
+    OutboundStreamEnd(MessageId),
     Cancel(MessageId),
     // A new publish from the client publisher
     Publish {
@@ -60,34 +104,176 @@ pub(crate) enum ServerBrokerItem {
         id: MessageId,
         topic: String,
     },
-    // A publication message to the client subscriber
-    Publication {
+    // Publications are waiting in the publication queue of the client
+    Publications,
+    // A new message for a work queue from the client producer
+    Produce {
+        id: MessageId,
+        topic: String,
+        tickets: u32,
+        content: Vec<u8>,
+    },
+    // A pull from the client consumer
+    Consume {
+        id: MessageId,
+        topic: String,
+    },
+    // A work queue message to the client consumer
+    Delivery {
         seq_id: SeqId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     },
+    // An extension message from the client
+    Extension {
+        id: MessageId,
+        marker: Marker,
+        content: String,
+        body: Box<InboundBody>,
+    },
+    // The reply of an extension handler
+    ExtensionReply(ExtensionFrame),
     // The server broker should only receive Ack from the client
     InboundAck {
         seq_id: SeqId,
     },
+    // A reverse call to a service registered on the client
+    ReverseRequest {
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        body: Box<OutboundBody>,
+        resp_tx: oneshot::Sender<Result<ReverseResult, Error>>,
+    },
+    // The response of the client to a reverse call
+    InboundResponse {
+        id: MessageId,
+        result: ReverseResult,
+    },
+    // A reverse call is dropped or has reached the timeout
+    ReverseCancel(MessageId),
+    // An extension message to the client
+    OutboundExtension {
+        id: MessageId,
+        marker: Marker,
+        content: String,
+        body: Box<OutboundBody>,
+        resp_tx: oneshot::Sender<Result<ExtensionMessage, Error>>,
+    },
+    // Time to send a keepalive ping
+    KeepaliveTick,
+    // A keepalive ping from the client
+    InboundPing(MessageId),
+    // The answer to a keepalive ping from the client
+    InboundPong(MessageId),
     Stopping,
     Stop,
 }
 
+impl ServerBrokerItem {
+    /// Whether the item writes a response, a publication or a request to the client. The
+    /// broker only waits for the writer queue before these items, and the control items
+    /// are handled right away
+    fn writes_data(&self) -> bool {
+        matches!(
+            self,
+            Self::Response { .. }
+                | Self::BatchResponse { .. }
+                | Self::OutboundStreamItem { .. }
+                | Self::Publications
+                | Self::Delivery { .. }
+                | Self::ExtensionReply(_)
+                | Self::ReverseRequest { .. }
+                | Self::OutboundExtension { .. }
+        )
+    }
+}
+
+/// A request in a batch, which is an error if the service is not found
+pub(crate) type BatchCall = (
+    MessageId,
+    Result<(ArcAsyncServiceCall, String, RequestBody), Error>,
+);
+
+/// The result of a request in a batch along with the metadata of the response
+pub(crate) type BatchResult = (MessageId, HandlerResult, MetadataMap);
+
+/// Response body of a reverse call, which is either the result or an error message
+pub(crate) type ReverseResult = Result<Box<InboundBody>, Error>;
+
+/// Queues of a client connection
+#[derive(Clone)]
+pub(crate) struct ConnectionQueues {
+    /// Messages waiting for the broker
+    pub broker: Arc<QueueGauge>,
+    /// Messages waiting to be written to the client
+    pub writer: Arc<QueueGauge>,
+    pub publications: ClientPublications,
+    pub policy: FullQueuePolicy,
+}
+
+impl ConnectionQueues {
+    pub fn new(config: QueueConfig) -> (Self, ClientPublicationsReceiver) {
+        let (publications, receiver) =
+            client_publications(config.publication_capacity, config.policy);
+        let queues = Self {
+            broker: Arc::new(QueueGauge::new(config.broker_capacity)),
+            writer: Arc::new(QueueGauge::new(config.writer_capacity)),
+            publications,
+            policy: config.policy,
+        };
+        (queues, receiver)
+    }
+}
+
 pub(crate) struct ServerBroker<AckMode> {
     pub client_id: ClientId,
-    pub executions: HashMap<MessageId, JoinHandle<()>>,
-    pub pubsub_broker: Sender<PubSubItem>,
+    pub remote_addr: Option<SocketAddr>,
+    // Name of the format of the connection, which the publications of the client are
+    // encoded with
+    pub codec: &'static str,
+    pub grace_period: Duration,
+    // The spawned executions finish on their own once their token is canceled
+    pub executions: HashMap<MessageId, CancellationToken>,
+    pub request_streams: HashMap<MessageId, QueueSender<Box<InboundBody>>>,
+    pub extensions: Arc<ExtensionMap>,
+    // Ids of the messages sent by the server, shared with the `ClientHandle`
+    pub ids: Arc<MessageIdAllocator>,
+    pub pending_calls: HashMap<MessageId, oneshot::Sender<Result<ReverseResult, Error>>>,
+    pub pending_extensions: HashMap<MessageId, oneshot::Sender<Result<ExtensionMessage, Error>>>,
+    pub keepalive: Keepalive,
+    pub pubsub_broker: PubSubSender,
+    pub queues: ConnectionQueues,
+    // Set once the connection is closing, after which the broker no longer waits for the writer
+    pub closing: bool,
 
     ack_mode: PhantomData<AckMode>,
 }
 
 impl<AckMode> ServerBroker<AckMode> {
-    pub fn new(client_id: ClientId, pubsub_broker: Sender<PubSubItem>) -> Self {
+    pub fn new(
+        client_id: ClientId,
+        remote_addr: Option<SocketAddr>,
+        codec: &'static str,
+        config: &ConnectionConfig,
+        pubsub_broker: PubSubSender,
+        queues: ConnectionQueues,
+    ) -> Self {
         Self {
             client_id,
+            remote_addr,
+            codec,
+            grace_period: config.cancellation_grace_period,
             executions: HashMap::new(),
+            request_streams: HashMap::new(),
+            extensions: config.extensions.clone(),
+            ids: Arc::new(MessageIdAllocator::default()),
+            pending_calls: HashMap::new(),
+            pending_extensions: HashMap::new(),
+            keepalive: Keepalive::new(config.keepalive_miss_threshold),
             pubsub_broker,
+            queues,
+            closing: false,
             ack_mode: PhantomData,
         }
     }
@@ -95,16 +281,193 @@ impl<AckMode> ServerBroker<AckMode> {
     fn handle_request<'a>(
         &'a mut self,
         ctx: &'a Arc<brw::Context<ServerBrokerItem>>,
+        call: InboundCall,
+        request: RequestBody,
+    ) -> Result<(), Error> {
+        let InboundCall {
+            call,
+            id,
+            method,
+            deadline,
+            metadata,
+        } = call;
+        let _broker = ctx.broker.clone();
+        // The request metadata and deadline are available to the RPC method during its
+        // execution
+        let scope = MetadataScope::new(metadata, deadline);
+        let token = CancellationToken::new();
+        let rpc_ctx = RpcContext::new(
+            self.client_id,
+            self.remote_addr,
+            deadline,
+            scope.request(),
+            token.clone(),
+        );
+        let timing = Timing {
+            deadline,
+            grace_period: self.grace_period,
+            token: token.clone(),
+        };
+        match scope.enter(|| call(method, rpc_ctx, request)) {
+            HandlerOutput::Unary(fut) => {
+                spawn_timed_request_execution(_broker, timing, id, scope, fut)
+            }
+            HandlerOutput::Stream(stream) => {
+                spawn_timed_stream_execution(_broker, timing, id, scope.wrap(stream))
+            }
+        };
+        self.executions.insert(id, token);
+        Ok(())
+    }
+
+    fn handle_open_stream<'a>(
+        &'a mut self,
+        ctx: &'a Arc<brw::Context<ServerBrokerItem>>,
+        call: InboundCall,
+    ) -> Result<(), Error> {
+        let (tx, rx) = queue::stream_channel(self.queues.broker.capacity(), self.queues.policy);
+        self.request_streams.insert(call.id, tx);
+        let request = RequestBody::Stream(Box::pin(rx));
+        self.handle_request(ctx, call, request)
+    }
+
+    fn handle_notify(
+        &mut self,
         call: ArcAsyncServiceCall,
         id: MessageId,
         method: String,
-        duration: Duration,
-        deserializer: Box<InboundBody>,
+        request: RequestBody,
+        metadata: MetadataMap,
     ) -> Result<(), Error> {
-        let fut = call(method, deserializer);
-        let _broker = ctx.broker.clone();
-        let handle = spawn_timed_request_execution(_broker, duration, id, fut);
-        self.executions.insert(id, handle);
+        // A notification has no deadline and cannot be canceled by the client
+        let scope = MetadataScope::new(metadata, None);
+        let rpc_ctx = RpcContext::new(
+            self.client_id,
+            self.remote_addr,
+            None,
+            scope.request(),
+            CancellationToken::new(),
+        );
+        match scope.enter(|| call(method, rpc_ctx, request)) {
+            HandlerOutput::Unary(fut) => spawn_notification_execution(id, scope.wrap(fut)),
+            HandlerOutput::Stream(_) => {
+                log::error!(
+                    "Streaming RPC methods cannot be notified, notification: {}",
+                    id
+                )
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_batch<'a>(
+        &'a mut self,
+        ctx: &'a Arc<brw::Context<ServerBrokerItem>>,
+        id: MessageId,
+        deadline: Option<Instant>,
+        metadata: MetadataMap,
+        calls: Vec<BatchCall>,
+    ) -> Result<(), Error> {
+        let mut executions = Vec::with_capacity(calls.len());
+        for (call_id, call) in calls {
+            let (call, method, request) = match call {
+                Ok(call) => call,
+                Err(err) => {
+                    log::error!("{}", &err);
+                    executions.push(future::ready((call_id, Err(err), MetadataMap::new())).boxed());
+                    continue;
+                }
+            };
+            let scope = MetadataScope::new(metadata.clone(), deadline);
+            let token = CancellationToken::new();
+            let rpc_ctx = RpcContext::new(
+                self.client_id,
+                self.remote_addr,
+                deadline,
+                scope.request(),
+                token.clone(),
+            );
+            let timing = Timing {
+                deadline,
+                grace_period: self.grace_period,
+                token: token.clone(),
+            };
+            match scope.enter(|| call(method, rpc_ctx, request)) {
+                HandlerOutput::Unary(fut) => {
+                    let fut = scope.wrap(fut);
+                    executions.push(
+                        async move {
+                            let result = execute_timed_call(call_id, timing, fut).await;
+                            (call_id, result, scope.take_response())
+                        }
+                        .boxed(),
+                    );
+                    self.executions.insert(call_id, token);
+                }
+                HandlerOutput::Stream(_) => {
+                    let err = Error::ExecutionError(
+                        "Streaming RPC methods cannot be called in a batch".into(),
+                    );
+                    executions.push(future::ready((call_id, Err(err), MetadataMap::new())).boxed());
+                }
+            }
+        }
+        spawn_batch_execution(ctx.broker.clone(), id, executions);
+        Ok(())
+    }
+
+    async fn handle_batch_response<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+        results: Vec<BatchResult>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        for (call_id, _, _) in &results {
+            self.executions.remove(call_id);
+        }
+        let msg = ServerWriterItem::BatchResponse { id, results };
+        writer.send(msg).await.map_err(|err| err.into())
+    }
+
+    /// Queues the item without waiting for the stream to be read, so that the other calls
+    /// of the connection are not held back by a stream that is read out of order
+    async fn handle_inbound_stream_item(
+        &mut self,
+        ctx: &Arc<brw::Context<ServerBrokerItem>>,
+        id: MessageId,
+        body: Box<InboundBody>,
+    ) -> Result<(), Error> {
+        // The execution may have already finished or been canceled, in which
+        // case the item is simply discarded
+        if let Some(tx) = self.request_streams.get(&id) {
+            match tx.push(body).await {
+                Ok(()) => {}
+                Err(PushError::Full) => {
+                    log::error!(
+                        "Client {} is sending faster than the stream is read, closing connection",
+                        self.client_id
+                    );
+                    self.closing = true;
+                    return ctx
+                        .broker
+                        .send_async(ServerBrokerItem::Stopping)
+                        .await
+                        .map_err(Into::into);
+                }
+                Err(PushError::Closed) => {
+                    self.request_streams.remove(&id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_inbound_stream_end(&mut self, id: MessageId) -> Result<(), Error> {
+        // Dropping the sender ends the `RequestStream`
+        self.request_streams.remove(&id);
         Ok(())
     }
 
@@ -113,21 +476,52 @@ impl<AckMode> ServerBroker<AckMode> {
         writer: &'w mut W,
         id: MessageId,
         result: HandlerResult,
+        metadata: MetadataMap,
     ) -> Result<(), Error>
     where
         W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
     {
         self.executions.remove(&id);
-        let msg = ServerWriterItem::Response { id, result };
+        self.request_streams.remove(&id);
+        let msg = ServerWriterItem::Response {
+            id,
+            result,
+            metadata,
+        };
+        writer.send(msg).await.map_err(|err| err.into())
+    }
+
+    async fn handle_stream_item<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+        result: HandlerResult,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        let msg = ServerWriterItem::StreamItem { id, result };
+        writer.send(msg).await.map_err(|err| err.into())
+    }
+
+    async fn handle_stream_end<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        self.executions.remove(&id);
+        self.request_streams.remove(&id);
+        let msg = ServerWriterItem::StreamEnd { id };
         writer.send(msg).await.map_err(|err| err.into())
     }
 
     async fn handle_cancel(&mut self, id: MessageId) -> Result<(), Error> {
-        if let Some(handle) = self.executions.remove(&id) {
-            #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
-            handle.abort();
-            #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-            handle.cancel().await;
+        self.request_streams.remove(&id);
+        if let Some(token) = self.executions.remove(&id) {
+            token.cancel();
         }
         Ok(())
     }
@@ -138,17 +532,17 @@ impl<AckMode> ServerBroker<AckMode> {
         topic: String,
         content: Vec<u8>,
     ) -> Result<(), Error> {
-        let content = Arc::new(content);
+        let content = Arc::new(Content {
+            codec: self.codec,
+            bytes: content,
+        });
         let msg = PubSubItem::Publish {
             client_id: self.client_id,
             msg_id: id,
             topic,
             content,
         };
-        self.pubsub_broker
-            .send_async(msg)
-            .await
-            .map_err(|err| err.into())
+        self.pubsub_broker.send_async(msg).await
     }
 
     async fn handle_subscribe<'a>(
@@ -158,17 +552,18 @@ impl<AckMode> ServerBroker<AckMode> {
         topic: String,
     ) -> Result<(), Error> {
         log::debug!("Message ID: {}, Subscribe to topic: {}", &id, &topic);
-        let sender = PubSubResponder::Sender(ctx.broker.clone());
+        let sender = PubSubResponder::Client {
+            queue: self.queues.publications.clone(),
+            broker: ctx.broker.clone(),
+            codec: self.codec,
+        };
         let msg = PubSubItem::Subscribe {
             client_id: self.client_id,
             topic,
             sender,
         };
 
-        self.pubsub_broker
-            .send_async(msg)
-            .await
-            .map_err(|err| err.into())
+        self.pubsub_broker.send_async(msg).await
     }
 
     async fn handle_unsubscribe(&mut self, id: MessageId, topic: String) -> Result<(), Error> {
@@ -178,24 +573,57 @@ impl<AckMode> ServerBroker<AckMode> {
             topic,
         };
 
-        self.pubsub_broker
-            .send_async(msg)
-            .await
-            .map_err(|err| err.into())
+        self.pubsub_broker.send_async(msg).await
+    }
+
+    async fn handle_produce(
+        &mut self,
+        id: MessageId,
+        topic: String,
+        tickets: u32,
+        content: Vec<u8>,
+    ) -> Result<(), Error> {
+        log::debug!("Message ID: {}, Produce to topic: {}", &id, &topic);
+        let msg = PubSubItem::Produce {
+            client_id: self.client_id,
+            msg_id: id,
+            topic,
+            tickets,
+            content: Arc::new(Content {
+                codec: self.codec,
+                bytes: content,
+            }),
+        };
+        self.pubsub_broker.send_async(msg).await
     }
 
-    async fn handle_publication<'w, W>(
+    async fn handle_consume<'a>(
+        &'a mut self,
+        ctx: &'a Arc<brw::Context<ServerBrokerItem>>,
+        id: MessageId,
+        topic: String,
+    ) -> Result<(), Error> {
+        log::debug!("Message ID: {}, Consume from topic: {}", &id, &topic);
+        let msg = PubSubItem::Consume {
+            client_id: self.client_id,
+            topic,
+            codec: Some(self.codec),
+            sender: PubSubResponder::Sender(ctx.broker.clone()),
+        };
+        self.pubsub_broker.send_async(msg).await
+    }
+
+    async fn handle_delivery<'w, W>(
         &'w mut self,
         writer: &'w mut W,
         seq_id: SeqId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     ) -> Result<(), Error>
     where
         W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
     {
-        // Publication is the PubSub message from server to client
-        let msg = ServerWriterItem::Publication {
+        let msg = ServerWriterItem::Delivery {
             seq_id,
             topic,
             content,
@@ -203,15 +631,176 @@ impl<AckMode> ServerBroker<AckMode> {
         writer.send(msg).await.map_err(|err| err.into())
     }
 
+    async fn handle_extension<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        ctx: &'w Arc<brw::Context<ServerBrokerItem>>,
+        id: MessageId,
+        marker: Marker,
+        content: String,
+        body: Box<InboundBody>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        if extension::is_reply(marker) {
+            let resp_tx = self.pending_extensions.remove(&id).ok_or_else(|| {
+                Error::Internal(format!("Extension message ({}) is not found", id).into())
+            })?;
+            self.ids.release(id);
+            let result = extension::into_reply_result(marker, content, body);
+            return resp_tx
+                .send(result)
+                .map_err(|_| Error::Internal("InternalError: Extension caller is dropped".into()));
+        }
+        match self.extensions.get(&marker) {
+            Some(handler) => {
+                let fut = handler(ExtensionMessage::new(marker, content, body));
+                let broker = ctx.broker.clone();
+                spawn_extension_handler(broker, id, fut);
+                Ok(())
+            }
+            None => {
+                let frame = ExtensionFrame::unknown_marker(id, marker);
+                writer
+                    .send(ServerWriterItem::Extension(frame))
+                    .await
+                    .map_err(|err| err.into())
+            }
+        }
+    }
+
+    async fn handle_reverse_request<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        body: Box<OutboundBody>,
+        resp_tx: oneshot::Sender<Result<ReverseResult, Error>>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        self.pending_calls.insert(id, resp_tx);
+        let msg = ServerWriterItem::Request {
+            id,
+            service_method,
+            duration,
+            body,
+        };
+        writer.send(msg).await.map_err(|err| {
+            self.pending_calls.remove(&id);
+            self.ids.release(id);
+            err.into()
+        })
+    }
+
+    fn handle_inbound_response(
+        &mut self,
+        id: MessageId,
+        result: ReverseResult,
+    ) -> Result<(), Error> {
+        // The reverse call may have already been canceled
+        match self.pending_calls.remove(&id) {
+            Some(resp_tx) => {
+                self.ids.release(id);
+                resp_tx
+                    .send(Ok(result))
+                    .map_err(|_| Error::Internal("InternalError: Reverse caller is dropped".into()))
+            }
+            None => {
+                log::debug!("Reverse call ({}) is not found", id);
+                Ok(())
+            }
+        }
+    }
+
+    async fn handle_reverse_cancel<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        id: MessageId,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        if self.pending_calls.remove(&id).is_none() {
+            return Ok(());
+        }
+        self.ids.release(id);
+        writer
+            .send(ServerWriterItem::Cancel(id))
+            .await
+            .map_err(|err| err.into())
+    }
+
+    async fn handle_outbound_extension<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        frame: ExtensionFrame,
+        resp_tx: oneshot::Sender<Result<ExtensionMessage, Error>>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        let id = frame.id;
+        self.pending_extensions.insert(id, resp_tx);
+        writer
+            .send(ServerWriterItem::Extension(frame))
+            .await
+            .map_err(|err| {
+                self.pending_extensions.remove(&id);
+                self.ids.release(id);
+                err.into()
+            })
+    }
+
+    /// Fails the pending reverse calls and extension messages once the client is disconnected
+    fn fail_pending(&mut self) {
+        for (id, tx) in self.pending_calls.drain() {
+            self.ids.release(id);
+            let _ = tx.send(Err(Error::ConnectionLost));
+        }
+        for (id, tx) in self.pending_extensions.drain() {
+            self.ids.release(id);
+            let _ = tx.send(Err(Error::ConnectionLost));
+        }
+    }
+
+    async fn handle_keepalive_tick<'w, W>(
+        &'w mut self,
+        writer: &'w mut W,
+        ctx: &'w Arc<brw::Context<ServerBrokerItem>>,
+    ) -> Result<(), Error>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        if !self.keepalive.tick() {
+            log::error!(
+                "Client {} is not answering the keepalive pings, closing connection",
+                self.client_id
+            );
+            return ctx
+                .broker
+                .send_async(ServerBrokerItem::Stopping)
+                .await
+                .map_err(|err| err.into());
+        }
+
+        // No reply other than the Pong is expected, and thus the id does not need to be kept live
+        let id = self.ids.next();
+        writer
+            .send(ServerWriterItem::Ping(id))
+            .await
+            .map_err(|err| err.into())
+    }
+
     async fn handle_inbound_ack(&mut self, seq_id: SeqId) -> Result<(), Error> {
         let item = PubSubItem::Ack {
             seq_id,
             client_id: self.client_id,
         };
-        self.pubsub_broker
-            .send_async(item)
-            .await
-            .map_err(|err| err.into())
+        self.pubsub_broker.send_async(item).await
     }
 }
 
@@ -272,23 +861,69 @@ macro_rules! impl_server_broker_for_ack_modes {
                     &mut self,
                     ctx: &Arc<brw::Context<Self::Item>>,
                     item: Self::Item,
-                    mut writer: W,
+                    writer: W,
                 ) -> Running<Result<Self::Ok, Self::Error>, Option<Self::Error>>
                 where
                     W: Sink<Self::WriterItem, Error = flume::SendError<Self::WriterItem>> + Send + Unpin,
                 {
+                    self.queues.broker.set_depth(ctx.broker.len());
+                    if item.writes_data() && !self.closing && self.queues.writer.is_full() {
+                        if self.queues.policy == FullQueuePolicy::Disconnect {
+                            log::error!(
+                                "Client {} is not reading fast enough, closing connection",
+                                self.client_id
+                            );
+                            self.closing = true;
+                            if let Err(err) = ctx.broker.send_async(ServerBrokerItem::Stopping).await {
+                                log::debug!("{}", err);
+                            }
+                        } else {
+                            self.queues.writer.wait_for_space().await;
+                        }
+                    }
+                    let mut writer = GaugedSink::new(writer, self.queues.writer.clone());
+
                     let result = match item {
-                        ServerBrokerItem::Request {
+                        ServerBrokerItem::Request { call, request } => {
+                            self.handle_request(ctx, call, request)
+                        },
+                        ServerBrokerItem::OpenStream(call) => {
+                            self.handle_open_stream(ctx, call)
+                        },
+                        ServerBrokerItem::Notify {
                             call,
                             id,
                             method,
-                            duration,
-                            deserializer,
+                            request,
+                            metadata,
                         } => {
-                            self.handle_request(ctx, call, id, method, duration, deserializer)
+                            self.handle_notify(call, id, method, request, metadata)
+                        },
+                        ServerBrokerItem::Batch {
+                            id,
+                            deadline,
+                            metadata,
+                            calls,
+                        } => {
+                            self.handle_batch(ctx, id, deadline, metadata, calls)
+                        },
+                        ServerBrokerItem::BatchResponse { id, results } => {
+                            self.handle_batch_response(&mut writer, id, results).await
                         },
-                        ServerBrokerItem::Response { id, result } => {
-                           self.handle_response(&mut writer, id, result).await
+                        ServerBrokerItem::InboundStreamItem { id, body } => {
+                            self.handle_inbound_stream_item(ctx, id, body).await
+                        },
+                        ServerBrokerItem::InboundStreamEnd(id) => {
+                            self.handle_inbound_stream_end(id)
+                        },
+                        ServerBrokerItem::Response { id, result, metadata } => {
+                           self.handle_response(&mut writer, id, result, metadata).await
+                        },
+                        ServerBrokerItem::OutboundStreamItem { id, result } => {
+                            self.handle_stream_item(&mut writer, id, result).await
+                        },
+                        ServerBrokerItem::OutboundStreamEnd(id) => {
+                            self.handle_stream_end(&mut writer, id).await
                         },
                         ServerBrokerItem::Cancel(id) => {
                             self.handle_cancel(id).await
@@ -302,20 +937,63 @@ macro_rules! impl_server_broker_for_ack_modes {
                         ServerBrokerItem::Unsubscribe { id, topic } => {
                             self.handle_unsubscribe(id, topic).await
                         },
-                        ServerBrokerItem::Publication { seq_id, topic, content } => {
-                            self.handle_publication(&mut writer, seq_id, topic, content).await
+                        ServerBrokerItem::Publications => {
+                            // the writer takes the publications from the queue
+                            writer.send(ServerWriterItem::Publications).await
+                                .map_err(|err| err.into())
+                        },
+                        ServerBrokerItem::Produce { id, topic, tickets, content } => {
+                            self.handle_produce(id, topic, tickets, content).await
+                        },
+                        ServerBrokerItem::Consume { id, topic } => {
+                            self.handle_consume(ctx, id, topic).await
+                        },
+                        ServerBrokerItem::Delivery { seq_id, topic, content } => {
+                            self.handle_delivery(&mut writer, seq_id, topic, content).await
+                        },
+                        ServerBrokerItem::Extension { id, marker, content, body } => {
+                            self.handle_extension(&mut writer, ctx, id, marker, content, body).await
+                        },
+                        ServerBrokerItem::ExtensionReply(frame) => {
+                            writer.send(ServerWriterItem::Extension(frame)).await
+                                .map_err(|err| err.into())
                         },
                         ServerBrokerItem::InboundAck {seq_id} => {
                             self.handle_inbound_ack(seq_id).await
                         },
+                        ServerBrokerItem::ReverseRequest { id, service_method, duration, body, resp_tx } => {
+                            self.handle_reverse_request(&mut writer, id, service_method, duration, body, resp_tx).await
+                        },
+                        ServerBrokerItem::InboundResponse { id, result } => {
+                            self.handle_inbound_response(id, result)
+                        },
+                        ServerBrokerItem::ReverseCancel(id) => {
+                            self.handle_reverse_cancel(&mut writer, id).await
+                        },
+                        ServerBrokerItem::OutboundExtension { id, marker, content, body, resp_tx } => {
+                            let frame = ExtensionFrame { id, marker, content, body };
+                            self.handle_outbound_extension(&mut writer, frame, resp_tx).await
+                        },
+                        ServerBrokerItem::KeepaliveTick => {
+                            self.handle_keepalive_tick(&mut writer, ctx).await
+                        },
+                        ServerBrokerItem::InboundPing(id) => {
+                            writer.send(ServerWriterItem::Pong(id)).await
+                                .map_err(|err| err.into())
+                        },
+                        ServerBrokerItem::InboundPong(id) => {
+                            log::trace!("Received Pong({})", id);
+                            self.keepalive.pong();
+                            Ok(())
+                        },
                         ServerBrokerItem::Stopping => {
-                            for (_, handle) in self.executions.drain() {
+                            self.closing = true;
+                            self.request_streams.clear();
+                            for (_, token) in self.executions.drain() {
                                 log::debug!("Stopping execution as client is disconnected");
-                                #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
-                                handle.abort();
-                                #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-                                handle.cancel().await;
+                                token.cancel();
                             }
+                            self.fail_pending();
 
                             let result = writer.send(ServerWriterItem::Stopping).await
                                 .map_err(Into::into);
@@ -325,9 +1003,16 @@ macro_rules! impl_server_broker_for_ack_modes {
                                 .and(result)
                         }
                         ServerBrokerItem::Stop => {
+                            self.fail_pending();
+                            // releases the reader if it is waiting for room
+                            self.queues.broker.close();
                             if let Err(err) = writer.send(ServerWriterItem::Stop).await {
                                 log::debug!("{}", err);
                             }
+                            let item = PubSubItem::RemoveClient { client_id: self.client_id };
+                            if let Err(err) = self.pubsub_broker.send_async(item).await {
+                                log::debug!("{}", err);
+                            }
                             log::debug!("Client connection is closed");
                             return Running::Stop(None)
                         }
@@ -342,82 +1027,221 @@ macro_rules! impl_server_broker_for_ack_modes {
 
 impl_server_broker_for_ack_modes!(AckModeNone, AckModeAuto);
 
-/// Spawn the execution in a async_std task and return the JoinHandle
+/// Spawn the execution in a async_std task
 #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
 fn spawn_timed_request_execution(
     broker: Sender<ServerBrokerItem>,
-    duration: Duration,
+    timing: Timing,
     id: MessageId,
+    scope: MetadataScope,
     fut: impl Future<Output = HandlerResult> + Send + 'static,
-) -> ::async_std::task::JoinHandle<()> {
+) {
     ::async_std::task::spawn(async move {
-        let result = execute_timed_call(id, duration, fut).await;
-        broker
-            .send_async(ServerBrokerItem::Response { id, result })
-            .await
-            .unwrap_or_else(|e| log::error!("{}", e));
-    })
+        let result = execute_timed_call(id, timing, scope.wrap(fut)).await;
+        send_response(broker, id, result, scope).await
+    });
 }
 
-/// Spawn the execution in a tokio task and return the JoinHandle
+/// Spawn the execution in a tokio task
 #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
 fn spawn_timed_request_execution(
     broker: Sender<ServerBrokerItem>,
-    duration: Duration,
+    timing: Timing,
     id: MessageId,
+    scope: MetadataScope,
     fut: impl Future<Output = HandlerResult> + Send + 'static,
-) -> ::tokio::task::JoinHandle<()> {
+) {
     ::tokio::task::spawn(async move {
-        let result = execute_timed_call(id, duration, fut).await;
-        broker
-            .send_async(ServerBrokerItem::Response { id, result })
-            .await
-            .unwrap_or_else(|e| log::error!("{}", e));
-    })
+        let result = execute_timed_call(id, timing, scope.wrap(fut)).await;
+        send_response(broker, id, result, scope).await
+    });
 }
 
-pub(crate) async fn execute_call(
+/// Spawn the execution of a notification in a async_std task. The result is discarded
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+fn spawn_notification_execution(
     id: MessageId,
-    fut: impl Future<Output = HandlerResult>,
-) -> HandlerResult {
-    let result: HandlerResult = fut.await.map_err(|err| {
-        log::error!(
-            "Error found executing request id: {}, error msg: {}",
-            &id,
-            &err
-        );
-        match err {
-            // if serde cannot parse request, the argument is likely mistaken
-            Error::ParseError(e) => {
-                log::error!("ParseError {:?}", e);
-                Error::InvalidArgument
-            }
-            e => e,
+    fut: impl Future<Output = HandlerResult> + Send + 'static,
+) {
+    ::async_std::task::spawn(async move {
+        // Errors are logged by `execute_call`
+        let _ = execute_call(id, fut).await;
+    });
+}
+
+/// Spawn the execution of a notification in a tokio task. The result is discarded
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
+fn spawn_notification_execution(
+    id: MessageId,
+    fut: impl Future<Output = HandlerResult> + Send + 'static,
+) {
+    ::tokio::task::spawn(async move {
+        // Errors are logged by `execute_call`
+        let _ = execute_call(id, fut).await;
+    });
+}
+
+/// Spawn the executions of a batch in a async_std task. The results are sent back
+/// once all executions are finished
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+fn spawn_batch_execution(
+    broker: Sender<ServerBrokerItem>,
+    id: MessageId,
+    executions: Vec<BoxFuture<'static, BatchResult>>,
+) {
+    ::async_std::task::spawn(send_batch_response(broker, id, executions));
+}
+
+/// Spawn the executions of a batch in a tokio task. The results are sent back once
+/// all executions are finished
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
+fn spawn_batch_execution(
+    broker: Sender<ServerBrokerItem>,
+    id: MessageId,
+    executions: Vec<BoxFuture<'static, BatchResult>>,
+) {
+    ::tokio::task::spawn(send_batch_response(broker, id, executions));
+}
+
+/// Spawn the execution of a streaming method in a async_std task
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+fn spawn_timed_stream_execution(
+    broker: Sender<ServerBrokerItem>,
+    timing: Timing,
+    id: MessageId,
+    stream: impl Stream<Item = HandlerResult> + Send + 'static,
+) {
+    ::async_std::task::spawn(execute_timed_stream(broker, timing, id, stream));
+}
+
+/// Spawn the execution of a streaming method in a tokio task
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
+fn spawn_timed_stream_execution(
+    broker: Sender<ServerBrokerItem>,
+    timing: Timing,
+    id: MessageId,
+    stream: impl Stream<Item = HandlerResult> + Send + 'static,
+) {
+    ::tokio::task::spawn(execute_timed_stream(broker, timing, id, stream));
+}
+
+/// Spawn an extension handler in a async_std task
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+fn spawn_extension_handler(
+    broker: Sender<ServerBrokerItem>,
+    id: MessageId,
+    fut: impl Future<Output = extension::ExtensionResult> + Send + 'static,
+) {
+    ::async_std::task::spawn(async move {
+        let frame = ExtensionFrame::reply(id, fut.await);
+        if let Err(err) = broker
+            .send_async(ServerBrokerItem::ExtensionReply(frame))
+            .await
+        {
+            log::error!("{}", err);
         }
     });
-    result
 }
 
-pub(crate) async fn execute_timed_call(
+/// Spawn an extension handler in a tokio task
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
+fn spawn_extension_handler(
+    broker: Sender<ServerBrokerItem>,
     id: MessageId,
-    duration: Duration,
-    fut: impl Future<Output = HandlerResult>,
-) -> HandlerResult {
-    #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-    match ::async_std::future::timeout(duration, execute_call(id, fut)).await {
-        Ok(res) => res,
-        Err(err) => {
-            log::error!("Request {} reached timeout (err: {})", id, err);
-            Err(Error::Timeout(id))
+    fut: impl Future<Output = extension::ExtensionResult> + Send + 'static,
+) {
+    ::tokio::task::spawn(async move {
+        let frame = ExtensionFrame::reply(id, fut.await);
+        if let Err(err) = broker
+            .send_async(ServerBrokerItem::ExtensionReply(frame))
+            .await
+        {
+            log::error!("{}", err);
         }
+    });
+}
+
+async fn send_response(
+    broker: Sender<ServerBrokerItem>,
+    id: MessageId,
+    result: HandlerResult,
+    scope: MetadataScope,
+) {
+    // The client is no longer waiting for the response of a canceled call
+    if let Err(Error::Canceled(_)) = result {
+        return;
     }
+    let metadata = scope.take_response();
+    broker
+        .send_async(ServerBrokerItem::Response {
+            id,
+            result,
+            metadata,
+        })
+        .await
+        .unwrap_or_else(|e| log::error!("{}", e));
+}
 
-    #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
-    match ::tokio::time::timeout(duration, execute_call(id, fut)).await {
-        Ok(res) => res,
-        Err(err) => {
-            log::error!("Request {} reached timeout (err: {})", id, err);
-            Err(Error::Timeout(id))
+async fn send_batch_response(
+    broker: Sender<ServerBrokerItem>,
+    id: MessageId,
+    executions: Vec<BoxFuture<'static, BatchResult>>,
+) {
+    // The executions run concurrently within the task
+    let results = future::join_all(executions).await;
+    broker
+        .send_async(ServerBrokerItem::BatchResponse { id, results })
+        .await
+        .unwrap_or_else(|e| log::error!("{}", e));
+}
+
+/// Forwards all items of the stream to the broker followed by an end-of-stream.
+///
+/// The timeout applies to the whole stream rather than each item. A stream that
+/// reaches the timeout sends a timeout error to the client. Once the timeout is
+/// reached or the call is canceled, the remaining items are discarded for the
+/// grace period.
+pub(crate) async fn execute_timed_stream(
+    broker: Sender<ServerBrokerItem>,
+    timing: Timing,
+    id: MessageId,
+    stream: impl Stream<Item = HandlerResult>,
+) {
+    let canceled = timing.token.clone();
+    // A panic ends the stream with an error item
+    let stream = AssertUnwindSafe(stream)
+        .catch_unwind()
+        .map(|item| item.unwrap_or_else(|panic| Err(Error::Internal(panic_message(panic).into()))));
+    let fut = async {
+        futures::pin_mut!(stream);
+        while let Some(result) = stream.next().await {
+            // items yielded after the cancellation are discarded
+            if canceled.is_cancelled() {
+                continue;
+            }
+            let result = execute_call(id, futures::future::ready(result)).await;
+            broker
+                .send_async(ServerBrokerItem::OutboundStreamItem { id, result })
+                .await?;
         }
-    }
+        Ok::<(), flume::SendError<ServerBrokerItem>>(())
+    };
+
+    let item = match run_until_canceled(id, &timing, fut).await {
+        Ok(Ok(_)) => ServerBrokerItem::OutboundStreamEnd(id),
+        Ok(Err(err)) => {
+            log::error!("{}", err);
+            return;
+        }
+        Err(Error::Timeout(_)) => ServerBrokerItem::Response {
+            id,
+            result: Err(Error::Timeout(id)),
+            metadata: MetadataMap::new(),
+        },
+        Err(_) => return,
+    };
+    broker
+        .send_async(item)
+        .await
+        .unwrap_or_else(|e| log::error!("{}", e));
 }
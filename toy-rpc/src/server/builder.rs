@@ -1,6 +1,5 @@
 //! Builder of the Server
 
-use erased_serde as erased;
 use std::{collections::HashMap, marker::PhantomData, sync::Arc, time::Duration};
 
 #[cfg(any(
@@ -10,9 +9,23 @@ use std::{collections::HashMap, marker::PhantomData, sync::Arc, time::Duration};
 ))]
 use super::Server;
 
+use super::{ConnectionHook, ConnectionInfo, DEFAULT_CANCELLATION_GRACE_PERIOD};
 use crate::{
-    pubsub::{AckModeAuto, AckModeNone, DEFAULT_PUB_RETRIES, DEFAULT_PUB_RETRY_TIMEOUT},
-    service::{build_service, AsyncServiceMap, HandleService, HandlerResultFut, Service},
+    codec::Format,
+    compression::{Compression, DEFAULT_COMPRESSION_THRESHOLD},
+    extension::{self, ExtensionMap, ExtensionMessage, ExtensionResult, Marker},
+    keepalive::DEFAULT_KEEPALIVE_MISS_THRESHOLD,
+    protocol::{DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_FRAME_SIZE},
+    pubsub::{
+        AckModeAuto, AckModeNone, DEFAULT_PUB_RETRIES, DEFAULT_PUB_RETRY_TIMEOUT,
+        DEFAULT_VISIBILITY_TIMEOUT,
+    },
+    queue::{FullQueuePolicy, DEFAULT_QUEUE_CAPACITY},
+    service::{
+        build_service, AsyncServiceMap, HandleService, HandlerOutput, RequestBody, RpcContext,
+        Service,
+    },
+    transport::{MessageSizeLimit, DEFAULT_MAX_MESSAGE_SIZE},
     util::RegisterService,
 };
 
@@ -24,6 +37,45 @@ pub struct ServerBuilder<AckMode> {
     pub pub_retry_timeout: Duration,
     /// Max number of retries for publishing
     pub max_num_retries: u32,
+    /// Duration a work queue message stays invisible to other consumers after
+    /// it is delivered and before it is acked
+    pub visibility_timeout: Duration,
+    /// Period a canceled or timed out RPC method is given to clean up
+    pub cancellation_grace_period: Duration,
+    /// Hook that is called when a client connection completes the handshake
+    pub on_connect: Option<ConnectionHook>,
+    /// Registered extension handlers
+    pub extensions: ExtensionMap,
+    /// Interval of the keepalive pings. Keepalive is disabled if this is `None`
+    pub keepalive_interval: Option<Duration>,
+    /// Number of consecutive keepalive pings that can be left unanswered before the
+    /// client is declared dead
+    pub keepalive_miss_threshold: u32,
+    /// Algorithm used to compress the payloads sent to the clients. Payloads are not
+    /// compressed if this is `None`
+    pub compression: Option<Compression>,
+    /// Size in bytes below which payloads are sent uncompressed
+    pub compression_threshold: usize,
+    /// Max size in bytes of the payload of a frame. Larger bodies are split into chunks
+    pub max_frame_size: u32,
+    /// Time a client is given to complete the handshake
+    pub handshake_timeout: Duration,
+    /// Max size in bytes of an inbound message
+    pub max_message_size: usize,
+    /// Whether an inbound message that exceeds the max message size closes the connection
+    pub close_on_oversized_message: bool,
+    /// Max number of messages from a client that wait for the broker of the connection
+    pub broker_queue_capacity: usize,
+    /// Max number of messages that wait to be written to a client
+    pub writer_queue_capacity: usize,
+    /// Max number of publications that wait for the PubSub broker, and that wait to be
+    /// written to each subscriber
+    pub pubsub_queue_capacity: usize,
+    /// What happens when the queue of a slow client is full
+    pub full_queue_policy: FullQueuePolicy,
+    /// Format of the connections that are not accepted with a format of their own. The
+    /// format of `DefaultCodec` is used if this is `None`
+    pub format: Option<Format>,
     ack_mode: PhantomData<AckMode>,
 }
 
@@ -34,6 +86,23 @@ impl<AckMode> ServerBuilder<AckMode> {
             services: HashMap::new(),
             pub_retry_timeout: DEFAULT_PUB_RETRY_TIMEOUT,
             max_num_retries: DEFAULT_PUB_RETRIES,
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            cancellation_grace_period: DEFAULT_CANCELLATION_GRACE_PERIOD,
+            on_connect: None,
+            extensions: HashMap::new(),
+            keepalive_interval: None,
+            keepalive_miss_threshold: DEFAULT_KEEPALIVE_MISS_THRESHOLD,
+            compression: None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            close_on_oversized_message: false,
+            broker_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            writer_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            pubsub_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            full_queue_policy: FullQueuePolicy::default(),
+            format: None,
             ack_mode: PhantomData,
         }
     }
@@ -44,6 +113,23 @@ impl<AckMode> ServerBuilder<AckMode> {
             services: self.services,
             pub_retry_timeout: self.pub_retry_timeout,
             max_num_retries: self.max_num_retries,
+            visibility_timeout: self.visibility_timeout,
+            cancellation_grace_period: self.cancellation_grace_period,
+            on_connect: self.on_connect,
+            extensions: self.extensions,
+            keepalive_interval: self.keepalive_interval,
+            keepalive_miss_threshold: self.keepalive_miss_threshold,
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            max_frame_size: self.max_frame_size,
+            handshake_timeout: self.handshake_timeout,
+            max_message_size: self.max_message_size,
+            close_on_oversized_message: self.close_on_oversized_message,
+            broker_queue_capacity: self.broker_queue_capacity,
+            writer_queue_capacity: self.writer_queue_capacity,
+            pubsub_queue_capacity: self.pubsub_queue_capacity,
+            full_queue_policy: self.full_queue_policy,
+            format: self.format,
             ack_mode: PhantomData,
         }
     }
@@ -54,10 +140,252 @@ impl<AckMode> ServerBuilder<AckMode> {
             services: self.services,
             pub_retry_timeout: self.pub_retry_timeout,
             max_num_retries: self.max_num_retries,
+            visibility_timeout: self.visibility_timeout,
+            cancellation_grace_period: self.cancellation_grace_period,
+            on_connect: self.on_connect,
+            extensions: self.extensions,
+            keepalive_interval: self.keepalive_interval,
+            keepalive_miss_threshold: self.keepalive_miss_threshold,
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            max_frame_size: self.max_frame_size,
+            handshake_timeout: self.handshake_timeout,
+            max_message_size: self.max_message_size,
+            close_on_oversized_message: self.close_on_oversized_message,
+            broker_queue_capacity: self.broker_queue_capacity,
+            writer_queue_capacity: self.writer_queue_capacity,
+            pubsub_queue_capacity: self.pubsub_queue_capacity,
+            full_queue_policy: self.full_queue_policy,
+            format: self.format,
             ack_mode: PhantomData,
         }
     }
 
+    /// Sets the period a RPC method is given to clean up after it is canceled by the
+    /// client or reaches the timeout.
+    ///
+    /// The `CancellationToken` of the `RpcContext` is canceled first, and the RPC method
+    /// keeps running until it returns or the grace period elapses. A RPC method that has
+    /// not returned by then is dropped at its next `.await`, so a method that must not be
+    /// interrupted should check the token and finish its work within the grace period.
+    /// The error response is sent once the RPC method has returned or is dropped.
+    ///
+    /// Defaults to `DEFAULT_CANCELLATION_GRACE_PERIOD`. A zero grace period polls the RPC
+    /// method only once more after the cancellation.
+    pub fn set_cancellation_grace_period(mut self, duration: Duration) -> Self {
+        self.cancellation_grace_period = duration;
+        self
+    }
+
+    /// Sets the duration a work queue message stays invisible to other consumers
+    /// after it is delivered.
+    ///
+    /// A message that is not acked by the consumer within the visibility timeout
+    /// is delivered again.
+    pub fn set_visibility_timeout(mut self, duration: Duration) -> Self {
+        self.visibility_timeout = duration;
+        self
+    }
+
+    /// Enables keepalive, which sends a ping to each client every `interval`.
+    ///
+    /// If a client leaves more consecutive pings than the miss threshold unanswered, its
+    /// connection is closed, its executions are canceled and it is removed from the
+    /// subscriptions and work queues. Keepalive is disabled by default.
+    pub fn set_keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Sets the number of consecutive keepalive pings that can be left unanswered before a
+    /// client is declared dead. This is at least 1 and defaults to
+    /// `DEFAULT_KEEPALIVE_MISS_THRESHOLD`.
+    pub fn set_keepalive_miss_threshold(mut self, val: u32) -> Self {
+        self.keepalive_miss_threshold = val.max(1);
+        self
+    }
+
+    /// Compresses the payloads sent to the clients with `compression`.
+    ///
+    /// Payloads are only compressed for the clients that support the algorithm, and
+    /// payloads smaller than the compression threshold are sent uncompressed. Compression
+    /// is disabled by default.
+    pub fn set_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Sets the size in bytes below which payloads are sent uncompressed. This defaults to
+    /// `DEFAULT_COMPRESSION_THRESHOLD`.
+    pub fn set_compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Sets the max size in bytes of the payload of a frame, which defaults to
+    /// `DEFAULT_MAX_FRAME_SIZE`.
+    ///
+    /// The smaller of the sizes of the server and the client is used on a connection.
+    /// Larger bodies are split into chunks, which are interleaved with the other messages so
+    /// that a large transfer does not hold them back. Chunking is only used by the raw TCP
+    /// transport.
+    pub fn set_max_frame_size(mut self, size: u32) -> Self {
+        self.max_frame_size = size.max(1);
+        self
+    }
+
+    /// Sets the time a client is given to complete the handshake after it connects, which
+    /// defaults to `DEFAULT_HANDSHAKE_TIMEOUT`.
+    ///
+    /// A client that does not send its capabilities within the timeout is disconnected, and
+    /// serving the connection fails with `Error::IoError` of kind `TimedOut`.
+    pub fn set_handshake_timeout(mut self, duration: Duration) -> Self {
+        self.handshake_timeout = duration;
+        self
+    }
+
+    /// Sets the max size in bytes of a message received from the client, which defaults to
+    /// `DEFAULT_MAX_MESSAGE_SIZE`.
+    ///
+    /// The size is checked before the message is read into memory, and a compressed
+    /// message is checked against the size after decompression. An oversized message is
+    /// skipped and the call it belongs to fails with `Error::MessageTooLarge`, unless
+    /// `set_close_on_oversized_message` is enabled. The WebSocket transports cannot skip
+    /// a message and always close the connection.
+    ///
+    /// The `tide` integration is the exception, as `tide` reads the whole WebSocket message
+    /// before it is handed to the server. The size is checked after the message is read into
+    /// memory, and the oversized message is then skipped like on the other transports.
+    pub fn set_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size.max(1);
+        self
+    }
+
+    /// Closes the connection when a message received from the client exceeds the max message
+    /// size instead of skipping the message. This is disabled by default.
+    pub fn set_close_on_oversized_message(mut self, close: bool) -> Self {
+        self.close_on_oversized_message = close;
+        self
+    }
+
+    /// Sets the max number of messages from a client that wait for the broker of the
+    /// connection, which defaults to `DEFAULT_QUEUE_CAPACITY`.
+    ///
+    /// Once the queue is full, the server stops reading from the connection until the broker
+    /// catches up. The items of a streaming call are queued for each stream without holding
+    /// back the broker. The queue of a stream only has the same capacity under
+    /// `FullQueuePolicy::Disconnect`, which closes the connection once it is full.
+    pub fn set_broker_queue_capacity(mut self, capacity: usize) -> Self {
+        self.broker_queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets the max number of messages that wait to be written to a client, which defaults
+    /// to `DEFAULT_QUEUE_CAPACITY`.
+    ///
+    /// Once the queue is full, the broker of the connection stops taking new messages until
+    /// the client reads the pending ones, unless the full queue policy is
+    /// `FullQueuePolicy::Disconnect`, which closes the connection.
+    pub fn set_writer_queue_capacity(mut self, capacity: usize) -> Self {
+        self.writer_queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets the max number of publications and work queue messages that wait for the PubSub
+    /// broker, and the max number of publications that wait to be written to each
+    /// subscriber. This defaults to `DEFAULT_QUEUE_CAPACITY`.
+    ///
+    /// Publishers wait once the PubSub broker falls behind. What happens when the queue of a
+    /// subscriber is full is set with `set_full_queue_policy`.
+    pub fn set_pubsub_queue_capacity(mut self, capacity: usize) -> Self {
+        self.pubsub_queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets what happens when the queue of a slow client or subscriber is full, which
+    /// defaults to `FullQueuePolicy::Block`.
+    ///
+    /// - `Block` makes the PubSub broker, and thus the publishers, wait for the subscriber
+    /// - `DropNewest` and `DropOldest` discard a publication to the subscriber. With
+    ///   `AckModeAuto`, a dropped publication is sent again once its Ack times out
+    /// - `Disconnect` closes the connection of the client
+    ///
+    /// Responses and the other RPC messages are never dropped, and wait for a full writer
+    /// queue unless the policy is `Disconnect`.
+    pub fn set_full_queue_policy(mut self, policy: FullQueuePolicy) -> Self {
+        self.full_queue_policy = policy;
+        self
+    }
+
+    /// Sets the format of the connections, which defaults to the format of `DefaultCodec`.
+    ///
+    /// This applies to `accept`, `accept_websocket`, `serve_stream` and the HTTP
+    /// integrations. `Server::accept_with_format` accepts the connections on a listener with
+    /// another format, so that clients using different formats can be served by one server.
+    ///
+    /// Publications are forwarded to the clients as they are encoded by the publisher, and a
+    /// client only receives the publications and work queue messages in the format of its
+    /// connection. The publishers and producers on the server side use this format, and the
+    /// subscribers and consumers on the server side decode every format.
+    pub fn set_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the hook that is called when a client connection completes the handshake.
+    ///
+    /// The hook receives the client id, the remote address and the capabilities agreed
+    /// upon by the server and the client.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let server = Server::builder()
+    ///     .on_connect(|info| {
+    ///         println!("Client {} connected with {:?}", info.client_id(), info.capabilities())
+    ///     })
+    ///     .register(foo)
+    ///     .build();
+    /// ```
+    pub fn on_connect<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) + Send + Sync + 'static,
+    {
+        self.on_connect = Some(Arc::new(hook));
+        self
+    }
+
+    /// Registers a handler for the extension messages (`Header::Ext`) with the `marker`.
+    ///
+    /// The handler gets the message with the body still encoded by the codec of the
+    /// connection, and its reply is sent back to the client. A message whose marker has
+    /// no handler is answered with an error, which the client sees as
+    /// `Error::UnknownExtension`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `marker` is reserved, ie. not smaller than
+    /// `extension::RESERVED_MARKER_START`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let server = Server::builder()
+    ///     .register_extension(PRESENCE, |msg| async move {
+    ///         let user: String = msg.body()?;
+    ///         Ok(Some(ExtensionReply::new(is_online(&user))))
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn register_extension<F, Fut>(mut self, marker: Marker, handler: F) -> Self
+    where
+        F: Fn(ExtensionMessage) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ExtensionResult> + Send + 'static,
+    {
+        extension::register(&mut self.extensions, marker, handler);
+        self
+    }
+
     /// Registers a new service to the `Server` with the default name.
     ///
     /// Internally the `Service` object will be built using the supplied `service`
@@ -121,8 +449,9 @@ impl<AckMode> ServerBuilder<AckMode> {
         S: Send + Sync + 'static,
     {
         let call = move |method_name: String,
-                         _deserializer: Box<(dyn erased::Deserializer<'static> + Send)>|
-              -> HandlerResultFut { service.call(&method_name, _deserializer) };
+                         ctx: RpcContext,
+                         request: RequestBody|
+              -> HandlerOutput { service.call(&method_name, ctx, request) };
 
         log::debug!("Registering service: {}", name);
         let mut builder = self;
@@ -179,17 +508,45 @@ macro_rules! impl_server_builder_for_ack_modes {
                 /// let server: Server = builder.build();
                 /// ```
                 pub fn build(self) -> Server<$ack_mode> {
-                    use super::{AtomicClientId, RESERVED_CLIENT_ID, PubSubBroker};
+                    use super::{AtomicClientId, ConnectionConfig, QueueConfig, RESERVED_CLIENT_ID, PubSubBroker, PubSubGuard};
 
                     let services = Arc::new(self.services);
 
-                    let (pubsub_broker, pubsub_tx) = PubSubBroker::<$ack_mode>::new(self.pub_retry_timeout, self.max_num_retries);
+                    let (pubsub_broker, pubsub_tx) = PubSubBroker::<$ack_mode>::new(
+                        self.pub_retry_timeout,
+                        self.max_num_retries,
+                        self.visibility_timeout,
+                        self.pubsub_queue_capacity,
+                    );
                     pubsub_broker.spawn();
 
                     Server::<$ack_mode> {
                         client_counter: Arc::new(AtomicClientId::new(RESERVED_CLIENT_ID + 1)),
+                        conn_config: ConnectionConfig {
+                            cancellation_grace_period: self.cancellation_grace_period,
+                            on_connect: self.on_connect,
+                            extensions: Arc::new(self.extensions),
+                            keepalive_interval: self.keepalive_interval,
+                            keepalive_miss_threshold: self.keepalive_miss_threshold,
+                            compression: self.compression,
+                            compression_threshold: self.compression_threshold,
+                            max_frame_size: self.max_frame_size,
+                            handshake_timeout: self.handshake_timeout,
+                            message_size_limit: MessageSizeLimit::new(
+                                self.max_message_size,
+                                self.close_on_oversized_message,
+                            ),
+                            queues: QueueConfig {
+                                broker_capacity: self.broker_queue_capacity,
+                                writer_capacity: self.writer_queue_capacity,
+                                publication_capacity: self.pubsub_queue_capacity,
+                                policy: self.full_queue_policy,
+                            },
+                            format: self.format,
+                            client_handles: Default::default(),
+                        },
                         services,
-                        pubsub_tx,
+                        pubsub: Arc::new(PubSubGuard { sender: pubsub_tx }),
                         ack_mode: PhantomData,
                     }
                 }
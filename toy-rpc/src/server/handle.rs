@@ -0,0 +1,224 @@
+//! Handle to a connected client, which the server uses to call the services
+//! registered on the client
+
+use flume::Sender;
+use futures::channel::oneshot;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, IoError},
+    execution::{deserialize_response, timeout},
+    extension::{ExtensionMessage, Marker, RESERVED_MARKER_START},
+    message::{MessageId, MessageIdAllocator},
+    queue::QueueMetrics,
+    service::request_deadline,
+};
+
+use super::{
+    broker::{ConnectionQueues, ServerBrokerItem},
+    ClientId,
+};
+
+/// Default timeout of a reverse call
+pub const DEFAULT_REVERSE_CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handles of the clients that are currently connected, keyed by their id
+pub(crate) type ClientHandleMap = Arc<Mutex<HashMap<ClientId, ClientHandle>>>;
+
+/// Handle to a client that is connected to the server.
+///
+/// The handle calls the services that the client registered with `ClientBuilder::register`
+/// over the existing connection. It can be obtained with `Server::client` and is cheap to
+/// clone. Once the client disconnects, all calls return an error.
+///
+/// # Example
+///
+/// ```rust
+/// let handle = server.client(client_id).unwrap();
+/// let reply: String = handle.call("Agent.echo", "hello".to_string()).await?;
+/// ```
+#[derive(Clone)]
+pub struct ClientHandle {
+    client_id: ClientId,
+    broker: Sender<ServerBrokerItem>,
+    ids: Arc<MessageIdAllocator>,
+    queues: ConnectionQueues,
+    timeout: Duration,
+}
+
+/// Cancels the reverse call on the client if it is dropped before the response arrives
+struct PendingCall<'a> {
+    id: MessageId,
+    broker: &'a Sender<ServerBrokerItem>,
+    done: bool,
+}
+
+impl<'a> Drop for PendingCall<'a> {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(err) = self.broker.send(ServerBrokerItem::ReverseCancel(self.id)) {
+                log::debug!("{}", err);
+            }
+        }
+    }
+}
+
+fn not_connected() -> Error {
+    Error::IoError(IoError::new(
+        std::io::ErrorKind::NotConnected,
+        "Client is disconnected",
+    ))
+}
+
+impl ClientHandle {
+    pub(crate) fn new(
+        client_id: ClientId,
+        broker: Sender<ServerBrokerItem>,
+        ids: Arc<MessageIdAllocator>,
+        queues: ConnectionQueues,
+    ) -> Self {
+        Self {
+            client_id,
+            broker,
+            ids,
+            queues,
+            timeout: DEFAULT_REVERSE_CALL_TIMEOUT,
+        }
+    }
+
+    /// Id of the client
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Returns `true` if the connection to the client is closed
+    pub fn is_disconnected(&self) -> bool {
+        self.broker.is_disconnected()
+    }
+
+    /// Returns the metrics of the queue of messages from the client that wait for the
+    /// broker of the connection
+    pub fn broker_queue_metrics(&self) -> QueueMetrics {
+        self.queues.broker.metrics()
+    }
+
+    /// Returns the metrics of the queue of messages that wait to be written to the client
+    pub fn writer_queue_metrics(&self) -> QueueMetrics {
+        self.queues.writer.metrics()
+    }
+
+    /// Returns the metrics of the queue of publications that wait to be written to the
+    /// client, which is shared by all the topics the client subscribes to
+    pub fn publication_queue_metrics(&self) -> QueueMetrics {
+        self.queues.publications.metrics()
+    }
+
+    /// Sets the timeout of the calls made with this handle. The default is
+    /// `DEFAULT_REVERSE_CALL_TIMEOUT`
+    pub fn set_timeout(&mut self, duration: Duration) -> &mut Self {
+        self.timeout = duration;
+        self
+    }
+
+    /// Calls a method of a service registered on the client and waits for the response
+    ///
+    /// The call is canceled on the client if the timeout is reached, in which case
+    /// `Err(Error::Timeout(id))` is returned, or if the returned future is dropped. A call
+    /// made from within a RPC method is also limited by the deadline of the request being
+    /// handled.
+    pub async fn call<Req, Res>(
+        &self,
+        service_method: impl ToString,
+        args: Req,
+    ) -> Result<Res, Error>
+    where
+        Req: Serialize + Send + Sync + 'static,
+        Res: DeserializeOwned,
+    {
+        // A call made from within a RPC method does not outlive the request being handled
+        let duration = match request_deadline() {
+            Some(deadline) => self
+                .timeout
+                .min(deadline.saturating_duration_since(Instant::now())),
+            None => self.timeout,
+        };
+        // waits for the broker of the connection to catch up
+        if !self.queues.broker.wait_for_space().await {
+            return Err(not_connected());
+        }
+        let id = self.ids.acquire();
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let item = ServerBrokerItem::ReverseRequest {
+            id,
+            service_method: service_method.to_string(),
+            duration,
+            body: Box::new(args),
+            resp_tx,
+        };
+        if self.broker.send_async(item).await.is_err() {
+            self.ids.release(id);
+            return Err(not_connected());
+        }
+
+        let mut pending = PendingCall {
+            id,
+            broker: &self.broker,
+            done: false,
+        };
+        let result = match timeout(duration, resp_rx).await {
+            Some(result) => result,
+            None => {
+                log::error!("Reverse call {} reached timeout", id);
+                return Err(Error::Timeout(id));
+            }
+        };
+        pending.done = true;
+        // The response channel is dropped if the connection is closed
+        let result = result.map_err(|_| not_connected())??;
+        deserialize_response(result)
+    }
+
+    /// Sends an extension message to the client and waits for the reply
+    ///
+    /// The message is handed to the extension handler that the client registered for the
+    /// `marker` with `ClientBuilder::register_extension`. If no handler is registered for the
+    /// marker, this returns `Err(Error::UnknownExtension(marker))`.
+    pub async fn extension<T>(
+        &self,
+        marker: Marker,
+        content: impl Into<String>,
+        body: T,
+    ) -> Result<ExtensionMessage, Error>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        if marker >= RESERVED_MARKER_START {
+            return Err(Error::UnknownExtension(marker));
+        }
+
+        // waits for the broker of the connection to catch up
+        if !self.queues.broker.wait_for_space().await {
+            return Err(not_connected());
+        }
+        let id = self.ids.acquire();
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let item = ServerBrokerItem::OutboundExtension {
+            id,
+            marker,
+            content: content.into(),
+            body: Box::new(body),
+            resp_tx,
+        };
+        if self.broker.send_async(item).await.is_err() {
+            self.ids.release(id);
+            return Err(not_connected());
+        }
+
+        resp_rx.await.map_err(|_| not_connected())?
+    }
+}
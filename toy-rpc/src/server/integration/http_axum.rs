@@ -1,16 +1,20 @@
 //! Integration with `axum` using WebSocket
 
+use std::net::SocketAddr;
 use std::sync::atomic::Ordering;
 
 use axum::{
-    extract::ws::{WebSocket, WebSocketUpgrade},
+    extract::{
+        ws::{WebSocket, WebSocketUpgrade},
+        ConnectInfo,
+    },
     response::IntoResponse,
     routing::get,
     Extension, Router,
 };
 
 use crate::{
-    codec::DefaultCodec,
+    codec::{with_format, Codec},
     pubsub::{AckModeAuto, AckModeNone},
     server::Server,
     DEFAULT_RPC_PATH,
@@ -25,20 +29,37 @@ macro_rules! impl_http_axum_for_ack_modes {
                     ws: WebSocket,
                     state: Server<$ack_mode>
                 ) {
-                    let codec = DefaultCodec::with_axum_websocket(ws);
+                    Self::serve_axum_websocket(ws, state, None).await
+                }
+
+                /// Serves the websocket connection. The remote address is only known
+                /// if the app is served with `into_make_service_with_connect_info`
+                async fn serve_axum_websocket(
+                    ws: WebSocket,
+                    state: Server<$ack_mode>,
+                    remote_addr: Option<SocketAddr>,
+                ) {
                     let services = state.services.clone();
                     let client_id = state.client_counter.fetch_add(1, Ordering::Relaxed);
-                    let pubsub_broker = state.pubsub_tx.clone();
+                    let pubsub_broker = state.pubsub.sender.clone();
+                    let config = state.conn_config.clone();
 
-                    let fut = Self::start_broker_reader_writer(codec, services, client_id, pubsub_broker);
-                    fut.await.unwrap_or_else(|e| log::error!("{}", e));
+                    let ret = with_format!(config.format.unwrap_or_default(), F => {
+                        let codec = Codec::<_, _, _, F>::with_axum_websocket(ws);
+                        Self::start_broker_reader_writer(codec, services, client_id, remote_addr, config, pubsub_broker).await
+                    });
+                    ret.unwrap_or_else(|e| log::error!("{}", e));
                 }
 
                 async fn on_websocket_upgrade(
                     ws: WebSocketUpgrade,
+                    connect_info: Option<ConnectInfo<SocketAddr>>,
                     Extension(state): Extension<Server<$ack_mode>>,
                 ) -> impl IntoResponse {
-                    ws.on_upgrade(|websocket| Self::handle_axum_websocket(websocket, state))
+                    let remote_addr = connect_info.map(|ConnectInfo(addr)| addr);
+                    let max = state.conn_config.message_size_limit.max;
+                    let ws = ws.max_message_size(max).max_frame_size(max);
+                    ws.on_upgrade(move |websocket| Self::serve_axum_websocket(websocket, state, remote_addr))
                 }
 
                 /// Consumes `Server` and returns something that can nested in axum as a service
@@ -8,34 +8,15 @@ use crate::transport::ws::WebSocketConn;
 cfg_if! {
     if #[cfg(any(
         any(feature = "docs", doc),
-        all(
-            feature = "serde_bincode",
-            not(feature = "serde_json"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_cbor",
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_json",
-            not(feature = "serde_bincode"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_rmp",
-            not(feature = "serde_cbor"),
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-        ),
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_json",
+        feature = "serde_rmp",
+        feature = "serde_gob",
     ))] {
         use std::sync::atomic::Ordering;
 
-        use crate::codec::DefaultCodec;
+        use crate::codec::{with_format, Codec};
         use crate::DEFAULT_RPC_PATH;
         use crate::pubsub::{AckModeNone, AckModeAuto};
 
@@ -43,11 +24,12 @@ cfg_if! {
             ($($ack_mode:ty),*) => {
                 $(
                     /// The following impl block is controlled by feature flag. It is enabled
-                    /// if and only if **exactly one** of the the following feature flag is turned on
+                    /// if at least one of the the following feature flags is turned on
                     /// - `serde_bincode`
                     /// - `serde_json`
                     /// - `serde_cbor`
                     /// - `serde_rmp`
+                    /// - `serde_gob`
                     impl Server<$ack_mode> {
                         #[cfg(any(feature = "http_tide", feature = "docs"))]
                         #[cfg_attr(feature = "docs", doc(cfg(feature = "http_tide")))]
@@ -59,11 +41,12 @@ cfg_if! {
                         /// end of the nested `tide` endpoint.
                         ///
                         /// This is enabled
-                        /// if and only if **exactly one** of the the following feature flag is turned on
+                        /// if at least one of the the following feature flags is turned on
                         /// - `serde_bincode`
                         /// - `serde_json`
                         /// - `serde_cbor`
                         /// - `serde_rmp`
+                        /// - `serde_gob`
                         ///
                         /// # Example
                         ///
@@ -88,14 +71,18 @@ cfg_if! {
                                 .get(tide_ws::WebSocket::new(
                                     |req: tide::Request<Server<$ack_mode>>, ws_stream| async move {
                                         let ws_stream = WebSocketConn::new_without_sink(ws_stream);
-                                        let codec = DefaultCodec::with_tide_websocket(ws_stream);
                                         let services = req.state().services.clone();
                                         let client_id = req.state().client_counter.fetch_add(1, Ordering::Relaxed);
-                                        let pubsub_broker = req.state().pubsub_tx.clone();
+                                        let remote_addr = req.peer_addr().and_then(|addr| addr.parse().ok());
+                                        let pubsub_broker = req.state().pubsub.sender.clone();
+                                        let config = req.state().conn_config.clone();
 
-                                        let fut = Self::start_broker_reader_writer(codec, services, client_id, pubsub_broker);
+                                        let ret = with_format!(config.format.unwrap_or_default(), F => {
+                                            let codec = Codec::<_, _, _, F>::with_tide_websocket(ws_stream);
+                                            Self::start_broker_reader_writer(codec, services, client_id, remote_addr, config, pubsub_broker).await
+                                        });
                                         log::trace!("Client disconnected.");
-                                        fut.await?;
+                                        ret?;
                                         Ok(())
                                     },
                                 ));
@@ -130,11 +117,12 @@ cfg_if! {
                         /// | `http_axum` | [`into_boxed_route`](#method.into_boxed_route) |
                         ///
                         /// This is enabled
-                        /// if and only if **exactly one** of the the following feature flag is turned on
+                        /// if at least one of the the following feature flags is turned on
                         /// - `serde_bincode`
                         /// - `serde_json`
                         /// - `serde_cbor`
                         /// - `serde_rmp`
+                        /// - `serde_gob`
                         ///
                         /// # Example
                         ///
@@ -4,58 +4,50 @@ use cfg_if::cfg_if;
 cfg_if! {
     if #[cfg(any(
         any(feature = "docs", doc),
-        all(
-            feature = "serde_bincode",
-            not(feature = "serde_json"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_cbor",
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_json",
-            not(feature = "serde_bincode"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_rmp",
-            not(feature = "serde_cbor"),
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-        ),
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_json",
+        feature = "serde_rmp",
+        feature = "serde_gob",
     ))] {
+        use std::net::SocketAddr;
         use std::sync::{Arc, atomic::Ordering};
         use warp::{Filter, Reply, filters::BoxedFilter};
 
         use crate::{server::Server};
-        use crate::codec::DefaultCodec;
+        use crate::codec::{with_format, Codec};
         use crate::pubsub::{AckModeNone, AckModeAuto};
 
         macro_rules! impl_warp_integration_for_ack_modes {
             ($($ack_mode:ty),*) => {
                 $(
                     /// The following impl block is controlled by feature flag. It is enabled
-                    /// if and only if **exactly one** of the the following feature flag is turned on
+                    /// if at least one of the the following feature flags is turned on
                     /// - `serde_bincode`
                     /// - `serde_json`
                     /// - `serde_cbor`
                     /// - `serde_rmp`
+                    /// - `serde_gob`
                     impl Server<$ack_mode> {
                         /// WebSocket handler for integration with `warp`
-                        fn warp_websocket_handler(state: Arc<Self>, ws: warp::ws::Ws) -> impl warp::Reply {
-                            ws.on_upgrade(|websocket| async move {
-                                let codec = DefaultCodec::with_warp_websocket(websocket);
+                        fn warp_websocket_handler(
+                            state: Arc<Self>,
+                            remote_addr: Option<SocketAddr>,
+                            ws: warp::ws::Ws
+                        ) -> impl warp::Reply {
+                            let max = state.conn_config.message_size_limit.max;
+                            let ws = ws.max_message_size(max).max_frame_size(max);
+                            ws.on_upgrade(move |websocket| async move {
                                 let services = state.services.clone();
                                 let client_id = state.client_counter.fetch_add(1, Ordering::Relaxed);
-                                let pubsub_broker = state.pubsub_tx.clone();
+                                let pubsub_broker = state.pubsub.sender.clone();
+                                let config = state.conn_config.clone();
 
-                                let fut = Self::start_broker_reader_writer(codec, services, client_id, pubsub_broker);
-                                fut.await.unwrap_or_else(|e| log::error!("{}", e));
+                                let ret = with_format!(config.format.unwrap_or_default(), F => {
+                                    let codec = Codec::<_, _, _, F>::with_warp_websocket(websocket);
+                                    Self::start_broker_reader_writer(codec, services, client_id, remote_addr, config, pubsub_broker).await
+                                });
+                                ret.unwrap_or_else(|e| log::error!("{}", e));
                             })
                         }
 
@@ -85,6 +77,7 @@ cfg_if! {
 
                             let rpc_route = warp::path(Self::handler_path())
                                 .and(state)
+                                .and(warp::addr::remote())
                                 .and(warp::ws())
                                 .map(Self::warp_websocket_handler)
                                 .boxed();
@@ -119,11 +112,12 @@ cfg_if! {
                         /// | `http_axum` | [`into_boxed_route`](#method.into_boxed_route) |
                         ///
                         /// This is enabled
-                        /// if and only if **exactly one** of the the following feature flag is turned on
+                        /// if at least one of the the following feature flags is turned on
                         /// - `serde_bincode`
                         /// - `serde_json`
                         /// - `serde_cbor`
                         /// - `serde_rmp`
+                        /// - `serde_gob`
                         ///
                         /// # Example
                         ///
@@ -5,11 +5,20 @@
 use cfg_if::cfg_if;
 use std::{
     marker::PhantomData,
+    net::SocketAddr,
     sync::{atomic::AtomicU64, Arc},
+    time::Duration,
 };
 
 use crate::{pubsub::AckModeNone, service::AsyncServiceMap};
+pub use crate::service::DEFAULT_CANCELLATION_GRACE_PERIOD;
 use crate::pubsub::AckModeAuto;
+use crate::protocol::Capabilities;
+use crate::extension::ExtensionMap;
+use crate::compression::Compression;
+use crate::codec::Format;
+use crate::transport::MessageSizeLimit;
+use crate::queue::FullQueuePolicy;
 
 cfg_if! {
     if #[cfg(any(
@@ -17,14 +26,17 @@ cfg_if! {
         all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
         all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
     ))] {
-        use flume::Sender;
         mod integration;
         mod broker;
         mod reader;
         mod writer;
+        mod handle;
 
         pub mod pubsub;
-        use pubsub::{PubSubBroker, PubSubItem};
+        use pubsub::{PubSubBroker, PubSubItem, PubSubSender};
+        use crate::queue::QueueMetrics;
+        pub use handle::{ClientHandle, DEFAULT_REVERSE_CALL_TIMEOUT};
+        use handle::ClientHandleMap;
     }
 }
 
@@ -38,6 +50,67 @@ pub(crate) type AtomicClientId = AtomicU64;
 /// Remote client have their ID starting from `RESERVED_CLIENT_ID + 1`
 pub const RESERVED_CLIENT_ID: ClientId = 0;
 
+/// Hook that is called when a client connection completes the handshake
+pub type ConnectionHook = Arc<dyn Fn(&ConnectionInfo) + Send + Sync>;
+
+/// Information of a client connection that is passed to the connection hook
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    client_id: ClientId,
+    remote_addr: Option<SocketAddr>,
+    capabilities: Capabilities,
+}
+
+impl ConnectionInfo {
+    /// Id of the client
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Address of the client. This is `None` if the address is not known to the server,
+    /// ie. the connection is served with `serve_codec`
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Capabilities agreed upon by the server and the client in the handshake
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+}
+
+/// Capacities of the queues of a connection and the policy for a full queue
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct QueueConfig {
+    pub broker_capacity: usize,
+    pub writer_capacity: usize,
+    pub publication_capacity: usize,
+    pub policy: FullQueuePolicy,
+}
+
+/// Configuration that is shared by all connections of a server
+#[derive(Clone)]
+pub(crate) struct ConnectionConfig {
+    pub cancellation_grace_period: Duration,
+    pub on_connect: Option<ConnectionHook>,
+    pub extensions: Arc<ExtensionMap>,
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_miss_threshold: u32,
+    pub compression: Option<Compression>,
+    pub compression_threshold: usize,
+    pub max_frame_size: u32,
+    pub handshake_timeout: Duration,
+    pub message_size_limit: MessageSizeLimit,
+    pub queues: QueueConfig,
+    pub format: Option<Format>,
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub client_handles: ClientHandleMap,
+}
+
 /// RPC Server
 ///
 /// ```
@@ -47,32 +120,90 @@ pub const RESERVED_CLIENT_ID: ClientId = 0;
 pub struct Server<AckMode> {
     services: Arc<AsyncServiceMap>,
     client_counter: Arc<AtomicClientId>, // monotomically increase counter
+    conn_config: ConnectionConfig,
 
     #[cfg(any(
         feature = "docs",
         all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
         all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
     ))]
-    pubsub_tx: Sender<PubSubItem>,
+    pubsub: Arc<PubSubGuard>,
 
     ack_mode: PhantomData<AckMode>,
 }
 
-// Drop is implemented here because only **ONE** PubSub broker is available
-// on one server
+/// Stops the PubSub broker when it is dropped.
+///
+/// Only **ONE** PubSub broker is available on one server. The broker is shared
+/// by the clones of the server (eg. the ones held by the HTTP integrations), which
+/// share one guard, so the broker is only stopped when the last clone is dropped
+#[cfg(any(
+    feature = "docs",
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+pub(crate) struct PubSubGuard {
+    sender: PubSubSender,
+}
+
 #[cfg(any(
     feature = "docs",
     all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
     all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
 ))]
-impl<AckMode> Drop for Server<AckMode> {
+impl Drop for PubSubGuard {
     fn drop(&mut self) {
-        if let Err(err) = self.pubsub_tx.send(PubSubItem::Stop) {
+        if let Err(err) = self.sender.send(PubSubItem::Stop) {
             log::error!("{}", err);
         }
     }
 }
 
+#[cfg(any(
+    feature = "docs",
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+impl<AckMode> Server<AckMode> {
+    /// Returns the handle to a connected client, which can call the services that the
+    /// client registered with `ClientBuilder::register`. Returns `None` if no client with
+    /// the id is connected
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// if let Some(handle) = server.client(client_id) {
+    ///     let reply: String = handle.call("Agent.echo", "hello".to_string()).await?;
+    /// }
+    /// ```
+    pub fn client(&self, client_id: ClientId) -> Option<ClientHandle> {
+        let handles = self
+            .conn_config
+            .client_handles
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        handles.get(&client_id).cloned()
+    }
+
+    /// Returns the ids of the clients that are currently connected
+    pub fn client_ids(&self) -> Vec<ClientId> {
+        let handles = self
+            .conn_config
+            .client_handles
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let mut ids: Vec<ClientId> = handles.keys().cloned().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Returns the metrics of the queue of publications and work queue messages that wait
+    /// for the PubSub broker
+    pub fn pubsub_queue_metrics(&self) -> QueueMetrics {
+        self.pubsub.sender.metrics()
+    }
+}
+
 impl Server<AckModeNone> {
     /// Creates a `ServerBuilder`
     ///
@@ -100,7 +231,7 @@ cfg_if! {
         use tokio::io::{AsyncRead, AsyncWrite};
 
         #[cfg(feature = "ws_tokio")]
-        use async_tungstenite::{tokio::{accept_async}, WebSocketStream};
+        use async_tungstenite::{tokio::{accept_async_with_config}, WebSocketStream};
     } else if #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))] {
         #[cfg(feature = "tls")]
         use futures_rustls::{TlsAcceptor};
@@ -109,7 +240,7 @@ cfg_if! {
         use futures::io::{AsyncRead, AsyncWrite};
 
         #[cfg(feature = "ws_async_std")]
-        use async_tungstenite::{accept_async, WebSocketStream};
+        use async_tungstenite::{accept_async_with_config, WebSocketStream};
     }
 }
 
@@ -118,22 +249,11 @@ cfg_if! {
         feature = "docs",
         all(
             any(
-                all(
-                    feature = "serde_bincode",
-                    not(any(feature = "serde_json", feature = "serde_cbor", feature = "serde_rmp"))
-                ),
-                all(
-                    feature = "serde_cbor",
-                    not(any(feature = "serde_json", feature = "serde_bincode", feature = "serde_rmp")),
-                ),
-                all(
-                    feature = "serde_json",
-                    not(any(feature = "serde_bincode", feature = "serde_cbor", feature = "serde_rmp")),
-                ),
-                all(
-                    feature = "serde_rmp",
-                    not(any(feature = "serde_cbor", feature = "serde_json", feature = "serde_bincode")),
-                )
+                feature = "serde_bincode",
+                feature = "serde_cbor",
+                feature = "serde_json",
+                feature = "serde_rmp",
+                feature = "serde_gob"
             )
         )
     ))] {
@@ -143,30 +263,32 @@ cfg_if! {
         use futures::{StreamExt};
         use std::sync::atomic::Ordering;
 
-        use crate::{error::Error, codec::{split::SplittableCodec, DefaultCodec}};
+        use crate::{error::Error, codec::{split::SplittableCodec, with_format, Codec, CodecRead, CodecWrite}, compression::NegotiatedCompression, execution::timeout, keepalive, protocol::{handshake, FEATURE_CHUNKING, FEATURE_KEEPALIVE}};
 
         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
-        use crate::{transport::ws::WebSocketConn};
+        use crate::{transport::ws::{ws_config, WebSocketConn}};
 
         macro_rules! impl_server_for_ack_modes {
             ($($ack_mode:ty),*) => {
                 $(
                     /// The following impl block is controlled by feature flag. It is enabled
-                    /// if and only if **exactly one** of the the following feature flag is turned on
+                    /// if at least one of the the following feature flags is turned on
                     /// - `serde_bincode`
                     /// - `serde_json`
                     /// - `serde_cbor`
                     /// - `serde_rmp`
+                    /// - `serde_gob`
                     impl Server<$ack_mode> {
                         /// Accepts connections on an `tokio::net::TcpListener` and serves requests to default
                         /// server for each incoming connection
                         ///
                         /// This is enabled
-                        /// if and only if **exactly one** of the the following feature flag is turned on
+                        /// if at least one of the the following feature flags is turned on
                         /// - `serde_bincode`
                         /// - `serde_json`
                         /// - `serde_cbor`
                         /// - `serde_rmp`
+                        /// - `serde_gob`
                         ///
                         /// # Example
                         ///
@@ -181,6 +303,25 @@ cfg_if! {
                         ///
                         /// See `toy-rpc/examples/tokio_tcp/` for the example
                         pub async fn accept(&self, listener: TcpListener) -> Result<(), Error> {
+                            let format = self.conn_config.format.unwrap_or_default();
+                            self.accept_with_format(listener, format).await
+                        }
+
+                        /// Similar to `accept`, but the connections on the listener use `format`
+                        /// instead of the format set with `ServerBuilder::set_format`. The
+                        /// listeners of one server can use different formats.
+                        ///
+                        /// # Example
+                        ///
+                        /// ```rust
+                        /// let internal = tokio::net::TcpListener::bind(internal_addr).await.unwrap();
+                        /// let browser = tokio::net::TcpListener::bind(browser_addr).await.unwrap();
+                        /// tokio::try_join!(
+                        ///     server.accept_with_format(internal, Format::Bincode),
+                        ///     server.accept_with_format(browser, Format::Json),
+                        /// ).unwrap();
+                        /// ```
+                        pub async fn accept_with_format(&self, listener: TcpListener, format: Format) -> Result<(), Error> {
                             #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
                             let mut incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
                             #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
@@ -191,9 +332,9 @@ cfg_if! {
                                 log::info!("Accepting incoming connection from {}", stream.peer_addr()?);
 
                                 let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                                let pubsub_broker = self.pubsub_tx.clone();
+                                let pubsub_broker = self.pubsub.sender.clone();
                                 task::spawn(
-                                    Self::serve_tcp_connection(stream, self.services.clone(), client_id, pubsub_broker)
+                                    Self::serve_tcp_connection(stream, self.services.clone(), client_id, self.conn_config.clone(), pubsub_broker, format)
                                 );
                             }
 
@@ -219,9 +360,9 @@ cfg_if! {
                                 let acceptor = acceptor.clone();
 
                                 let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                                let pubsub_broker = self.pubsub_tx.clone();
+                                let pubsub_broker = self.pubsub.sender.clone();
                                 task::spawn(
-                                    Self::serve_tls_connection(stream, acceptor, self.services.clone(), client_id, pubsub_broker)
+                                    Self::serve_tls_connection(stream, acceptor, self.services.clone(), client_id, self.conn_config.clone(), pubsub_broker)
                                 );
                             }
 
@@ -232,11 +373,12 @@ cfg_if! {
                         /// requests using WebSocket transport protocol and the default codec.
                         ///
                         /// This is enabled
-                        /// if and only if **exactly one** of the the following feature flag is turned on
+                        /// if at least one of the the following feature flags is turned on
                         /// - `serde_bincode`
                         /// - `serde_json`
                         /// - `serde_cbor`
                         /// - `serde_rmp`
+                        /// - `serde_gob`
                         ///
                         /// # Example
                         ///
@@ -251,6 +393,15 @@ cfg_if! {
                         #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
                         #[cfg_attr(feature = "docs", doc(cfg(any(feature = "ws_tokio", feature = "ws_async_std"))))]
                         pub async fn accept_websocket(&self, listener: TcpListener) -> Result<(), Error> {
+                            let format = self.conn_config.format.unwrap_or_default();
+                            self.accept_websocket_with_format(listener, format).await
+                        }
+
+                        /// Similar to `accept_websocket`, but the connections on the listener use
+                        /// `format` instead of the format set with `ServerBuilder::set_format`
+                        #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
+                        #[cfg_attr(feature = "docs", doc(cfg(any(feature = "ws_tokio", feature = "ws_async_std"))))]
+                        pub async fn accept_websocket_with_format(&self, listener: TcpListener, format: Format) -> Result<(), Error> {
                             #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
                             let mut incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
                             #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
@@ -258,13 +409,15 @@ cfg_if! {
 
                             while let Some(conn) = incoming.next().await {
                                 let stream = conn?;
-                                log::info!("Accepting incoming connection from {}", stream.peer_addr()?);
+                                let peer_addr = stream.peer_addr()?;
+                                log::info!("Accepting incoming connection from {}", peer_addr);
 
                                 let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                                let pubsub_broker = self.pubsub_tx.clone();
-                                let ws_stream = accept_async(stream).await?;
+                                let pubsub_broker = self.pubsub.sender.clone();
+                                let config = ws_config(self.conn_config.message_size_limit.max);
+                                let ws_stream = accept_async_with_config(stream, Some(config)).await?;
                                 task::spawn(
-                                    Self::serve_ws_connection(ws_stream, self.services.clone(), client_id, pubsub_broker)
+                                    Self::serve_ws_connection(ws_stream, self.services.clone(), client_id, Some(peer_addr), self.conn_config.clone(), pubsub_broker, format)
                                 );
                             }
 
@@ -274,11 +427,12 @@ cfg_if! {
                         /// Serves a single connection using the default codec
                         ///
                         /// This is enabled
-                        /// if and only if **exactly one** of the the following feature flag is turned on
+                        /// if at least one of the the following feature flags is turned on
                         /// - `serde_bincode`
                         /// - `serde_json`
                         /// - `serde_cbor`
                         /// - `serde_rmp`
+                        /// - `serde_gob`
                         ///
                         /// Example
                         ///
@@ -305,8 +459,9 @@ cfg_if! {
                             T: AsyncRead + AsyncWrite + Send + Unpin + 'static
                         {
                             // let ret = serve_readwrite_stream(stream, self.services.clone()).await;
-                            let codec = DefaultCodec::new(stream);
-                            let ret = self.serve_codec(codec).await;
+                            let ret = with_format!(self.conn_config.format.unwrap_or_default(), F => {
+                                self.serve_codec(Codec::<_, _, _, F>::new(stream)).await
+                            });
                             log::info!("Client disconnected from stream");
                             ret
                         }
@@ -317,7 +472,7 @@ cfg_if! {
                         ///
                         /// ```rust
                         /// let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-                        /// let codec = toy_rpc::codec::Codec::new(stream);
+                        /// let codec = toy_rpc::codec::DefaultCodec::new(stream);
                         /// let server = Server::builder()
                         ///     .register(example_service)
                         ///     .build();
@@ -328,26 +483,92 @@ cfg_if! {
                             C: SplittableCodec + Send + 'static,
                         {
                             let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                            let pubsub_broker = self.pubsub_tx.clone();
-                            Self::start_broker_reader_writer(codec, self.services.clone(), client_id, pubsub_broker).await
+                            let pubsub_broker = self.pubsub.sender.clone();
+                            Self::start_broker_reader_writer(codec, self.services.clone(), client_id, None, self.conn_config.clone(), pubsub_broker).await
                         }
                     }
 
                     impl Server<$ack_mode> {
-                        pub(crate) async fn start_broker_reader_writer(
-                            codec: impl crate::codec::split::SplittableCodec + 'static,
+                        pub(crate) async fn start_broker_reader_writer<C>(
+                            codec: C,
                             services: Arc<AsyncServiceMap>,
                             client_id: ClientId,
-                            pubsub_tx: Sender<PubSubItem>,
-                        ) -> Result<(), crate::Error> {
-                            let (writer, reader) = codec.split();
-
-                            let reader = reader::ServerReader::new(reader, services);
-                            let writer = writer::ServerWriter::new(writer);
-                            let broker = broker::ServerBroker::<$ack_mode>::new(client_id, pubsub_tx);
+                            remote_addr: Option<SocketAddr>,
+                            config: ConnectionConfig,
+                            pubsub_tx: PubSubSender,
+                        ) -> Result<(), crate::Error>
+                        where
+                            C: SplittableCodec + 'static,
+                        {
+                            let (mut writer, mut reader) = codec.split();
+                            reader.set_size_limit(config.message_size_limit);
+
+                            let mut local = Capabilities::local(C::codec_name());
+                            local.max_frame_size = config.max_frame_size;
+                            let handshake = handshake(&mut writer, &mut reader, &local);
+                            let capabilities = match timeout(config.handshake_timeout, handshake).await {
+                                Some(result) => result,
+                                None => Err(Error::IoError(std::io::Error::new(
+                                    std::io::ErrorKind::TimedOut,
+                                    "The handshake is not completed within the timeout",
+                                ))),
+                            }
+                            .map_err(|err| {
+                                log::error!("Handshake with client {} failed: {}", client_id, err);
+                                err
+                            })?;
+                            log::debug!("Client {} connected with {:?}", client_id, capabilities);
+                            let compression = NegotiatedCompression::new(
+                                config.compression,
+                                config.compression_threshold,
+                                &capabilities,
+                            );
+                            writer.set_compression(compression);
+                            reader.set_compression(compression);
+                            if capabilities.supports(FEATURE_CHUNKING) {
+                                writer.set_max_frame_size(capabilities.max_frame_size as usize);
+                            }
+                            let keepalive = capabilities.supports(FEATURE_KEEPALIVE);
+                            if let Some(on_connect) = &config.on_connect {
+                                on_connect(&ConnectionInfo { client_id, remote_addr, capabilities });
+                            }
 
-                            let (broker_handle, _) = brw::spawn(broker, reader, writer);
+                            let (queues, publications) = broker::ConnectionQueues::new(config.queues);
+                            let reader =
+                                reader::ServerReader::new(reader, services, queues.broker.clone());
+                            let writer =
+                                writer::ServerWriter::new(writer, queues.writer.clone(), publications);
+                            let broker = broker::ServerBroker::<$ack_mode>::new(
+                                client_id,
+                                remote_addr,
+                                C::codec_name(),
+                                &config,
+                                pubsub_tx,
+                                queues.clone(),
+                            );
+
+                            let ids = broker.ids.clone();
+
+                            let (broker_handle, broker) = brw::spawn(broker, reader, writer);
+                            let handle = ClientHandle::new(client_id, broker.clone(), ids, queues);
+                            config.client_handles
+                                .lock()
+                                .unwrap_or_else(|err| err.into_inner())
+                                .insert(client_id, handle);
+                            if let Some(interval) = config.keepalive_interval {
+                                if keepalive {
+                                    keepalive::spawn_ticker(interval, broker, || {
+                                        broker::ServerBrokerItem::KeepaliveTick
+                                    });
+                                } else {
+                                    log::warn!("Client {} does not support keepalive", client_id);
+                                }
+                            }
                             let _ = broker_handle.await;
+                            config.client_handles
+                                .lock()
+                                .unwrap_or_else(|err| err.into_inner())
+                                .remove(&client_id);
                             Ok(())
                         }
 
@@ -357,13 +578,16 @@ cfg_if! {
                             acceptor: TlsAcceptor,
                             services: Arc<AsyncServiceMap>,
                             client_id: ClientId,
-                            pubsub_broker: Sender<PubSubItem>
+                            config: ConnectionConfig,
+                            pubsub_broker: PubSubSender
                         ) -> Result<(), Error> {
                             let peer_addr = stream.peer_addr()?;
                             let tls_stream = acceptor.accept(stream).await?;
                             // let ret = serve_readwrite_stream(tls_stream, services).await;
-                            let codec = DefaultCodec::new(tls_stream);
-                            let ret = Self::start_broker_reader_writer(codec, services, client_id, pubsub_broker).await;
+                            let ret = with_format!(config.format.unwrap_or_default(), F => {
+                                let codec = Codec::<_, _, _, F>::new(tls_stream);
+                                Self::start_broker_reader_writer(codec, services, client_id, Some(peer_addr), config, pubsub_broker).await
+                            });
                             log::info!("Client disconnected from {}", peer_addr);
                             ret
                         }
@@ -373,13 +597,17 @@ cfg_if! {
                             stream: TcpStream,
                             services: Arc<AsyncServiceMap>,
                             client_id: ClientId,
-                            pubsub_broker: Sender<PubSubItem>
+                            config: ConnectionConfig,
+                            pubsub_broker: PubSubSender,
+                            format: Format,
                         ) -> Result<(), Error> {
-                            let _peer_addr = stream.peer_addr()?;
+                            let peer_addr = stream.peer_addr()?;
                             // let ret = serve_readwrite_stream(stream, services, client_id, pubsub_broker);
-                            let codec = DefaultCodec::new(stream);
-                            let ret = Self::start_broker_reader_writer(codec, services, client_id, pubsub_broker).await;
-                            log::info!("Client disconnected from {}", _peer_addr);
+                            let ret = with_format!(format, F => {
+                                let codec = Codec::<_, _, _, F>::new(stream);
+                                Self::start_broker_reader_writer(codec, services, client_id, Some(peer_addr), config, pubsub_broker).await
+                            });
+                            log::info!("Client disconnected from {}", peer_addr);
                             ret
                         }
 
@@ -388,15 +616,21 @@ cfg_if! {
                             ws_stream: WebSocketStream<T>,
                             services: Arc<AsyncServiceMap>,
                             client_id: ClientId,
-                            pubsub_broker: Sender<PubSubItem>
+                            remote_addr: Option<SocketAddr>,
+                            config: ConnectionConfig,
+                            pubsub_broker: PubSubSender,
+                            format: Format,
                         )
                         where
                             T: futures::AsyncRead + futures::AsyncWrite + Send + Sync + Unpin + 'static,
                         {
                             let ws_stream = WebSocketConn::new(ws_stream);
-                            let codec = DefaultCodec::with_websocket(ws_stream);
+                            let ret = with_format!(format, F => {
+                                let codec = Codec::<_, _, _, F>::with_websocket(ws_stream);
+                                Self::start_broker_reader_writer(codec, services, client_id, remote_addr, config, pubsub_broker).await
+                            });
 
-                            if let Err(err) = Self::start_broker_reader_writer(codec, services, client_id, pubsub_broker).await {
+                            if let Err(err) = ret {
                                 log::error!("{}", err);
                             }
                             log::info!("Client disconnected from WebSocket connection");
@@ -0,0 +1,139 @@
+//! Consumer on the server side
+
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use flume::{r#async::RecvStream, Sender};
+use futures::Stream;
+
+use crate::{
+    codec::{DefaultCodec, Reserved, Unmarshal},
+    error::Error,
+    pubsub::{AckModeAuto, AckModeNone, SeqId, Topic},
+    server::{broker::ServerBrokerItem, Server, RESERVED_CLIENT_ID},
+};
+
+use super::{PubSubItem, PubSubResponder, PubSubSender};
+
+/// Delivery of a work queue message on the server side
+///
+/// The message is delivered again if it is not acked before the visibility timeout
+pub struct Delivery<Item> {
+    seq_id: SeqId,
+    pubsub_tx: PubSubSender,
+    item: Item,
+}
+
+impl<Item> Delivery<Item> {
+    /// Acks the work queue message
+    pub async fn ack(self) -> Result<Item, Error> {
+        self.pubsub_tx
+            .send_async(PubSubItem::Ack {
+                seq_id: self.seq_id,
+                client_id: RESERVED_CLIENT_ID,
+            })
+            .await?;
+        Ok(self.item)
+    }
+}
+
+/// Consumer of a work queue on the server side
+///
+/// All consumers on the server count as the same consumer, so the server receives
+/// at most one ticket of each message.
+#[pin_project::pin_project]
+pub struct Consumer<T: Topic, C: Unmarshal> {
+    #[pin]
+    inner: RecvStream<'static, ServerBrokerItem>,
+    sender: Sender<ServerBrokerItem>,
+    pubsub_tx: PubSubSender,
+    pulling: bool,
+    marker: PhantomData<T>,
+    codec: PhantomData<C>,
+}
+
+impl<T: Topic, C: Unmarshal> Consumer<T, C> {
+    fn new(pubsub_tx: PubSubSender) -> Self {
+        // one message is pulled at a time
+        let (sender, rx) = flume::bounded(1);
+        Self {
+            inner: rx.into_stream(),
+            sender,
+            pubsub_tx,
+            pulling: false,
+            marker: PhantomData,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<T: Topic, C: Unmarshal> Stream for Consumer<T, C> {
+    type Item = Result<Delivery<T::Item>, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        // Pulls one message at a time
+        if !*this.pulling {
+            let item = PubSubItem::Consume {
+                client_id: RESERVED_CLIENT_ID,
+                topic: T::topic(),
+                codec: None,
+                sender: PubSubResponder::Sender(this.sender.clone()),
+            };
+            if let Err(err) = this.pubsub_tx.send(item) {
+                return Poll::Ready(Some(Err(err.into())));
+            }
+            *this.pulling = true;
+        }
+
+        match this.inner.poll_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(opt) => match opt {
+                Some(ServerBrokerItem::Delivery {
+                    seq_id,
+                    topic: _,
+                    content,
+                }) => {
+                    *this.pulling = false;
+                    let pubsub_tx = this.pubsub_tx.clone();
+                    let result = content.decode().map(|item| Delivery {
+                        seq_id,
+                        pubsub_tx,
+                        item,
+                    });
+                    Poll::Ready(Some(result))
+                }
+                Some(_) => {
+                    let result = Err(Error::Internal("Invalid work queue item".into()));
+                    Poll::Ready(Some(result))
+                }
+                None => Poll::Ready(None),
+            },
+        }
+    }
+}
+
+type PhantomCodec = DefaultCodec<Reserved, Reserved, Reserved>;
+
+macro_rules! impl_server_queue_for_ack_modes {
+    ($($ack_mode:ty),*) => {
+        $(
+            impl Server<$ack_mode> {
+                /// Creates a new consumer on a work queue
+                ///
+                /// The consumer pulls one message at a time, and each message must be
+                /// acked with `Delivery::ack`. The messages are decoded with the format of
+                /// their producers.
+                pub fn consumer<T: Topic>(&self) -> Consumer<T, PhantomCodec> {
+                    Consumer::new(self.pubsub.sender.clone())
+                }
+            }
+        )*
+    }
+}
+
+impl_server_queue_for_ack_modes!(AckModeNone, AckModeAuto);
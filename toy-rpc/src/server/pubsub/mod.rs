@@ -1,25 +1,163 @@
 //! PubSub impl on the server side
 
 use flume::{Receiver, Sender};
+use futures::future::{self, Either};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::marker::PhantomData;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use crate::message::{AtomicMessageId, MessageId};
 use crate::pubsub::{AckModeAuto, AckModeNone, SeqId};
+use crate::queue::{
+    FullQueuePolicy, PushError, QueueGauge, QueueMetrics, QueueReceiver, QueueSender,
+};
 use crate::Error;
 
 use super::{broker::ServerBrokerItem, ClientId};
 
+mod queue;
+use queue::{InFlight, WorkQueue};
+
 #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
 use async_std::task;
 #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
 use tokio::task;
 
 pub(crate) enum PubSubResponder {
+    /// The broker of a client connection or a consumer on the server, which receives
+    /// work queue deliveries
     Sender(Sender<ServerBrokerItem>),
+    /// The queue of a subscriber on the server
+    Local(QueueSender<Publication>),
+    /// The publication queue of a client connection along with its broker, which is told
+    /// when publications are waiting
+    Client {
+        queue: ClientPublications,
+        broker: Sender<ServerBrokerItem>,
+        /// Name of the format of the connection
+        codec: &'static str,
+    },
+}
+
+impl PubSubResponder {
+    /// Whether the subscriber can decode a publication in the format named `codec`. The
+    /// subscribers on the server decode every format
+    fn accepts(&self, codec: &str) -> bool {
+        match self {
+            Self::Client { codec: own, .. } => *own == codec,
+            Self::Sender(_) | Self::Local(_) => true,
+        }
+    }
+}
+
+/// Body of a publication or a work queue message
+pub(crate) struct Content {
+    /// Name of the format of the publisher, which the body is encoded with
+    pub codec: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// A publication to a subscriber
+pub(crate) struct Publication {
+    pub seq_id: SeqId,
+    pub topic: String,
+    pub content: Arc<Content>,
+}
+
+/// Sending half of the publication queue of a client connection, which is shared by all
+/// the topics the client subscribes to
+#[derive(Clone)]
+pub(crate) struct ClientPublications {
+    queue: QueueSender<Publication>,
+    /// Set once the broker is told that publications are waiting, and cleared by the writer
+    /// before it takes them from the queue
+    notified: Arc<AtomicBool>,
+}
+
+impl ClientPublications {
+    pub fn metrics(&self) -> QueueMetrics {
+        self.queue.metrics()
+    }
+}
+
+/// Receiving half of the publication queue of a client connection, which is drained by
+/// the writer
+pub(crate) struct ClientPublicationsReceiver {
+    queue: QueueReceiver<Publication>,
+    notified: Arc<AtomicBool>,
+}
+
+impl ClientPublicationsReceiver {
+    /// Takes the publications that are waiting. Publications that arrive afterwards make
+    /// the broker notify the writer again
+    pub fn take(&self) -> Vec<Publication> {
+        self.notified.store(false, Ordering::SeqCst);
+        (0..self.queue.len())
+            .map_while(|_| self.queue.try_recv())
+            .collect()
+    }
+}
+
+/// Creates the publication queue of a client connection
+pub(crate) fn client_publications(
+    capacity: usize,
+    policy: FullQueuePolicy,
+) -> (ClientPublications, ClientPublicationsReceiver) {
+    let (queue, rx) = crate::queue::channel(Some(capacity), policy);
+    let notified = Arc::new(AtomicBool::new(false));
+    (
+        ClientPublications {
+            queue,
+            notified: notified.clone(),
+        },
+        ClientPublicationsReceiver {
+            queue: rx,
+            notified,
+        },
+    )
+}
+
+/// Sending half of the PubSub broker
+///
+/// Publications and work queue messages wait for room in a bounded queue, so publishers
+/// are slowed down once the broker falls behind. The other messages, ie. subscriptions,
+/// acks and pulls, go through an unbounded channel and are never held back. Some of them
+/// are sent when a subscriber is dropped, and the broker of a connection that waited for
+/// them would stop forwarding the publications that the PubSub broker may be waiting on.
+#[derive(Clone)]
+pub(crate) struct PubSubSender {
+    publish: Sender<PubSubItem>,
+    control: Sender<PubSubItem>,
+    gauge: Arc<QueueGauge>,
+}
+
+impl PubSubSender {
+    pub async fn send_async(&self, item: PubSubItem) -> Result<(), Error> {
+        match item {
+            PubSubItem::Publish { .. } | PubSubItem::Produce { .. } => {
+                self.publish.send_async(item).await?;
+                self.gauge.set_depth(self.publish.len());
+            }
+            _ => self.control.send_async(item).await?,
+        }
+        Ok(())
+    }
+
+    /// Sends a message other than a publication without waiting
+    pub fn send(&self, item: PubSubItem) -> Result<(), Error> {
+        self.control.send(item).map_err(Into::into)
+    }
+
+    /// Sender of the publications and work queue messages
+    pub fn publisher(&self) -> Sender<PubSubItem> {
+        self.publish.clone()
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.gauge.metrics()
+    }
 }
 
 pub(crate) enum PubSubItem {
@@ -28,14 +166,14 @@ pub(crate) enum PubSubItem {
         client_id: ClientId,
         msg_id: MessageId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     },
     PublishRetry {
         count: u32,
         client_ids: BTreeSet<ClientId>,
         seq_id: SeqId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     },
     RemovePendingAcks {
         seq_id: SeqId,
@@ -53,6 +191,30 @@ pub(crate) enum PubSubItem {
         seq_id: SeqId,
         client_id: ClientId,
     },
+    /// A new message for a work queue
+    Produce {
+        client_id: ClientId,
+        msg_id: MessageId,
+        topic: String,
+        tickets: u32,
+        content: Arc<Content>,
+    },
+    /// A consumer pulls one message from a work queue
+    Consume {
+        client_id: ClientId,
+        topic: String,
+        /// Name of the format of the consumer, or `None` if it decodes every format
+        codec: Option<&'static str>,
+        sender: PubSubResponder,
+    },
+    /// The visibility timeout of a work queue delivery is reached
+    Redeliver {
+        seq_id: SeqId,
+    },
+    /// The connection of a client is closed
+    RemoveClient {
+        client_id: ClientId,
+    },
     Stop,
 }
 
@@ -60,34 +222,82 @@ pub(crate) struct PubSubBroker<AckMode> {
     seq_counter: AtomicMessageId,
     pubsub_tx: Sender<PubSubItem>,
     listener: Receiver<PubSubItem>,
+    publications: Receiver<PubSubItem>,
+    gauge: Arc<QueueGauge>,
     subscriptions: HashMap<String, BTreeMap<ClientId, PubSubResponder>>,
     pending_acks: BTreeMap<SeqId, Sender<ClientId>>,
     pub_retry_timeout: Duration,
     max_num_retries: u32,
+    queues: HashMap<String, WorkQueue>,
+    in_flight: BTreeMap<SeqId, InFlight>,
+    visibility_timeout: Duration,
     ack_mode: PhantomData<AckMode>,
 }
 
 impl<AckMode: Send + 'static> PubSubBroker<AckMode> {
-    pub fn new(retry_timeout: Duration, max_num_retries: u32) -> (Self, Sender<PubSubItem>) {
+    pub fn new(
+        retry_timeout: Duration,
+        max_num_retries: u32,
+        visibility_timeout: Duration,
+        capacity: usize,
+    ) -> (Self, PubSubSender) {
         let (pubsub_tx, listener) = flume::unbounded();
+        let (publish, publications) = flume::bounded(capacity.max(1));
+        let gauge = Arc::new(QueueGauge::new(capacity));
+        let sender = PubSubSender {
+            publish,
+            control: pubsub_tx.clone(),
+            gauge: gauge.clone(),
+        };
         (
             Self {
                 seq_counter: AtomicMessageId::new(0),
-                pubsub_tx: pubsub_tx.clone(),
+                pubsub_tx,
                 listener,
+                publications,
+                gauge,
                 subscriptions: HashMap::new(),
                 pending_acks: BTreeMap::new(),
                 pub_retry_timeout: retry_timeout,
                 max_num_retries,
+                queues: HashMap::new(),
+                in_flight: BTreeMap::new(),
+                visibility_timeout,
                 ack_mode: PhantomData,
             },
-            pubsub_tx,
+            sender,
         )
     }
 
+    /// Waits for the next message. The control messages are taken before the publications
+    async fn next_item(&self) -> Option<PubSubItem> {
+        if let Ok(item) = self.listener.try_recv() {
+            return Some(item);
+        }
+        let item = match future::select(self.listener.recv_async(), self.publications.recv_async())
+            .await
+        {
+            Either::Left((item, _)) => item.ok(),
+            // the senders of publications are dropped along with the server
+            Either::Right((Err(_), control)) => control.await.ok(),
+            Either::Right((item, _)) => item.ok(),
+        };
+        self.gauge.set_depth(self.publications.len());
+        item
+    }
+
+    fn next_seq_id(&self) -> SeqId {
+        // Skips the sequence ids that are still waiting for Acks in case the counter wraps around
+        loop {
+            let seq_id = SeqId::new(self.seq_counter.fetch_add(1, Ordering::Relaxed));
+            if !self.pending_acks.contains_key(&seq_id) && !self.in_flight.contains_key(&seq_id) {
+                return seq_id;
+            }
+        }
+    }
+
     fn seq_id(&mut self, client_id: &ClientId, msg_id: &MessageId) -> SeqId {
-        let seq_id = self.seq_counter.fetch_add(1, Ordering::Relaxed);
-        let seq_id = SeqId::new(seq_id);
+        let seq_id = self.next_seq_id();
         log::info!(
             "{:?} assigned to Publish message {} from client {}",
             &seq_id,
@@ -97,17 +307,67 @@ impl<AckMode: Send + 'static> PubSubBroker<AckMode> {
         seq_id
     }
 
-    pub fn handle_publish_inner(&mut self, seq_id: SeqId, topic: &String, content: Arc<Vec<u8>>) {
-        if let Some(entry) = self.subscriptions.get_mut(topic) {
-            entry.retain(|_, responder| {
-                let msg = ServerBrokerItem::Publication {
-                    seq_id: seq_id.clone(),
-                    topic: topic.clone(),
-                    content: content.clone(),
-                };
+    /// Publishes to the subscribers of the topic that use the format of the publisher, and
+    /// returns them
+    pub async fn handle_publish_inner(
+        &mut self,
+        seq_id: SeqId,
+        topic: &String,
+        content: Arc<Content>,
+    ) -> BTreeSet<ClientId> {
+        let client_ids: BTreeSet<ClientId> = match self.subscriptions.get(topic) {
+            Some(entry) => entry
+                .iter()
+                .filter(|(client_id, responder)| {
+                    let accepted = responder.accepts(content.codec);
+                    if !accepted {
+                        log::error!(
+                            "{:?} on topic {} is not sent to client {}, which does not use {}",
+                            &seq_id,
+                            topic,
+                            client_id,
+                            content.codec
+                        );
+                    }
+                    accepted
+                })
+                .map(|(client_id, _)| *client_id)
+                .collect(),
+            None => return BTreeSet::new(),
+        };
+        for client_id in client_ids.iter() {
+            self.publish_to(*client_id, seq_id.clone(), topic, content.clone())
+                .await;
+        }
+        client_ids
+    }
 
-                send_broker_item_publication(responder, msg)
-            })
+    /// Queues a publication for a subscriber, which is removed from the subscriptions if
+    /// it is disconnected or too slow
+    async fn publish_to(
+        &mut self,
+        client_id: ClientId,
+        seq_id: SeqId,
+        topic: &String,
+        content: Arc<Content>,
+    ) {
+        let responder = match self
+            .subscriptions
+            .get(topic)
+            .and_then(|e| e.get(&client_id))
+        {
+            Some(responder) => responder,
+            None => return,
+        };
+        let publication = Publication {
+            seq_id,
+            topic: topic.clone(),
+            content,
+        };
+        if !send_publication(responder, publication).await {
+            if let Some(entry) = self.subscriptions.get_mut(topic) {
+                entry.remove(&client_id);
+            }
         }
     }
 
@@ -117,7 +377,7 @@ impl<AckMode: Send + 'static> PubSubBroker<AckMode> {
         set: BTreeSet<ClientId>,
         topic: String,
         seq_id: SeqId,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     ) {
         #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
         use async_std::future::timeout;
@@ -172,22 +432,18 @@ impl<AckMode: Send + 'static> PubSubBroker<AckMode> {
         set: BTreeSet<ClientId>,
         seq_id: SeqId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     ) {
         log::debug!("Retry publish");
+        // A new entry is inserted if the message is published again
+        self.pending_acks.remove(&seq_id);
         if count < self.max_num_retries {
             count += 1;
 
-            if let Some(entry) = self.subscriptions.get_mut(&topic) {
+            if self.subscriptions.contains_key(&topic) {
                 for client_id in set.iter() {
-                    let msg = ServerBrokerItem::Publication {
-                        seq_id: seq_id.clone(),
-                        topic: topic.clone(),
-                        content: content.clone(),
-                    };
-                    if let Some(responder) = entry.get_mut(&client_id) {
-                        send_broker_item_publication(responder, msg);
-                    }
+                    self.publish_to(*client_id, seq_id.clone(), &topic, content.clone())
+                        .await;
                 }
 
                 self.spawn_timed_task_waiting_for_acks(count, set, topic, seq_id, content)
@@ -221,12 +477,25 @@ impl<AckMode: Send + 'static> PubSubBroker<AckMode> {
         }
     }
 
+    /// Removes a disconnected client from all the subscriptions and work queues
+    pub fn handle_remove_client(&mut self, client_id: ClientId) {
+        log::debug!("Removing client {} from subscriptions", client_id);
+        for entry in self.subscriptions.values_mut() {
+            entry.remove(&client_id);
+        }
+        self.subscriptions.retain(|_, entry| !entry.is_empty());
+        self.remove_consumer(client_id);
+    }
+
     pub async fn handle_ack(&mut self, seq_id: SeqId, client_id: ClientId) {
         log::debug!(
             "Received Ack for seq_id: {:?} from client {:?}",
             &seq_id,
             &client_id
         );
+        if self.handle_delivery_ack(&seq_id, client_id) {
+            return;
+        }
         if let Some(sender) = self.pending_acks.get_mut(&seq_id) {
             sender.send_async(client_id).await.unwrap_or_else(|_| {
                 log::error!("Pending Ack entry for seq_id: {:?} is not found", seq_id)
@@ -236,31 +505,32 @@ impl<AckMode: Send + 'static> PubSubBroker<AckMode> {
 }
 
 impl PubSubBroker<AckModeNone> {
-    pub fn handle_publish(
+    pub async fn handle_publish(
         &mut self,
         client_id: ClientId,
         msg_id: MessageId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     ) {
         let seq_id = self.seq_id(&client_id, &msg_id);
-        self.handle_publish_inner(seq_id, &topic, content)
+        self.handle_publish_inner(seq_id, &topic, content).await;
     }
 }
 
 impl PubSubBroker<AckModeAuto> {
-    pub fn handle_publish(
+    pub async fn handle_publish(
         &mut self,
         client_id: ClientId,
         msg_id: MessageId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     ) {
         let seq_id = self.seq_id(&client_id, &msg_id);
         let count = 0;
-        self.handle_publish_inner(seq_id.clone(), &topic, content.clone());
-        if let Some(entry) = self.subscriptions.get(&topic) {
-            let set: BTreeSet<ClientId> = entry.keys().map(|val| *val).collect();
+        let set = self
+            .handle_publish_inner(seq_id.clone(), &topic, content.clone())
+            .await;
+        if !set.is_empty() {
             self.spawn_timed_task_waiting_for_acks(count, set, topic, seq_id, content)
         }
     }
@@ -280,7 +550,7 @@ macro_rules! impl_pubsub_broker_for_ack_modes {
                 }
 
                 pub async fn pubsub_loop(mut self) {
-                    while let Ok(item) = self.listener.recv_async().await {
+                    while let Some(item) = self.next_item().await {
                         match item {
                             PubSubItem::Publish {
                                 client_id,
@@ -288,7 +558,7 @@ macro_rules! impl_pubsub_broker_for_ack_modes {
                                 topic,
                                 content,
                             } => {
-                                self.handle_publish(client_id, msg_id, topic, content)
+                                self.handle_publish(client_id, msg_id, topic, content).await
                             },
                             PubSubItem::PublishRetry {
                                 count,
@@ -316,6 +586,29 @@ macro_rules! impl_pubsub_broker_for_ack_modes {
                             PubSubItem::Ack{seq_id, client_id} => {
                                 self.handle_ack(seq_id, client_id).await
                             },
+                            PubSubItem::Produce {
+                                client_id,
+                                msg_id,
+                                topic,
+                                tickets,
+                                content,
+                            } => {
+                                self.handle_produce(client_id, msg_id, topic, tickets, content)
+                            },
+                            PubSubItem::Consume {
+                                client_id,
+                                topic,
+                                codec,
+                                sender,
+                            } => {
+                                self.handle_consume(client_id, topic, codec, sender)
+                            },
+                            PubSubItem::Redeliver { seq_id } => {
+                                self.handle_redeliver(seq_id)
+                            },
+                            PubSubItem::RemoveClient { client_id } => {
+                                self.handle_remove_client(client_id)
+                            },
                             PubSubItem::Stop => return,
                         }
                     }
@@ -327,7 +620,8 @@ macro_rules! impl_pubsub_broker_for_ack_modes {
 
 impl_pubsub_broker_for_ack_modes!(AckModeNone, AckModeAuto);
 
-fn send_broker_item_publication(responder: &mut PubSubResponder, msg: ServerBrokerItem) -> bool {
+/// Sends a work queue delivery. Returns `false` if the consumer is disconnected
+fn send_broker_item(responder: &mut PubSubResponder, msg: ServerBrokerItem) -> bool {
     match responder {
         PubSubResponder::Sender(tx) => {
             if let Err(err) = tx.try_send(msg) {
@@ -337,10 +631,49 @@ fn send_broker_item_publication(responder: &mut PubSubResponder, msg: ServerBrok
                 }
             }
         }
+        PubSubResponder::Local(_) | PubSubResponder::Client { .. } => {
+            log::error!("Work queue delivery is sent to a subscriber");
+            return false;
+        }
     }
     true
 }
 
+/// Queues a publication according to the `FullQueuePolicy` of the subscriber. Returns
+/// `false` if the subscriber is gone or is too slow and gets disconnected
+async fn send_publication(responder: &PubSubResponder, publication: Publication) -> bool {
+    let result = match responder {
+        PubSubResponder::Local(queue) => queue.push(publication).await,
+        PubSubResponder::Client { queue, broker, .. } => {
+            let result = queue.queue.push(publication).await;
+            // the broker is told only once until the writer takes the publications
+            let notify = result.is_ok() && !queue.notified.swap(true, Ordering::SeqCst);
+            if notify && broker.try_send(ServerBrokerItem::Publications).is_err() {
+                return false;
+            }
+            result
+        }
+        PubSubResponder::Sender(_) => {
+            log::error!("Publication is sent to a work queue consumer");
+            return false;
+        }
+    };
+    match result {
+        Ok(()) => true,
+        Err(PushError::Full) => {
+            log::error!("Subscriber is too slow, disconnecting");
+            if let PubSubResponder::Client { broker, .. } = responder {
+                let _ = broker.try_send(ServerBrokerItem::Stopping);
+            }
+            false
+        }
+        Err(PushError::Closed) => {
+            log::error!("Client is disconnected, removing from subscriptions");
+            false
+        }
+    }
+}
+
 /* -------------------------------------------------------------------------- */
 /*                                 Public API                                 */
 /* -------------------------------------------------------------------------- */
@@ -348,33 +681,209 @@ fn send_broker_item_publication(responder: &mut PubSubResponder, msg: ServerBrok
 cfg_if::cfg_if! {
     if #[cfg(any(
         any(feature = "docs", doc),
-        all(
-            feature = "serde_bincode",
-            not(feature = "serde_json"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_cbor",
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_json",
-            not(feature = "serde_bincode"),
-            not(feature = "serde_cbor"),
-            not(feature = "serde_rmp"),
-        ),
-        all(
-            feature = "serde_rmp",
-            not(feature = "serde_cbor"),
-            not(feature = "serde_json"),
-            not(feature = "serde_bincode"),
-        ),
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_json",
+        feature = "serde_rmp",
+        feature = "serde_gob",
     ))] {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use crate::codec::{with_format, Format, Marshal, Unmarshal};
+
     pub mod publisher;
 
     pub mod subscriber;
+
+    pub mod producer;
+
+    pub mod consumer;
+
+    impl Content {
+        /// Encodes an item with the format of the publisher on the server
+        fn encode<T: Serialize>(format: Format, item: &T) -> Result<Self, Error> {
+            let bytes = with_format!(format, F => F::marshal(item))?;
+            Ok(Self {
+                codec: format.name(),
+                bytes,
+            })
+        }
+
+        /// Decodes the body with the format of its publisher, so that the subscribers on the
+        /// server can decode the publications of the clients using every format
+        fn decode<T: DeserializeOwned>(&self) -> Result<T, Error> {
+            match Format::from_name(self.codec) {
+                Some(format) => {
+                    with_format!(format, F => F::unmarshal(&self.bytes)).map_err(Into::into)
+                }
+                None => Err(Error::Internal(
+                    format!("{} is not an enabled format", self.codec).into(),
+                )),
+            }
+        }
+    }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(bytes: Vec<u8>) -> Arc<Content> {
+        Arc::new(Content {
+            codec: "json",
+            bytes,
+        })
+    }
+
+    #[test]
+    fn seq_id_skips_pending_acks_after_wraparound() {
+        let (mut broker, _tx) = PubSubBroker::<AckModeAuto>::new(
+            Duration::from_secs(1),
+            3,
+            Duration::from_secs(1),
+            crate::queue::DEFAULT_QUEUE_CAPACITY,
+        );
+        broker.seq_counter = AtomicMessageId::new(MessageId::MAX);
+        let (ack_tx, _ack_rx) = flume::unbounded();
+        broker.pending_acks.insert(SeqId::new(0), ack_tx);
+
+        assert_eq!(broker.seq_id(&1, &0), SeqId::new(MessageId::MAX));
+        // seq id 0 is still waiting for Acks
+        assert_eq!(broker.seq_id(&1, &1), SeqId::new(1));
+
+        broker.pending_acks.remove(&SeqId::new(0));
+        broker.seq_counter = AtomicMessageId::new(0);
+        assert_eq!(broker.seq_id(&1, &2), SeqId::new(0));
+    }
+
+    #[test]
+    fn removed_client_is_dropped_from_subscriptions_and_queues() {
+        let (mut broker, _tx) = PubSubBroker::<AckModeNone>::new(
+            Duration::from_secs(1),
+            3,
+            Duration::from_secs(1),
+            crate::queue::DEFAULT_QUEUE_CAPACITY,
+        );
+        let (sub_tx, _sub_rx) = flume::unbounded();
+        broker.handle_subscribe(1, "topic".into(), PubSubResponder::Sender(sub_tx.clone()));
+        broker.handle_subscribe(2, "topic".into(), PubSubResponder::Sender(sub_tx.clone()));
+        broker.handle_consume(1, "jobs".into(), None, PubSubResponder::Sender(sub_tx));
+
+        broker.handle_remove_client(1);
+        let subscribers: Vec<ClientId> = broker.subscriptions["topic"].keys().copied().collect();
+        assert_eq!(subscribers, vec![2]);
+        assert!(broker.queues["jobs"].pulls.is_empty());
+    }
+
+    #[test]
+    fn message_with_more_tickets_than_consumers_is_removed() {
+        // the visibility timers are spawned on the runtime
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        let _guard = rt.enter();
+
+        let (mut broker, _tx) = PubSubBroker::<AckModeNone>::new(
+            Duration::from_secs(1),
+            3,
+            Duration::from_secs(60),
+            crate::queue::DEFAULT_QUEUE_CAPACITY,
+        );
+        let consume = |broker: &mut PubSubBroker<AckModeNone>, client_id: ClientId| {
+            let (tx, rx) = flume::unbounded();
+            broker.handle_consume(client_id, "jobs".into(), None, PubSubResponder::Sender(tx));
+            match rx.try_recv() {
+                Ok(ServerBrokerItem::Delivery { seq_id, .. }) => Some(seq_id),
+                _ => None,
+            }
+        };
+
+        broker.handle_produce(1, 0, "jobs".into(), 3, content(vec![7]));
+        let first = consume(&mut broker, 1).expect("Expecting a delivery");
+        let second = consume(&mut broker, 2).expect("Expecting a delivery");
+        // a consumer does not get a second ticket
+        assert!(consume(&mut broker, 1).is_none());
+        assert!(broker.handle_delivery_ack(&first, 1));
+        assert_eq!(broker.queues["jobs"].len(), 1);
+        assert!(broker.handle_delivery_ack(&second, 2));
+        // every consumer has the message, and the last ticket is never delivered
+        assert!(broker.queues["jobs"].len() == 0);
+
+        // a consumer that disconnects with a delivery in flight does not hold back the others
+        broker.handle_produce(1, 1, "jobs".into(), 3, content(vec![8]));
+        let first = consume(&mut broker, 1).expect("Expecting a delivery");
+        let _unacked = consume(&mut broker, 2).expect("Expecting a delivery");
+        assert!(broker.handle_delivery_ack(&first, 1));
+        broker.handle_remove_client(2);
+        assert!(broker.queues["jobs"].len() == 0);
+        assert!(broker.in_flight.is_empty());
+    }
+
+    #[test]
+    fn publication_is_only_sent_to_subscribers_using_its_format() {
+        let (mut broker, _tx) = PubSubBroker::<AckModeNone>::new(
+            Duration::from_secs(1),
+            3,
+            Duration::from_secs(1),
+            crate::queue::DEFAULT_QUEUE_CAPACITY,
+        );
+        let mut receivers = Vec::new();
+        for (client_id, codec) in [(1, "json"), (2, "bincode")] {
+            let (queue, rx) = client_publications(4, FullQueuePolicy::Block);
+            let (broker_tx, broker_rx) = flume::unbounded();
+            let sender = PubSubResponder::Client {
+                queue,
+                broker: broker_tx,
+                codec,
+            };
+            broker.handle_subscribe(client_id, "topic".into(), sender);
+            receivers.push((rx, broker_rx));
+        }
+
+        let client_ids = futures::executor::block_on(broker.handle_publish_inner(
+            SeqId::new(0),
+            &"topic".into(),
+            content(vec![7]),
+        ));
+        assert_eq!(client_ids.into_iter().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(receivers[0].0.take().len(), 1);
+        assert!(receivers[1].0.take().is_empty());
+    }
+
+    #[test]
+    fn message_is_only_delivered_to_consumers_using_its_format() {
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        let _guard = rt.enter();
+
+        let (mut broker, _tx) = PubSubBroker::<AckModeNone>::new(
+            Duration::from_secs(1),
+            3,
+            Duration::from_secs(60),
+            crate::queue::DEFAULT_QUEUE_CAPACITY,
+        );
+        let (bincode_tx, bincode_rx) = flume::unbounded();
+        broker.handle_consume(
+            1,
+            "jobs".into(),
+            Some("bincode"),
+            PubSubResponder::Sender(bincode_tx),
+        );
+        broker.handle_produce(2, 0, "jobs".into(), 1, content(vec![7]));
+        assert!(bincode_rx.try_recv().is_err());
+
+        let (json_tx, json_rx) = flume::unbounded();
+        broker.handle_consume(
+            3,
+            "jobs".into(),
+            Some("json"),
+            PubSubResponder::Sender(json_tx),
+        );
+        assert!(matches!(
+            json_rx.try_recv(),
+            Ok(ServerBrokerItem::Delivery { .. })
+        ));
     }
 }
@@ -0,0 +1,119 @@
+//! Producer on the server side
+
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc},
+    task::Poll,
+};
+
+use flume::{r#async::SendSink, Sender};
+use futures::Sink;
+use pin_project::pin_project;
+
+use crate::{
+    codec::{DefaultCodec, Format, Marshal, Reserved},
+    error::Error,
+    message::AtomicMessageId,
+    pubsub::{AckModeAuto, AckModeNone, Topic, DEFAULT_TICKETS},
+    server::{Server, RESERVED_CLIENT_ID},
+};
+
+use super::{Content, PubSubItem};
+
+/// Producer of a work queue on the server side
+#[pin_project]
+pub struct Producer<T: Topic, C: Marshal> {
+    #[pin]
+    inner: SendSink<'static, PubSubItem>,
+    counter: AtomicMessageId,
+    tickets: u32,
+    /// Format of the messages, which is the format of the server
+    format: Format,
+    marker: PhantomData<T>,
+    codec: PhantomData<C>,
+}
+
+impl<T: Topic, C: Marshal> Producer<T, C> {
+    fn new(inner: Sender<PubSubItem>, format: Format) -> Self {
+        Self {
+            inner: inner.into_sink(),
+            counter: AtomicMessageId::new(0),
+            tickets: DEFAULT_TICKETS,
+            format,
+            marker: PhantomData,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<T: Topic, C: Marshal> Producer<T, C> {
+    /// Sets the number of consumers each produced message is delivered to
+    pub fn with_tickets(mut self, tickets: u32) -> Self {
+        self.tickets = tickets;
+        self
+    }
+}
+
+impl<T: Topic, C: Marshal> Sink<T::Item> for Producer<T, C> {
+    type Error = Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_ready(cx).map_err(|err| err.into())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T::Item) -> Result<(), Self::Error> {
+        let this = self.project();
+        let msg_id = this.counter.fetch_add(1, Ordering::Relaxed);
+        let content = Content::encode(*this.format, &item)?;
+        let item = PubSubItem::Produce {
+            client_id: RESERVED_CLIENT_ID,
+            msg_id,
+            topic: T::topic(),
+            tickets: *this.tickets,
+            content: Arc::new(content),
+        };
+        this.inner.start_send(item).map_err(|err| err.into())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_flush(cx).map_err(|err| err.into())
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_close(cx).map_err(|err| err.into())
+    }
+}
+
+type PhantomCodec = DefaultCodec<Reserved, Reserved, Reserved>;
+
+macro_rules! impl_server_queue_for_ack_modes {
+    ($($ack_mode:ty),*) => {
+        $(
+            impl Server<$ack_mode> {
+                /// Creates a new producer on a work queue
+                ///
+                /// The messages are encoded with the format set with
+                /// `ServerBuilder::set_format`, and are only delivered to the clients using it.
+                pub fn producer<T: Topic>(&self) -> Producer<T, PhantomCodec> {
+                    let tx = self.pubsub.sender.publisher();
+                    Producer::new(tx, self.conn_config.format.unwrap_or_default())
+                }
+            }
+        )*
+    }
+}
+
+impl_server_queue_for_ack_modes!(AckModeNone, AckModeAuto);
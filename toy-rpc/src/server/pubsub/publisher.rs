@@ -12,14 +12,14 @@ use futures::Sink;
 use pin_project::pin_project;
 
 use crate::{
-    codec::{DefaultCodec, Marshal, Reserved},
+    codec::{DefaultCodec, Format, Marshal, Reserved},
     error::Error,
     message::AtomicMessageId,
     pubsub::{AckModeAuto, AckModeNone, Topic},
     server::{Server, RESERVED_CLIENT_ID},
 };
 
-use super::PubSubItem;
+use super::{Content, PubSubItem};
 
 /// Publisher on the server side
 #[pin_project]
@@ -27,15 +27,18 @@ pub struct Publisher<T: Topic, C: Marshal> {
     #[pin]
     inner: SendSink<'static, PubSubItem>,
     counter: AtomicMessageId,
+    /// Format of the publications, which is the format of the server
+    format: Format,
     marker: PhantomData<T>,
     codec: PhantomData<C>,
 }
 
-impl<T: Topic, C: Marshal> From<Sender<PubSubItem>> for Publisher<T, C> {
-    fn from(inner: Sender<PubSubItem>) -> Self {
+impl<T: Topic, C: Marshal> Publisher<T, C> {
+    fn new(inner: Sender<PubSubItem>, format: Format) -> Self {
         Self {
             inner: inner.into_sink(),
             counter: AtomicMessageId::new(0),
+            format,
             marker: PhantomData,
             codec: PhantomData,
         }
@@ -57,8 +60,7 @@ impl<T: Topic, C: Marshal> Sink<T::Item> for Publisher<T, C> {
         let this = self.project();
         let topic = T::topic();
         let msg_id = this.counter.fetch_add(1, Ordering::Relaxed);
-        let body = C::marshal(&item)?;
-        let content = Arc::new(body);
+        let content = Arc::new(Content::encode(*this.format, &item)?);
         let item = PubSubItem::Publish {
             client_id: RESERVED_CLIENT_ID,
             msg_id,
@@ -92,9 +94,12 @@ macro_rules! impl_server_pubsub_for_ack_modes {
         $(
             impl Server<$ack_mode> {
                 /// Creates a new publihser on a topic
+                ///
+                /// The publications are encoded with the format set with
+                /// `ServerBuilder::set_format`, and are only sent to the clients using it.
                 pub fn publisher<T: Topic>(&self) -> Publisher<T, PhantomCodec> {
-                    let tx = self.pubsub_tx.clone();
-                    Publisher::from(tx)
+                    let tx = self.pubsub.sender.publisher();
+                    Publisher::new(tx, self.conn_config.format.unwrap_or_default())
                 }
             }
         )*
@@ -0,0 +1,271 @@
+//! Work queues on the server side
+//!
+//! A message produced to a work queue is delivered to at most `tickets` consumers.
+//! Each delivery stays in flight until the consumer acks it. A delivery that is not
+//! acked within the visibility timeout is made available to the consumers again.
+//!
+//! A consumer never gets more than one ticket of the same message, so a message is
+//! removed once nothing is in flight and either all of its tickets are acked or every
+//! connected consumer of the queue has acked it. A message is only delivered to the
+//! consumers that use the format of its producer.
+
+use futures::channel::oneshot;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
+
+use crate::message::MessageId;
+use crate::pubsub::SeqId;
+
+use super::{
+    send_broker_item, task, ClientId, Content, PubSubBroker, PubSubItem, PubSubResponder,
+    ServerBrokerItem,
+};
+
+/// Names of the formats of the connected consumers, where `None` decodes every format
+type Consumers = BTreeMap<ClientId, Option<&'static str>>;
+
+/// Whether a consumer using the format `codec` can decode the message
+fn accepts(codec: Option<&str>, message: &QueuedMessage) -> bool {
+    match codec {
+        Some(codec) => codec == message.content.codec,
+        None => true,
+    }
+}
+
+/// A message waiting in a work queue
+struct QueuedMessage {
+    content: Arc<Content>,
+    /// Number of tickets that can still be delivered
+    available: u32,
+    /// Number of deliveries that are not acked yet
+    in_flight: u32,
+    /// Consumers that hold or have acked a delivery of the message
+    consumers: BTreeSet<ClientId>,
+}
+
+impl QueuedMessage {
+    /// Whether the message can no longer be delivered to any connected consumer
+    fn is_done(&self, consumers: &Consumers) -> bool {
+        self.in_flight == 0
+            && !self.consumers.is_empty()
+            && (self.available == 0
+                || consumers
+                    .iter()
+                    .filter(|(_, codec)| accepts(**codec, self))
+                    .all(|(client_id, _)| self.consumers.contains(client_id)))
+    }
+}
+
+/// Messages and pending pulls of a topic
+#[derive(Default)]
+pub(crate) struct WorkQueue {
+    next_key: u64,
+    messages: BTreeMap<u64, QueuedMessage>,
+    pub(super) pulls: VecDeque<(ClientId, PubSubResponder)>,
+    /// Connected clients that have pulled from the queue
+    consumers: Consumers,
+}
+
+impl WorkQueue {
+    /// Removes the messages that are done
+    fn remove_done(&mut self) {
+        let consumers = &self.consumers;
+        self.messages
+            .retain(|_, message| !message.is_done(consumers));
+    }
+
+    /// Number of messages in the queue
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+/// A delivery that is waiting for the Ack from the consumer
+pub(crate) struct InFlight {
+    topic: String,
+    key: u64,
+    client_id: ClientId,
+    // Dropping the sender stops the visibility timer
+    _timer: oneshot::Sender<()>,
+}
+
+impl<AckMode: Send + 'static> PubSubBroker<AckMode> {
+    pub fn handle_produce(
+        &mut self,
+        client_id: ClientId,
+        msg_id: MessageId,
+        topic: String,
+        tickets: u32,
+        content: Arc<Content>,
+    ) {
+        if tickets == 0 {
+            log::warn!(
+                "Message {} from client {} has no tickets and is dropped",
+                msg_id,
+                client_id
+            );
+            return;
+        }
+
+        let queue = self.queues.entry(topic.clone()).or_default();
+        let key = queue.next_key;
+        queue.next_key += 1;
+        queue.messages.insert(
+            key,
+            QueuedMessage {
+                content,
+                available: tickets,
+                in_flight: 0,
+                consumers: BTreeSet::new(),
+            },
+        );
+        self.dispatch(&topic);
+    }
+
+    pub fn handle_consume(
+        &mut self,
+        client_id: ClientId,
+        topic: String,
+        codec: Option<&'static str>,
+        sender: PubSubResponder,
+    ) {
+        let queue = self.queues.entry(topic.clone()).or_default();
+        queue.consumers.insert(client_id, codec);
+        queue.pulls.push_back((client_id, sender));
+        self.dispatch(&topic);
+    }
+
+    /// Returns `false` if the `seq_id` does not belong to a work queue delivery
+    pub fn handle_delivery_ack(&mut self, seq_id: &SeqId, client_id: ClientId) -> bool {
+        let delivery = match self.in_flight.remove(seq_id) {
+            Some(delivery) => delivery,
+            None => return false,
+        };
+        if delivery.client_id != client_id {
+            log::error!(
+                "{:?} is delivered to client {} but acked by client {}",
+                seq_id,
+                delivery.client_id,
+                client_id
+            );
+            self.in_flight.insert(seq_id.clone(), delivery);
+            return true;
+        }
+
+        if let Some(queue) = self.queues.get_mut(&delivery.topic) {
+            if let Some(message) = queue.messages.get_mut(&delivery.key) {
+                message.in_flight -= 1;
+            }
+            queue.remove_done();
+        }
+        true
+    }
+
+    pub fn handle_redeliver(&mut self, seq_id: SeqId) {
+        if let Some(delivery) = self.in_flight.remove(&seq_id) {
+            log::debug!("{:?} is not acked before the visibility timeout", &seq_id);
+            if let Some(queue) = self.queues.get_mut(&delivery.topic) {
+                if let Some(message) = queue.messages.get_mut(&delivery.key) {
+                    message.available += 1;
+                    message.in_flight -= 1;
+                    message.consumers.remove(&delivery.client_id);
+                }
+            }
+            self.dispatch(&delivery.topic);
+        }
+    }
+
+    /// Drops the pending pulls of a disconnected consumer and makes the messages
+    /// delivered to it available to the other consumers right away
+    pub fn remove_consumer(&mut self, client_id: ClientId) {
+        for queue in self.queues.values_mut() {
+            queue.pulls.retain(|(id, _)| *id != client_id);
+            queue.consumers.remove(&client_id);
+        }
+        let seq_ids: Vec<SeqId> = self
+            .in_flight
+            .iter()
+            .filter(|(_, delivery)| delivery.client_id == client_id)
+            .map(|(seq_id, _)| seq_id.clone())
+            .collect();
+        for seq_id in seq_ids {
+            self.handle_redeliver(seq_id);
+        }
+        // the messages that only the consumer has not acked yet are done
+        for queue in self.queues.values_mut() {
+            queue.remove_done();
+        }
+    }
+
+    /// Matches the pending pulls with the available messages of a topic
+    fn dispatch(&mut self, topic: &String) {
+        let mut queue = match self.queues.remove(topic) {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        let mut waiting = VecDeque::new();
+        while let Some((client_id, mut responder)) = queue.pulls.pop_front() {
+            // A consumer never gets more than one ticket of the same message
+            let codec = queue.consumers.get(&client_id).copied().flatten();
+            let found = queue.messages.iter_mut().find(|(_, message)| {
+                message.available > 0
+                    && !message.consumers.contains(&client_id)
+                    && accepts(codec, message)
+            });
+            let (key, message) = match found {
+                Some((key, message)) => (*key, message),
+                None => {
+                    waiting.push_back((client_id, responder));
+                    continue;
+                }
+            };
+
+            let seq_id = self.next_seq_id();
+            let msg = ServerBrokerItem::Delivery {
+                seq_id: seq_id.clone(),
+                topic: topic.clone(),
+                content: message.content.clone(),
+            };
+            if send_broker_item(&mut responder, msg) {
+                message.available -= 1;
+                message.in_flight += 1;
+                message.consumers.insert(client_id);
+                let timer = self.spawn_visibility_timer(seq_id.clone());
+                self.in_flight.insert(
+                    seq_id,
+                    InFlight {
+                        topic: topic.clone(),
+                        key,
+                        client_id,
+                        _timer: timer,
+                    },
+                );
+            }
+        }
+        queue.pulls = waiting;
+        self.queues.insert(topic.clone(), queue);
+    }
+
+    fn spawn_visibility_timer(&self, seq_id: SeqId) -> oneshot::Sender<()> {
+        #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+        use async_std::future::timeout;
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        use tokio::time::timeout;
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let duration = self.visibility_timeout;
+        let pubsub_tx = self.pubsub_tx.clone();
+        task::spawn(async move {
+            // The sender is dropped once the delivery is acked
+            if timeout(duration, rx).await.is_err() {
+                pubsub_tx
+                    .send_async(PubSubItem::Redeliver { seq_id })
+                    .await
+                    .unwrap_or_else(|err| log::error!("{}", err))
+            }
+        });
+        tx
+    }
+}
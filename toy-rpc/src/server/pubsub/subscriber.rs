@@ -6,24 +6,24 @@ use std::{
     task::{Context, Poll},
 };
 
-use flume::{r#async::RecvStream, Receiver, Sender};
 use futures::Stream;
 
 use crate::{
     codec::{DefaultCodec, Reserved, Unmarshal},
     error::Error,
     pubsub::{AckModeAuto, AckModeNone, Topic},
-    server::{broker::ServerBrokerItem, Server, RESERVED_CLIENT_ID},
+    queue::{self, QueueMetrics, QueueReceiver},
+    server::{Server, RESERVED_CLIENT_ID},
 };
 
-use super::{PubSubItem, PubSubResponder};
+use super::{PubSubItem, PubSubResponder, PubSubSender, Publication};
 
 /// Subscriber on the client side
 #[pin_project::pin_project(PinnedDrop)]
 pub struct Subscriber<T: Topic, C: Unmarshal, AckMode> {
     #[pin]
-    inner: RecvStream<'static, ServerBrokerItem>,
-    pubsub_tx: Sender<PubSubItem>,
+    inner: QueueReceiver<Publication>,
+    pubsub_tx: PubSubSender,
     topic: String,
     marker: PhantomData<T>,
     codec: PhantomData<C>,
@@ -31,9 +31,9 @@ pub struct Subscriber<T: Topic, C: Unmarshal, AckMode> {
 }
 
 impl<T: Topic, C: Unmarshal, AckMode> Subscriber<T, C, AckMode> {
-    fn new(inner: Receiver<ServerBrokerItem>, pubsub_tx: Sender<PubSubItem>) -> Self {
+    fn new(inner: QueueReceiver<Publication>, pubsub_tx: PubSubSender) -> Self {
         Self {
-            inner: inner.into_stream(),
+            inner,
             pubsub_tx,
             topic: T::topic(),
             marker: PhantomData,
@@ -41,6 +41,11 @@ impl<T: Topic, C: Unmarshal, AckMode> Subscriber<T, C, AckMode> {
             ack_mode: PhantomData,
         }
     }
+
+    /// Returns the metrics of the publication queue of the subscriber
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.inner.metrics()
+    }
 }
 
 #[pin_project::pinned_drop]
@@ -68,20 +73,7 @@ impl<T: Topic, C: Unmarshal> Stream for Subscriber<T, C, AckModeNone> {
         match this.inner.poll_next(cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(opt) => match opt {
-                Some(item) => match item {
-                    ServerBrokerItem::Publication {
-                        seq_id: _,
-                        topic: _,
-                        content,
-                    } => {
-                        let result = C::unmarshal(&content);
-                        Poll::Ready(Some(result.map_err(Into::into)))
-                    }
-                    _ => {
-                        let result = Err(Error::Internal("Invalid PubSub item".into()));
-                        Poll::Ready(Some(result))
-                    }
-                },
+                Some(publication) => Poll::Ready(Some(publication.content.decode())),
                 None => Poll::Ready(None),
             },
         }
@@ -96,32 +88,17 @@ impl<T: Topic, C: Unmarshal> Stream for Subscriber<T, C, AckModeAuto> {
         match this.inner.poll_next(cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(opt) => match opt {
-                Some(item) => match item {
-                    ServerBrokerItem::Publication {
-                        seq_id,
-                        topic: _,
-                        content,
-                    } => {
-                        // Send back Ack first
-                        log::debug!("Auto Ack");
-                        if let Err(err) = this
-                            .pubsub_tx
-                            .send(PubSubItem::Ack {
-                                seq_id,
-                                client_id: RESERVED_CLIENT_ID,
-                            })
-                            .map_err(|err| err.into())
-                        {
-                            return Poll::Ready(Some(Err(err)));
-                        }
-                        let result = C::unmarshal(&content);
-                        Poll::Ready(Some(result.map_err(Into::into)))
+                Some(publication) => {
+                    // Send back Ack first
+                    log::debug!("Auto Ack");
+                    if let Err(err) = this.pubsub_tx.send(PubSubItem::Ack {
+                        seq_id: publication.seq_id,
+                        client_id: RESERVED_CLIENT_ID,
+                    }) {
+                        return Poll::Ready(Some(Err(err)));
                     }
-                    _ => {
-                        let result = Err(Error::Internal("Invalid PubSub item".into()));
-                        Poll::Ready(Some(result))
-                    }
-                },
+                    Poll::Ready(Some(publication.content.decode()))
+                }
                 None => Poll::Ready(None),
             },
         }
@@ -138,13 +115,17 @@ macro_rules! impl_server_pubsub_for_ack_modes {
                 ///
                 /// Only one subscriber per topic can exist at the same time on the server.
                 /// Creating a new subscriber will drop the sender of the old subscriber.
+                ///
+                /// At most `cap` publications are queued for the subscriber, after which the
+                /// `FullQueuePolicy` of the server applies. The publications are decoded with
+                /// the format of their publishers.
                 pub fn subscriber<T: Topic>(&self, cap: usize) -> Result<Subscriber<T, PhantomCodec, $ack_mode>, Error> {
-                    let (sender, rx) = flume::bounded(cap);
+                    let (sender, rx) = queue::channel(Some(cap), self.conn_config.queues.policy);
                     let client_id = RESERVED_CLIENT_ID;
                     let topic = T::topic();
-                    let sender = PubSubResponder::Sender(sender);
-                    self.pubsub_tx.send(PubSubItem::Subscribe{client_id, topic, sender})?;
-                    Ok(Subscriber::new(rx, self.pubsub_tx.clone()))
+                    let sender = PubSubResponder::Local(sender);
+                    self.pubsub.sender.send(PubSubItem::Subscribe{client_id, topic, sender})?;
+                    Ok(Subscriber::new(rx, self.pubsub.sender.clone()))
                 }
             }
         )*
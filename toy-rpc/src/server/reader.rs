@@ -5,70 +5,30 @@ use std::sync::Arc;
 use crate::{
     codec::CodecRead,
     error::Error,
-    message::{MessageId, CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM},
+    execution::{
+        deadline, deserialize_error, handle_cancel, map_parse_error, service, InboundCall,
+    },
     pubsub::SeqId,
-    service::{ArcAsyncServiceCall, AsyncServiceMap},
+    queue::{GaugedSink, QueueGauge},
+    service::{AsyncServiceMap, RequestBody},
 };
 
 use super::broker::ServerBrokerItem;
-use crate::protocol::{Header, InboundBody};
+use crate::protocol::{BatchRequest, Header, InboundBody, MetadataMap};
 
 pub(crate) struct ServerReader<T> {
     reader: T,
     services: Arc<AsyncServiceMap>,
+    broker_queue: Arc<QueueGauge>,
 }
 
 impl<T: CodecRead> ServerReader<T> {
-    pub fn new(reader: T, services: Arc<AsyncServiceMap>) -> Self {
-        Self { reader, services }
-    }
-}
-
-pub(crate) fn service(
-    services: &Arc<AsyncServiceMap>,
-    service_method: String,
-) -> Result<(ArcAsyncServiceCall, String), Error> {
-    // split service and method
-    let args: Vec<&str> = service_method.split('.').collect();
-    let (service, method) = match args[..] {
-        [s, m] => (s, m),
-        _ => {
-            // Method not found
-            return Err(Error::MethodNotFound);
-        }
-    };
-
-    // look up the service
-    match services.get(service) {
-        Some(call) => Ok((call.clone(), method.into())),
-        None => Err(Error::ServiceNotFound),
-    }
-}
-
-pub(crate) fn handle_cancel(
-    id: MessageId,
-    mut deserializer: Box<InboundBody>,
-) -> Result<(), Error> {
-    let token: String = erased_serde::deserialize(&mut deserializer)?;
-    if is_correct_cancellation_token(id, &token) {
-        Ok(())
-    } else {
-        Err(Error::InvalidArgument)
-    }
-}
-
-fn is_correct_cancellation_token(id: MessageId, token: &str) -> bool {
-    match token.find(CANCELLATION_TOKEN_DELIM) {
-        Some(ind) => {
-            let base = &token[..ind];
-            let id_str = &token[ind + 1..];
-            let _id: MessageId = match id_str.parse() {
-                Ok(num) => num,
-                Err(_) => return false,
-            };
-            base == CANCELLATION_TOKEN && _id == id
+    pub fn new(reader: T, services: Arc<AsyncServiceMap>, broker_queue: Arc<QueueGauge>) -> Self {
+        Self {
+            reader,
+            services,
+            broker_queue,
         }
-        None => false,
     }
 }
 
@@ -80,11 +40,17 @@ impl<T: CodecRead> Reader for ServerReader<T> {
 
     async fn op<B>(
         &mut self,
-        mut broker: B,
+        broker: B,
     ) -> Running<Result<Self::Ok, Self::Error>, Option<Self::Error>>
     where
         B: Sink<Self::BrokerItem, Error = flume::SendError<Self::BrokerItem>> + Send + Unpin,
     {
+        // stops reading from the connection until the broker catches up
+        if !self.broker_queue.wait_for_space().await {
+            return Running::Stop(None);
+        }
+        let mut broker = GaugedSink::new(broker, self.broker_queue.clone());
+
         if let Some(header) = self.reader.read_header().await {
             let header: Header = match header {
                 Ok(header) => header,
@@ -97,22 +63,39 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                     id,
                     service_method,
                     timeout,
+                    metadata,
                 } => {
                     let deserializer = match self.reader.read_body().await {
                         Some(res) => match res {
                             Ok(de) => de,
-                            Err(err) => return Running::Continue(Err(err.into())),
+                            Err(err) => {
+                                // The client is still told that the request failed
+                                let err: Error = err.into();
+                                log::error!("{}", &err);
+                                let msg = ServerBrokerItem::Response {
+                                    id,
+                                    result: Err(err),
+                                    metadata: MetadataMap::new(),
+                                };
+                                return Running::Continue(
+                                    broker.send(msg).await.map_err(|err| err.into()),
+                                );
+                            }
                         },
                         None => return Running::Stop(None),
                     };
                     match service(&self.services, service_method) {
                         Ok((call, method)) => {
-                            let msg = ServerBrokerItem::Request {
+                            let call = InboundCall {
                                 call,
                                 id,
                                 method,
-                                duration: timeout,
-                                deserializer,
+                                deadline: deadline(timeout),
+                                metadata,
+                            };
+                            let msg = ServerBrokerItem::Request {
+                                call,
+                                request: RequestBody::Unary(deserializer),
                             };
                             Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
                         }
@@ -120,23 +103,111 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                             log::error!("{}", &err);
                             let msg = ServerBrokerItem::Response {
                                 id,
-                                result: Err(err),
+                                result: Err(map_parse_error(err)),
+                                metadata: MetadataMap::new(),
+                            };
+                            Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
+                        }
+                    }
+                }
+                Header::Notify {
+                    id,
+                    service_method,
+                    metadata,
+                } => {
+                    // Errors are only logged since the client does not expect a response
+                    let deserializer = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => return Running::Stop(None),
+                    };
+                    match service(&self.services, service_method) {
+                        Ok((call, method)) => {
+                            let msg = ServerBrokerItem::Notify {
+                                call,
+                                id,
+                                method,
+                                request: RequestBody::Unary(deserializer),
+                                metadata,
                             };
                             Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
                         }
+                        Err(err) => Running::Continue(Err(err)),
                     }
                 }
-                Header::Response { id, is_ok } => {
-                    let _ = match self.reader.read_body().await {
+                Header::Batch {
+                    id,
+                    timeout,
+                    metadata,
+                } => {
+                    let requests = match self.reader.read_body().await {
+                        Some(res) => res.map_err(Into::into).and_then(|mut de| {
+                            erased_serde::deserialize::<Vec<BatchRequest>>(&mut de)
+                                .map_err(|err| Error::ParseError(Box::new(err)))
+                        }),
+                        None => return Running::Stop(None),
+                    };
+                    let requests = match requests {
+                        Ok(requests) => requests,
+                        Err(err) => {
+                            // The calls of the batch are failed with the response to the batch
+                            log::error!("{}", &err);
+                            let msg = ServerBrokerItem::Response {
+                                id,
+                                result: Err(map_parse_error(err)),
+                                metadata: MetadataMap::new(),
+                            };
+                            return Running::Continue(
+                                broker.send(msg).await.map_err(|err| err.into()),
+                            );
+                        }
+                    };
+                    // A request whose service is not found is answered in the batch response
+                    let calls = requests
+                        .into_iter()
+                        .map(|request| {
+                            let BatchRequest {
+                                id,
+                                service_method,
+                                args,
+                            } = request;
+                            let call =
+                                service(&self.services, service_method).map(|(call, method)| {
+                                    (call, method, RequestBody::Unary(T::from_bytes(args)))
+                                });
+                            (id, call)
+                        })
+                        .collect();
+                    let msg = ServerBrokerItem::Batch {
+                        id,
+                        deadline: deadline(timeout),
+                        metadata,
+                        calls,
+                    };
+                    Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
+                }
+                Header::BatchResponse(_) => {
+                    // The server does not send batches, the body is simply discarded
+                    let _ = self.reader.read_bytes().await;
+                    Running::Continue(Err(Error::Internal("Unexpected Header type".into())))
+                }
+                Header::Response { id, status, .. } => {
+                    // Response to a reverse call made by the server
+                    let deserializer: Box<InboundBody> = match self.reader.read_body().await {
                         Some(res) => match res {
                             Ok(de) => de,
                             Err(err) => return Running::Continue(Err(err.into())),
                         },
                         None => return Running::Stop(None),
                     };
-                    Running::Continue(Err(Error::Internal(
-                        format!("Server received Response {{id: {}, is_ok: {}}}", id, is_ok).into(),
-                    )))
+                    let result = match status.is_ok() {
+                        true => Ok(deserializer),
+                        false => Err(deserialize_error::<T>(id, deserializer)),
+                    };
+                    let msg = ServerBrokerItem::InboundResponse { id, result };
+                    Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
                 }
                 Header::Cancel(id) => {
                     let deserializer = match self.reader.read_body().await {
@@ -155,6 +226,7 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                             let msg = ServerBrokerItem::Response {
                                 id,
                                 result: Err(err),
+                                metadata: MetadataMap::new(),
                             };
                             Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
                         }
@@ -203,23 +275,126 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                             .map_err(|err| err.into()),
                     )
                 }
-                Header::Produce {
-                    id: _,
-                    topic: _,
-                    tickets: _,
-                } => Running::Continue(Err(Error::Internal(
-                    "Unexpected Header type (Header::Produce)".into(),
-                ))),
-                Header::Consume { id: _, topic: _ } => Running::Continue(Err(Error::Internal(
-                    "Unexpected Header type (Header::Consume)".into(),
-                ))),
+                Header::Ping(id) => Running::Continue(
+                    broker
+                        .send(ServerBrokerItem::InboundPing(id))
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                Header::Pong(id) => Running::Continue(
+                    broker
+                        .send(ServerBrokerItem::InboundPong(id))
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                Header::Produce { id, topic, tickets } => {
+                    let content = match self.reader.read_bytes().await {
+                        Some(res) => match res {
+                            Ok(b) => b,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => return Running::Stop(None),
+                    };
+                    Running::Continue(
+                        broker
+                            .send(ServerBrokerItem::Produce {
+                                id,
+                                topic,
+                                tickets,
+                                content,
+                            })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
+                Header::Consume { id, topic } => {
+                    // The body of a pull is empty
+                    let _ = self.reader.read_bytes().await;
+                    Running::Continue(
+                        broker
+                            .send(ServerBrokerItem::Consume { id, topic })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
                 Header::Ext {
-                    id: _,
-                    content: _,
-                    marker: _,
-                } => Running::Continue(Err(Error::Internal(
-                    "Unexpected Header type (Header::Ext)".into(),
-                ))),
+                    id,
+                    content,
+                    marker,
+                } => {
+                    let body = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => return Running::Stop(None),
+                    };
+                    Running::Continue(
+                        broker
+                            .send(ServerBrokerItem::Extension {
+                                id,
+                                marker,
+                                content,
+                                body,
+                            })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
+                Header::StreamItem { id, is_ok } => {
+                    let body = match self.reader.read_body().await {
+                        Some(res) => match res {
+                            Ok(de) => de,
+                            Err(err) => return Running::Continue(Err(err.into())),
+                        },
+                        None => return Running::Stop(None),
+                    };
+                    if !is_ok {
+                        log::error!("Client sent an error item in stream {}", id);
+                        return Running::Continue(Ok(()));
+                    }
+                    Running::Continue(
+                        broker
+                            .send(ServerBrokerItem::InboundStreamItem { id, body })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
+                Header::StreamEnd(id) => {
+                    // There is no body frame for stream end message
+                    Running::Continue(
+                        broker
+                            .send(ServerBrokerItem::InboundStreamEnd(id))
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
+                Header::OpenStream {
+                    id,
+                    service_method,
+                    timeout,
+                    metadata,
+                } => {
+                    // There is no body frame for open stream message
+                    let msg = match service(&self.services, service_method) {
+                        Ok((call, method)) => ServerBrokerItem::OpenStream(InboundCall {
+                            call,
+                            id,
+                            method,
+                            deadline: deadline(timeout),
+                            metadata,
+                        }),
+                        Err(err) => {
+                            log::error!("{}", &err);
+                            ServerBrokerItem::Response {
+                                id,
+                                result: Err(err),
+                                metadata: MetadataMap::new(),
+                            }
+                        }
+                    };
+                    Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
+                }
             }
         } else {
             // Stop is not needed on the server because server broker will send a stop to itself after stopping
@@ -1,60 +1,178 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use brw::{Running, Writer};
 
 use crate::{
-    codec::CodecWrite,
+    codec::{CodecWrite, Marshal},
     error::Error,
-    message::{ErrorMessage, MessageId},
+    extension::ExtensionFrame,
+    message::{ErrorMessage, MessageId, CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM},
     pubsub::SeqId,
+    queue::QueueGauge,
     service::HandlerResult,
+    status::Status,
     util::GracefulShutdown,
 };
 
-use crate::protocol::Header;
+use crate::protocol::{BatchReply, Header, MetadataMap, OutboundBody};
+
+use super::broker::BatchResult;
+use super::pubsub::{ClientPublicationsReceiver, Content};
 
 pub(crate) enum ServerWriterItem {
     Response {
         id: MessageId,
         result: HandlerResult,
+        metadata: MetadataMap,
+    },
+    /// Item yielded by a streaming RPC method
+    StreamItem {
+        id: MessageId,
+        result: HandlerResult,
+    },
+    /// End of a stream
+    StreamEnd {
+        id: MessageId,
     },
-    /// Publish subscription item to client
-    Publication {
+    /// Publish the queued subscription items to client
+    Publications,
+    /// Deliver a work queue message to client consumer
+    Delivery {
         seq_id: SeqId,
         topic: String,
-        content: Arc<Vec<u8>>,
+        content: Arc<Content>,
     },
+    /// Extension message or reply to client
+    Extension(ExtensionFrame),
     Ack {
         // Server will only need to Ack Publish request from client.
         // Thus should reply with the MessageId that came from the client
         id: MessageId,
     },
+    /// Reverse call to a service registered on the client
+    Request {
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        body: Box<OutboundBody>,
+    },
+    /// Results of a batch of requests
+    BatchResponse {
+        id: MessageId,
+        results: Vec<BatchResult>,
+    },
+    /// Cancels a reverse call
+    Cancel(MessageId),
+    /// Keepalive ping to client
+    Ping(MessageId),
+    /// Answer to a keepalive ping from client
+    Pong(MessageId),
     Stopping,
     Stop,
 }
 
+/// Serializes the result of a request in a batch with the codec `M`
+fn batch_reply<M: Marshal>(
+    id: MessageId,
+    result: HandlerResult,
+    metadata: MetadataMap,
+) -> BatchReply {
+    let result = result.and_then(|body| M::marshal(&body).map_err(Into::into));
+    let (status, body) = match result {
+        Ok(body) => (Status::ok(), body),
+        Err(err) => {
+            let status = err.status();
+            let msg = ErrorMessage::from_err::<M>(err)
+                .unwrap_or_else(|err| ErrorMessage::Internal(err.to_string()));
+            let body = M::marshal(&msg).unwrap_or_else(|err| {
+                log::error!("Non-sendable error: {}", err);
+                Vec::new()
+            });
+            (status, body)
+        }
+    };
+    BatchReply {
+        id,
+        status,
+        metadata,
+        body,
+    }
+}
+
 pub(crate) struct ServerWriter<W> {
     writer: W,
+    queue: Arc<QueueGauge>,
+    publications: ClientPublicationsReceiver,
 }
 
 impl<W: CodecWrite> ServerWriter<W> {
-    pub fn new(writer: W) -> Self {
-        Self { writer }
+    pub fn new(
+        writer: W,
+        queue: Arc<QueueGauge>,
+        publications: ClientPublicationsReceiver,
+    ) -> Self {
+        Self {
+            writer,
+            queue,
+            publications,
+        }
+    }
+
+    async fn write_publications(&mut self) -> Result<(), Error> {
+        for publication in self.publications.take() {
+            let id = publication.seq_id.0;
+            self.write_publication(id, publication.topic, &publication.content.bytes)
+                .await?;
+            self.writer.end_message().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_response(
+        &mut self,
+        id: MessageId,
+        result: HandlerResult,
+        metadata: MetadataMap,
+    ) -> Result<(), Error> {
+        self.write_result(id, result, |status| Header::Response {
+            id,
+            status,
+            metadata,
+        })
+        .await
+    }
+
+    async fn write_stream_item(
+        &mut self,
+        id: MessageId,
+        result: HandlerResult,
+    ) -> Result<(), Error> {
+        self.write_result(id, result, |status| Header::StreamItem {
+            id,
+            is_ok: status.is_ok(),
+        })
+        .await
     }
 
-    async fn write_response(&mut self, id: MessageId, result: HandlerResult) -> Result<(), Error> {
+    async fn write_result(
+        &mut self,
+        id: MessageId,
+        result: HandlerResult,
+        header: impl FnOnce(Status) -> Header,
+    ) -> Result<(), Error> {
         match result {
             Ok(body) => {
                 log::trace!("Message {} Success", &id);
-                let header = Header::Response { id, is_ok: true };
+                let header = header(Status::ok());
                 self.writer.write_header(header).await?;
                 self.writer.write_body(id, &body).await?;
                 Ok(())
             }
             Err(err) => {
                 log::trace!("Message {} Error", &id);
-                let header = Header::Response { id, is_ok: false };
-                let msg = match ErrorMessage::from_err(err) {
+                let header = header(err.status());
+                let msg = match ErrorMessage::from_err::<W>(err) {
                     Ok(m) => m,
                     Err(err) => {
                         log::debug!("Non-sendable error: {}", err);
@@ -76,7 +194,84 @@ impl<W: CodecWrite> ServerWriter<W> {
     ) -> Result<(), Error> {
         let header = Header::Publish { id, topic };
         self.writer.write_header(header).await?;
-        self.writer.write_body_bytes(id, &content).await?;
+        self.writer.write_body_bytes(id, content).await?;
+        Ok(())
+    }
+
+    async fn write_delivery(
+        &mut self,
+        id: MessageId,
+        topic: String,
+        content: &[u8],
+    ) -> Result<(), Error> {
+        let header = Header::Consume { id, topic };
+        self.writer.write_header(header).await?;
+        self.writer.write_body_bytes(id, content).await?;
+        Ok(())
+    }
+
+    async fn write_extension(&mut self, frame: ExtensionFrame) -> Result<(), Error> {
+        let ExtensionFrame {
+            id,
+            marker,
+            content,
+            body,
+        } = frame;
+        let header = Header::Ext {
+            id,
+            content,
+            marker,
+        };
+        self.writer.write_header(header).await?;
+        self.writer.write_body(id, &body).await?;
+        Ok(())
+    }
+
+    async fn write_request(
+        &mut self,
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        body: Box<OutboundBody>,
+    ) -> Result<(), Error> {
+        let header = Header::Request {
+            id,
+            service_method,
+            timeout: Some(duration),
+            metadata: MetadataMap::new(),
+        };
+        self.writer.write_header(header).await?;
+        self.writer.write_body(id, &body).await?;
+        Ok(())
+    }
+
+    async fn write_batch_response(
+        &mut self,
+        id: MessageId,
+        results: Vec<BatchResult>,
+    ) -> Result<(), Error> {
+        let replies: Vec<BatchReply> = results
+            .into_iter()
+            .map(|(id, result, metadata)| batch_reply::<W>(id, result, metadata))
+            .collect();
+        let header = Header::BatchResponse(id);
+        self.writer.write_header(header).await?;
+        self.writer.write_body(id, &replies).await?;
+        Ok(())
+    }
+
+    async fn write_cancel(&mut self, id: MessageId) -> Result<(), Error> {
+        let header = Header::Cancel(id);
+        let body: String = format!("{}{}{}", CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM, id);
+        self.writer.write_header(header).await?;
+        self.writer.write_body(id, &body).await?;
+        Ok(())
+    }
+
+    // End of stream message
+    async fn write_stream_end(&mut self, id: MessageId) -> Result<(), Error> {
+        let header = Header::StreamEnd(id);
+        self.writer.write_header(header).await?;
         Ok(())
     }
 
@@ -86,6 +281,12 @@ impl<W: CodecWrite> ServerWriter<W> {
         self.writer.write_header(header).await?;
         Ok(())
     }
+
+    // Ping and Pong messages, which do not come with a body
+    async fn write_keepalive(&mut self, header: Header) -> Result<(), Error> {
+        self.writer.write_header(header).await?;
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -99,20 +300,46 @@ impl<W: CodecWrite + GracefulShutdown> Writer for ServerWriter<W> {
         item: Self::Item,
     ) -> Running<Result<Self::Ok, Self::Error>, Option<Self::Error>> {
         let res = match item {
-            ServerWriterItem::Response { id, result } => self.write_response(id, result).await,
-            ServerWriterItem::Publication {
+            ServerWriterItem::Response {
+                id,
+                result,
+                metadata,
+            } => self.write_response(id, result, metadata).await,
+            ServerWriterItem::StreamItem { id, result } => self.write_stream_item(id, result).await,
+            ServerWriterItem::BatchResponse { id, results } => {
+                self.write_batch_response(id, results).await
+            }
+            ServerWriterItem::StreamEnd { id } => self.write_stream_end(id).await,
+            ServerWriterItem::Publications => self.write_publications().await,
+            ServerWriterItem::Delivery {
                 seq_id,
                 topic,
                 content,
             } => {
                 let id = seq_id.0;
-                self.write_publication(id, topic, &content).await
+                self.write_delivery(id, topic, &content.bytes).await
             }
+            ServerWriterItem::Extension(frame) => self.write_extension(frame).await,
             ServerWriterItem::Ack { id } => self.write_ack(id).await,
-            ServerWriterItem::Stopping => Ok(self.writer.close().await),
+            ServerWriterItem::Request {
+                id,
+                service_method,
+                duration,
+                body,
+            } => self.write_request(id, service_method, duration, body).await,
+            ServerWriterItem::Cancel(id) => self.write_cancel(id).await,
+            ServerWriterItem::Ping(id) => self.write_keepalive(Header::Ping(id)).await,
+            ServerWriterItem::Pong(id) => self.write_keepalive(Header::Pong(id)).await,
+            ServerWriterItem::Stopping => {
+                self.writer.close().await;
+                Ok(())
+            }
             ServerWriterItem::Stop => return Running::Stop(None),
         };
-        Running::Continue(res)
+        // a header without a body is held back until the message ends
+        let ended = self.writer.end_message().await.map_err(Into::into);
+        self.queue.pop();
+        Running::Continue(res.and(ended))
     }
 
     async fn handle_result(res: Result<Self::Ok, Self::Error>) -> Running<(), Option<Self::Error>> {
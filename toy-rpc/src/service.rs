@@ -3,13 +3,24 @@
 use async_trait::async_trait;
 use erased_serde as erased;
 use futures::future::Future;
+use futures::stream::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::net::SocketAddr;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
 use crate::error::Error;
-use crate::protocol::OutboundBody;
+use crate::protocol::{MetadataMap, OutboundBody};
+
+/// Default period a canceled or timed out RPC method is given to observe the cancellation
+/// and clean up before it is dropped
+pub const DEFAULT_CANCELLATION_GRACE_PERIOD: Duration = Duration::from_secs(1);
 
 /// Ok type of HandlerResult
 // pub(crate) type Success = Box<dyn erased::Serialize + Send + Sync + 'static>;
@@ -21,15 +32,285 @@ pub type HandlerResult = Result<Success, Error>;
 /// Future of RPC handler, this must be `.await`ed to obtain the result
 pub type HandlerResultFut = Pin<Box<dyn Future<Output = HandlerResult> + Send>>;
 
+/// Stream of results yielded by a server-streaming RPC handler
+pub type HandlerResultStream = Pin<Box<dyn Stream<Item = HandlerResult> + Send>>;
+
+/// Output of a RPC handler
+pub enum HandlerOutput {
+    /// The handler produces exactly one response
+    Unary(HandlerResultFut),
+    /// The handler produces a stream of responses, which is terminated
+    /// with an end-of-stream message
+    Stream(HandlerResultStream),
+}
+
+/// Stream of the request bodies sent by the client in a client-streaming
+/// or bidirectional streaming RPC call
+pub type RequestBodyStream =
+    Pin<Box<dyn Stream<Item = Box<dyn erased::Deserializer<'static> + Send>> + Send>>;
+
+/// Request passed to a RPC handler
+pub enum RequestBody {
+    /// A single request
+    Unary(Box<dyn erased::Deserializer<'static> + Send>),
+    /// A stream of requests sent by the client. The stream ends when the client
+    /// closes its `CallSink`
+    Stream(RequestBodyStream),
+}
+
+impl RequestBody {
+    /// Deserializes a single request.
+    ///
+    /// Returns `Error::InvalidArgument` if the client sent a stream of requests
+    pub fn deserialize<T: DeserializeOwned>(self) -> Result<T, Error> {
+        match self {
+            RequestBody::Unary(mut deserializer) => {
+                erased::deserialize(&mut deserializer).map_err(|e| Error::ParseError(Box::new(e)))
+            }
+            RequestBody::Stream(_) => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// Converts into a `RequestStream` that deserializes each request sent by the client.
+    ///
+    /// Returns `Error::InvalidArgument` if the client sent a single request
+    pub fn into_stream<T>(self) -> Result<RequestStream<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        match self {
+            RequestBody::Stream(bodies) => {
+                Ok(RequestStream::new(bodies.map(|mut deserializer| {
+                    erased::deserialize(&mut deserializer)
+                        .map_err(|e| Error::ParseError(Box::new(e)))
+                })))
+            }
+            RequestBody::Unary(_) => Err(Error::InvalidArgument),
+        }
+    }
+}
+
+/// Stream of requests of a client-streaming or bidirectional streaming RPC method.
+///
+/// A RPC method that takes a `RequestStream<T>` as its argument receives the items
+/// sent by the client through a `CallSink<T>`.
+pub struct RequestStream<T> {
+    inner: Pin<Box<dyn Stream<Item = Result<T, Error>> + Send>>,
+}
+
+impl<T> RequestStream<T> {
+    /// Creates a `RequestStream` from a stream of requests
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, Error>> + Send + 'static,
+    {
+        Self {
+            inner: Box::pin(stream),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for RequestStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestStream").finish()
+    }
+}
+
+impl<T> Stream for RequestStream<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// Context of a RPC call.
+///
+/// An exported method can take a `RpcContext` as the argument right before the
+/// request, which will be injected by the server.
+///
+/// # Example
+///
+/// ```rust
+/// #[export_impl]
+/// impl Foo {
+///     #[export_method]
+///     async fn whoami(&self, ctx: RpcContext, _: ()) -> Result<u64, String> {
+///         Ok(ctx.client_id())
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RpcContext {
+    client_id: u64,
+    remote_addr: Option<SocketAddr>,
+    deadline: Option<Instant>,
+    metadata: Arc<MetadataMap>,
+    cancellation: CancellationToken,
+}
+
+impl RpcContext {
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub(crate) fn new(
+        client_id: u64,
+        remote_addr: Option<SocketAddr>,
+        deadline: Option<Instant>,
+        metadata: Arc<MetadataMap>,
+        cancellation: CancellationToken,
+    ) -> Self {
+        Self {
+            client_id,
+            remote_addr,
+            deadline,
+            metadata,
+            cancellation,
+        }
+    }
+
+    /// ID the server assigned to the client that sent the request.
+    ///
+    /// This is `0`, ie. `server::RESERVED_CLIENT_ID`, for a reverse call made by the server
+    /// to a service registered on the client
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    /// Address of the client if it is known.
+    ///
+    /// This is `None` for connections served with `Server::serve_codec` or `Server::serve_stream`
+    /// and for reverse calls made by the server
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Point in time at which the server gives up on the request, or `None` if the
+    /// request has no timeout
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Time left until the deadline, or `None` if the request has no timeout
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Metadata of the request
+    pub fn metadata(&self) -> &MetadataMap {
+        &self.metadata
+    }
+
+    /// Token that is canceled when the client cancels the request or disconnects, or
+    /// when the request reaches the timeout.
+    ///
+    /// The RPC method is then given the grace period set by
+    /// `ServerBuilder::set_cancellation_grace_period`, or by
+    /// `ClientBuilder::set_cancellation_grace_period` for calls made by the server, to clean
+    /// up before it is dropped.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Returns `true` if the request has been canceled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Returns a future that completes when the request is canceled
+    pub fn cancelled(&self) -> Cancelled {
+        self.cancellation.cancelled()
+    }
+}
+
+/// A signal that a RPC call has been canceled.
+///
+/// RPC methods can observe the token through `RpcContext` to stop their work
+/// cooperatively instead of being dropped in the middle of it.
+///
+/// Cloned tokens share the same state, so canceling any of them cancels all.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<CancellationInner>,
+}
+
+#[derive(Default)]
+struct CancellationInner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not canceled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token and wakes all the tasks waiting on `cancelled()`
+    pub fn cancel(&self) {
+        if !self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            if let Ok(mut wakers) = self.inner.wakers.lock() {
+                wakers.drain(..).for_each(Waker::wake);
+            }
+        }
+    }
+
+    /// Returns `true` if the token has been canceled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns a future that completes when the token is canceled
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            token: self.clone(),
+        }
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("is_cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Future returned by `CancellationToken::cancelled`
+#[derive(Debug)]
+pub struct Cancelled {
+    token: CancellationToken,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+
+        let mut wakers = match self.token.inner.wakers.lock() {
+            Ok(wakers) => wakers,
+            Err(_) => return Poll::Pending,
+        };
+        // check again while holding the lock so that a concurrent `cancel()`
+        // cannot be missed
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
 /// Async handler definition
-pub type AsyncHandler<S> =
-    fn(Arc<S>, Box<dyn erased::Deserializer<'static> + Send>) -> HandlerResultFut;
+pub type AsyncHandler<S> = fn(Arc<S>, RpcContext, RequestBody) -> HandlerOutput;
 
 /// Async trait objects to invoke a service
-pub type AsyncServiceCall = dyn Fn(String, Box<dyn erased::Deserializer<'static> + Send>) -> HandlerResultFut
-    + Send
-    + Sync
-    + 'static;
+pub type AsyncServiceCall =
+    dyn Fn(String, RpcContext, RequestBody) -> HandlerOutput + Send + Sync + 'static;
 
 /// Arc wrapper of `AsyncServiceCall`
 pub type ArcAsyncServiceCall = Arc<AsyncServiceCall>;
@@ -71,17 +352,14 @@ where
     /// Returns a function pointer to the requested method
     fn method(&self, name: &str) -> Option<AsyncHandler<State>>;
 
-    /// Returns a future that will execute the RPC method when `.await`ed.
+    /// Returns the output of the RPC method, which is either a future or a stream
+    /// that will execute the RPC method when polled.
     /// Returns `Error::MethodNotFound` if the requested method is not registered.
-    fn call(
-        &self,
-        name: &str,
-        deserializer: Box<dyn erased::Deserializer<'static> + Send>,
-    ) -> HandlerResultFut {
+    fn call(&self, name: &str, ctx: RpcContext, request: RequestBody) -> HandlerOutput {
         let _state = self.state();
         match self.method(name) {
-            Some(m) => m(_state, deserializer),
-            None => Box::pin(async move { Err(Error::MethodNotFound) }),
+            Some(m) => m(_state, ctx, request),
+            None => HandlerOutput::Unary(Box::pin(async move { Err(Error::MethodNotFound) })),
         }
     }
 }
@@ -216,3 +494,141 @@ where
         .register_handlers(handlers)
         .build()
 }
+
+thread_local! {
+    static METADATA_SCOPE: RefCell<Option<MetadataScope>> = const { RefCell::new(None) };
+}
+
+/// Returns the metadata of the request that is being handled.
+///
+/// Returns `None` if it is not called from within a RPC method, ie. from a task
+/// spawned by the RPC method.
+///
+/// # Example
+///
+/// ```rust
+/// #[export_impl]
+/// impl Foo {
+///     #[export_method]
+///     async fn whoami(&self, _: ()) -> Result<String, String> {
+///         toy_rpc::service::request_metadata()
+///             .and_then(|metadata| metadata.get("user").cloned())
+///             .ok_or_else(|| "Unknown user".to_string())
+///     }
+/// }
+/// ```
+pub fn request_metadata() -> Option<Arc<MetadataMap>> {
+    METADATA_SCOPE.with(|scope| scope.borrow().as_ref().map(|s| s.request.clone()))
+}
+
+/// Returns the deadline of the request that is being handled.
+///
+/// Returns `None` if the request has no timeout or if it is not called from within a
+/// RPC method. A `Client` that makes a call from within a RPC method caps the timeout
+/// of the call to this deadline.
+pub fn request_deadline() -> Option<Instant> {
+    METADATA_SCOPE.with(|scope| scope.borrow().as_ref().and_then(|s| s.deadline))
+}
+
+/// Inserts a key-value pair into the metadata of the response to the request
+/// that is being handled.
+///
+/// Only the response of a unary RPC method carries the metadata. This does nothing if it
+/// is not called from within a RPC method.
+pub fn set_response_metadata(key: impl ToString, value: impl ToString) {
+    METADATA_SCOPE.with(|scope| {
+        if let Some(scope) = scope.borrow().as_ref() {
+            if let Ok(mut response) = scope.response.lock() {
+                response.insert(key.to_string(), value.to_string());
+            }
+        }
+    })
+}
+
+/// Request and response metadata and the deadline of a RPC call, which are made
+/// available to the RPC method while it is executed
+#[derive(Clone, Default)]
+pub(crate) struct MetadataScope {
+    request: Arc<MetadataMap>,
+    response: Arc<Mutex<MetadataMap>>,
+    deadline: Option<Instant>,
+}
+
+impl MetadataScope {
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub fn new(request: MetadataMap, deadline: Option<Instant>) -> Self {
+        Self {
+            request: Arc::new(request),
+            response: Default::default(),
+            deadline,
+        }
+    }
+
+    /// Metadata of the request
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub fn request(&self) -> Arc<MetadataMap> {
+        self.request.clone()
+    }
+
+    /// Takes the metadata set by the RPC method for the response
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub fn take_response(&self) -> MetadataMap {
+        self.response
+            .lock()
+            .map(|mut response| std::mem::take(&mut *response))
+            .unwrap_or_default()
+    }
+
+    /// Executes `f` with the scope set as the current scope
+    pub fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        // restores the previous scope even if `f` panics
+        struct Guard(Option<MetadataScope>);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                METADATA_SCOPE.with(|scope| *scope.borrow_mut() = prev);
+            }
+        }
+
+        let prev = METADATA_SCOPE.with(|scope| scope.borrow_mut().replace(self.clone()));
+        let _guard = Guard(prev);
+        f()
+    }
+
+    /// Wraps a future or a stream so that the scope is set whenever it is polled
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub fn wrap<T>(&self, inner: T) -> WithMetadata<T> {
+        WithMetadata {
+            scope: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A future or a stream that is polled with a `MetadataScope`
+#[pin_project::pin_project]
+pub(crate) struct WithMetadata<T> {
+    scope: MetadataScope,
+    #[pin]
+    inner: T,
+}
+
+impl<F: Future> Future for WithMetadata<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let inner = this.inner;
+        this.scope.enter(|| inner.poll(cx))
+    }
+}
+
+impl<S: Stream> Stream for WithMetadata<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let inner = this.inner;
+        this.scope.enter(|| inner.poll_next(cx))
+    }
+}
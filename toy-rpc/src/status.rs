@@ -0,0 +1,179 @@
+//! Status of a response
+//!
+//! Every `Header::Response` carries a `Status`, which tells the caller whether the call
+//! succeeded and, if not, why it failed. The codes are modelled after the gRPC status codes
+//! so that failures can be classified without parsing error messages. On either side,
+//! `Error::code` returns the code of an error.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Status code of a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Code {
+    /// The call succeeded
+    Ok,
+    /// The call was canceled by the caller
+    Cancelled,
+    /// The RPC method returned an error that does not fit any other code
+    Unknown,
+    /// The argument of the call is invalid
+    InvalidArgument,
+    /// The call did not complete before its timeout
+    DeadlineExceeded,
+    /// A requested entity was not found
+    NotFound,
+    /// An entity that the call tried to create already exists
+    AlreadyExists,
+    /// The caller is not allowed to perform the call
+    PermissionDenied,
+    /// A resource, ie. a quota or a queue, is exhausted
+    ResourceExhausted,
+    /// The system is not in a state required to perform the call
+    FailedPrecondition,
+    /// The call was aborted, usually because of a concurrency conflict
+    Aborted,
+    /// The call was attempted past a valid range
+    OutOfRange,
+    /// The service or method is not implemented by the peer
+    Unimplemented,
+    /// An internal error, ie. the RPC method panicked
+    Internal,
+    /// The peer is currently unavailable
+    Unavailable,
+    /// Unrecoverable data loss or corruption
+    DataLoss,
+    /// The caller is not authenticated
+    Unauthenticated,
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Status of a response, which consists of a code and an optional message and details
+///
+/// RPC methods can fail with a specific code by returning a `Status` converted into
+/// `Error::Status`.
+///
+/// # Example
+///
+/// ```rust
+/// #[export_method]
+/// async fn withdraw(&self, amount: u64) -> Result<u64, toy_rpc::Error> {
+///     Err(Status::permission_denied("account is frozen").into())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Status {
+    code: Code,
+    message: Option<String>,
+    details: Option<Vec<u8>>,
+}
+
+impl Status {
+    /// Creates a status with a code and a message
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: Some(message.into()),
+            details: None,
+        }
+    }
+
+    /// Status of a successful response
+    pub fn ok() -> Self {
+        Self::from_code(Code::Ok)
+    }
+
+    /// Creates a status with a code only
+    pub fn from_code(code: Code) -> Self {
+        Self {
+            code,
+            message: None,
+            details: None,
+        }
+    }
+
+    /// Attaches opaque details to the status
+    pub fn with_details(mut self, details: Vec<u8>) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Creates a status with `Code::Cancelled`
+    pub fn cancelled(message: impl Into<String>) -> Self {
+        Self::new(Code::Cancelled, message)
+    }
+
+    /// Creates a status with `Code::InvalidArgument`
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(Code::InvalidArgument, message)
+    }
+
+    /// Creates a status with `Code::DeadlineExceeded`
+    pub fn deadline_exceeded(message: impl Into<String>) -> Self {
+        Self::new(Code::DeadlineExceeded, message)
+    }
+
+    /// Creates a status with `Code::NotFound`
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(Code::NotFound, message)
+    }
+
+    /// Creates a status with `Code::PermissionDenied`
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        Self::new(Code::PermissionDenied, message)
+    }
+
+    /// Creates a status with `Code::ResourceExhausted`
+    pub fn resource_exhausted(message: impl Into<String>) -> Self {
+        Self::new(Code::ResourceExhausted, message)
+    }
+
+    /// Creates a status with `Code::Unimplemented`
+    pub fn unimplemented(message: impl Into<String>) -> Self {
+        Self::new(Code::Unimplemented, message)
+    }
+
+    /// Creates a status with `Code::Internal`
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(Code::Internal, message)
+    }
+
+    /// Creates a status with `Code::Unavailable`
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(Code::Unavailable, message)
+    }
+
+    /// Code of the status
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// Message of the status
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Details of the status
+    pub fn details(&self) -> Option<&[u8]> {
+        self.details.as_deref()
+    }
+
+    /// Returns `true` if the code is `Code::Ok`
+    pub fn is_ok(&self) -> bool {
+        self.code == Code::Ok
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.code, message),
+            None => write!(f, "{}", self.code),
+        }
+    }
+}
@@ -0,0 +1,574 @@
+//! Chunked transmission of large payloads over the frame transport
+//!
+//! The frames of a connection are written by a scheduler task. A body that is larger than
+//! the max frame size agreed upon in the handshake is split into chunks, and the scheduler
+//! writes one chunk of each pending message in turn so that a large transfer does not block
+//! the other messages on the connection. Messages with the same message id are written in
+//! the order they are sent.
+//!
+//! A chunk carries `CHUNK_FRAME_ID` and a transfer id in place of the message id. The header
+//! of a message is written right before the last chunk of its body, which is a regular body
+//! frame, so the reader only needs to put the chunks back together before handing the body
+//! to the codec.
+//!
+//! The scheduler holds at most `MAX_PENDING_TRANSFERS` messages. Beyond that, the writing
+//! half waits until a message is written, so a slow peer holds back the writer of the
+//! connection instead of making the scheduler buffer without bound.
+//!
+//! The reader checks the size of a body against the max message size before the payload of
+//! each frame is read, so a peer cannot make it allocate more than the limit. A peer cannot
+//! have more than `MAX_PENDING_TRANSFERS` bodies in flight either, which is the most the
+//! scheduler writes at a time, and the reader closes the connection beyond that.
+
+use async_trait::async_trait;
+use cfg_if::cfg_if;
+use flume::{Receiver, Sender};
+use futures::channel::oneshot;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::ErrorKind;
+
+use crate::{error::IoError, message::MessageId, util::GracefulShutdown};
+
+use super::frame::{Frame, FrameHeader, FrameId, FrameRead, FrameWrite, PayloadType};
+use super::MessageSizeLimit;
+
+cfg_if! {
+    if #[cfg(feature = "async_std_runtime")] {
+        use async_std::task::spawn;
+    } else if #[cfg(feature = "tokio_runtime")] {
+        use tokio::task::spawn;
+    }
+}
+
+const HEADER_FRAME_ID: FrameId = 0;
+const BODY_FRAME_ID: FrameId = 1;
+/// Frame id of a chunk that is followed by more chunks of the same body
+const CHUNK_FRAME_ID: FrameId = 2;
+/// Max number of messages the scheduler holds before the writing half waits, which is also
+/// the max number of bodies the reader puts back together at a time
+const MAX_PENDING_TRANSFERS: usize = 64;
+
+/// Payload of a frame along with its compression mark
+#[derive(Debug)]
+pub(crate) struct Payload {
+    mark: u8,
+    bytes: Vec<u8>,
+}
+
+impl Payload {
+    pub fn new(mark: u8, bytes: Vec<u8>) -> Self {
+        Self { mark, bytes }
+    }
+}
+
+/// A message waiting to be written, which has a header, a body or both
+#[derive(Debug)]
+struct Transfer {
+    id: MessageId,
+    transfer_id: MessageId,
+    header: Option<Payload>,
+    body: Option<Payload>,
+    /// Number of bytes of the body that are written
+    offset: usize,
+    chunk_size: usize,
+}
+
+impl Transfer {
+    /// Writes the next chunk of the body, or the header along with the rest of the body.
+    /// Returns `true` once the whole message is written
+    async fn write_next<W>(&mut self, writer: &mut W) -> Result<bool, IoError>
+    where
+        W: FrameWrite + Send,
+    {
+        if let Some(body) = &self.body {
+            let remaining = body.bytes.len() - self.offset;
+            if remaining > self.chunk_size {
+                let end = self.offset + self.chunk_size;
+                let chunk = &body.bytes[self.offset..end];
+                let frame_header = FrameHeader::new(
+                    self.transfer_id,
+                    CHUNK_FRAME_ID,
+                    PayloadType::Data,
+                    chunk.len() as u32,
+                )
+                .with_compression(body.mark);
+                writer.write_frame(frame_header, chunk).await?;
+                self.offset = end;
+                return Ok(false);
+            }
+        }
+
+        // The header is written right before the last frame of the body so that the reader
+        // always finds the body right after the header
+        if let Some(header) = self.header.take() {
+            let frame_header = FrameHeader::new(
+                self.id,
+                HEADER_FRAME_ID,
+                PayloadType::Header,
+                header.bytes.len() as u32,
+            )
+            .with_compression(header.mark);
+            writer.write_frame(frame_header, &header.bytes).await?;
+        }
+        if let Some(body) = self.body.take() {
+            let last = &body.bytes[self.offset..];
+            let frame_header = FrameHeader::new(
+                self.transfer_id,
+                BODY_FRAME_ID,
+                PayloadType::Data,
+                last.len() as u32,
+            )
+            .with_compression(body.mark);
+            writer.write_frame(frame_header, last).await?;
+        }
+        Ok(true)
+    }
+}
+
+/// Messages with the same message id, which are written in order
+#[derive(Debug)]
+struct Lane {
+    id: MessageId,
+    transfers: VecDeque<Transfer>,
+}
+
+enum Command {
+    Message {
+        id: MessageId,
+        header: Option<Payload>,
+        body: Option<Payload>,
+        chunk_size: usize,
+    },
+    Close(oneshot::Sender<()>),
+}
+
+/// Writes the pending messages one frame at a time in a round-robin manner
+struct Scheduler<W> {
+    writer: W,
+    lanes: VecDeque<Lane>,
+    next_transfer_id: MessageId,
+    /// Number of messages that are not completely written
+    pending: usize,
+}
+
+impl<W> Scheduler<W>
+where
+    W: FrameWrite + GracefulShutdown + Send,
+{
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            lanes: VecDeque::new(),
+            next_transfer_id: 0,
+            pending: 0,
+        }
+    }
+
+    fn push(
+        &mut self,
+        id: MessageId,
+        header: Option<Payload>,
+        body: Option<Payload>,
+        chunk_size: usize,
+    ) {
+        let transfer = Transfer {
+            id,
+            transfer_id: self.next_transfer_id,
+            header,
+            body,
+            offset: 0,
+            chunk_size,
+        };
+        self.next_transfer_id = self.next_transfer_id.wrapping_add(1);
+        self.pending += 1;
+
+        match self.lanes.iter_mut().find(|lane| lane.id == id) {
+            Some(lane) => lane.transfers.push_back(transfer),
+            None => self.lanes.push_back(Lane {
+                id,
+                transfers: VecDeque::from(vec![transfer]),
+            }),
+        }
+    }
+
+    /// Writes the next frame of the first lane and moves the lane to the back
+    async fn write_next(&mut self) -> Result<(), IoError> {
+        if let Some(mut lane) = self.lanes.pop_front() {
+            if let Some(transfer) = lane.transfers.front_mut() {
+                if transfer.write_next(&mut self.writer).await? {
+                    lane.transfers.pop_front();
+                    self.pending -= 1;
+                }
+            }
+            if !lane.transfers.is_empty() {
+                self.lanes.push_back(lane);
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_all(&mut self) -> Result<(), IoError> {
+        while !self.lanes.is_empty() {
+            self.write_next().await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&mut self, command: Command) -> Result<(), IoError> {
+        match command {
+            Command::Message {
+                id,
+                header,
+                body,
+                chunk_size,
+            } => self.push(id, header, body, chunk_size),
+            Command::Close(done) => {
+                // the pending messages are written before the trailer
+                self.write_all().await?;
+                self.writer.close().await;
+                let _ = done.send(());
+            }
+        }
+        Ok(())
+    }
+
+    async fn run(mut self, commands: Receiver<Command>) {
+        loop {
+            let res = match self.lanes.is_empty() {
+                true => match commands.recv_async().await {
+                    Ok(command) => self.handle(command).await,
+                    // the writing half is dropped
+                    Err(_) => return,
+                },
+                // the new messages wait in the channel until the pending ones are written
+                false if self.pending >= MAX_PENDING_TRANSFERS => self.write_next().await,
+                false => match commands.try_recv() {
+                    Ok(command) => self.handle(command).await,
+                    Err(_) => self.write_next().await,
+                },
+            };
+            if let Err(err) = res {
+                log::error!("{}", err);
+                return;
+            }
+        }
+    }
+}
+
+/// Writing half of the frame transport, which hands the messages to the scheduler task
+pub(crate) struct ChunkedWriter {
+    commands: Sender<Command>,
+    /// The header of a message is held back until its body is written or the message ends
+    pending_header: Option<(MessageId, Payload)>,
+    chunk_size: usize,
+}
+
+impl ChunkedWriter {
+    /// Spawns the scheduler task that writes to `writer`
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: FrameWrite + GracefulShutdown + Send + 'static,
+    {
+        let (commands, rx) = flume::bounded(MAX_PENDING_TRANSFERS);
+        spawn(Scheduler::new(writer).run(rx));
+        Self {
+            commands,
+            pending_header: None,
+            // bodies are not split until the max frame size is agreed upon
+            chunk_size: u32::MAX as usize,
+        }
+    }
+
+    /// Sets the largest payload of a frame. Larger bodies are split into chunks
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    async fn send(
+        &self,
+        id: MessageId,
+        header: Option<Payload>,
+        body: Option<Payload>,
+    ) -> Result<(), IoError> {
+        let command = Command::Message {
+            id,
+            header,
+            body,
+            chunk_size: self.chunk_size,
+        };
+        self.commands
+            .send_async(command)
+            .await
+            .map_err(|_| IoError::new(ErrorKind::BrokenPipe, "Frame scheduler is stopped"))
+    }
+
+    /// Holds back the header until the body is written or the message ends
+    pub async fn write_header(&mut self, id: MessageId, header: Payload) -> Result<(), IoError> {
+        self.end_message().await?;
+        self.pending_header = Some((id, header));
+        Ok(())
+    }
+
+    /// Sends the body along with the header of the same message
+    pub async fn write_body(&mut self, id: MessageId, body: Payload) -> Result<(), IoError> {
+        let header = match self.pending_header.take() {
+            Some((header_id, header)) if header_id == id => Some(header),
+            Some((header_id, header)) => {
+                self.send(header_id, Some(header), None).await?;
+                None
+            }
+            None => None,
+        };
+        self.send(id, header, Some(body)).await
+    }
+
+    /// Sends the header that is held back, which belongs to a message without a body
+    pub async fn end_message(&mut self) -> Result<(), IoError> {
+        match self.pending_header.take() {
+            Some((id, header)) => self.send(id, Some(header), None).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl GracefulShutdown for ChunkedWriter {
+    async fn close(&mut self) {
+        if let Err(err) = self.end_message().await {
+            log::error!("{}", err);
+        }
+        let (done, wait) = oneshot::channel();
+        if self.commands.send_async(Command::Close(done)).await.is_ok() {
+            let _ = wait.await;
+        }
+    }
+}
+
+/// Reading half of the frame transport, which puts the chunks of a body back together
+pub(crate) struct ChunkedReader<R> {
+    reader: R,
+    /// Bodies whose last chunk is not received yet, keyed by the transfer id
+    partial: HashMap<MessageId, Vec<u8>>,
+    /// Transfers whose body exceeds the size limit. Their remaining chunks are skipped
+    dropped: HashSet<MessageId>,
+    limit: MessageSizeLimit,
+    /// Set once the peer starts more transfers than it is allowed to
+    closed: bool,
+}
+
+impl<R> ChunkedReader<R>
+where
+    R: FrameRead + Send,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            partial: HashMap::new(),
+            dropped: HashSet::new(),
+            limit: MessageSizeLimit::default(),
+            closed: false,
+        }
+    }
+
+    pub fn set_size_limit(&mut self, limit: MessageSizeLimit) {
+        self.limit = limit;
+    }
+
+    /// Reads the next frame with the whole body of a message. A frame whose body exceeds
+    /// the size limit is skipped without reading it into memory, and an error is returned
+    /// in its place. If the limit closes the connection, the error is returned before
+    /// the payload is read. The connection is closed if the peer starts more than
+    /// `MAX_PENDING_TRANSFERS` transfers at a time
+    pub async fn read_frame(&mut self) -> Option<Result<Frame, IoError>> {
+        if self.closed {
+            return None;
+        }
+        loop {
+            let header = match self.reader.read_frame_header().await? {
+                Ok(header) => header,
+                Err(err) => return Some(Err(err)),
+            };
+            let len = header.payload_len();
+            // chunks and bodies are keyed by the transfer id rather than the message id
+            let transfer_id = match header.payload_type() {
+                PayloadType::Data => Some(header.message_id()),
+                _ => None,
+            };
+            let is_chunk = transfer_id.is_some() && header.frame_id() == CHUNK_FRAME_ID;
+
+            let received = transfer_id
+                .and_then(|id| self.partial.get(&id))
+                .map(Vec::len)
+                .unwrap_or(0);
+            let dropped = transfer_id
+                .map(|id| self.dropped.contains(&id))
+                .unwrap_or(false);
+            let is_new = is_chunk
+                && !dropped
+                && !transfer_id.is_some_and(|id| self.partial.contains_key(&id));
+            if is_new && self.partial.len() + self.dropped.len() >= MAX_PENDING_TRANSFERS {
+                self.closed = true;
+                return Some(Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    "Too many transfers are in flight",
+                )));
+            }
+            if dropped || received.saturating_add(len) > self.limit.max {
+                if self.limit.close {
+                    return Some(Err(self.limit.error()));
+                }
+                self.reader.skip_payload(len).await.ok()?;
+                if let Some(id) = transfer_id {
+                    self.partial.remove(&id);
+                    if is_chunk {
+                        self.dropped.insert(id);
+                        continue;
+                    }
+                    self.dropped.remove(&id);
+                }
+                return Some(Err(self.limit.error()));
+            }
+
+            let payload = self.reader.read_payload(len).await.ok()?;
+            let mut frame = header.into_frame(payload);
+            if let Some(id) = transfer_id {
+                if is_chunk {
+                    match self.partial.get_mut(&id) {
+                        Some(buf) => buf.extend_from_slice(&frame.payload),
+                        None => {
+                            self.partial.insert(id, frame.payload);
+                        }
+                    }
+                    continue;
+                }
+                if let Some(mut buf) = self.partial.remove(&id) {
+                    buf.extend_from_slice(&frame.payload);
+                    frame.payload = buf;
+                }
+            }
+            return Some(Ok(frame));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(bytes: &[u8]) -> Option<Payload> {
+        Some(Payload::new(0, bytes.to_vec()))
+    }
+
+    #[test]
+    fn chunks_are_interleaved_and_reassembled() {
+        futures::executor::block_on(async {
+            let large = vec![7u8; 1000];
+            let mut scheduler = Scheduler::new(Vec::new());
+            scheduler.push(1, payload(b"header 1"), payload(&large), 100);
+            scheduler.push(2, payload(b"header 2"), payload(b"body 2"), 100);
+            scheduler.push(1, payload(b"end 1"), None, 100);
+            scheduler.write_all().await.unwrap();
+
+            let mut reader = ChunkedReader::new(&scheduler.writer[..]);
+            let mut payloads = Vec::new();
+            while let Some(frame) = reader.read_frame().await {
+                payloads.push(frame.unwrap().payload);
+            }
+            // the small message is not blocked by the large one, and the messages with the
+            // same id stay in order
+            let expected = vec![
+                b"header 2".to_vec(),
+                b"body 2".to_vec(),
+                b"header 1".to_vec(),
+                large,
+                b"end 1".to_vec(),
+            ];
+            assert_eq!(payloads, expected);
+            assert!(reader.partial.is_empty());
+        })
+    }
+
+    #[test]
+    fn oversized_bodies_are_skipped() {
+        futures::executor::block_on(async {
+            let mut scheduler = Scheduler::new(Vec::new());
+            // one body is split into chunks and the other one is sent in a single frame
+            scheduler.push(1, payload(b"header 1"), payload(&[7u8; 1000]), 100);
+            scheduler.write_all().await.unwrap();
+            scheduler.push(2, payload(b"header 2"), payload(&[7u8; 1000]), 4096);
+            scheduler.write_all().await.unwrap();
+            scheduler.push(3, payload(b"header 3"), payload(b"body 3"), 100);
+            scheduler.write_all().await.unwrap();
+
+            let mut reader = ChunkedReader::new(&scheduler.writer[..]);
+            reader.set_size_limit(MessageSizeLimit {
+                max: 500,
+                close: false,
+            });
+            let mut payloads = Vec::new();
+            while let Some(frame) = reader.read_frame().await {
+                match frame {
+                    Ok(frame) => payloads.push(frame.payload),
+                    Err(err) => {
+                        assert!(crate::transport::is_message_too_large(&err));
+                        payloads.push(b"too large".to_vec());
+                    }
+                }
+            }
+            let expected = vec![
+                b"header 1".to_vec(),
+                b"too large".to_vec(),
+                b"header 2".to_vec(),
+                b"too large".to_vec(),
+                b"header 3".to_vec(),
+                b"body 3".to_vec(),
+            ];
+            assert_eq!(payloads, expected);
+            assert!(reader.partial.is_empty());
+            assert!(reader.dropped.is_empty());
+
+            // the first chunk is already too large, and it is not read if the connection
+            // is closed
+            let mut bytes = &scheduler.writer[..];
+            let mut reader = ChunkedReader::new(&mut bytes);
+            reader.set_size_limit(MessageSizeLimit {
+                max: 50,
+                close: true,
+            });
+            assert!(reader.read_frame().await.unwrap().is_err());
+            assert!(reader.partial.is_empty());
+            drop(reader);
+            assert!(scheduler.writer.len() - bytes.len() < 100);
+        })
+    }
+
+    #[test]
+    fn too_many_transfers_close_the_connection() {
+        futures::executor::block_on(async {
+            // the first chunk of every body is written before any of them is complete
+            let mut scheduler = Scheduler::new(Vec::new());
+            for id in 0..MAX_PENDING_TRANSFERS as MessageId {
+                scheduler.push(id, None, payload(&[7u8; 200]), 100);
+            }
+            scheduler.write_all().await.unwrap();
+            let mut reader = ChunkedReader::new(&scheduler.writer[..]);
+            let mut count = 0;
+            while let Some(frame) = reader.read_frame().await {
+                assert_eq!(frame.unwrap().payload, vec![7u8; 200]);
+                count += 1;
+            }
+            assert_eq!(count, MAX_PENDING_TRANSFERS);
+
+            let mut scheduler = Scheduler::new(Vec::new());
+            for id in 0..=MAX_PENDING_TRANSFERS as MessageId {
+                scheduler.push(id, None, payload(&[7u8; 200]), 100);
+            }
+            scheduler.write_all().await.unwrap();
+            let mut reader = ChunkedReader::new(&scheduler.writer[..]);
+            let err = reader.read_frame().await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(reader.partial.len(), MAX_PENDING_TRANSFERS);
+            assert!(reader.read_frame().await.is_none());
+        })
+    }
+}
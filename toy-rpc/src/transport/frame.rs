@@ -7,6 +7,7 @@ use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::io::ErrorKind;
 
+use crate::compression::UNCOMPRESSED;
 use crate::error::IoError;
 use crate::message::MessageId;
 use crate::{error::Error, util::GracefulShutdown};
@@ -30,10 +31,15 @@ cfg_if! {
     }
 }
 
-type FrameId = u8;
+pub(crate) type FrameId = u8;
 type PayloadLen = u32;
 const MAGIC: u8 = 13;
 
+/// The lower four bits of `payload_type` carry the `PayloadType`, and the upper four
+/// bits carry the compression mark of the payload
+const PAYLOAD_TYPE_MASK: u8 = 0x0F;
+const COMPRESSION_SHIFT: u8 = 4;
+
 // const HEADER_LEN: usize = 8; // header length in bytes
 lazy_static! {
     static ref HEADER_LEN: usize =
@@ -48,8 +54,26 @@ lazy_static! {
 ///
 #[async_trait]
 pub trait FrameRead {
+    /// Reads the header of the next frame. Returns `None` once the trailer is received or
+    /// the connection is closed
+    async fn read_frame_header(&mut self) -> Option<Result<FrameHeader, IoError>>;
+
+    /// Reads the payload of the frame whose header is just read
+    async fn read_payload(&mut self, len: usize) -> Result<Vec<u8>, IoError>;
+
+    /// Discards the payload of the frame whose header is just read without holding
+    /// the whole payload in memory
+    async fn skip_payload(&mut self, len: usize) -> Result<(), IoError>;
+
     /// Reads a frame
-    async fn read_frame(&mut self) -> Option<Result<Frame, IoError>>;
+    async fn read_frame(&mut self) -> Option<Result<Frame, IoError>> {
+        let header = match self.read_frame_header().await? {
+            Ok(header) => header,
+            Err(err) => return Some(Err(err)),
+        };
+        let payload = self.read_payload(header.payload_len()).await.ok()?;
+        Some(Ok(header.into_frame(payload)))
+    }
 }
 
 /// Trait for custom binary transport protocol
@@ -72,7 +96,7 @@ pub trait FrameWrite {
 pub struct FrameHeader {
     message_id: MessageId,
     frame_id: FrameId,
-    payload_type: u8, // this is not used for now
+    payload_type: u8,
     payload_len: PayloadLen,
 }
 
@@ -92,6 +116,41 @@ impl FrameHeader {
         }
     }
 
+    /// Marks the payload of the frame as compressed
+    pub fn with_compression(mut self, mark: u8) -> Self {
+        self.payload_type = (self.payload_type & PAYLOAD_TYPE_MASK) | (mark << COMPRESSION_SHIFT);
+        self
+    }
+
+    pub(crate) fn message_id(&self) -> MessageId {
+        self.message_id
+    }
+
+    pub(crate) fn frame_id(&self) -> FrameId {
+        self.frame_id
+    }
+
+    pub(crate) fn payload_type(&self) -> PayloadType {
+        self.payload_type.into()
+    }
+
+    /// Length of the payload in bytes
+    pub(crate) fn payload_len(&self) -> usize {
+        self.payload_len as usize
+    }
+
+    /// Constructs the frame that carries the payload
+    pub(crate) fn into_frame(self, payload: Vec<u8>) -> Frame {
+        let mut frame = Frame::new(
+            self.message_id,
+            self.frame_id,
+            self.payload_type.into(),
+            payload,
+        );
+        frame.compression = self.payload_type >> COMPRESSION_SHIFT;
+        frame
+    }
+
     /// Constructs a new frame header from bytes
     pub fn from_slice(buf: &[u8]) -> Result<Self, Error> {
         DefaultOptions::new()
@@ -128,7 +187,7 @@ impl Default for PayloadType {
 
 impl From<u8> for PayloadType {
     fn from(t: u8) -> Self {
-        match t {
+        match t & PAYLOAD_TYPE_MASK {
             0 => Self::Header,
             1 => Self::Data,
             2 => Self::Trailer,
@@ -152,10 +211,12 @@ impl From<PayloadType> for u8 {
 pub struct Frame {
     /// Message id
     pub message_id: MessageId,
-    /// (RESERVED) Frame id, this is a separate id reserved for multi-frame transport
+    /// Frame id, which tells the header, the body and the chunks of a body apart
     pub frame_id: FrameId,
     /// Type of the payload
     pub payload_type: PayloadType,
+    /// Compression mark of the payload
+    pub compression: u8,
     /// Payload
     pub payload: Vec<u8>,
 }
@@ -172,6 +233,7 @@ impl Frame {
             message_id,
             frame_id,
             payload_type,
+            compression: UNCOMPRESSED,
             payload,
         }
     }
@@ -179,7 +241,7 @@ impl Frame {
 
 #[async_trait]
 impl<R: AsyncRead + Unpin + Send> FrameRead for R {
-    async fn read_frame(&mut self) -> Option<Result<Frame, IoError>> {
+    async fn read_frame_header(&mut self) -> Option<Result<FrameHeader, IoError>> {
         // read magic first
         let magic = &mut [0];
         let _ = self.read_exact(magic).await.ok()?;
@@ -208,17 +270,23 @@ impl<R: AsyncRead + Unpin + Send> FrameRead for R {
                 return None;
             }
         }
+        Some(Ok(header))
+    }
 
-        // read frame payload
-        let mut payload = vec![0; header.payload_len as usize];
-        let _ = self.read_exact(&mut payload).await.ok()?;
+    async fn read_payload(&mut self, len: usize) -> Result<Vec<u8>, IoError> {
+        let mut payload = vec![0; len];
+        self.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
 
-        Some(Ok(Frame::new(
-            header.message_id,
-            header.frame_id,
-            header.payload_type.into(),
-            payload,
-        )))
+    async fn skip_payload(&mut self, mut len: usize) -> Result<(), IoError> {
+        let mut buf = [0; 8 * 1024];
+        while len > 0 {
+            let n = len.min(buf.len());
+            self.read_exact(&mut buf[..n]).await?;
+            len -= n;
+        }
+        Ok(())
     }
 }
 
@@ -1,6 +1,7 @@
 //! Custom binary transport and WebSocket integration
 
 use async_trait::async_trait;
+use cfg_if::cfg_if;
 
 use crate::error::IoError;
 
@@ -8,12 +9,24 @@ use crate::error::IoError;
     any(
         feature = "serde_bincode",
         feature = "serde_cbor",
-        feature = "serde_rmp"
+        feature = "serde_rmp",
+        feature = "serde_gob"
     ),
     any(feature = "async_std_runtime", feature = "tokio_runtime",)
 ))]
 pub(crate) mod frame;
 
+#[cfg(all(
+    any(
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_rmp",
+        feature = "serde_gob"
+    ),
+    any(feature = "async_std_runtime", feature = "tokio_runtime",)
+))]
+pub(crate) mod chunk;
+
 #[cfg(any(feature = "ws_tokio", feature = "ws_async_std"))]
 pub(crate) mod ws;
 
@@ -22,7 +35,8 @@ pub(crate) mod ws;
         any(
             feature = "serde_bincode",
             feature = "serde_cbor",
-            feature = "serde_rmp"
+            feature = "serde_rmp",
+            feature = "serde_gob"
         ),
         any(feature = "async_std_runtime", feature = "tokio_runtime",)
     ),
@@ -33,6 +47,83 @@ pub(crate) fn as_io_err_other(err: &impl std::fmt::Display) -> IoError {
     std::io::Error::new(std::io::ErrorKind::Other, msg)
 }
 
+/// Default max size in bytes of an inbound message
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Limit on the size of the inbound messages of a connection, which is enforced by the
+/// transport before the message is held in memory
+#[derive(Debug, Clone, Copy)]
+pub struct MessageSizeLimit {
+    #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    pub(crate) max: usize,
+    /// Whether an oversized message closes the connection. It is skipped otherwise
+    #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    pub(crate) close: bool,
+}
+
+impl Default for MessageSizeLimit {
+    fn default() -> Self {
+        Self {
+            #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+            max: DEFAULT_MAX_MESSAGE_SIZE,
+            #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+            close: false,
+        }
+    }
+}
+
+impl MessageSizeLimit {
+    #[cfg(any(feature = "server", feature = "client"))]
+    pub(crate) fn new(max: usize, close: bool) -> Self {
+        Self { max, close }
+    }
+
+    /// Error returned in place of an oversized message
+    #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    pub(crate) fn error(&self) -> IoError {
+        message_too_large(self.max)
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "tokio_runtime",
+        feature = "ws_tokio",
+        feature = "ws_async_std"
+    ))] {
+        use crate::error::Error;
+
+        /// Wraps `Error::MessageTooLarge` in an `IoError`, which is unwrapped again when it
+        /// is converted into `Error`
+        pub(crate) fn message_too_large(max: usize) -> IoError {
+            IoError::new(std::io::ErrorKind::InvalidData, Error::MessageTooLarge(max))
+        }
+    }
+}
+
+/// Whether the error is returned in place of an oversized message
+#[cfg(any(
+    feature = "client",
+    feature = "ws_tokio",
+    feature = "ws_async_std",
+    all(
+        any(feature = "async_std_runtime", feature = "tokio_runtime"),
+        any(
+            feature = "serde_bincode",
+            feature = "serde_cbor",
+            feature = "serde_rmp",
+            feature = "serde_gob"
+        )
+    )
+))]
+pub(crate) fn is_message_too_large(err: &IoError) -> bool {
+    err.get_ref()
+        .and_then(|inner| inner.downcast_ref::<Error>())
+        .map(|inner| matches!(inner, Error::MessageTooLarge(_)))
+        .unwrap_or(false)
+}
+
 /// Reads bytes from transport protocols that carry payload (ie. WebSocket)
 #[async_trait]
 pub trait PayloadRead {
@@ -45,4 +136,10 @@ pub trait PayloadRead {
 pub trait PayloadWrite {
     /// Writes bytes to the payload
     async fn write_payload(&mut self, payload: &[u8]) -> Result<(), IoError>;
+
+    /// Writes text to the payload. The text is written as bytes unless the protocol tells
+    /// text apart (ie. the text messages of WebSocket)
+    async fn write_text(&mut self, text: &str) -> Result<(), IoError> {
+        self.write_payload(text.as_bytes()).await
+    }
 }
@@ -6,22 +6,33 @@ use crate::transport::as_io_err_other;
 use super::*;
 use axum::extract::ws::{Message, WebSocket};
 
+/// Converts an error of the WebSocket stream. `axum` depends on another version of
+/// `tungstenite`, so a message that exceeds the max message size is told apart by the
+/// error message, ie. "Space limit exceeded: Message too long: 1025 > 1024"
+fn read_err(err: axum::Error) -> IoError {
+    let msg = err.to_string();
+    let max_size = msg
+        .strip_prefix("Space limit exceeded: Message too long: ")
+        .and_then(|rest| rest.split(" > ").nth(1))
+        .and_then(|max_size| max_size.parse().ok());
+    match max_size {
+        Some(max_size) => crate::transport::message_too_large(max_size),
+        None => std::io::Error::new(ErrorKind::InvalidData, msg),
+    }
+}
+
 #[async_trait]
 impl PayloadRead for StreamHalf<SplitStream<WebSocket>, CanSink> {
     async fn read_payload(&mut self) -> Option<Result<Vec<u8>, IoError>> {
         match self.next().await? {
-            Err(e) => {
-                return Some(Err(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    e.to_string(),
-                )))
-            }
+            Err(e) => return Some(Err(read_err(e))),
             Ok(m) => match m {
                 Message::Close(_) => None,
                 Message::Binary(bytes) => Some(Ok(bytes)),
+                Message::Text(text) => Some(Ok(text.into_bytes())),
                 _ => Some(Err(std::io::Error::new(
                     ErrorKind::InvalidData,
-                    "Expecting WebSocket::Message::Binary, but found something else".to_string(),
+                    "Expecting WebSocket::Message::Binary or WebSocket::Message::Text, but found something else".to_string(),
                 ))),
             },
         }
@@ -37,6 +48,11 @@ impl PayloadWrite for SinkHalf<SplitSink<WebSocket, Message>, CanSink> {
         // provide public API to retrieve the original error.
         self.send(msg).await.map_err(|e| as_io_err_other(&e))
     }
+
+    async fn write_text(&mut self, text: &str) -> Result<(), IoError> {
+        let msg = Message::Text(text.to_owned());
+        self.send(msg).await.map_err(|e| as_io_err_other(&e))
+    }
 }
 
 #[async_trait]
@@ -13,7 +13,7 @@ use std::{io::ErrorKind, marker::PhantomData};
 
 use super::{PayloadRead, PayloadWrite};
 use crate::error::IoError;
-use crate::transport::as_io_err_other;
+use crate::transport::{as_io_err_other, message_too_large};
 use crate::util::GracefulShutdown;
 
 type WsSinkHalf<S> = SinkHalf<SplitSink<S, Message>, CanSink>;
@@ -127,6 +127,29 @@ where
     }
 }
 
+/// Converts an error of the WebSocket stream. A message that exceeds the max message size
+/// of the connection is reported as `Error::MessageTooLarge`
+fn read_err(err: &tungstenite::Error) -> IoError {
+    use tungstenite::error::CapacityError;
+
+    match err {
+        tungstenite::Error::Capacity(CapacityError::MessageTooLong { max_size, .. }) => {
+            message_too_large(*max_size)
+        }
+        err => std::io::Error::new(ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+/// Configures a WebSocket connection to reject the messages and frames that exceed
+/// `max_message_size` before they are read into memory
+pub(crate) fn ws_config(max_message_size: usize) -> tungstenite::protocol::WebSocketConfig {
+    tungstenite::protocol::WebSocketConfig {
+        max_message_size: Some(max_message_size),
+        max_frame_size: Some(max_message_size),
+        ..Default::default()
+    }
+}
+
 #[async_trait]
 impl<T> PayloadRead for StreamHalf<SplitStream<WebSocketStream<T>>, CanSink>
 where
@@ -134,22 +157,18 @@ where
 {
     async fn read_payload(&mut self) -> Option<Result<Vec<u8>, IoError>> {
         match self.next().await? {
-            Err(e) => {
-                return Some(Err(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    e.to_string(),
-                )))
-            }
+            Err(e) => return Some(Err(read_err(&e))),
             Ok(msg) => {
-                if let Message::Binary(bytes) = msg {
-                    return Some(Ok(bytes));
-                } else if let Message::Close(_) = msg {
-                    return None;
+                match msg {
+                    Message::Binary(bytes) => return Some(Ok(bytes)),
+                    Message::Text(text) => return Some(Ok(text.into_bytes())),
+                    Message::Close(_) => return None,
+                    _ => (),
                 }
 
                 Some(Err(std::io::Error::new(
                     ErrorKind::InvalidData,
-                    "Expecting WebSocket::Message::Binary",
+                    "Expecting WebSocket::Message::Binary or WebSocket::Message::Text",
                 )))
             }
         }
@@ -173,6 +192,19 @@ where
             },
         }
     }
+
+    async fn write_text(&mut self, text: &str) -> Result<(), IoError> {
+        use tungstenite::error;
+        let msg = Message::Text(text.to_owned());
+
+        match self.send(msg).await {
+            Ok(_) => Ok(()),
+            Err(err) => match err {
+                error::Error::Io(e) => Err(e),
+                _ => Err(as_io_err_other(&err)),
+            },
+        }
+    }
 }
 
 // GracefulShutdown is only required on the client side.
@@ -41,15 +41,16 @@ impl PayloadRead for StreamHalf<tide_websockets::WebSocketConnection, CannotSink
                 )))
             }
             Ok(msg) => {
-                if let tide_websockets::Message::Binary(bytes) = msg {
-                    return Some(Ok(bytes));
-                } else if let tide_websockets::Message::Close(_) = msg {
-                    return None;
+                match msg {
+                    tide_websockets::Message::Binary(bytes) => return Some(Ok(bytes)),
+                    tide_websockets::Message::Text(text) => return Some(Ok(text.into_bytes())),
+                    tide_websockets::Message::Close(_) => return None,
+                    _ => (),
                 }
 
                 Some(Err(std::io::Error::new(
                     ErrorKind::InvalidData,
-                    "Expecting WebSocket::Message::Binary",
+                    "Expecting WebSocket::Message::Binary or WebSocket::Message::Text",
                 )))
             }
         }
@@ -67,6 +68,16 @@ impl PayloadWrite for SinkHalf<tide_websockets::WebSocketConnection, CannotSink>
             },
         }
     }
+
+    async fn write_text(&mut self, text: &str) -> Result<(), IoError> {
+        match self.inner.send_string(text.to_owned()).await {
+            Ok(_) => Ok(()),
+            Err(err) => match err {
+                tungstenite::error::Error::Io(e) => Err(e),
+                _ => Err(as_io_err_other(&err)),
+            },
+        }
+    }
 }
 
 #[async_trait]
@@ -9,20 +9,27 @@ impl PayloadRead for StreamHalf<SplitStream<WebSocket>, CanSink> {
         let msg = self.next().await?;
         match msg {
             Err(e) => {
-                return Some(Err(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    e.to_string(),
-                )))
+                use std::error::Error;
+
+                // `warp` wraps the error of the same version of `tungstenite`
+                let err = match e
+                    .source()
+                    .and_then(|e| e.downcast_ref::<tungstenite::Error>())
+                {
+                    Some(e) => read_err(e),
+                    None => std::io::Error::new(ErrorKind::InvalidData, e.to_string()),
+                };
+                return Some(Err(err));
             }
             Ok(m) => {
                 if m.is_close() {
                     return None;
-                } else if m.is_binary() {
+                } else if m.is_binary() || m.is_text() {
                     return Some(Ok(m.into_bytes()));
                 }
                 Some(Err(std::io::Error::new(
                     ErrorKind::InvalidData,
-                    "Expecting WebSocket::Message::Binary",
+                    "Expecting WebSocket::Message::Binary or WebSocket::Message::Text",
                 )))
             }
         }
@@ -38,6 +45,11 @@ impl PayloadWrite for SinkHalf<SplitSink<WebSocket, Message>, CanSink> {
         // provide public API to retrieve the original error.
         self.send(msg).await.map_err(|e| as_io_err_other(&e))
     }
+
+    async fn write_text(&mut self, text: &str) -> Result<(), IoError> {
+        let msg = Message::text(text);
+        self.send(msg).await.map_err(|e| as_io_err_other(&e))
+    }
 }
 
 #[async_trait]
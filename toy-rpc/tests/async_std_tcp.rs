@@ -2,17 +2,29 @@ use anyhow::Result;
 
 use async_std::{net::TcpListener, task};
 use futures::channel::oneshot::{channel, Receiver};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use toy_rpc::pubsub::AckModeNone;
 use toy_rpc::{Client, Server};
 
 mod rpc;
 
-async fn test_client(addr: &'static str, mut ready: Receiver<()>) -> Result<()> {
+async fn test_client(
+    addr: &'static str,
+    mut ready: Receiver<()>,
+    server: Server<AckModeNone>,
+) -> Result<()> {
     let _ = ready.try_recv()?.expect("Error receiving ready");
 
     println!("Client received ready");
 
-    let client = Client::dial(addr).await.expect("Error dialing server");
+    // keepalive pings are interleaved with the calls below
+    let mut client = Client::builder()
+        .set_keepalive_interval(Duration::from_millis(20))
+        .register(Arc::new(rpc::Agent))
+        .register_extension(rpc::ECHO_EXTENSION, rpc::echo_extension)
+        .dial(addr)
+        .await
+        .expect("Error dialing server");
 
     rpc::test_get_magic_u8(&client).await;
     rpc::test_get_magic_u16(&client).await;
@@ -28,6 +40,31 @@ async fn test_client(addr: &'static str, mut ready: Receiver<()>) -> Result<()>
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_typed_error(&client).await;
+    rpc::test_status_codes(&client).await;
+    rpc::test_server_streaming(&client).await;
+    rpc::test_server_streaming_error(&client).await;
+    rpc::test_server_streaming_timeout(&client).await;
+    rpc::test_server_streaming_cancel(&client).await;
+    rpc::test_client_streaming(&client).await;
+    rpc::test_client_streaming_cancel(&client).await;
+    rpc::test_bidi_streaming(&client).await;
+    rpc::test_metadata(&mut client).await;
+    rpc::test_context(&client).await;
+    rpc::test_deadline(&client).await;
+    rpc::test_cooperative_cancellation(&client).await;
+    rpc::test_notify(&client).await;
+    rpc::test_batch(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_capabilities(&client).await;
+    rpc::test_extension(&client).await;
+    rpc::test_reverse_call(&server).await;
+    rpc::test_work_queue(&client).await;
+    rpc::test_work_queue_redelivery(&client).await;
+
+    let other = Client::dial(addr).await.expect("Error dialing server");
+    rpc::test_work_queue_tickets(&client, &other).await;
+    other.close().await;
 
     println!("Client received correct RPC result");
     client.close().await;
@@ -39,12 +76,19 @@ async fn run(addr: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .set_keepalive_interval(Duration::from_millis(20))
+        .set_visibility_timeout(Duration::from_millis(200))
+        .register(common_test_service)
+        .register_extension(rpc::ECHO_EXTENSION, rpc::echo_extension)
+        .register_extension(rpc::FAILING_EXTENSION, rpc::failing_extension)
+        .build();
 
     let listener = TcpListener::bind(addr)
         .await
         .expect("Cannot bind to address");
 
+    let reverse_server = server.clone();
     let server_handle = task::spawn(async move {
         println!("Starting server at {}", &addr);
         server.accept(listener).await.unwrap();
@@ -52,7 +96,7 @@ async fn run(addr: &'static str) {
 
     tx.send(()).expect("Error sending ready");
 
-    let client_handle = task::spawn(test_client(addr, rx));
+    let client_handle = task::spawn(test_client(addr, rx, reverse_server));
 
     // stop server after all clients finishes
     client_handle.await.expect("Error testing client");
@@ -9,10 +9,18 @@ cfg_if::cfg_if! {
         )
     ))] {
 
+        use futures::sink::SinkExt;
+        use futures::stream::{self, BoxStream, Stream, StreamExt};
         use serde::{Deserialize, Serialize};
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::time::Duration;
 
         use toy_rpc::macros::export_impl;
+        use toy_rpc::service::{RequestStream, RpcContext};
+        use toy_rpc::status::{Code, Status};
+        use toy_rpc::error::CallError;
         use toy_rpc::Error;
+        use toy_rpc::extension::{ExtensionMessage, ExtensionReply, ExtensionResult};
 
         pub const COMMON_TEST_MAGIC_U8: u8 = 167;
         pub const COMMON_TEST_MAGIC_U16: u16 = 512;
@@ -44,6 +52,142 @@ cfg_if::cfg_if! {
             }
         }
 
+        /// Marker of the extension that echoes the content and body
+        pub const ECHO_EXTENSION: u32 = 1;
+        /// Marker of the extension that always fails
+        pub const FAILING_EXTENSION: u32 = 2;
+        /// Marker that no extension is registered for
+        pub const UNKNOWN_EXTENSION: u32 = 3;
+
+        pub async fn echo_extension(msg: ExtensionMessage) -> ExtensionResult {
+            let content = msg.content().to_string();
+            let body: String = msg.body()?;
+            Ok(Some(ExtensionReply::new(body).with_content(content)))
+        }
+
+        pub async fn failing_extension(_: ExtensionMessage) -> ExtensionResult {
+            Err(Error::ExecutionError("extension failed".into()))
+        }
+
+        /// Domain error returned by `CommonTest::withdraw`
+        #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+        pub enum BalanceError {
+            InsufficientFunds { balance: u64, requested: u64 },
+            AccountLocked,
+        }
+
+        impl From<BalanceError> for Error {
+            fn from(err: BalanceError) -> Self {
+                Error::typed(err)
+            }
+        }
+
+        /// Error returned by `CommonTest::reserve`, which is sent as a typed error
+        /// without a `From` impl or a `Debug` impl
+        #[derive(Serialize, Deserialize)]
+        pub struct ReservationError {
+            pub available: u64,
+        }
+
+        pub const COMMON_TEST_BALANCE: u64 = 100;
+
+        /// Sleeps on a thread, which works with either runtime
+        async fn sleep(duration: Duration) {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            std::thread::spawn(move || {
+                std::thread::sleep(duration);
+                let _ = tx.send(());
+            });
+            let _ = rx.await;
+        }
+
+        /// Number of the reverse calls that observed their cancellation on the client
+        static AGENT_CANCELLATIONS: AtomicU32 = AtomicU32::new(0);
+
+        /// Service registered on the client, which the server calls over the connection
+        pub struct Agent;
+
+        #[export_impl]
+        impl Agent {
+            #[export_method]
+            async fn echo(&self, ctx: RpcContext, args: String) -> Result<(u64, bool, String), String> {
+                Ok((ctx.client_id(), ctx.remote_addr().is_none(), args))
+            }
+
+            #[export_method]
+            async fn echo_error(&self, args: String) -> Result<(), String> {
+                Err(args)
+            }
+
+            #[export_method]
+            async fn never_returns(&self, _: ()) -> Result<(), String> {
+                futures::future::pending().await
+            }
+
+            #[export_method]
+            async fn wait_for_cancel(&self, ctx: RpcContext, _: ()) -> Result<(), String> {
+                ctx.cancelled().await;
+                // cleans up within the grace period of the client
+                sleep(Duration::from_millis(50)).await;
+                AGENT_CANCELLATIONS.fetch_add(1, Ordering::SeqCst);
+                Err("Canceled".to_string())
+            }
+
+            #[export_method]
+            async fn observed_cancellations(&self, _: ()) -> Result<u32, String> {
+                Ok(AGENT_CANCELLATIONS.load(Ordering::SeqCst))
+            }
+        }
+
+        /// Services that are named in Go's convention
+        pub mod go {
+            #![allow(non_snake_case)]
+
+            use serde::{Deserialize, Serialize};
+            use toy_rpc::macros::export_impl;
+
+            /// The `Arith` service of the example of Go's `net/rpc`
+            pub struct Arith;
+
+            /// Arguments of `Arith`, which are named as they are declared in Go
+            #[derive(Debug, Serialize, Deserialize)]
+            #[serde(rename = "Args", rename_all = "PascalCase")]
+            pub struct ArithArgs {
+                pub a: i64,
+                pub b: i64,
+            }
+
+            #[export_impl]
+            impl Arith {
+                #[export_method]
+                async fn Multiply(&self, args: ArithArgs) -> Result<i64, String> {
+                    Ok(args.a * args.b)
+                }
+            }
+        }
+
+        /// Work queue used by the tests
+        pub struct Jobs;
+
+        impl toy_rpc::pubsub::Topic for Jobs {
+            type Item = u32;
+
+            fn topic() -> String {
+                "jobs".into()
+            }
+        }
+
+        /// PubSub topic used by the tests
+        pub struct Ticks;
+
+        impl toy_rpc::pubsub::Topic for Ticks {
+            type Item = u32;
+
+            fn topic() -> String {
+                "ticks".into()
+            }
+        }
+
         #[derive(Debug)]
         pub struct CommonTest {
             magic_u8: u8,
@@ -57,6 +201,8 @@ cfg_if::cfg_if! {
             magic_bool: bool,
             magic_str: &'static str,
             custom_struct: CustomStruct,
+            observed_cancellations: AtomicU32,
+            notifications: AtomicU32,
         }
 
         impl CommonTest {
@@ -73,6 +219,8 @@ cfg_if::cfg_if! {
                     magic_bool: COMMON_TEST_MAGIC_BOOL,
                     magic_str: COMMON_TEST_MAGIC_STR,
                     custom_struct: CustomStruct::new(),
+                    observed_cancellations: AtomicU32::new(0),
+                    notifications: AtomicU32::new(0),
                 }
             }
         }
@@ -138,9 +286,166 @@ cfg_if::cfg_if! {
             async fn echo_error(&self, args: String) -> Result<(), String> {
                 Err(args)
             }
+
+            #[export_method]
+            async fn withdraw(&self, args: u64) -> Result<u64, BalanceError> {
+                match args {
+                    0 => Err(BalanceError::AccountLocked),
+                    n if n > COMMON_TEST_BALANCE => Err(BalanceError::InsufficientFunds {
+                        balance: COMMON_TEST_BALANCE,
+                        requested: n,
+                    }),
+                    n => Ok(COMMON_TEST_BALANCE - n),
+                }
+            }
+
+            #[export_method]
+            async fn reserve(&self, args: u64) -> Result<u64, ReservationError> {
+                match args {
+                    n if n > COMMON_TEST_BALANCE => Err(ReservationError {
+                        available: COMMON_TEST_BALANCE,
+                    }),
+                    n => Ok(n),
+                }
+            }
+
+            #[export_method]
+            async fn panics(&self, _: ()) -> Result<(), String> {
+                panic!("on purpose")
+            }
+
+            #[export_method]
+            async fn deny(&self, args: String) -> Result<(), Error> {
+                Err(Status::permission_denied(args).with_details(vec![4, 2]).into())
+            }
+
+            #[export_method]
+            fn count_to(&self, args: u32) -> impl Stream<Item = Result<u32, String>> {
+                stream::iter((0..args).map(Ok))
+            }
+
+            #[export_method]
+            async fn echo_items(
+                &self,
+                args: Vec<Result<String, String>>,
+            ) -> BoxStream<'static, Result<String, String>> {
+                stream::iter(args).boxed()
+            }
+
+            #[export_method]
+            fn never_ending_stream(&self, _: ()) -> impl Stream<Item = Result<u32, String>> {
+                stream::pending()
+            }
+
+            #[export_method]
+            async fn sum(&self, mut args: RequestStream<i32>) -> Result<i32, String> {
+                let mut sum = 0;
+                while let Some(item) = args.next().await {
+                    sum += item.map_err(|err| err.to_string())?;
+                }
+                Ok(sum)
+            }
+
+            #[export_method]
+            async fn echo_metadata(&self, key: String) -> Result<Option<String>, String> {
+                toy_rpc::service::set_response_metadata("echoed", &key);
+                Ok(toy_rpc::service::request_metadata()
+                    .and_then(|metadata| metadata.get(&key).cloned()))
+            }
+
+            #[export_method]
+            fn echo_metadata_items(
+                &self,
+                keys: Vec<String>,
+            ) -> impl Stream<Item = Result<Option<String>, String>> {
+                stream::iter(keys).map(|key| {
+                    Ok(toy_rpc::service::request_metadata()
+                        .and_then(|metadata| metadata.get(&key).cloned()))
+                })
+            }
+
+            #[export_method]
+            async fn context(
+                &self,
+                ctx: RpcContext,
+                key: String,
+            ) -> Result<(u64, bool, Option<String>), String> {
+                let deadline = ctx.deadline().ok_or("Expecting a deadline")?;
+                let has_time_left = deadline > std::time::Instant::now();
+                Ok((ctx.client_id(), has_time_left, ctx.metadata().get(&key).cloned()))
+            }
+
+            #[export_method]
+            async fn remaining_millis(&self, ctx: RpcContext, _: ()) -> Result<Option<u64>, String> {
+                Ok(ctx.remaining().map(|remaining| remaining.as_millis() as u64))
+            }
+
+            #[export_method]
+            async fn wait_for_cancel(&self, ctx: RpcContext, _: ()) -> Result<(), String> {
+                ctx.cancelled().await;
+                // cleans up after the cancellation, which takes a while and is only
+                // finished within the grace period
+                let (tx, rx) = futures::channel::oneshot::channel();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(50));
+                    let _ = tx.send(());
+                });
+                let _ = rx.await;
+                self.observed_cancellations.fetch_add(1, Ordering::SeqCst);
+                Err("Canceled".to_string())
+            }
+
+            #[export_method]
+            async fn observed_cancellations(&self, _: ()) -> Result<u32, String> {
+                Ok(self.observed_cancellations.load(Ordering::SeqCst))
+            }
+
+            #[export_method]
+            async fn record(&self, args: u32) -> Result<(), String> {
+                self.notifications.fetch_add(args, Ordering::SeqCst);
+                Ok(())
+            }
+
+            #[export_method]
+            async fn notifications(&self, _: ()) -> Result<u32, String> {
+                Ok(self.notifications.load(Ordering::SeqCst))
+            }
+
+            #[export_method]
+            async fn echo(&self, args: String) -> Result<String, String> {
+                Ok(args)
+            }
+
+            // Takes the name of the notification stub of `echo`
+            #[export_method]
+            async fn echo_notify(&self, args: String) -> Result<String, String> {
+                Ok(format!("notified {}", args))
+            }
+
+            #[export_method]
+            fn echo_duplex(
+                &self,
+                args: RequestStream<String>,
+            ) -> impl Stream<Item = Result<String, String>> {
+                args.map(|item| item.map_err(|err| err.to_string()))
+            }
+        }
+
+        use toy_rpc::client::{Call, Client};
+
+        /// Service that forwards the calls to another server
+        pub struct Proxy {
+            pub backend: Client<toy_rpc::pubsub::AckModeNone>,
         }
 
-        use toy_rpc::client::{Client};
+        #[export_impl]
+        impl Proxy {
+            #[export_method]
+            async fn remaining_millis(&self, _: ()) -> Result<Option<u64>, Error> {
+                let method = format!("{}.remaining_millis", COMMON_TEST_SERVICE_NAME);
+                self.backend.call(method, ()).await
+            }
+        }
 
         pub async fn test_get_magic_u8<AckMode>(client: &Client<AckMode>) {
             let reply: u8 = client
@@ -294,8 +599,764 @@ cfg_if::cfg_if! {
             println!("test_execution_error() Passed")
         }
 
+        pub async fn test_typed_error<AckMode>(client: &Client<AckMode>) {
+            let reply = client.common_test().withdraw(40).await.unwrap();
+            assert_eq!(reply, COMMON_TEST_BALANCE - 40);
+
+            let err = client.common_test().withdraw(1000).await.unwrap_err();
+            let expected = BalanceError::InsufficientFunds {
+                balance: COMMON_TEST_BALANCE,
+                requested: 1000,
+            };
+            assert_eq!(err.payload::<BalanceError>(), Some(expected.clone()));
+            assert_eq!(err.to_string(), format!("{:?}", expected));
+            match err {
+                Error::Typed(typed) => {
+                    assert_eq!(typed.decode::<BalanceError>().unwrap(), expected)
+                }
+                e => panic!("Expecting a typed error, found {:?}", e),
+            }
+
+            let err = client.common_test().withdraw(0).await.unwrap_err();
+            assert_eq!(err.payload::<BalanceError>(), Some(BalanceError::AccountLocked));
+
+            // the stubs decode the error type of the method
+            match client.common_test().withdraw(1000).typed().await {
+                Err(CallError::Typed(err)) => assert_eq!(err, expected),
+                res => panic!("Expecting a typed error, found {:?}", res),
+            }
+            let reply = client.common_test().reserve(40).typed().await;
+            assert!(matches!(reply, Ok(40)));
+            match client.common_test().reserve(1000).typed().await {
+                Err(CallError::Typed(err)) => assert_eq!(err.available, COMMON_TEST_BALANCE),
+                Err(CallError::Rpc(err)) => panic!("Expecting a typed error, found {:?}", err),
+                Ok(_) => panic!("Expecting an error"),
+            }
+            // the message of an error without `Debug` is the name of the type
+            let err = client.common_test().reserve(1000).await.unwrap_err();
+            assert_eq!(err.to_string(), std::any::type_name::<ReservationError>());
+
+            // String errors are not typed
+            let err = client
+                .common_test()
+                .echo_error("an error message".to_string())
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::ExecutionError(_)));
+            assert_eq!(err.payload::<String>(), None);
+            println!("test_typed_error() Passed")
+        }
+
+        pub async fn test_status_codes<AckMode>(client: &Client<AckMode>) {
+            let err = client.common_test().panics(()).await.unwrap_err();
+            assert_eq!(err.code(), Code::Internal);
+            assert!(matches!(err, Error::Internal(_)));
+            assert!(err.to_string().contains("on purpose"), "{}", err);
+
+            let err = client.common_test().deny("not allowed".to_string()).await.unwrap_err();
+            let expected = Status::permission_denied("not allowed").with_details(vec![4, 2]);
+            assert_eq!(err.code(), Code::PermissionDenied);
+            match err {
+                Error::Status(status) => assert_eq!(status, expected),
+                e => panic!("Expecting a status, found {:?}", e),
+            }
+
+            let err = client.common_test().echo_error("failed".to_string()).await.unwrap_err();
+            assert_eq!(err.code(), Code::Unknown);
+
+            let err = client.call::<(), ()>("Missing.method", ()).await.unwrap_err();
+            assert_eq!(err.code(), Code::Unimplemented);
+
+            // the server reports the timeout of a stream
+            let mut call = client
+                .set_next_timeout(Duration::from_millis(200))
+                .common_test()
+                .never_ending_stream(());
+            let err = call.next().await.unwrap().unwrap_err();
+            assert_eq!(err.code(), Code::DeadlineExceeded);
+
+            // the connection is still usable after a panic
+            test_get_magic_u8(client).await;
+            println!("test_status_codes() Passed")
+        }
+
+        pub async fn test_server_streaming<AckMode>(client: &Client<AckMode>) {
+            let reply: Vec<u32> = client
+                .common_test()
+                .count_to(5u32)
+                .map(|item| item.expect("Unexpected error executing RPC"))
+                .collect()
+                .await;
+            assert_eq!(reply, vec![0, 1, 2, 3, 4]);
+
+            let reply: Vec<u32> = client
+                .common_test()
+                .count_to(0u32)
+                .map(|item| item.expect("Unexpected error executing RPC"))
+                .collect()
+                .await;
+            assert!(reply.is_empty());
+            println!("test_server_streaming() Passed")
+        }
+
+        pub async fn test_server_streaming_error<AckMode>(client: &Client<AckMode>) {
+            let items = vec![
+                Ok("first".to_string()),
+                Err("second".to_string()),
+                Ok("third".to_string()),
+            ];
+            let reply: Vec<Result<String, toy_rpc::Error>> = client
+                .common_test()
+                .echo_items(items)
+                .collect()
+                .await;
+            assert_eq!(reply.len(), 3);
+            assert_eq!(reply[0].as_ref().unwrap(), "first");
+            match &reply[1] {
+                Err(toy_rpc::Error::ExecutionError(msg)) => assert_eq!(msg, "second"),
+                _ => panic!("Expecting an ExecutionError"),
+            }
+            assert_eq!(reply[2].as_ref().unwrap(), "third");
+            println!("test_server_streaming_error() Passed")
+        }
+
+        pub async fn test_server_streaming_timeout<AckMode>(client: &Client<AckMode>) {
+            let mut call = client
+                .set_next_timeout(Duration::from_millis(200))
+                .common_test()
+                .never_ending_stream(());
+            match call.next().await {
+                Some(Err(toy_rpc::Error::Timeout(id))) => assert_eq!(id, call.id()),
+                _ => panic!("Expecting a timeout error"),
+            }
+            assert!(call.next().await.is_none());
+            println!("test_server_streaming_timeout() Passed")
+        }
+
+        pub async fn test_server_streaming_cancel<AckMode>(client: &Client<AckMode>) {
+            let mut call = client.common_test().never_ending_stream(());
+            call.cancel();
+            assert!(call.next().await.is_none());
+
+            // the connection should still be usable after cancellation
+            test_server_streaming(client).await;
+            println!("test_server_streaming_cancel() Passed")
+        }
+
+        pub async fn test_client_streaming<AckMode>(client: &Client<AckMode>) {
+            let (mut sink, call) = client.common_test().sum();
+            assert_eq!(sink.id(), call.id());
+            for i in 1..=4 {
+                sink.send(i).await.expect("Unexpected error sending request item");
+            }
+            sink.close().await.expect("Unexpected error closing sink");
+            let reply = call.await.expect("Unexpected error executing RPC");
+            assert_eq!(reply, 10);
+
+            // an empty request stream
+            let (sink, call) = client.common_test().sum();
+            drop(sink);
+            let reply = call.await.expect("Unexpected error executing RPC");
+            assert_eq!(reply, 0);
+            println!("test_client_streaming() Passed")
+        }
+
+        pub async fn test_client_streaming_cancel<AckMode>(client: &Client<AckMode>) {
+            let (mut sink, mut call) = client.common_test().sum();
+            sink.send(1).await.expect("Unexpected error sending request item");
+            call.cancel();
+            match call.await {
+                Err(toy_rpc::Error::Canceled(_)) => {}
+                _ => panic!("Expecting a canceled error"),
+            }
+            // items sent after cancellation are discarded
+            sink.send(2).await.expect("Unexpected error sending request item");
+            sink.close().await.expect("Unexpected error closing sink");
+
+            // the connection should still be usable after cancellation
+            test_client_streaming(client).await;
+            println!("test_client_streaming_cancel() Passed")
+        }
+
+        pub async fn test_bidi_streaming<AckMode>(client: &Client<AckMode>) {
+            let (mut sink, mut call) = client.common_test().echo_duplex();
+            for item in &["a", "b", "c"] {
+                sink.send(item.to_string())
+                    .await
+                    .expect("Unexpected error sending request item");
+                let reply = call.next().await.expect("Expecting an item");
+                assert_eq!(reply.expect("Unexpected error executing RPC"), *item);
+            }
+            sink.close().await.expect("Unexpected error closing sink");
+            assert!(call.next().await.is_none());
+            println!("test_bidi_streaming() Passed")
+        }
+
+        pub async fn test_stream_backpressure<AckMode>(client: &Client<AckMode>) {
+            // the items wait for the streams to be read instead of being dropped
+            let (mut sink, call) = client.common_test().sum();
+            for i in 1..=1000 {
+                sink.send(i).await.expect("Unexpected error sending request item");
+            }
+            sink.close().await.expect("Unexpected error closing sink");
+            let reply = call.await.expect("Unexpected error executing RPC");
+            assert_eq!(reply, 500500);
+
+            let call = client.common_test().count_to(1000u32);
+            let reply: Vec<u32> = call
+                .map(|item| item.expect("Unexpected error executing RPC"))
+                .collect()
+                .await;
+            assert_eq!(reply, (0..1000).collect::<Vec<u32>>());
+            println!("test_stream_backpressure() Passed")
+        }
+
+        pub async fn test_streams_read_out_of_order<AckMode>(client: &Client<AckMode>) {
+            // the unread stream does not hold back the items of the other stream
+            let first = client.common_test().count_to(100u32);
+            let second = client.common_test().count_to(100u32);
+            for call in [second, first] {
+                let reply: Vec<u32> = call
+                    .map(|item| item.expect("Unexpected error executing RPC"))
+                    .collect()
+                    .await;
+                assert_eq!(reply, (0..100).collect::<Vec<u32>>());
+            }
+            println!("test_streams_read_out_of_order() Passed")
+        }
+
+        pub async fn test_metadata<AckMode: Sync>(client: &mut Client<AckMode>) {
+            client.set_default_metadata("tenant", "toy");
+            client.set_default_metadata("locale", "en");
+
+            let mut call = client
+                .set_next_metadata("locale", "fr")
+                .common_test()
+                .echo_metadata("locale".to_string());
+            assert!(call.metadata().is_none());
+            let reply = (&mut call).await.expect("Unexpected error executing RPC");
+            assert_eq!(reply.as_deref(), Some("fr"));
+            let metadata = call.metadata().expect("Expecting response metadata");
+            assert_eq!(metadata.get("echoed").map(|s| s.as_str()), Some("locale"));
+
+            // the metadata for the next request should not be reused
+            let reply = client
+                .common_test()
+                .echo_metadata("locale".to_string())
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(reply.as_deref(), Some("en"));
+
+            let items: Vec<Option<String>> = client
+                .set_next_metadata("trace-id", "0af7651916cd43dd")
+                .common_test()
+                .echo_metadata_items(vec!["tenant".to_string(), "trace-id".to_string(), "none".to_string()])
+                .map(|item| item.expect("Unexpected error executing RPC"))
+                .collect()
+                .await;
+            assert_eq!(
+                items,
+                vec![Some("toy".to_string()), Some("0af7651916cd43dd".to_string()), None]
+            );
+
+            // the keys set concurrently are all sent with the next request
+            let keys: Vec<String> = (0..8).map(|i| format!("key-{}", i)).collect();
+            std::thread::scope(|scope| {
+                for key in keys.iter() {
+                    let client = &*client;
+                    scope.spawn(move || {
+                        client.set_next_metadata(key, key);
+                    });
+                }
+            });
+            let items: Vec<Option<String>> = client
+                .common_test()
+                .echo_metadata_items(keys.clone())
+                .map(|item| item.expect("Unexpected error executing RPC"))
+                .collect()
+                .await;
+            assert_eq!(items, keys.into_iter().map(Some).collect::<Vec<_>>());
+            println!("test_metadata() Passed")
+        }
+
+        pub async fn test_context<AckMode>(client: &Client<AckMode>) {
+            let (client_id, has_time_left, value) = client
+                .set_next_metadata("user", "alice")
+                .common_test()
+                .context("user".to_string())
+                .await
+                .expect("Unexpected error executing RPC");
+            assert!(client_id > toy_rpc::server::RESERVED_CLIENT_ID);
+            assert!(has_time_left);
+            assert_eq!(value.as_deref(), Some("alice"));
+
+            // the same connection keeps the same client id
+            let (same_id, _, value) = client
+                .common_test()
+                .context("user".to_string())
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(same_id, client_id);
+            assert!(value.is_none());
+            println!("test_context() Passed")
+        }
+
+        pub async fn test_deadline<AckMode>(client: &Client<AckMode>) {
+            let remaining = client
+                .set_next_timeout(Duration::from_secs(2))
+                .common_test()
+                .remaining_millis(())
+                .await
+                .expect("Unexpected error executing RPC")
+                .expect("Expecting a deadline");
+            assert!(remaining > 1000 && remaining <= 2000, "{}", remaining);
+
+            let remaining = client
+                .set_next_no_timeout()
+                .common_test()
+                .remaining_millis(())
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(remaining, None);
+            println!("test_deadline() Passed")
+        }
+
+        /// `frontend` is connected to a server with the `Proxy` service, which forwards
+        /// the calls to a server with the `CommonTest` service
+        pub async fn test_deadline_propagation<AckMode>(frontend: &Client<AckMode>) {
+            // the proxy's own client has the default timeout of 10 seconds
+            let remaining = frontend
+                .set_next_timeout(Duration::from_millis(500))
+                .proxy()
+                .remaining_millis(())
+                .await
+                .expect("Unexpected error executing RPC")
+                .expect("Expecting a deadline");
+            assert!(remaining <= 500, "{}", remaining);
+
+            // the proxy's client still applies its default timeout
+            let remaining = frontend
+                .set_next_no_timeout()
+                .proxy()
+                .remaining_millis(())
+                .await
+                .expect("Unexpected error executing RPC")
+                .expect("Expecting a deadline");
+            assert!(remaining > 5000, "{}", remaining);
+            println!("test_deadline_propagation() Passed")
+        }
+
+        pub async fn test_cooperative_cancellation<AckMode>(client: &Client<AckMode>) {
+            // The RPC method observes the cancellation in a task on the server, which
+            // may not have finished by the time the client gets the error
+            let observed = |expected: u32| async move {
+                let mut count = 0;
+                for _ in 0..100 {
+                    count = client
+                        .common_test()
+                        .observed_cancellations(())
+                        .await
+                        .expect("Unexpected error executing RPC");
+                    if count == expected {
+                        break;
+                    }
+                }
+                count
+            };
+            let before = observed(0).await;
+
+            // the RPC method observes the timeout
+            let reply = client
+                .set_next_timeout(Duration::from_millis(100))
+                .common_test()
+                .wait_for_cancel(())
+                .await;
+            match reply {
+                Err(toy_rpc::Error::Timeout(_)) => {}
+                _ => panic!("Expecting a timeout error"),
+            }
+            assert_eq!(observed(before + 1).await, before + 1);
+
+            // the RPC method observes the cancellation sent by the client
+            let mut call = client.common_test().wait_for_cancel(());
+            call.cancel();
+            match call.await {
+                Err(toy_rpc::Error::Canceled(_)) => {}
+                _ => panic!("Expecting a canceled error"),
+            }
+            assert_eq!(observed(before + 2).await, before + 2);
+            println!("test_cooperative_cancellation() Passed")
+        }
+
+        pub async fn test_notify<AckMode>(client: &Client<AckMode>) {
+            // The notifications are executed in tasks on the server, which may not have
+            // finished by the time the following call is handled
+            let recorded = |expected: u32| async move {
+                let mut count = 0;
+                for _ in 0..100 {
+                    count = client
+                        .common_test()
+                        .notifications(())
+                        .await
+                        .expect("Unexpected error executing RPC");
+                    if count == expected {
+                        break;
+                    }
+                }
+                count
+            };
+            let before = recorded(0).await;
+
+            let method = format!("{}.record", COMMON_TEST_SERVICE_NAME);
+            client.notify(method, 1u32).expect("Error sending notification");
+            client
+                .common_test()
+                .record_notify(2u32)
+                .expect("Error sending notification");
+            assert_eq!(recorded(before + 3).await, before + 3);
+
+            // Errors are not sent back, and the connection is still usable afterwards
+            client
+                .notify("NonExistService.record", 1u32)
+                .expect("Error sending notification");
+            client
+                .common_test()
+                .echo_error_notify("notified".to_string())
+                .expect("Error sending notification");
+            client
+                .common_test()
+                .panics_notify(())
+                .expect("Error sending notification");
+            client
+                .common_test()
+                .record_notify(4u32)
+                .expect("Error sending notification");
+            assert_eq!(recorded(before + 7).await, before + 7);
+
+            // an exported method keeps its stub over the notification stub of the same name
+            let reply = client
+                .common_test()
+                .echo_notify("hi".to_string())
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(reply, "notified hi");
+            println!("test_notify() Passed")
+        }
+
+        pub async fn test_batch<AckMode>(client: &Client<AckMode>) {
+            let method = |name: &str| format!("{}.{}", COMMON_TEST_SERVICE_NAME, name);
+
+            // the metadata is shared by all calls in the batch
+            let mut batch = client.set_next_metadata("trace-id", "batched").batch();
+            let magic_u8: Call<u8> = batch.call(method("get_magic_u8"), ());
+            let magic_str: Call<String> = batch.call(method("get_magic_str"), ());
+            let mut metadata: Call<Option<String>> =
+                batch.call(method("echo_metadata"), "trace-id".to_string());
+            let withdraw: Call<u64> = batch.call(method("withdraw"), 1000u64);
+            let not_found: Call<()> = batch.call("NonExistService.method", ());
+            let stream: Call<u32> = batch.call(method("count_to"), 3u32);
+            assert_eq!(batch.len(), 6);
+            batch.send().expect("Error sending batch");
+
+            assert_eq!(magic_u8.await.unwrap(), COMMON_TEST_MAGIC_U8);
+            assert_eq!(magic_str.await.unwrap(), COMMON_TEST_MAGIC_STR);
+            let reply = (&mut metadata).await.expect("Unexpected error executing RPC");
+            assert_eq!(reply.as_deref(), Some("batched"));
+            let echoed = metadata.metadata().and_then(|metadata| metadata.get("echoed"));
+            assert_eq!(echoed.map(|s| s.as_str()), Some("trace-id"));
+            let err = withdraw.await.unwrap_err();
+            assert_eq!(
+                err.payload::<BalanceError>(),
+                Some(BalanceError::InsufficientFunds {
+                    balance: COMMON_TEST_BALANCE,
+                    requested: 1000,
+                })
+            );
+            assert!(matches!(not_found.await, Err(Error::ServiceNotFound)));
+            assert!(matches!(stream.await, Err(Error::ExecutionError(_))));
+
+            // the calls of a batch that is not sent are canceled
+            let mut batch = client.batch();
+            let call: Call<u8> = batch.call(method("get_magic_u8"), ());
+            drop(batch);
+            assert!(matches!(call.await, Err(Error::Canceled(_))));
+
+            let batch = client.batch();
+            assert!(batch.is_empty());
+            batch.send().expect("Error sending empty batch");
+            println!("test_batch() Passed")
+        }
+
+        /// Size of the payload sent by `test_large_payload`
+        pub const LARGE_PAYLOAD_LEN: usize = 64 * 1024;
+
+        pub async fn test_large_payload<AckMode>(client: &Client<AckMode>) {
+            let large = COMMON_TEST_MAGIC_STR.repeat(LARGE_PAYLOAD_LEN / COMMON_TEST_MAGIC_STR.len());
+            let reply = client.common_test().echo(large.clone()).await.unwrap();
+            assert_eq!(reply, large);
+
+            let small = COMMON_TEST_MAGIC_STR.to_string();
+            let reply = client.common_test().echo(small.clone()).await.unwrap();
+            assert_eq!(reply, small);
+
+            let items = vec![large.clone(), small, large];
+            let args: Vec<Result<String, String>> = items.iter().cloned().map(Ok).collect();
+            let reply: Vec<String> = client
+                .common_test()
+                .echo_items(args)
+                .map(|item| item.unwrap())
+                .collect()
+                .await;
+            assert_eq!(reply, items);
+            println!("test_large_payload() Passed")
+        }
+
+        /// Max message size of the side that rejects the payload of `test_large_payload`
+        pub const SMALL_MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+        /// Sends a request that exceeds the max message size of the server
+        pub async fn test_oversized_request<AckMode>(client: &Client<AckMode>) {
+            let large = COMMON_TEST_MAGIC_STR.repeat(LARGE_PAYLOAD_LEN / COMMON_TEST_MAGIC_STR.len());
+            let err = client.common_test().echo(large).await.unwrap_err();
+            assert_eq!(err.code(), Code::ResourceExhausted);
+            println!("test_oversized_request() Passed")
+        }
+
+        /// Receives a response that exceeds the max message size of the client
+        pub async fn test_oversized_response<AckMode>(client: &Client<AckMode>) {
+            let large = COMMON_TEST_MAGIC_STR.repeat(LARGE_PAYLOAD_LEN / COMMON_TEST_MAGIC_STR.len());
+            let reply = client.common_test().echo(large).await;
+            assert!(
+                matches!(reply, Err(Error::MessageTooLarge(SMALL_MAX_MESSAGE_SIZE))),
+                "{:?}",
+                reply
+            );
+            println!("test_oversized_response() Passed")
+        }
+
+        pub async fn test_capabilities<AckMode>(client: &Client<AckMode>) {
+            use toy_rpc::protocol::{
+                FEATURE_BATCH, FEATURE_METADATA, FEATURE_NOTIFY, FEATURE_STREAMING, PROTOCOL_VERSION,
+            };
+
+            let capabilities = client.capabilities();
+            assert_eq!(capabilities.version, PROTOCOL_VERSION);
+            assert!(!capabilities.codec.is_empty());
+            assert!(capabilities.supports(FEATURE_STREAMING));
+            assert!(capabilities.supports(FEATURE_METADATA));
+            assert!(capabilities.supports(FEATURE_NOTIFY));
+            assert!(capabilities.supports(FEATURE_BATCH));
+            assert!(capabilities.max_frame_size > 0);
+            println!("test_capabilities() Passed")
+        }
+
+        pub async fn test_extension<AckMode>(client: &Client<AckMode>) {
+            let reply = client
+                .extension(ECHO_EXTENSION, "echo", "hello".to_string())
+                .await
+                .expect("Error sending extension message");
+            assert_eq!(reply.content(), "echo");
+            let body: String = reply.body().expect("Error deserializing reply");
+            assert_eq!(body, "hello");
+
+            let reply = client
+                .extension(FAILING_EXTENSION, "", ())
+                .await;
+            assert!(matches!(reply, Err(Error::ExecutionError(_))));
+
+            let reply = client
+                .extension(UNKNOWN_EXTENSION, "", ())
+                .await;
+            assert!(matches!(reply, Err(Error::UnknownExtension(UNKNOWN_EXTENSION))));
+            println!("test_extension() Passed")
+        }
+
+        /// The client must register `Agent` and the echo extension, and it must be the
+        /// only client connected to the server
+        #[cfg(feature = "server")]
+        pub async fn test_reverse_call<AckMode>(server: &toy_rpc::Server<AckMode>) {
+            let ids = server.client_ids();
+            assert_eq!(ids.len(), 1);
+            let mut handle = server.client(ids[0]).expect("Client is not connected");
+            assert_eq!(handle.client_id(), ids[0]);
+
+            let reply: (u64, bool, String) = handle
+                .call("Agent.echo", "hello".to_string())
+                .await
+                .expect("Error calling client");
+            assert_eq!(reply, (toy_rpc::server::RESERVED_CLIENT_ID, true, "hello".to_string()));
+
+            let reply = handle
+                .call::<_, ()>("Agent.echo_error", "reverse error".to_string())
+                .await;
+            match reply {
+                Err(Error::ExecutionError(msg)) => assert_eq!(msg, "reverse error"),
+                _ => panic!("Expecting Error::ExecutionError"),
+            }
+
+            let reply = handle.call::<_, ()>("Missing.echo", ()).await;
+            assert!(matches!(reply, Err(Error::ServiceNotFound)));
+
+            handle.set_timeout(Duration::from_millis(100));
+            let reply = handle.call::<_, ()>("Agent.never_returns", ()).await;
+            assert!(matches!(reply, Err(Error::Timeout(_))));
+
+            // the reverse call observes the timeout and cleans up within the grace period
+            let before: u32 = handle
+                .call("Agent.observed_cancellations", ())
+                .await
+                .expect("Error calling client");
+            let reply = handle.call::<_, ()>("Agent.wait_for_cancel", ()).await;
+            assert!(matches!(reply, Err(Error::Timeout(_))));
+            let mut observed = before;
+            for _ in 0..100 {
+                observed = handle
+                    .call("Agent.observed_cancellations", ())
+                    .await
+                    .expect("Error calling client");
+                if observed > before {
+                    break;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(observed, before + 1);
+
+            let reply = handle
+                .extension(ECHO_EXTENSION, "echo", "hello".to_string())
+                .await
+                .expect("Error sending extension message");
+            assert_eq!(reply.content(), "echo");
+            let body: String = reply.body().expect("Error deserializing reply");
+            assert_eq!(body, "hello");
+
+            let reply = handle.extension(UNKNOWN_EXTENSION, "", ()).await;
+            assert!(matches!(reply, Err(Error::UnknownExtension(UNKNOWN_EXTENSION))));
+            println!("test_reverse_call() Passed")
+        }
+
+        pub async fn test_work_queue<AckMode>(client: &Client<AckMode>) {
+            let mut producer = client.producer::<Jobs>();
+            let mut consumer = client.consumer::<Jobs>();
+            for job in 1..=3 {
+                producer.send(job).await.expect("Error producing job");
+            }
+            let mut jobs = Vec::new();
+            for _ in 0..3 {
+                let delivery = consumer
+                    .next()
+                    .await
+                    .expect("Consumer is closed")
+                    .expect("Error consuming job");
+                jobs.push(delivery.ack().await.expect("Error acking job"));
+            }
+            jobs.sort();
+            assert_eq!(jobs, vec![1, 2, 3]);
+
+            // competing local consumers get different jobs
+            let mut other = client.consumer::<Jobs>();
+            producer.send(4).await.expect("Error producing job");
+            producer.send(5).await.expect("Error producing job");
+            let (a, b) = futures::join!(consumer.next(), other.next());
+            let a = a.unwrap().unwrap().ack().await.unwrap();
+            let b = b.unwrap().unwrap().ack().await.unwrap();
+            assert_eq!(a + b, 9);
+            assert_ne!(a, b);
+            println!("test_work_queue() Passed")
+        }
+
+        pub async fn test_work_queue_tickets<AckMode>(
+            client: &Client<AckMode>,
+            other: &Client<AckMode>,
+        ) {
+            let mut producer = client.producer::<Jobs>().with_tickets(2);
+            let mut consumer = client.consumer::<Jobs>();
+            let mut other_consumer = other.consumer::<Jobs>();
+            producer.send(7).await.expect("Error producing job");
+
+            // each ticket is delivered to a different consumer. Both consumers pull before
+            // either acks, otherwise the job is done once the only consumer acks it
+            let (job, other_job) = futures::join!(consumer.next(), other_consumer.next());
+            assert_eq!(job.unwrap().unwrap().ack().await.unwrap(), 7);
+            assert_eq!(other_job.unwrap().unwrap().ack().await.unwrap(), 7);
+
+            // a consumer does not get a second ticket of the same job
+            let mut producer = producer.with_tickets(1);
+            producer.send(8).await.expect("Error producing job");
+            let job = consumer.next().await.unwrap().unwrap();
+            assert_eq!(job.ack().await.unwrap(), 8);
+            println!("test_work_queue_tickets() Passed")
+        }
+
+        /// The server must use a short visibility timeout
+        pub async fn test_work_queue_redelivery<AckMode>(client: &Client<AckMode>) {
+            let mut producer = client.producer::<Jobs>();
+            let mut consumer = client.consumer::<Jobs>();
+            producer.send(9).await.expect("Error producing job");
+
+            // the job is not acked and is delivered again after the visibility timeout
+            let job = consumer.next().await.unwrap().unwrap();
+            drop(job);
+            let job = consumer.next().await.unwrap().unwrap();
+            assert_eq!(job.ack().await.unwrap(), 9);
+            println!("test_work_queue_redelivery() Passed")
+        }
+
         pub fn simply_panic() {
             panic!("just panics");
         }
+
+        /// Calls the server at `base` in the `jsonrpc` format. Browsers send the objects in
+        /// text messages and expect text messages in return
+        #[cfg(all(
+            feature = "serde_json",
+            any(feature = "ws_tokio", feature = "ws_async_std")
+        ))]
+        pub async fn test_jsonrpc_websocket(base: &str) {
+            #[cfg(all(feature = "ws_async_std", not(feature = "ws_tokio")))]
+            use async_tungstenite::async_std::connect_async;
+            #[cfg(feature = "ws_tokio")]
+            use async_tungstenite::tokio::connect_async;
+            use async_tungstenite::tungstenite::Message;
+            use serde_json::{json, Value};
+            use toy_rpc::codec::Format;
+
+            let url = format!("ws://{}/rpc/{}", base, toy_rpc::DEFAULT_RPC_PATH);
+            let (mut ws, _) = connect_async(url).await.expect("Error connecting to server");
+            let batch = json!([
+                {"jsonrpc": "2.0", "method": "CommonTest.get_magic_u64", "id": 1},
+                {"jsonrpc": "2.0", "method": "CommonTest.undefined_method", "id": 2},
+            ]);
+            ws.send(Message::Text(batch.to_string())).await.unwrap();
+            let mut replies: Vec<Value> = match ws.next().await {
+                Some(Ok(Message::Text(text))) => serde_json::from_str(&text).unwrap(),
+                msg => panic!("Expecting a text message, found {:?}", msg),
+            };
+            replies.sort_by_key(|reply| reply["id"].as_u64());
+            assert_eq!(
+                replies,
+                vec![
+                    json!({"jsonrpc": "2.0", "result": COMMON_TEST_MAGIC_U64, "id": 1}),
+                    json!({
+                        "jsonrpc": "2.0",
+                        "error": {"code": -32601, "message": "Method not found"},
+                        "id": 2
+                    }),
+                ]
+            );
+            ws.close(None).await.unwrap();
+
+            let client = Client::builder()
+                .set_format(Format::JsonRpc)
+                .dial_http(&format!("ws://{}/rpc/", base))
+                .await
+                .expect("Error dialing server");
+            test_get_magic_u64(&client).await;
+            test_get_magic_str(&client).await;
+            test_method_not_found(&client).await;
+            test_execution_error(&client).await;
+            test_typed_error(&client).await;
+            client.close().await;
+            println!("test_jsonrpc_websocket() Passed")
+        }
     }
 }
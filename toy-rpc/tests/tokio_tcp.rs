@@ -1,18 +1,39 @@
 use anyhow::Result;
 use futures::channel::oneshot::{channel, Receiver};
-use std::{str, sync::Arc};
+use futures::{SinkExt, StreamExt};
+use std::{
+    str,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpListener;
 use tokio::task;
-use toy_rpc::{Client, Server};
+use toy_rpc::pubsub::AckModeNone;
+use toy_rpc::status::Code;
+use toy_rpc::{Client, Error, Server};
 
 mod rpc;
 
-async fn test_client(addr: &'static str, mut ready: Receiver<()>) -> Result<()> {
+async fn test_client(
+    addr: &'static str,
+    mut ready: Receiver<()>,
+    server: Server<AckModeNone>,
+) -> Result<()> {
     let _ = ready.try_recv()?.expect("Error receiving ready");
 
     println!("Client received ready");
 
-    let client = Client::dial(addr).await.expect("Error dialing server");
+    // the server calls the services registered on the client
+    let mut client = Client::builder()
+        .register(Arc::new(rpc::Agent))
+        .register_extension(rpc::ECHO_EXTENSION, rpc::echo_extension)
+        .dial(addr)
+        .await
+        .expect("Error dialing server");
 
     rpc::test_get_magic_u8(&client).await;
     rpc::test_get_magic_u16(&client).await;
@@ -28,6 +49,31 @@ async fn test_client(addr: &'static str, mut ready: Receiver<()>) -> Result<()>
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_typed_error(&client).await;
+    rpc::test_status_codes(&client).await;
+    rpc::test_server_streaming(&client).await;
+    rpc::test_server_streaming_error(&client).await;
+    rpc::test_server_streaming_timeout(&client).await;
+    rpc::test_server_streaming_cancel(&client).await;
+    rpc::test_client_streaming(&client).await;
+    rpc::test_client_streaming_cancel(&client).await;
+    rpc::test_bidi_streaming(&client).await;
+    rpc::test_metadata(&mut client).await;
+    rpc::test_context(&client).await;
+    rpc::test_deadline(&client).await;
+    rpc::test_cooperative_cancellation(&client).await;
+    rpc::test_notify(&client).await;
+    rpc::test_batch(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_capabilities(&client).await;
+    rpc::test_extension(&client).await;
+    rpc::test_reverse_call(&server).await;
+    rpc::test_work_queue(&client).await;
+    rpc::test_work_queue_redelivery(&client).await;
+
+    let other = Client::dial(addr).await.expect("Error dialing server");
+    rpc::test_work_queue_tickets(&client, &other).await;
+    other.close().await;
 
     println!("Client received all correct RPC result");
     client.close().await;
@@ -40,12 +86,32 @@ async fn run(addr: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let connected_client = Arc::new(AtomicU64::new(0));
+    let hook_client = connected_client.clone();
+    let server = Server::builder()
+        .on_connect(move |info| {
+            assert!(info.remote_addr().is_some());
+            assert!(info.capabilities().max_frame_size > 0);
+            hook_client.store(info.client_id(), Ordering::SeqCst);
+        })
+        .set_visibility_timeout(Duration::from_millis(200))
+        .register(common_test_service)
+        .register_extension(rpc::ECHO_EXTENSION, rpc::echo_extension)
+        .register_extension(rpc::FAILING_EXTENSION, rpc::failing_extension)
+        .build();
+
+    // work queue on the server side
+    let mut producer = server.producer::<rpc::Jobs>();
+    let mut consumer = server.consumer::<rpc::Jobs>();
+    producer.send(11).await.expect("Error producing job");
+    let job = consumer.next().await.unwrap().unwrap();
+    assert_eq!(job.ack().await.unwrap(), 11);
 
     let listener = TcpListener::bind(addr)
         .await
         .expect("Cannot bind to address");
 
+    let reverse_server = server.clone();
     let server_handle = task::spawn(async move {
         println!("Starting server at {}", &addr);
         server.accept(listener).await.unwrap();
@@ -53,18 +119,1110 @@ async fn run(addr: &'static str) {
 
     tx.send(()).expect("Error sending ready");
 
-    let client_handle = task::spawn(test_client(addr, rx));
+    let client_handle = task::spawn(test_client(addr, rx, reverse_server));
 
     // stop server after all clients finishes
     client_handle
         .await
         .expect("Error joining client thread")
         .expect("Error testing client");
+    assert!(connected_client.load(Ordering::SeqCst) > toy_rpc::server::RESERVED_CLIENT_ID);
 
     println!("Aborting server");
     server_handle.abort();
 }
 
+async fn incompatible_peer() {
+    let (stream, mut peer) = tokio::io::duplex(1024);
+    // the peer does not speak the toy-rpc protocol
+    let peer_handle = task::spawn(async move {
+        peer.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+            .await
+            .unwrap();
+        peer
+    });
+
+    match Client::with_stream(stream).await {
+        Err(Error::IncompatiblePeer(_)) => {}
+        Err(err) => panic!("Expecting incompatible peer error, found {:?}", err),
+        Ok(_) => panic!("Expecting incompatible peer error"),
+    }
+    drop(peer_handle.await.unwrap());
+}
+
+#[test]
+fn test_incompatible_peer() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(incompatible_peer());
+}
+
+async fn silent_peer() {
+    let timed_out = |result: Result<_, Error>| match result {
+        Err(Error::IoError(err)) => err.kind() == std::io::ErrorKind::TimedOut,
+        _ => false,
+    };
+
+    // a client that connects but never sends its capabilities is disconnected
+    let server = Server::builder()
+        .set_handshake_timeout(Duration::from_millis(100))
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move { server.accept(listener).await });
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    // the capabilities of the server are read and discarded until the connection is closed
+    let mut buf = Vec::new();
+    let closed = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf)).await;
+    assert!(closed.is_ok(), "The handshake is not timed out");
+    server_handle.abort();
+
+    // likewise, the client gives up on a server that does not answer
+    let (stream, _peer) = tokio::io::duplex(1024);
+    let dialed = Client::builder()
+        .set_handshake_timeout(Duration::from_millis(100))
+        .with_stream(stream);
+    let dialed = tokio::time::timeout(Duration::from_secs(5), dialed)
+        .await
+        .expect("The handshake is not timed out");
+    assert!(timed_out(dialed.map(|_| ())));
+}
+
+#[test]
+fn test_silent_peer() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(silent_peer());
+}
+
+/// Forwards the bytes until `frozen` is set. Afterwards nothing is forwarded but
+/// both ends are kept open, which looks like a half-open connection to the peers
+async fn forward<R, W>(mut from: R, mut to: W, frozen: Arc<AtomicBool>)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = match from.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        if frozen.load(Ordering::SeqCst) {
+            futures::future::pending::<()>().await;
+        }
+        if to.write_all(&buf[..n]).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn dead_peer() {
+    let server = Server::builder()
+        .set_keepalive_interval(Duration::from_millis(50))
+        .set_keepalive_miss_threshold(2)
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+
+    let (client_io, client_proxy) = tokio::io::duplex(4096);
+    let (server_io, server_proxy) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    let frozen = Arc::new(AtomicBool::new(false));
+    task::spawn(forward(client_read, server_write, frozen.clone()));
+    task::spawn(forward(server_read, client_write, frozen.clone()));
+    let server_handle = task::spawn(async move { server.serve_stream(server_io).await });
+
+    let client = Client::builder()
+        .set_keepalive_interval(Duration::from_millis(50))
+        .set_keepalive_miss_threshold(2)
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+
+    // the connection stays alive as long as the pings are answered
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    rpc::test_get_magic_u8(&client).await;
+
+    let method = format!("{}.wait_for_cancel", rpc::COMMON_TEST_SERVICE_NAME);
+    let call = client.call::<(), ()>(method, ());
+    frozen.store(true, Ordering::SeqCst);
+    let reply = tokio::time::timeout(Duration::from_secs(2), call)
+        .await
+        .expect("Dead server is not detected");
+    assert!(matches!(reply, Err(Error::ConnectionLost)), "{:?}", reply);
+
+    // the server closes the connection as well
+    tokio::time::timeout(Duration::from_secs(2), server_handle)
+        .await
+        .expect("Dead client is not detected")
+        .unwrap()
+        .unwrap();
+}
+
+#[test]
+fn test_dead_peer() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(dead_peer());
+}
+
+async fn deadline_propagation() {
+    let backend = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let (backend_io, server_io) = tokio::io::duplex(4096);
+    task::spawn(async move { backend.serve_stream(server_io).await });
+    let backend = Client::with_stream(backend_io)
+        .await
+        .expect("Error connecting to backend");
+
+    let frontend = Server::builder()
+        .register(Arc::new(rpc::Proxy { backend }))
+        .build();
+    let (frontend_io, server_io) = tokio::io::duplex(4096);
+    task::spawn(async move { frontend.serve_stream(server_io).await });
+    let client = Client::with_stream(frontend_io)
+        .await
+        .expect("Error connecting to frontend");
+
+    rpc::test_deadline_propagation(&client).await;
+}
+
+#[test]
+fn test_deadline_propagation() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(deadline_propagation());
+}
+
+/// Forwards the bytes and counts them
+async fn forward_counted<R, W>(mut from: R, mut to: W, count: Arc<AtomicU64>)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = match from.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        count.fetch_add(n as u64, Ordering::SeqCst);
+        if to.write_all(&buf[..n]).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(any(feature = "compression_deflate", feature = "compression_lz4"))]
+async fn compression() {
+    use toy_rpc::compression::Compression;
+
+    let mut algorithms = Compression::available();
+    let server_compression = algorithms.next().unwrap();
+    // each side uses its own algorithm if more than one is enabled
+    let client_compression = algorithms.next().unwrap_or(server_compression);
+
+    let server = Server::builder()
+        .set_compression(server_compression)
+        .set_compression_threshold(64)
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+
+    let (client_io, client_proxy) = tokio::io::duplex(4096);
+    let (server_io, server_proxy) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    let sent = Arc::new(AtomicU64::new(0));
+    let received = Arc::new(AtomicU64::new(0));
+    task::spawn(forward_counted(client_read, server_write, sent.clone()));
+    task::spawn(forward_counted(server_read, client_write, received.clone()));
+    task::spawn(async move { server.serve_stream(server_io).await });
+
+    let client = Client::builder()
+        .set_compression(client_compression)
+        .set_compression_threshold(64)
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+    assert!(client
+        .capabilities()
+        .supports(&client_compression.feature()));
+
+    rpc::test_large_payload(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_batch(&client).await;
+
+    // the large payloads are compressed in both directions
+    assert!(sent.load(Ordering::SeqCst) < rpc::LARGE_PAYLOAD_LEN as u64);
+    assert!(received.load(Ordering::SeqCst) < rpc::LARGE_PAYLOAD_LEN as u64);
+    client.close().await;
+}
+
+#[cfg(any(feature = "compression_deflate", feature = "compression_lz4"))]
+#[test]
+fn test_compression() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(compression());
+}
+
+async fn chunking() {
+    use futures::future::{self, Either};
+    use toy_rpc::{client::Call, protocol::FEATURE_CHUNKING};
+
+    let server = Server::builder()
+        .set_max_frame_size(4096)
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+
+    let (client_io, client_proxy) = tokio::io::duplex(4096);
+    let (server_io, server_proxy) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    let sent = Arc::new(AtomicU64::new(0));
+    let received = Arc::new(AtomicU64::new(0));
+    task::spawn(forward_counted(client_read, server_write, sent.clone()));
+    task::spawn(forward_counted(server_read, client_write, received));
+    task::spawn(async move { server.serve_stream(server_io).await });
+
+    let client = Client::builder()
+        .set_max_frame_size(1024)
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+    // the smaller max frame size is used
+    assert!(client.capabilities().supports(FEATURE_CHUNKING));
+    assert_eq!(client.capabilities().max_frame_size, 1024);
+
+    rpc::test_large_payload(&client).await;
+    rpc::test_batch(&client).await;
+
+    // the small call is not held back by the large request that is sent before it
+    let huge = "a".repeat(64 * rpc::LARGE_PAYLOAD_LEN);
+    let before = sent.load(Ordering::SeqCst);
+    let large: Call<String> = client.call("CommonTest.echo", huge.clone());
+    let small: Call<String> = client.call("CommonTest.get_magic_str", ());
+    let large = match future::select(large, small).await {
+        Either::Right((reply, large)) => {
+            assert_eq!(reply.unwrap(), rpc::COMMON_TEST_MAGIC_STR);
+            assert!(sent.load(Ordering::SeqCst) - before < huge.len() as u64);
+            large
+        }
+        Either::Left(_) => panic!("Small call is held back by the large call"),
+    };
+    assert_eq!(large.await.unwrap(), huge);
+    client.close().await;
+}
+
+#[test]
+fn test_chunking() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(chunking());
+}
+
+/// Forwards the frames and replaces the body of every batch with bytes that cannot be
+/// parsed
+async fn forward_corrupting_batches<R, W>(mut from: R, mut to: W)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    use bincode::Options;
+    use std::convert::TryInto;
+    use toy_rpc::protocol::Header;
+
+    let options = bincode::DefaultOptions::new().with_varint_encoding();
+    let mut batch_body_next = false;
+    loop {
+        // the magic byte followed by the frame header, whose fields are fixint encoded
+        let mut frame_header = [0u8; 15];
+        if from.read_exact(&mut frame_header).await.is_err() {
+            return;
+        }
+        let payload_type = frame_header[10] & 0x0F;
+        let len = u32::from_le_bytes(frame_header[11..15].try_into().unwrap());
+        let mut payload = vec![0u8; len as usize];
+        if from.read_exact(&mut payload).await.is_err() {
+            return;
+        }
+        match payload_type {
+            // the body of a message is the frame right after its header
+            0 => {
+                batch_body_next = matches!(options.deserialize(&payload), Ok(Header::Batch { .. }))
+            }
+            1 if batch_body_next => {
+                batch_body_next = false;
+                payload = vec![0xFF; 4];
+                frame_header[11..15].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+            }
+            _ => {}
+        }
+        if to.write_all(&frame_header).await.is_err() || to.write_all(&payload).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn malformed_batch() {
+    use toy_rpc::client::Call;
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+
+    let (client_io, client_proxy) = tokio::io::duplex(4096);
+    let (server_io, server_proxy) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    task::spawn(forward_corrupting_batches(client_read, server_write));
+    task::spawn(forward(
+        server_read,
+        client_write,
+        Arc::new(AtomicBool::new(false)),
+    ));
+    task::spawn(async move { server.serve_stream(server_io).await });
+
+    let client = Client::with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+
+    let method = |name: &str| format!("{}.{}", rpc::COMMON_TEST_SERVICE_NAME, name);
+    let mut batch = client.batch();
+    let magic_u8: Call<u8> = batch.call(method("get_magic_u8"), ());
+    let echo: Call<String> = batch.call(method("echo"), "hello".to_string());
+    batch.send().expect("Error sending batch");
+
+    // the calls fail as soon as the server reports the malformed batch
+    tokio::time::timeout(Duration::from_secs(2), async {
+        assert!(matches!(magic_u8.await, Err(Error::InvalidArgument)));
+        assert!(matches!(echo.await, Err(Error::InvalidArgument)));
+    })
+    .await
+    .expect("Calls of the malformed batch are not failed");
+
+    // the other requests are not affected
+    rpc::test_get_magic_u8(&client).await;
+    client.close().await;
+}
+
+#[test]
+fn test_malformed_batch() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(malformed_batch());
+}
+
+async fn max_message_size(close: bool) {
+    let server = Server::builder()
+        .set_max_message_size(rpc::SMALL_MAX_MESSAGE_SIZE)
+        .set_close_on_oversized_message(close)
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let (client_io, server_io) = tokio::io::duplex(4096);
+    task::spawn(async move { server.serve_stream(server_io).await });
+    let client = Client::builder()
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+
+    match close {
+        true => {
+            let large = "a".repeat(rpc::LARGE_PAYLOAD_LEN);
+            let reply: Result<String, Error> = client.call("CommonTest.echo", large).await;
+            assert!(reply.is_err());
+            // the server has closed the connection
+            let reply: Result<String, Error> = client.call("CommonTest.get_magic_str", ()).await;
+            assert!(reply.is_err());
+        }
+        false => {
+            rpc::test_oversized_request(&client).await;
+            rpc::test_get_magic_str(&client).await;
+        }
+    }
+    client.close().await;
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let (client_io, server_io) = tokio::io::duplex(4096);
+    task::spawn(async move { server.serve_stream(server_io).await });
+    let client = Client::builder()
+        .set_max_message_size(rpc::SMALL_MAX_MESSAGE_SIZE)
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+
+    rpc::test_oversized_response(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    client.close().await;
+}
+
+#[test]
+fn test_max_message_size() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(max_message_size(false));
+    rt.block_on(max_message_size(true));
+}
+
+async fn backpressure() {
+    use toy_rpc::queue::FullQueuePolicy;
+
+    let server = Server::builder()
+        .set_pubsub_queue_capacity(4)
+        .set_full_queue_policy(FullQueuePolicy::DropOldest)
+        .build();
+    let publishing_server = server.clone();
+
+    let (client_io, client_proxy) = tokio::io::duplex(4096);
+    let (server_io, server_proxy) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    let frozen = Arc::new(AtomicBool::new(false));
+    task::spawn(forward(
+        client_read,
+        server_write,
+        Arc::new(AtomicBool::new(false)),
+    ));
+    task::spawn(forward(server_read, client_write, frozen.clone()));
+    task::spawn(async move { server.serve_stream(server_io).await });
+
+    let mut client = Client::with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+    let mut subscriber = client.subscriber::<rpc::Ticks>(None).unwrap();
+    let mut publisher = publishing_server.publisher::<rpc::Ticks>();
+    // the first publication tells that the subscription is registered
+    while tokio::time::timeout(Duration::from_millis(100), subscriber.next())
+        .await
+        .is_err()
+    {
+        publisher.send(0).await.unwrap();
+    }
+
+    // the client stops reading, and the publications to it are dropped
+    // instead of holding back the publisher
+    frozen.store(true, Ordering::SeqCst);
+    tokio::time::timeout(Duration::from_secs(2), async {
+        for i in 1..=1000 {
+            publisher.send(i).await.unwrap();
+        }
+    })
+    .await
+    .expect("Publisher is held back by the slow subscriber");
+
+    let client_id = publishing_server.client_ids()[0];
+    let handle = publishing_server.client(client_id).unwrap();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while handle.publication_queue_metrics().dropped() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("No publication is dropped");
+    let metrics = handle.publication_queue_metrics();
+    assert_eq!(metrics.capacity(), 4);
+    assert!(metrics.high_water_mark() <= 4);
+    assert!(handle.writer_queue_metrics().high_water_mark() > 0);
+}
+
+#[test]
+fn test_backpressure() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(backpressure());
+}
+
+async fn stream_backpressure() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .set_broker_queue_capacity(2)
+        .build();
+    let (client_io, server_io) = tokio::io::duplex(4096);
+    task::spawn(async move { server.serve_stream(server_io).await });
+    let client = Client::builder()
+        .set_broker_queue_capacity(2)
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+
+    rpc::test_stream_backpressure(&client).await;
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        rpc::test_streams_read_out_of_order(&client),
+    )
+    .await
+    .expect("The streams read out of order are stuck");
+    client.close().await;
+}
+
+#[test]
+fn test_stream_backpressure() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(stream_backpressure());
+}
+
+async fn sink_backpressure() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let (client_io, client_proxy) = tokio::io::duplex(4096);
+    let (server_io, server_proxy) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    let frozen = Arc::new(AtomicBool::new(false));
+    task::spawn(forward(client_read, server_write, frozen.clone()));
+    task::spawn(forward(
+        server_read,
+        client_write,
+        Arc::new(AtomicBool::new(false)),
+    ));
+    task::spawn(async move { server.serve_stream(server_io).await });
+
+    let client = Client::builder()
+        .set_broker_queue_capacity(2)
+        .set_writer_queue_capacity(2)
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+
+    // the server stops reading, and the sink waits once the queues of the client are full
+    frozen.store(true, Ordering::SeqCst);
+    let method = format!("{}.sum", rpc::COMMON_TEST_SERVICE_NAME);
+    let (mut sink, _call) = client.call_sink::<i32, i32>(method);
+    let sent = tokio::time::timeout(Duration::from_secs(1), async {
+        for i in 0..100_000 {
+            sink.send(i)
+                .await
+                .expect("Unexpected error sending request item");
+        }
+    })
+    .await;
+    assert!(sent.is_err(), "CallSink is not held back by the broker");
+    assert!(client.broker_queue_metrics().high_water_mark() <= 4);
+}
+
+#[test]
+fn test_sink_backpressure() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(sink_backpressure());
+}
+
+async fn call_backpressure() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let (client_io, client_proxy) = tokio::io::duplex(4096);
+    let (server_io, server_proxy) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    let frozen = Arc::new(AtomicBool::new(false));
+    task::spawn(forward(client_read, server_write, frozen.clone()));
+    task::spawn(forward(
+        server_read,
+        client_write,
+        Arc::new(AtomicBool::new(false)),
+    ));
+    task::spawn(async move { server.serve_stream(server_io).await });
+
+    let client = Client::builder()
+        .set_broker_queue_capacity(2)
+        .set_writer_queue_capacity(2)
+        .with_stream(client_io)
+        .await
+        .expect("Error connecting to server");
+
+    // the server stops reading, and the calls wait once the queues of the client are full
+    frozen.store(true, Ordering::SeqCst);
+    let method = format!("{}.get_magic_u64", rpc::COMMON_TEST_SERVICE_NAME);
+    let mut calls = Vec::new();
+    for _ in 0..1000 {
+        let mut call = client.call::<_, u64>(method.clone(), ());
+        assert!(futures::poll!(&mut call).is_pending());
+        calls.push(call);
+        task::yield_now().await;
+    }
+    assert!(client.broker_queue_metrics().high_water_mark() <= 4);
+
+    // a notification cannot wait, and is rejected instead
+    let method = format!("{}.echo_notify", rpc::COMMON_TEST_SERVICE_NAME);
+    match client.notify(method, "rejected".to_string()) {
+        Err(Error::Status(status)) => assert_eq!(status.code(), Code::ResourceExhausted),
+        other => panic!("Expecting a resource exhausted status, found {:?}", other),
+    }
+}
+
+#[test]
+fn test_call_backpressure() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(call_backpressure());
+}
+
+#[cfg(feature = "serde_json")]
+async fn multiple_formats() {
+    use toy_rpc::codec::Format;
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let internal = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let internal_addr = internal.local_addr().unwrap();
+    let browser = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let browser_addr = browser.local_addr().unwrap();
+    let browser_server = server.clone();
+    let internal_handle = task::spawn(async move { server.accept(internal).await });
+    let browser_handle = task::spawn(async move {
+        browser_server
+            .accept_with_format(browser, Format::Json)
+            .await
+    });
+
+    let internal = Client::dial(internal_addr)
+        .await
+        .expect("Error dialing server");
+    let browser = Client::builder()
+        .set_format(Format::Json)
+        .dial(browser_addr)
+        .await
+        .expect("Error dialing server");
+    assert_eq!(internal.capabilities().codec, Format::default().name());
+    assert_eq!(browser.capabilities().codec, "json");
+
+    for client in [&internal, &browser].iter() {
+        rpc::test_get_magic_u64(client).await;
+        rpc::test_get_magic_str(client).await;
+        rpc::test_typed_error(client).await;
+        rpc::test_server_streaming(client).await;
+        rpc::test_batch(client).await;
+    }
+
+    // the format of the client must match the format of the listener
+    let mismatched = Client::builder()
+        .set_format(Format::Json)
+        .dial(internal_addr);
+    match tokio::time::timeout(Duration::from_secs(5), mismatched).await {
+        Ok(Err(Error::IncompatiblePeer(_))) => {}
+        Ok(Err(err)) => panic!("Expecting incompatible peer error, found {:?}", err),
+        Ok(Ok(_)) => panic!("Expecting incompatible peer error"),
+        Err(_) => panic!("Handshake with mismatched format is not rejected"),
+    }
+
+    internal.close().await;
+    browser.close().await;
+    internal_handle.abort();
+    browser_handle.abort();
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_multiple_formats() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(multiple_formats());
+}
+
+#[cfg(feature = "serde_json")]
+async fn go_jsonrpc() {
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use toy_rpc::codec::Format;
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle =
+        task::spawn(async move { server.accept_with_format(listener, Format::GoJsonRpc).await });
+
+    // a Go client sends the objects of `net/rpc/jsonrpc` without the handshake
+    let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let requests = [
+        json!({"method": "CommonTest.get_magic_u64", "params": [null], "id": 0}),
+        json!({"method": "CommonTest.echo_error", "params": ["go"], "id": 1}),
+        json!({"method": "Missing.method", "params": [null], "id": 2}),
+    ];
+    for request in requests.iter() {
+        let line = format!("{}\n", request);
+        writer.write_all(line.as_bytes()).await.unwrap();
+    }
+    let mut replies = HashMap::new();
+    for _ in 0..requests.len() {
+        let line = lines.next_line().await.unwrap().expect("Expecting a reply");
+        let reply: Value = serde_json::from_str(&line).unwrap();
+        replies.insert(reply["id"].as_u64().unwrap(), reply);
+    }
+    assert_eq!(
+        replies[&0],
+        json!({"id": 0, "result": rpc::COMMON_TEST_MAGIC_U64, "error": null})
+    );
+    assert_eq!(replies[&1], json!({"id": 1, "result": null, "error": "go"}));
+    assert!(replies[&2]["result"].is_null());
+    assert!(replies[&2]["error"].is_string());
+
+    // a `Client` in the same mode talks to the server like a Go client
+    let client = Client::builder()
+        .set_format(Format::GoJsonRpc)
+        .dial(addr)
+        .await
+        .expect("Error dialing server");
+    assert_eq!(client.capabilities().codec, "go-jsonrpc");
+    rpc::test_get_magic_u64(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    client.close().await;
+
+    // a Go server answers with the id of the request and an error string
+    let go_server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let go_addr = go_server.local_addr().unwrap();
+    let go_handle = task::spawn(async move {
+        let (stream, _) = go_server.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let reply = match request["method"].as_str().unwrap() {
+                "Arith.Multiply" => {
+                    let args = &request["params"][0];
+                    let product = args["A"].as_i64().unwrap() * args["B"].as_i64().unwrap();
+                    json!({"id": request["id"], "result": product, "error": null})
+                }
+                method => json!({
+                    "id": request["id"],
+                    "result": null,
+                    "error": format!("rpc: can't find service {}", method)
+                }),
+            };
+            let line = format!("{}\n", reply);
+            writer.write_all(line.as_bytes()).await.unwrap();
+        }
+    });
+
+    let client = Client::builder()
+        .set_format(Format::GoJsonRpc)
+        .dial(go_addr)
+        .await
+        .expect("Error dialing server");
+    let reply: i64 = client
+        .call("Arith.Multiply", json!({"A": 7, "B": 8}))
+        .await
+        .unwrap();
+    assert_eq!(reply, 56);
+    match client
+        .call::<_, i64>("Arith.Divide", json!({"A": 7, "B": 8}))
+        .await
+    {
+        Err(Error::ServiceNotFound) => {}
+        res => panic!("Expecting service not found, found {:?}", res),
+    }
+
+    client.close().await;
+    go_handle.abort();
+    server_handle.abort();
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_go_jsonrpc() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(go_jsonrpc());
+}
+
+#[cfg(feature = "serde_json")]
+async fn jsonrpc() {
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use toy_rpc::codec::Format;
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle =
+        task::spawn(async move { server.accept_with_format(listener, Format::JsonRpc).await });
+
+    let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let to_line = |request: Value| format!("{}\n", request);
+
+    let request = json!({"jsonrpc": "2.0", "method": "CommonTest.get_magic_u64", "id": 0});
+    writer.write_all(to_line(request).as_bytes()).await.unwrap();
+    let line = lines.next_line().await.unwrap().expect("Expecting a reply");
+    let reply: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(
+        reply,
+        json!({"jsonrpc": "2.0", "result": rpc::COMMON_TEST_MAGIC_U64, "id": 0})
+    );
+
+    // the responses to a batch are sent in one array, without the notification
+    let batch = json!([
+        {"jsonrpc": "2.0", "method": "CommonTest.get_magic_str", "params": [], "id": "a"},
+        {"jsonrpc": "2.0", "method": "CommonTest.record", "params": [1]},
+        {"jsonrpc": "2.0", "method": "CommonTest.echo_error", "params": ["oops"], "id": 1},
+        {"jsonrpc": "2.0", "method": "Missing.method", "id": 2},
+        {"jsonrpc": "2.0", "method": "CommonTest.get_magic_u64", "params": ["x"], "id": 3},
+        {"jsonrpc": "2.0", "id": 4},
+    ]);
+    writer.write_all(to_line(batch).as_bytes()).await.unwrap();
+    let line = lines.next_line().await.unwrap().expect("Expecting a reply");
+    let replies: Vec<Value> = serde_json::from_str(&line).unwrap();
+    assert_eq!(replies.len(), 5);
+    let replies: HashMap<_, _> = replies
+        .into_iter()
+        .map(|reply| (reply["id"].to_string(), reply))
+        .collect();
+    assert_eq!(
+        replies[r#""a""#],
+        json!({"jsonrpc": "2.0", "result": rpc::COMMON_TEST_MAGIC_STR, "id": "a"})
+    );
+    assert_eq!(
+        replies["1"]["error"],
+        json!({"code": -32000, "message": "oops"})
+    );
+    assert_eq!(
+        replies["2"]["error"],
+        json!({"code": -32601, "message": "Method not found"})
+    );
+    assert_eq!(
+        replies["3"]["error"],
+        json!({"code": -32602, "message": "Invalid params"})
+    );
+    assert_eq!(replies["4"]["error"]["code"], json!(-32600));
+
+    // an object that cannot be parsed is answered with a `null` id
+    writer
+        .write_all(b"{\"jsonrpc\": \"2.0\", \"method\n")
+        .await
+        .unwrap();
+    let line = lines.next_line().await.unwrap().expect("Expecting a reply");
+    let reply: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(reply["error"]["code"], json!(-32700));
+    assert!(reply["id"].is_null());
+
+    // a `Client` in the same mode talks to the server like a JSON-RPC client
+    let client = Client::builder()
+        .set_format(Format::JsonRpc)
+        .dial(addr)
+        .await
+        .expect("Error dialing server");
+    assert_eq!(client.capabilities().codec, "jsonrpc");
+    rpc::test_get_magic_u64(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_method_not_found(&client).await;
+    rpc::test_execution_error(&client).await;
+    rpc::test_typed_error(&client).await;
+    rpc::test_notify(&client).await;
+    client.close().await;
+
+    server_handle.abort();
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_jsonrpc() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(jsonrpc());
+}
+
+#[cfg(feature = "serde_rmp")]
+async fn msgpack_rpc() {
+    use toy_rpc::codec::Format;
+
+    /// Reads a response, which is written in one piece by the server
+    async fn read_response<R, T>(reader: &mut R) -> (u8, u32, Option<String>, Option<T>)
+    where
+        R: AsyncRead + Unpin,
+        T: serde::de::DeserializeOwned,
+    {
+        let mut buf = Vec::new();
+        loop {
+            let mut chunk = [0u8; 256];
+            let n = reader.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "Expecting a response");
+            buf.extend_from_slice(&chunk[..n]);
+            if let Ok(response) = rmp_serde::from_slice(&buf) {
+                return response;
+            }
+        }
+    }
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        server
+            .accept_with_format(listener, Format::MsgpackRpc)
+            .await
+    });
+
+    // a MessagePack-RPC client writes the arrays without the handshake
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let request = (0u8, 7u32, "CommonTest.get_magic_u64", Vec::<u64>::new());
+    let buf = rmp_serde::to_vec(&request).unwrap();
+    // a message may arrive in pieces
+    stream.write_all(&buf[..3]).await.unwrap();
+    stream.flush().await.unwrap();
+    stream.write_all(&buf[3..]).await.unwrap();
+    let response = read_response::<_, u64>(&mut stream).await;
+    assert_eq!(response, (1, 7, None, Some(rpc::COMMON_TEST_MAGIC_U64)));
+
+    // a notification is not answered
+    let notification = (2u8, "CommonTest.record", [1u32]);
+    let request = (0u8, 8u32, "CommonTest.get_magic_str", Vec::<u64>::new());
+    let mut buf = rmp_serde::to_vec(&notification).unwrap();
+    buf.extend(rmp_serde::to_vec(&request).unwrap());
+    stream.write_all(&buf).await.unwrap();
+    let response = read_response::<_, String>(&mut stream).await;
+    assert_eq!(
+        response,
+        (1, 8, None, Some(rpc::COMMON_TEST_MAGIC_STR.into()))
+    );
+
+    let failures = [
+        (("CommonTest.echo_error", ["oops"]), "oops"),
+        (("Missing.method", ["x"]), "ServiceNotFound"),
+        (("CommonTest.missing", ["x"]), "MethodNotFound"),
+        (("CommonTest.get_magic_u64", ["x"]), "InvalidArgument"),
+    ];
+    for (msgid, ((method, params), error)) in (9u32..).zip(failures.iter()) {
+        let buf = rmp_serde::to_vec(&(0u8, msgid, method, params)).unwrap();
+        stream.write_all(&buf).await.unwrap();
+        let response = read_response::<_, ()>(&mut stream).await;
+        assert_eq!(response, (1, msgid, Some(error.to_string()), None));
+    }
+
+    // a `Client` in the same mode talks to the server like a MessagePack-RPC client
+    let client = Client::builder()
+        .set_format(Format::MsgpackRpc)
+        .dial(addr)
+        .await
+        .expect("Error dialing server");
+    assert_eq!(client.capabilities().codec, "msgpack-rpc");
+    rpc::test_get_magic_u64(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_service_not_found(&client).await;
+    rpc::test_method_not_found(&client).await;
+    rpc::test_execution_error(&client).await;
+    rpc::test_notify(&client).await;
+    client.close().await;
+
+    server_handle.abort();
+}
+
+#[cfg(feature = "serde_rmp")]
+#[test]
+fn test_msgpack_rpc() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(msgpack_rpc());
+}
+
+/// Messages of Go's `rpc.Client` for `client.Call("Arith.Multiply", &Args{7, 8}, &reply)`
+/// on a new connection, which define `rpc.Request` and `Args` before their values
+#[cfg(feature = "serde_gob")]
+const GO_MULTIPLY_REQUEST: &[u8] = b"\
+    \x2f\xff\x81\x03\x01\x01\x07Request\x01\xff\x82\x00\x01\x02\
+    \x01\x0dServiceMethod\x01\x0c\x00\x01\x03Seq\x01\x06\x00\x00\x00\
+    \x13\xff\x82\x01\x0eArith.Multiply\x00\
+    \x1e\xff\x83\x03\x01\x01\x04Args\x01\xff\x84\x00\x01\x02\
+    \x01\x01A\x01\x04\x00\x01\x01B\x01\x04\x00\x00\x00\
+    \x07\xff\x84\x01\x0e\x01\x10\x00";
+
+/// Messages of Go's `rpc.Server` in reply to `GO_MULTIPLY_REQUEST`
+#[cfg(feature = "serde_gob")]
+const GO_MULTIPLY_RESPONSE: &[u8] = b"\
+    \x3a\xff\x81\x03\x01\x01\x08Response\x01\xff\x82\x00\x01\x03\
+    \x01\x0dServiceMethod\x01\x0c\x00\x01\x03Seq\x01\x06\x00\x01\x05Error\x01\x0c\x00\
+    \x00\x00\
+    \x13\xff\x82\x01\x0eArith.Multiply\x00\
+    \x03\x04\x00\x70";
+
+/// Messages of Go's `rpc.Client` for a following call of `Arith.Divide` with the same
+/// arguments
+#[cfg(feature = "serde_gob")]
+const GO_DIVIDE_REQUEST: &[u8] = b"\
+    \x13\xff\x82\x01\x0cArith.Divide\x01\x01\x00\
+    \x07\xff\x84\x01\x0e\x01\x10\x00";
+
+/// Messages of Go's `rpc.Server` in reply to `GO_DIVIDE_REQUEST`, where the body of the
+/// failed call is an empty struct
+#[cfg(feature = "serde_gob")]
+const GO_DIVIDE_RESPONSE: &[u8] = b"\
+    \x38\xff\x82\x01\x0cArith.Divide\x01\x01\
+    \x01\x23rpc: can't find method Arith.Divide\x00\
+    \x0a\xff\x83\x03\x01\x02\xff\x84\x00\x00\x00\
+    \x03\xff\x84\x00";
+
+#[cfg(feature = "serde_gob")]
+async fn go_rpc() {
+    use toy_rpc::codec::Format;
+
+    let server = Server::builder().register(Arc::new(rpc::go::Arith)).build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle =
+        task::spawn(async move { server.accept_with_format(listener, Format::GoRpc).await });
+
+    // a Go client writes the gob stream of `net/rpc` without the handshake
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(GO_MULTIPLY_REQUEST).await.unwrap();
+    let mut reply = vec![0u8; GO_MULTIPLY_RESPONSE.len()];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, GO_MULTIPLY_RESPONSE);
+
+    // the failed call carries an error string in the response that is already defined
+    stream.write_all(GO_DIVIDE_REQUEST).await.unwrap();
+    let mut count = [0u8; 1];
+    stream.read_exact(&mut count).await.unwrap();
+    let mut reply = vec![0u8; count[0] as usize];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply[..19], b"\xff\x82\x01\x0cArith.Divide\x01\x01\x01");
+    assert_ne!(reply[19], 0, "Expecting an error string");
+    drop(stream);
+
+    // a Go server answers a `Client` in the same mode
+    let go_server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let go_addr = go_server.local_addr().unwrap();
+    let go_handle = task::spawn(async move {
+        let (mut stream, _) = go_server.accept().await.unwrap();
+        let exchanges = [
+            (GO_MULTIPLY_REQUEST, GO_MULTIPLY_RESPONSE),
+            (GO_DIVIDE_REQUEST, GO_DIVIDE_RESPONSE),
+        ];
+        for (request, response) in exchanges.iter() {
+            let mut buf = vec![0u8; request.len()];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, request);
+            stream.write_all(response).await.unwrap();
+        }
+    });
+
+    let client = Client::builder()
+        .set_format(Format::GoRpc)
+        .dial(go_addr)
+        .await
+        .expect("Error dialing server");
+    assert_eq!(client.capabilities().codec, "go-rpc");
+    let args = || rpc::go::ArithArgs { a: 7, b: 8 };
+    let reply: i64 = client.call("Arith.Multiply", args()).await.unwrap();
+    assert_eq!(reply, 56);
+    match client.call::<_, i64>("Arith.Divide", args()).await {
+        Err(Error::MethodNotFound) => {}
+        res => panic!("Expecting method not found, found {:?}", res),
+    }
+
+    client.close().await;
+    go_handle.await.unwrap();
+    server_handle.abort();
+}
+
+#[cfg(feature = "serde_gob")]
+#[test]
+fn test_go_rpc() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(go_rpc());
+}
+
 #[test]
 fn test_main() {
     let rt = tokio::runtime::Runtime::new().unwrap();
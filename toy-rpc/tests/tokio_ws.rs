@@ -3,7 +3,7 @@ use futures::channel::oneshot::{channel, Receiver};
 use std::{str, sync::Arc};
 use tokio::net::TcpListener;
 use tokio::task;
-use toy_rpc::{Client, Server};
+use toy_rpc::{Client, Error, Server};
 
 mod rpc;
 
@@ -13,7 +13,7 @@ async fn test_client(base: &'static str, mut ready: Receiver<()>) -> Result<()>
 
     println!("Client received ready");
 
-    let client = Client::dial_websocket(&addr)
+    let mut client = Client::dial_websocket(&addr)
         .await
         .expect("Error dialing server");
 
@@ -31,6 +31,25 @@ async fn test_client(base: &'static str, mut ready: Receiver<()>) -> Result<()>
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_typed_error(&client).await;
+    rpc::test_status_codes(&client).await;
+    rpc::test_server_streaming(&client).await;
+    rpc::test_server_streaming_error(&client).await;
+    rpc::test_server_streaming_timeout(&client).await;
+    rpc::test_server_streaming_cancel(&client).await;
+    rpc::test_client_streaming(&client).await;
+    rpc::test_client_streaming_cancel(&client).await;
+    rpc::test_bidi_streaming(&client).await;
+    rpc::test_metadata(&mut client).await;
+    rpc::test_context(&client).await;
+    rpc::test_deadline(&client).await;
+    rpc::test_cooperative_cancellation(&client).await;
+    rpc::test_notify(&client).await;
+    rpc::test_batch(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_capabilities(&client).await;
+    rpc::test_extension(&client).await;
+    rpc::test_work_queue(&client).await;
 
     println!("Client received all correct RPC result");
     client.close().await;
@@ -43,7 +62,11 @@ async fn run(base: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .register(common_test_service)
+        .register_extension(rpc::ECHO_EXTENSION, rpc::echo_extension)
+        .register_extension(rpc::FAILING_EXTENSION, rpc::failing_extension)
+        .build();
 
     let listener = TcpListener::bind(addr)
         .await
@@ -67,6 +90,82 @@ async fn run(base: &'static str) {
     server_handle.abort();
 }
 
+#[cfg(any(feature = "compression_deflate", feature = "compression_lz4"))]
+async fn compression(addr: &'static str) {
+    use toy_rpc::compression::Compression;
+
+    let mut algorithms = Compression::available();
+    let server_compression = algorithms.next().unwrap();
+    // each side uses its own algorithm if more than one is enabled
+    let client_compression = algorithms.next().unwrap_or(server_compression);
+
+    let server = Server::builder()
+        .set_compression(server_compression)
+        .set_compression_threshold(64)
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let listener = TcpListener::bind(addr)
+        .await
+        .expect("Cannot bind to address");
+    let server_handle = task::spawn(async move {
+        server.accept_websocket(listener).await.unwrap();
+    });
+
+    let client = Client::builder()
+        .set_compression(client_compression)
+        .set_compression_threshold(64)
+        .dial_websocket(&format!("ws://{}", addr))
+        .await
+        .expect("Error dialing server");
+    rpc::test_large_payload(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_batch(&client).await;
+    client.close().await;
+
+    server_handle.abort();
+}
+
+#[cfg(any(feature = "compression_deflate", feature = "compression_lz4"))]
+#[test]
+fn websocket_compression() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(compression("127.0.0.1:8081"));
+}
+
+async fn max_message_size(addr: &'static str) {
+    let server = Server::builder()
+        .set_max_message_size(rpc::SMALL_MAX_MESSAGE_SIZE)
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let listener = TcpListener::bind(addr)
+        .await
+        .expect("Cannot bind to address");
+    let server_handle = task::spawn(async move {
+        server.accept_websocket(listener).await.unwrap();
+    });
+
+    let client = Client::builder()
+        .dial_websocket(&format!("ws://{}", addr))
+        .await
+        .expect("Error dialing server");
+    rpc::test_get_magic_str(&client).await;
+    let large = "a".repeat(rpc::LARGE_PAYLOAD_LEN);
+    let reply: Result<String, Error> = client.call("CommonTest.echo", large).await;
+    assert!(reply.is_err());
+    // a WebSocket connection is always closed by an oversized message
+    let reply: Result<String, Error> = client.call("CommonTest.get_magic_str", ()).await;
+    assert!(reply.is_err());
+    client.close().await;
+
+    server_handle.abort();
+}
+
+#[test]
+fn websocket_max_message_size() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(max_message_size("127.0.0.1:8082"));
+}
+
 #[test]
 fn websocket_with_tokio() {
     let rt = tokio::runtime::Runtime::new().unwrap();
@@ -13,7 +13,7 @@ async fn test_client(base: &str, mut ready: Receiver<()>) -> Result<()> {
     println!("Client received ready");
 
     let addr = format!("ws://{}/rpc/", base);
-    let client = Client::dial_http(&addr)
+    let mut client = Client::dial_http(&addr)
         .await
         .expect("Error dialing http server");
 
@@ -31,6 +31,25 @@ async fn test_client(base: &str, mut ready: Receiver<()>) -> Result<()> {
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_typed_error(&client).await;
+    rpc::test_status_codes(&client).await;
+    rpc::test_server_streaming(&client).await;
+    rpc::test_server_streaming_error(&client).await;
+    rpc::test_server_streaming_timeout(&client).await;
+    rpc::test_server_streaming_cancel(&client).await;
+    rpc::test_client_streaming(&client).await;
+    rpc::test_client_streaming_cancel(&client).await;
+    rpc::test_bidi_streaming(&client).await;
+    rpc::test_metadata(&mut client).await;
+    rpc::test_context(&client).await;
+    rpc::test_deadline(&client).await;
+    rpc::test_cooperative_cancellation(&client).await;
+    rpc::test_notify(&client).await;
+    rpc::test_batch(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_capabilities(&client).await;
+    rpc::test_extension(&client).await;
+    rpc::test_work_queue(&client).await;
 
     println!("Client received all correct RPC result");
     client.close().await;
@@ -43,7 +62,11 @@ async fn run(base: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .register(common_test_service)
+        .register_extension(rpc::ECHO_EXTENSION, rpc::echo_extension)
+        .register_extension(rpc::FAILING_EXTENSION, rpc::failing_extension)
+        .build();
 
     let routes = warp::path("rpc").and(server.into_boxed_filter());
 
@@ -67,3 +90,27 @@ fn http_warp_integration() {
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(run(rpc::ADDR));
 }
+
+/// Serves JSON-RPC 2.0 on the route
+#[cfg(feature = "serde_json")]
+async fn jsonrpc() {
+    use toy_rpc::codec::Format;
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .set_format(Format::JsonRpc)
+        .build();
+    let routes = warp::path("rpc").and(server.into_boxed_filter());
+    let (addr, serving) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+
+    rpc::test_jsonrpc_websocket(&addr.to_string()).await;
+    server_handle.abort();
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn http_warp_jsonrpc() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(jsonrpc());
+}